@@ -0,0 +1,18 @@
+// the parsing core used the way firmware would, without std. Builds for a target that has no std at all,
+// which is what keeps std from creeping into the modules that should only need alloc
+#![no_std]
+
+use core::net::Ipv4Addr;
+
+use dns_learning::{parse_bytes, DnsRecord, Result};
+
+// the first A record of a response, or the error the server answered with
+pub fn first_address(response: &[u8]) -> Result<Option<Ipv4Addr>> {
+    let packet = parse_bytes(response)?;
+    packet.check_response_code()?;
+
+    Ok(packet.answers.iter().find_map(|record| match record {
+        DnsRecord::A { address, .. } => Some(*address),
+        _ => None,
+    }))
+}
@@ -0,0 +1,32 @@
+#![no_main]
+
+use dns_learning::{buffer::MAX_BUFFER_SIZE, parse_bytes, BytePacketBuffer, DnsPacket};
+use libfuzzer_sys::fuzz_target;
+
+fn written(packet: &DnsPacket) -> Option<Vec<u8>> {
+    let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+    packet.write(&mut buffer).ok()?;
+
+    Some(buffer.buffer[..buffer.pos()].to_vec())
+}
+
+// run with `cargo +nightly fuzz run parse_packet`. Parsing must never panic, and a packet that parses and
+// can be written must parse back, after which writing and parsing it again changes nothing. The first
+// write can still change it: a label with a dot in it becomes two, and names sharing a suffix share its
+// case once compressed
+fuzz_target!(|data: &[u8]| {
+    let Ok(parsed) = parse_bytes(data) else {
+        return;
+    };
+    // formatting walks every parsed field, so make sure that can't panic either
+    let _ = parsed.to_string();
+
+    let Some(first) = written(&parsed) else {
+        return;
+    };
+    let packet = parse_bytes(&first).expect("A written packet parses");
+
+    let second = written(&packet).expect("A reparsed packet can be written");
+    assert_eq!(parse_bytes(&second).ok(), Some(packet));
+    assert_eq!(second, first);
+});
@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+
+use crate::ClientSubnet;
+
+// who may do something, by the networks they're in: clients in a denied network may not, and once
+// there are allowed networks only the clients in one of them may. Without any networks everyone may
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub allow: Vec<ClientSubnet>,
+    pub deny: Vec<ClientSubnet>,
+}
+
+impl Acl {
+    pub fn new() -> Acl {
+        Acl::default()
+    }
+
+    pub fn with_allowed(mut self, network: ClientSubnet) -> Acl {
+        self.allow.push(network);
+        self
+    }
+
+    pub fn with_denied(mut self, network: ClientSubnet) -> Acl {
+        self.deny.push(network);
+        self
+    }
+
+    // denying wins over allowing, so a network can be allowed with a part of it left out
+    pub fn allows(&self, client: IpAddr) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|network| network.contains(client));
+        allowed && !self.deny.iter().any(|network| network.contains(client))
+    }
+}
+
+// what a server lets its clients do, everything that isn't allowed is answered with REFUSED. Clients
+// that may query but not recurse still get the answers from the server's own zones, everything else is
+// refused to them instead of being resolved. Like the other limits these are the server's, whatever view
+// a client is in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessControl {
+    pub query: Acl,
+    pub recursion: Acl,
+    // AXFR and IXFR
+    pub transfer: Acl,
+}
+
+impl AccessControl {
+    pub fn new() -> AccessControl {
+        AccessControl::default()
+    }
+
+    pub fn with_query(mut self, acl: Acl) -> AccessControl {
+        self.query = acl;
+        self
+    }
+
+    pub fn with_recursion(mut self, acl: Acl) -> AccessControl {
+        self.recursion = acl;
+        self
+    }
+
+    pub fn with_transfer(mut self, acl: Acl) -> AccessControl {
+        self.transfer = acl;
+        self
+    }
+}
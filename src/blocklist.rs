@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    cache::lock,
+    edns::{DEFAULT_PAYLOAD_SIZE, EDE_BLOCKED},
+    DnsError, DnsPacket, DnsQuestion, DnsRecord, Edns, ExtendedError, QueryType, Result, ResultCode,
+};
+
+// what the addresses blocked names are answered with live for, short so unblocking shows up soon
+pub const BLOCKED_TTL: u32 = 60;
+
+// names hosts format lists map to their local addresses, which aren't there to be blocked
+const HOSTS_NAMES: [&str; 6] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+// how blocked names are answered
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BlockMode {
+    // as if they didn't exist
+    #[default]
+    NxDomain,
+    // with 0.0.0.0 and ::, which connections fail to right away, other types get NODATA
+    Null,
+}
+
+impl FromStr for BlockMode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<BlockMode> {
+        match s.to_ascii_lowercase().as_str() {
+            "nxdomain" => Ok(BlockMode::NxDomain),
+            "null" | "0.0.0.0" => Ok(BlockMode::Null),
+            _ => Err(format!("Unknown block mode {}, it's nxdomain or null", s).into()),
+        }
+    }
+}
+
+// names queries aren't resolved for, like Pi-hole blocks ads and trackers. Every name blocks the names
+// below it as well, so a lookup only takes one set lookup per label. Counts how many queries it blocked
+// while it's in use, shared between the resolver's clones
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    // lowercased, without the trailing dot
+    names: HashSet<String>,
+    pub mode: BlockMode,
+    queries: AtomicU64,
+    blocked: AtomicU64,
+    // by the name in the list that blocked them
+    hits: Mutex<HashMap<String, u64>>,
+}
+
+impl Blocklist {
+    pub fn new() -> Blocklist {
+        Blocklist::default()
+    }
+
+    pub fn with_mode(mut self, mode: BlockMode) -> Blocklist {
+        self.mode = mode;
+        self
+    }
+
+    // adds the names of the list in the file, returns how many of them weren't in it yet
+    pub fn read(&mut self, path: &Path) -> Result<usize> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the blocklist {}: {}", path.display(), e))?;
+
+        Ok(self.parse(&text))
+    }
+
+    // adds the names of a list, either in hosts format like `0.0.0.0 ads.example.com` or one name a
+    // line, which may start with `*.` or be written `||ads.example.com^` the way adblock lists do. # starts
+    // a comment, and lines that aren't names are skipped since big lists always have a few of those.
+    // Returns how many names weren't in the list yet
+    pub fn parse(&mut self, text: &str) -> usize {
+        let mut added = 0;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let names = match fields.as_slice() {
+                [address, names @ ..] if address.parse::<IpAddr>().is_ok() => names,
+                [name] => &[*name][..],
+                _ => continue,
+            };
+
+            for name in names {
+                let name = name.trim_start_matches("||").trim_end_matches('^');
+                let name = name.trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+                if blockable(&name) && self.names.insert(name) {
+                    added += 1;
+                }
+            }
+        }
+
+        added
+    }
+
+    pub fn add(&mut self, name: &str) -> bool {
+        self.names.insert(name.trim_end_matches('.').to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // the name in the list that blocks name, either name itself or one of its parents
+    pub fn blocking(&self, name: &str) -> Option<&str> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut suffix = name.as_str();
+        loop {
+            if let Some(blocked) = self.names.get(suffix) {
+                return Some(blocked);
+            }
+            suffix = suffix.split_once('.')?.1;
+        }
+    }
+
+    // the queries checked against the list so far
+    pub fn queries(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    // the queries that were blocked so far
+    pub fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    // the count most often blocked names in the list, with how many queries each one blocked
+    pub fn top(&self, count: usize) -> Vec<(String, u64)> {
+        let mut hits: Vec<(String, u64)> = lock(&self.hits)
+            .iter()
+            .map(|(name, hits)| (name.clone(), *hits))
+            .collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits.truncate(count);
+
+        hits
+    }
+
+    // the answer to question when its name is blocked, None when it's resolved as usual
+    pub(crate) fn answer(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        let blocked = self.blocking(&question.name)?;
+
+        Some(self.blocked_answer(question, blocked))
+    }
+
+    // answer, unless one of its cnames leads to a blocked name, which trackers hide behind in the
+    // first party's domain. That one is blocked like asking for it would have been
+    pub(crate) fn filtered(&self, question: &DnsQuestion, answer: DnsPacket) -> DnsPacket {
+        let blocked = answer.answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME { host, .. } => self.blocking(host),
+            _ => None,
+        });
+
+        match blocked {
+            Some(blocked) => self.blocked_answer(question, blocked),
+            None => answer,
+        }
+    }
+
+    fn blocked_answer(&self, question: &DnsQuestion, blocked: &str) -> DnsPacket {
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+        *lock(&self.hits).entry(blocked.to_string()).or_default() += 1;
+
+        let mut answer = DnsPacket::new();
+        let domain = question.name.clone();
+        match self.mode {
+            BlockMode::NxDomain => answer.header.result_code = ResultCode::NXDOMAIN,
+            BlockMode::Null => {
+                if matches!(question.qtype, QueryType::A | QueryType::ANY) {
+                    let address = Ipv4Addr::UNSPECIFIED;
+                    answer.answers.push(DnsRecord::A {
+                        domain: domain.clone(),
+                        address,
+                        ttl: BLOCKED_TTL,
+                    });
+                }
+                if matches!(question.qtype, QueryType::AAAA | QueryType::ANY) {
+                    let address = Ipv6Addr::UNSPECIFIED;
+                    answer.answers.push(DnsRecord::AAAA {
+                        domain,
+                        address,
+                        ttl: BLOCKED_TTL,
+                    });
+                }
+            }
+        }
+
+        // RFC 8914 has an extended error for it, so clients can tell it apart from the name not existing
+        let mut edns = Edns::new(DEFAULT_PAYLOAD_SIZE);
+        edns.add_extended_error(&ExtendedError::new(EDE_BLOCKED));
+        answer.set_edns(edns);
+
+        answer
+    }
+}
+
+// whether name from a list is one to block, rather than a local name from a hosts file or junk
+fn blockable(name: &str) -> bool {
+    !name.is_empty()
+        && !HOSTS_NAMES.contains(&name)
+        && name.parse::<IpAddr>().is_err()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
@@ -0,0 +1,622 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{idna::decode_idn, Result};
+
+// letters, digits and hyphens, plus the underscore used by service labels like _sip._tcp
+fn is_hostname_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'
+}
+
+// how many compression jumps read_q_name allows unless told otherwise
+pub const DEFAULT_MAX_JUMPS: usize = 5;
+
+// the classic udp limit, what new() gives you when EDNS and tcp aren't involved
+pub const DEFAULT_BUFFER_SIZE: usize = 512;
+
+// a tcp message is prefixed by a 16 bit length, so nothing on the wire is ever bigger than this
+pub const MAX_BUFFER_SIZE: usize = 65535;
+
+pub struct BytePacketBuffer {
+    pub buffer: Vec<u8>,
+    pub position: usize,
+    pub max_jumps: usize,
+    // when set, read_q_name errors on label bytes that aren't letters, digits, hyphens or underscores
+    pub strict_labels: bool,
+    // where each name suffix written so far starts, so later names can point back at it
+    names: BTreeMap<String, usize>,
+    // false writes every name in full, the way DNSSEC signs them
+    compress: bool,
+}
+
+impl Default for BytePacketBuffer {
+    fn default() -> Self {
+        BytePacketBuffer::new()
+    }
+}
+
+impl BytePacketBuffer {
+    pub fn new() -> BytePacketBuffer {
+        BytePacketBuffer::with_capacity(DEFAULT_BUFFER_SIZE)
+    }
+
+    // a zeroed buffer of the given size, capped at MAX_BUFFER_SIZE
+    pub fn with_capacity(capacity: usize) -> BytePacketBuffer {
+        BytePacketBuffer {
+            buffer: vec![0; capacity.min(MAX_BUFFER_SIZE)],
+            position: 0,
+            max_jumps: DEFAULT_MAX_JUMPS,
+            strict_labels: false,
+            names: BTreeMap::new(),
+            compress: true,
+        }
+    }
+
+    pub fn without_compression(mut self) -> BytePacketBuffer {
+        self.compress = false;
+        self
+    }
+
+    // a buffer holding exactly data, ready to be read from the start
+    pub fn from_bytes(data: &[u8]) -> Result<BytePacketBuffer> {
+        if data.len() > MAX_BUFFER_SIZE {
+            return Err(format!("Packet of {} bytes exceeds {} bytes", data.len(), MAX_BUFFER_SIZE).into());
+        }
+
+        let mut buffer = BytePacketBuffer::with_capacity(data.len());
+        buffer.buffer.copy_from_slice(data);
+
+        Ok(buffer)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn pos(&self) -> usize {
+        self.position
+    }
+
+    pub fn step(&mut self, steps: usize) -> Result<()> {
+        if self.position + steps > self.buffer.len() {
+            return Err("End of buffer exceeded".into());
+        }
+        self.position += steps;
+
+        Ok(())
+    }
+
+    pub fn seek(&mut self, pos: usize) -> Result<()> {
+        self.position = pos;
+        Ok(())
+    }
+
+    pub fn read(&mut self) -> Result<u8> {
+        if self.position >= self.buffer.len() {
+            return Err("End of buffer".into());
+        }
+        let result = self.buffer[self.position];
+        self.position += 1;
+
+        Ok(result)
+    }
+
+    pub fn get(&mut self, pos: usize) -> Result<u8> {
+        if pos >= self.buffer.len() {
+            return Err("End of buffer".into());
+        }
+        Ok(self.buffer[pos])
+    }
+
+    pub fn get_range(&mut self, start: usize, length: usize) -> Result<&[u8]> {
+        if start + length > self.buffer.len() {
+            return Err("End of buffer exceeded".into());
+        }
+        Ok(&self.buffer[start..start + length])
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        self.read()
+    }
+
+    // everything on the wire is big endian, so reads and writes both go through the be_bytes
+    // conversions instead of shifting by hand
+    pub fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        let bytes = self.get_range(self.pos(), length)?.to_vec();
+        self.step(length)?;
+
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.get_range(self.pos(), N)?);
+        self.step(N)?;
+
+        Ok(bytes)
+    }
+
+    // runs parse over the next data_length bytes of record data, then leaves the buffer right after
+    // them no matter where the parser stopped, so jumps inside compressed names can't throw off
+    // where the next record starts
+    pub fn read_rdata<T>(
+        &mut self,
+        data_length: usize,
+        parse: impl FnOnce(&mut BytePacketBuffer) -> Result<T>,
+    ) -> Result<T> {
+        let end = self.pos() + data_length;
+        if end > self.buffer.len() {
+            return Err("Record data exceeds the buffer".into());
+        }
+
+        let value = parse(self)?;
+        if self.pos() > end {
+            return Err("Record data was read past its length".into());
+        }
+        self.seek(end)?;
+
+        Ok(value)
+    }
+
+    // a single length prefixed character-string, as used in HINFO and TXT rdata
+    pub fn read_character_string(&mut self) -> Result<String> {
+        let len = self.read()? as usize;
+        let start = self.pos();
+        let text = String::from_utf8_lossy(self.get_range(start, len)?).to_string();
+        self.step(len)?;
+
+        Ok(text)
+    }
+
+    pub fn write(&mut self, val: u8) -> Result<()> {
+        if self.position >= self.buffer.len() {
+            return Err("End of buffer".into());
+        }
+        self.buffer[self.position] = val;
+        self.position += 1;
+
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, val: u8) -> Result<()> {
+        self.write(val)
+    }
+
+    pub fn write_u16(&mut self, val: u16) -> Result<()> {
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    pub fn write_u32(&mut self, val: u32) -> Result<()> {
+        self.write_bytes(&val.to_be_bytes())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.position + bytes.len() > self.buffer.len() {
+            return Err("End of buffer exceeded".into());
+        }
+        self.buffer[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+        self.position += bytes.len();
+
+        Ok(())
+    }
+
+    pub fn write_character_string(&mut self, text: &str) -> Result<()> {
+        if text.len() > 0xFF {
+            return Err("Character-string exceeds 255 bytes".into());
+        }
+        self.write_u8(text.len() as u8)?;
+        self.write_bytes(text.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn set(&mut self, pos: usize, val: u8) -> Result<()> {
+        if pos >= self.buffer.len() {
+            return Err("End of buffer".into());
+        }
+        self.buffer[pos] = val;
+
+        Ok(())
+    }
+
+    pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<()> {
+        let [high, low] = val.to_be_bytes();
+        self.set(pos, high)?;
+        self.set(pos + 1, low)?;
+
+        Ok(())
+    }
+
+    // counterpart of read_rdata: writes a placeholder length, runs write, then fills in how many
+    // bytes of record data it produced
+    pub fn write_rdata(&mut self, write: impl FnOnce(&mut BytePacketBuffer) -> Result<()>) -> Result<()> {
+        let length_pos = self.pos();
+        self.write_u16(0)?;
+
+        write(self)?;
+
+        let data_length = self.pos() - (length_pos + 2);
+        if data_length > 0xFFFF {
+            return Err("Record data exceeds 65535 bytes".into());
+        }
+        self.set_u16(length_pos, data_length as u16)?;
+
+        Ok(())
+    }
+
+    // writes the name as length prefixed labels, pointing back at an earlier copy of the
+    // longest suffix already in the buffer instead of repeating it
+    pub fn write_q_name(&mut self, qname: &str) -> Result<()> {
+        self.write_name(qname, true)
+    }
+
+    // for names inside rdata of types that must not be compressed, like SRV and RRSIG, later
+    // names can still point into it though
+    pub fn write_q_name_uncompressed(&mut self, qname: &str) -> Result<()> {
+        self.write_name(qname, false)
+    }
+
+    fn write_name(&mut self, qname: &str, compress: bool) -> Result<()> {
+        // a single trailing dot only marks the name as fully qualified
+        let qname = qname.strip_suffix('.').unwrap_or(qname);
+
+        if !qname.is_empty() {
+            let labels: Vec<&str> = qname.split('.').collect();
+            // checked before anything is written, so a bad name leaves no suffix behind to point at
+            for label in &labels {
+                if label.is_empty() {
+                    return Err(format!("Empty label in {}", qname).into());
+                }
+                if label.len() > 0x3F {
+                    return Err("Single label exceeds 63 characters of length".into());
+                }
+            }
+
+            for index in 0..labels.len() {
+                // names compare case insensitively, so a suffix matches whatever case it was written in
+                let suffix = labels[index..].join(".").to_lowercase();
+                if compress && self.compress {
+                    if let Some(&offset) = self.names.get(&suffix) {
+                        return self.write_u16(0xC000 | offset as u16);
+                    }
+                }
+
+                // pointers only have 14 bits for the offset
+                let pos = self.pos();
+                if pos <= 0x3FFF {
+                    self.names.entry(suffix).or_insert(pos);
+                }
+
+                let label = labels[index];
+                self.write_u8(label.len() as u8)?;
+                self.write_bytes(label.as_bytes())?;
+            }
+        }
+
+        // terminating empty label
+        self.write_u8(0)?;
+
+        Ok(())
+    }
+
+    pub fn read_q_name(&mut self, outstring: &mut String) -> Result<()> {
+        // tracking position in case there are jumps
+        let mut pos = self.pos();
+
+        // tracking whether there's been jumps and how many
+        let mut jumped = false;
+        let max_jumps = self.max_jumps;
+        let mut jumps_performed = 0;
+
+        let mut delimiter = "";
+        loop {
+            // in case there is a malicious loop in the packet
+            if jumps_performed > max_jumps {
+                return Err(format!("Limit of {} jumps was exceeded", max_jumps).into());
+            }
+
+            // labels always begin with a length byte by spec
+            let len = self.get(pos)?;
+
+            // check if the next byte needs to be read as well
+            if (len & 0xC0) == 0xC0 {
+                if !jumped {
+                    self.seek(pos + 2)?;
+                }
+
+                // read another byte
+                let len_second = self.get(pos + 1)? as u16;
+                let offset = (((len as u16) ^ 0xC0) << 8) | len_second;
+                pos = offset as usize;
+
+                // note that there was a jump performed
+                jumped = true;
+                jumps_performed += 1;
+
+                continue;
+            }
+            // 0x40 and 0x80 are reserved, a plain label can't be longer than 63 bytes
+            else if (len & 0xC0) != 0 {
+                return Err(format!("Invalid label length byte 0x{:02x}", len).into());
+            }
+            // base scenario where there is a single label read and then appended to the output
+            else {
+                pos += 1; // move a single byte forward past the length byte
+
+                // domain names are terminated by an empty label with length 0
+                // if length is 0 then we are done
+                if len == 0 {
+                    break;
+                }
+
+                outstring.push_str(delimiter);
+
+                // Get the actual ASCII bytes for the label
+                let strict = self.strict_labels;
+                let string_buffer = self.get_range(pos, len as usize)?;
+                if strict {
+                    if let Some(byte) = string_buffer.iter().find(|b| !is_hostname_byte(**b)) {
+                        return Err(format!("Invalid byte 0x{:02x} in label", byte).into());
+                    }
+                }
+                // the case is kept as it was sent, comparisons ignore it where they need to
+                outstring.push_str(&String::from_utf8_lossy(string_buffer));
+
+                delimiter = ".";
+
+                pos += len as usize;
+            }
+        }
+
+        if !jumped {
+            self.seek(pos)?;
+        }
+
+        Ok(())
+    }
+
+    // same as read_q_name, but hands back the name instead of appending to a string
+    pub fn read_qname(&mut self) -> Result<String> {
+        let mut name = String::new();
+        self.read_q_name(&mut name)?;
+
+        Ok(name)
+    }
+
+    // same as read_q_name, but any punycode (xn--) labels are decoded to unicode
+    pub fn read_q_name_unicode(&mut self, outstring: &mut String) -> Result<()> {
+        let mut ascii = String::new();
+        self.read_q_name(&mut ascii)?;
+
+        outstring.push_str(&decode_idn(&ascii));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "a" at offset 0 followed by a chain of pointers, each pointing at the one before it, so reading
+    // the name from the last pointer takes `jumps` jumps
+    fn pointer_chain(jumps: usize) -> BytePacketBuffer {
+        let mut data = vec![1, b'a', 0];
+        let mut target = 0;
+        for _ in 0..jumps {
+            let here = data.len();
+            data.extend_from_slice(&(0xC000 | target as u16).to_be_bytes());
+            target = here;
+        }
+
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        buffer.seek(target).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn follows_jumps_up_to_the_limit() {
+        let mut buffer = pointer_chain(DEFAULT_MAX_JUMPS);
+
+        assert_eq!(buffer.read_qname().unwrap(), "a");
+        // the position ends up right after the first pointer, not wherever the jumps went
+        assert_eq!(buffer.pos(), buffer.capacity());
+    }
+
+    #[test]
+    fn rejects_one_jump_past_the_limit() {
+        let mut buffer = pointer_chain(DEFAULT_MAX_JUMPS + 1);
+        assert!(buffer.read_qname().is_err());
+
+        let mut buffer = pointer_chain(2);
+        buffer.max_jumps = 1;
+        assert!(buffer.read_qname().is_err());
+    }
+
+    #[test]
+    fn rejects_a_pointer_loop() {
+        // a pointer to itself
+        let mut buffer = BytePacketBuffer::from_bytes(&[0xC0, 0x00]).unwrap();
+        assert!(buffer.read_qname().is_err());
+
+        // two labels pointing at each other
+        let mut buffer = BytePacketBuffer::from_bytes(&[1, b'a', 0xC0, 0x04, 1, b'b', 0xC0, 0x00]).unwrap();
+        assert!(buffer.read_qname().is_err());
+    }
+
+    #[test]
+    fn invalid_utf8_labels_are_lossy_unless_strict() {
+        let data = [2, 0xFF, b'a', 3, b'c', b'o', b'm', 0];
+
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        assert_eq!(buffer.read_qname().unwrap(), "\u{FFFD}a.com");
+
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        buffer.strict_labels = true;
+        assert!(buffer.read_qname().is_err());
+    }
+
+    #[test]
+    fn strict_labels_only_take_hostname_bytes() {
+        let name = |label: &[u8]| {
+            let mut data = vec![label.len() as u8];
+            data.extend_from_slice(label);
+            data.push(0);
+
+            let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+            buffer.strict_labels = true;
+            buffer.read_qname()
+        };
+
+        assert_eq!(name(b"_sip-2").unwrap(), "_sip-2");
+        assert!(name(b"a b").is_err());
+        assert!(name(b"a*").is_err());
+        assert!(name("bü".as_bytes()).is_err());
+
+        // lossy mode lets the same bytes through as they are
+        let mut buffer = BytePacketBuffer::from_bytes(&[3, b'a', b' ', b'b', 0]).unwrap();
+        assert_eq!(buffer.read_qname().unwrap(), "a b");
+    }
+
+    #[test]
+    fn rejects_reserved_label_lengths() {
+        // 64 is 0x40, past the 63 bytes a label can have
+        let mut data = vec![64];
+        data.extend_from_slice(&[b'a'; 64]);
+        data.push(0);
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        assert!(buffer.read_qname().is_err());
+
+        let mut buffer = BytePacketBuffer::from_bytes(&[0x80, b'a', 0]).unwrap();
+        assert!(buffer.read_qname().is_err());
+
+        let mut data = vec![63];
+        data.extend_from_slice(&[b'a'; 63]);
+        data.push(0);
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        assert_eq!(buffer.read_qname().unwrap().len(), 63);
+    }
+
+    #[test]
+    fn refuses_to_write_bad_labels() {
+        let long = format!("{}.example.com", "a".repeat(64));
+        for name in ["a..b", ".example.com", long.as_str()] {
+            let mut buffer = BytePacketBuffer::new();
+            assert!(buffer.write_q_name(name).is_err(), "{}", name);
+            assert_eq!(buffer.pos(), 0);
+
+            // nothing of the bad name was remembered, so the next one is written in full
+            buffer.write_q_name("example.com").unwrap();
+            assert_eq!(buffer.pos(), 13);
+        }
+    }
+
+    #[test]
+    fn written_names_compress_and_read_back() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_q_name("www.example.com.").unwrap();
+        buffer.write_q_name("mail.EXAMPLE.com").unwrap();
+        // 17 bytes for the first name, then "mail" and a pointer to "example.com"
+        assert_eq!(buffer.pos(), 17 + 5 + 2);
+
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_qname().unwrap(), "www.example.com");
+        assert_eq!(buffer.read_qname().unwrap(), "mail.example.com");
+    }
+
+    #[test]
+    fn read_qname_matches_read_q_name() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_q_name("www.example.com").unwrap();
+        buffer.write_q_name("mail.example.com").unwrap();
+        let end = buffer.pos();
+
+        buffer.seek(0).unwrap();
+        let (first, second) = (buffer.read_qname().unwrap(), buffer.read_qname().unwrap());
+        assert_eq!(buffer.pos(), end);
+
+        buffer.seek(0).unwrap();
+        let mut appended = String::from("kept:");
+        buffer.read_q_name(&mut appended).unwrap();
+        assert_eq!(appended, format!("kept:{}", first));
+        let mut appended = String::new();
+        buffer.read_q_name(&mut appended).unwrap();
+        assert_eq!(appended, second);
+        assert_eq!(buffer.pos(), end);
+
+        assert_eq!(
+            (first.as_str(), second.as_str()),
+            ("www.example.com", "mail.example.com")
+        );
+
+        // the root is the empty name
+        assert_eq!(BytePacketBuffer::from_bytes(&[0]).unwrap().read_qname().unwrap(), "");
+    }
+
+    #[test]
+    fn reads_big_endian_numbers() {
+        let data = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0xFF];
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+
+        assert_eq!(buffer.read_u8().unwrap(), 0x12);
+        assert_eq!(buffer.read_u16().unwrap(), 0x3456);
+        assert_eq!(buffer.read_u32().unwrap(), 0x789ABCDE);
+        assert_eq!(buffer.read_bytes(2).unwrap(), [0xF0, 0xFF]);
+        assert_eq!(buffer.pos(), 9);
+
+        // nothing is left, and a failed read doesn't move the position
+        assert!(buffer.read_u8().is_err());
+        buffer.seek(7).unwrap();
+        assert!(buffer.read_u32().is_err());
+        assert!(buffer.read_bytes(3).is_err());
+        assert_eq!(buffer.pos(), 7);
+        assert_eq!(buffer.read_array::<2>().unwrap(), [0xF0, 0xFF]);
+        assert_eq!(buffer.read_bytes(0).unwrap(), []);
+    }
+
+    #[test]
+    fn writes_big_endian_numbers() {
+        let mut buffer = BytePacketBuffer::with_capacity(9);
+        buffer.write_u8(0x12).unwrap();
+        buffer.write_u16(0x3456).unwrap();
+        buffer.write_u32(0x789ABCDE).unwrap();
+        buffer.write_bytes(&[0xF0]).unwrap();
+        assert!(buffer.write_u16(0xFFFF).is_err());
+        buffer.write_u8(0xFF).unwrap();
+        assert_eq!(buffer.buffer, [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0xFF]);
+
+        buffer.set_u16(1, 0xABCD).unwrap();
+        assert_eq!(buffer.get_range(0, 3).unwrap(), [0x12, 0xAB, 0xCD]);
+        assert!(buffer.set_u16(8, 0).is_err());
+    }
+
+    #[test]
+    fn character_strings_are_length_prefixed() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_character_string("v=spf1").unwrap();
+        buffer.write_character_string("").unwrap();
+        assert!(buffer.write_character_string(&"a".repeat(256)).is_err());
+        assert_eq!(buffer.get_range(0, 8).unwrap(), b"\x06v=spf1\x00");
+
+        buffer.seek(0).unwrap();
+        assert_eq!(buffer.read_character_string().unwrap(), "v=spf1");
+        assert_eq!(buffer.read_character_string().unwrap(), "");
+
+        // the length claims more than there is
+        let mut buffer = BytePacketBuffer::from_bytes(b"\x05abc").unwrap();
+        assert!(buffer.read_character_string().is_err());
+    }
+}
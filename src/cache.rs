@@ -0,0 +1,631 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    edns::{DEFAULT_PAYLOAD_SIZE, EDE_STALE_ANSWER},
+    ClientSubnet, DnsPacket, DnsQuestion, DnsRecord, Edns, ExtendedError, QueryType, ResultCode,
+};
+
+// how many questions DnsCache::new keeps answers for before it starts evicting
+pub const DEFAULT_CACHE_ENTRIES: usize = 10_000;
+
+// what stale answers are sent with, RFC 8767 picks 30 seconds so clients come back soon to see
+// whether the upstreams have recovered
+pub const STALE_TTL: u32 = 30;
+
+// lowercased name, type and class of the question the answers were given for, and the network they
+// hold for when a forwarding server scoped them with a client subnet
+pub(crate) type CacheKey = (String, QueryType, u16, Option<ClientSubnet>);
+
+pub(crate) fn cache_key(question: &DnsQuestion, subnet: Option<&ClientSubnet>) -> CacheKey {
+    let subnet = subnet.map(|subnet| ClientSubnet::new(subnet.address, subnet.source_prefix));
+    (question.name.to_lowercase(), question.qtype, question.class, subnet)
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    result_code: ResultCode,
+    records: Vec<DnsRecord>,
+    // the SOA a negative answer came with, its ttl is how long the name is known not to exist
+    soa: Option<DnsRecord>,
+    // the signatures and NSEC or NSEC3 records of the authority section, for clients that validate
+    proof: Vec<DnsRecord>,
+    // whether the answer was validated, which is what the AD bit of a response says
+    authed: bool,
+    inserted: Instant,
+    // position in the recency order, higher is more recently used
+    used: u64,
+    // how often it was answered from since it was stored
+    hits: u32,
+    // whether a refresh was already handed out by prefetch_due
+    prefetching: bool,
+}
+
+// answers keyed by the question they were given for, honoring each record's ttl, once full the
+// least recently used entry makes room for the new one. NXDOMAIN and NODATA answers are kept too,
+// for as long as RFC 2308 allows. Answers scoped to a client subnet are only given to clients in it
+#[derive(Debug)]
+pub struct DnsCache {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // used counter to key, the first entry is the one to evict next
+    recency: BTreeMap<u64, CacheKey>,
+    counter: u64,
+    max_entries: usize,
+    // how long past their expiry entries are kept for stale_answer
+    stale_window: Duration,
+    // hits an entry needs before prefetch_due refreshes it, 0 never does
+    prefetch_hits: u32,
+    // where the time entries are aged by comes from
+    now: fn() -> Instant,
+}
+
+// how the resolvers and servers share one cache between threads and tasks
+pub type SharedCache = Arc<Mutex<DnsCache>>;
+
+pub fn shared_cache(max_entries: usize) -> SharedCache {
+    DnsCache::with_capacity(max_entries).into_shared()
+}
+
+// a panic while holding the lock leaves the cache, or whatever else is shared, consistent enough
+// to keep using it
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        DnsCache::new()
+    }
+}
+
+impl DnsCache {
+    pub fn new() -> DnsCache {
+        DnsCache::with_capacity(DEFAULT_CACHE_ENTRIES)
+    }
+
+    pub fn with_capacity(max_entries: usize) -> DnsCache {
+        DnsCache {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            counter: 0,
+            max_entries: max_entries.max(1),
+            stale_window: Duration::ZERO,
+            prefetch_hits: 0,
+            now: Instant::now,
+        }
+    }
+
+    // entries asked for at least hits times get refreshed shortly before they expire, so popular
+    // names never have to wait for the upstream
+    pub fn with_prefetch(mut self, hits: u32) -> DnsCache {
+        self.prefetch_hits = hits;
+        self
+    }
+
+    // keeps expired entries around for window longer so stale_answer can fall back on them,
+    // RFC 8767 suggests somewhere between one and three days
+    pub fn with_stale_window(mut self, window: Duration) -> DnsCache {
+        self.stale_window = window;
+        self
+    }
+
+    // ages entries by the time now returns instead of the system clock, so tests can move it along
+    pub fn with_clock(mut self, now: fn() -> Instant) -> DnsCache {
+        self.now = now;
+        self
+    }
+
+    pub fn into_shared(self) -> SharedCache {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn stale_window(&self) -> Duration {
+        self.stale_window
+    }
+
+    // stores the response under each of its questions, replacing older entries. Answers are kept
+    // while their records live, NXDOMAIN and NODATA only when they come with an SOA to time them by.
+    // A client subnet with a scope in the response puts it under the network the scope covers, one
+    // with a scope of 0 holds for everyone
+    pub fn put(&mut self, packet: &DnsPacket) {
+        let result_code = packet.header.result_code;
+        let negative =
+            result_code == ResultCode::NXDOMAIN || (result_code == ResultCode::NOERROR && packet.answers.is_empty());
+
+        let soa = if negative {
+            let Some(soa) = negative_soa(packet) else {
+                return;
+            };
+            Some(soa)
+        } else if result_code == ResultCode::NOERROR {
+            None
+        } else {
+            // SERVFAIL and friends say nothing about the name
+            return;
+        };
+
+        let scope = packet
+            .edns()
+            .and_then(Edns::client_subnet)
+            .filter(|subnet| subnet.scope_prefix > 0)
+            .map(|subnet| ClientSubnet::new(subnet.address, subnet.scope_prefix.min(subnet.source_prefix)));
+
+        let proof: Vec<DnsRecord> = packet
+            .authorities
+            .iter()
+            .filter(|record| {
+                matches!(
+                    record,
+                    DnsRecord::RRSIG { .. } | DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }
+                )
+            })
+            .cloned()
+            .collect();
+        let authed = packet.header.authed_data;
+
+        for question in &packet.questions {
+            let key = cache_key(question, scope.as_ref());
+            self.insert(
+                key,
+                result_code,
+                packet.answers.clone(),
+                soa.clone(),
+                proof.clone(),
+                authed,
+            );
+        }
+    }
+
+    // returns the records that haven't expired yet, with their ttl lowered by the time spent in the
+    // cache, names cached as not existing or not having the type give None
+    pub fn get(&mut self, name: &str, qtype: QueryType, class: u16) -> Option<Vec<DnsRecord>> {
+        let key = (name.to_lowercase(), qtype, class, None);
+        let (result_code, records, _, _) = self.fresh(&key)?;
+
+        // negative entries are the ones without records
+        match result_code {
+            ResultCode::NOERROR if !records.is_empty() => Some(records),
+            _ => None,
+        }
+    }
+
+    // a response to question made up from the cache, negative ones carry the SOA in their authority
+    // section the way the upstream sent them, along with any signatures and NSEC records. With a
+    // subnet, an answer scoped to a network it's part of comes before one that holds for everyone,
+    // and says so in a client subnet of its own
+    pub fn answer(&mut self, question: &DnsQuestion, subnet: Option<&ClientSubnet>) -> Option<DnsPacket> {
+        let key = self.key_for(question, subnet);
+        let (result_code, records, authorities, authed) = self.fresh(&key)?;
+
+        Some(response(
+            question,
+            result_code,
+            records,
+            authorities,
+            authed,
+            key.3.as_ref(),
+        ))
+    }
+
+    // whether the entry for question is popular and has less than a tenth of its ttl left, it's only
+    // true once per entry so the caller can refresh it without others doing the same
+    pub fn prefetch_due(&mut self, question: &DnsQuestion, subnet: Option<&ClientSubnet>) -> bool {
+        let key = self.key_for(question, subnet);
+        let now = (self.now)();
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return false;
+        };
+        if self.prefetch_hits == 0 || entry.prefetching || entry.hits < self.prefetch_hits {
+            return false;
+        }
+
+        let lifetime = lifetime(entry);
+        let elapsed = now.duration_since(entry.inserted).as_secs();
+        if elapsed >= lifetime || (lifetime - elapsed) * 10 > lifetime {
+            return false;
+        }
+
+        entry.prefetching = true;
+        true
+    }
+
+    // the last answer for question even if it has expired, as long as it's within the stale window,
+    // for when the upstreams can't be reached. Every record gets STALE_TTL, and the answer says it's
+    // stale with an extended error
+    pub fn stale_answer(&mut self, question: &DnsQuestion, subnet: Option<&ClientSubnet>) -> Option<DnsPacket> {
+        let key = self.key_for(question, subnet);
+        let entry = self.entries.get(&key)?;
+        let age = (self.now)().duration_since(entry.inserted);
+        if age >= Duration::from_secs(lifetime(entry)) + self.stale_window {
+            return None;
+        }
+
+        let stale = |record: &DnsRecord| {
+            let mut record = record.clone();
+            record.set_ttl(record.ttl().min(STALE_TTL));
+            record
+        };
+        let (result_code, records, authorities, authed) = (
+            entry.result_code,
+            entry.records.iter().map(stale).collect(),
+            entry.soa.iter().chain(&entry.proof).map(stale).collect(),
+            entry.authed,
+        );
+
+        self.touch(&key);
+        let mut packet = response(question, result_code, records, authorities, authed, key.3.as_ref());
+        if packet.edns().is_none() {
+            packet.set_edns(Edns::new(DEFAULT_PAYLOAD_SIZE));
+        }
+        if let Some(edns) = packet.edns_mut() {
+            edns.add_extended_error(&ExtendedError::new(EDE_STALE_ANSWER));
+        }
+
+        Some(packet)
+    }
+
+    // every record of the answers that haven't expired, once, with what's left of its ttl and sorted by
+    // name. Negative answers and ones scoped to a client subnet are left out, the rest can be saved with
+    // zone_file and loaded again with put_records
+    pub fn records(&self) -> Vec<DnsRecord> {
+        let mut records = Vec::new();
+        let mut seen = HashSet::new();
+        for (key, entry) in &self.entries {
+            if key.3.is_some() || entry.result_code != ResultCode::NOERROR {
+                continue;
+            }
+
+            let elapsed = (self.now)().duration_since(entry.inserted).as_secs();
+            for record in entry.records.iter().filter(|record| u64::from(record.ttl()) > elapsed) {
+                // the same record can be in several answers, and with a different ttl left in each
+                let mut untimed = record.clone();
+                untimed.set_ttl(0);
+                if seen.insert(untimed.to_string()) {
+                    let mut record = record.clone();
+                    record.set_ttl(record.ttl() - elapsed as u32);
+                    records.push(record);
+                }
+            }
+        }
+        records.sort_by_key(|record| record.domain().to_ascii_lowercase());
+
+        records
+    }
+
+    // stores records as answers to the questions for their names and types, signatures along with the
+    // records they cover, the way records saved from the cache are loaded back into it
+    pub fn put_records(&mut self, records: impl IntoIterator<Item = DnsRecord>) {
+        let mut answers: HashMap<(String, QueryType), Vec<DnsRecord>> = HashMap::new();
+        for record in records {
+            let qtype = match &record {
+                DnsRecord::RRSIG { type_covered, .. } => *type_covered,
+                record => record.query_type(),
+            };
+            answers
+                .entry((record.domain().to_lowercase(), qtype))
+                .or_default()
+                .push(record);
+        }
+
+        for ((name, qtype), records) in answers {
+            self.insert(
+                (name, qtype, 1, None),
+                ResultCode::NOERROR,
+                records,
+                None,
+                Vec::new(),
+                false,
+            );
+        }
+    }
+
+    // the key of the narrowest entry for question whose network subnet is in, falling back to the
+    // one for everyone
+    fn key_for(&self, question: &DnsQuestion, subnet: Option<&ClientSubnet>) -> CacheKey {
+        let unscoped = cache_key(question, None);
+        let Some(subnet) = subnet else {
+            return unscoped;
+        };
+
+        let (name, qtype, class, _) = &unscoped;
+        (1..=subnet.source_prefix)
+            .rev()
+            .map(|prefix| {
+                (
+                    name.clone(),
+                    *qtype,
+                    *class,
+                    Some(ClientSubnet::new(subnet.address, prefix)),
+                )
+            })
+            .find(|key| self.entries.contains_key(key))
+            .unwrap_or(unscoped)
+    }
+
+    // what's left of the entry for key, with aged ttls, expired entries are dropped on the way
+    fn fresh(&mut self, key: &CacheKey) -> Option<(ResultCode, Vec<DnsRecord>, Vec<DnsRecord>, bool)> {
+        let entry = self.entries.get(key)?;
+        let age = (self.now)().duration_since(entry.inserted);
+        let elapsed = age.as_secs();
+        let aged = |record: &DnsRecord| {
+            let mut record = record.clone();
+            record.set_ttl(record.ttl() - elapsed as u32);
+            record
+        };
+        let alive = |record: &&DnsRecord| u64::from(record.ttl()) > elapsed;
+
+        let lifetime = lifetime(entry);
+        if elapsed >= lifetime {
+            // past the stale window there's no use for it anymore
+            if age >= Duration::from_secs(lifetime) + self.stale_window {
+                self.remove(key);
+            }
+            return None;
+        }
+
+        let result_code = entry.result_code;
+        let authorities = entry.soa.iter().chain(&entry.proof).filter(alive).map(aged).collect();
+        let records = entry.records.iter().map(aged).collect();
+        let authed = entry.authed;
+
+        self.touch(key);
+        Some((result_code, records, authorities, authed))
+    }
+
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        result_code: ResultCode,
+        records: Vec<DnsRecord>,
+        soa: Option<DnsRecord>,
+        proof: Vec<DnsRecord>,
+        authed: bool,
+    ) {
+        self.remove(&key);
+        while self.entries.len() >= self.max_entries {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.counter += 1;
+        self.recency.insert(self.counter, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                result_code,
+                records,
+                soa,
+                proof,
+                authed,
+                inserted: (self.now)(),
+                used: self.counter,
+                hits: 0,
+                prefetching: false,
+            },
+        );
+    }
+
+    fn touch(&mut self, key: &CacheKey) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+
+        self.recency.remove(&entry.used);
+        self.counter += 1;
+        entry.used = self.counter;
+        entry.hits = entry.hits.saturating_add(1);
+        self.recency.insert(self.counter, key.clone());
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used);
+        }
+    }
+}
+
+// seconds until the entry expires, which is when the first of its records or its SOA does. Handing
+// out what's left of an answer would break it up, a CNAME chain missing a link answers nothing
+fn lifetime(entry: &CacheEntry) -> u64 {
+    entry
+        .records
+        .iter()
+        .chain(entry.soa.iter())
+        .map(|record| u64::from(record.ttl()))
+        .min()
+        .unwrap_or(0)
+}
+
+fn response(
+    question: &DnsQuestion,
+    result_code: ResultCode,
+    records: Vec<DnsRecord>,
+    authorities: Vec<DnsRecord>,
+    authed: bool,
+    scope: Option<&ClientSubnet>,
+) -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.response = true;
+    packet.header.recursion_desired = true;
+    packet.header.recursion_available = true;
+    packet.header.result_code = result_code;
+    packet.header.authed_data = authed;
+    packet.questions.push(question.clone());
+    packet.answers = records;
+    packet.authorities = authorities;
+    if let Some(scope) = scope {
+        let mut edns = Edns::new(DEFAULT_PAYLOAD_SIZE);
+        edns.set_client_subnet(&scope.with_scope(scope.source_prefix));
+        packet.set_edns(edns);
+    }
+    packet.header = packet.synced_header();
+
+    packet
+}
+
+// the SOA from the authority section with its ttl lowered to the SOA minimum if that's smaller,
+// which RFC 2308 makes the time a negative answer may be cached for
+fn negative_soa(packet: &DnsPacket) -> Option<DnsRecord> {
+    let mut soa = packet
+        .authorities
+        .iter()
+        .find(|record| matches!(record, DnsRecord::SOA { .. }))?
+        .clone();
+
+    if let DnsRecord::SOA { minimum, ttl, .. } = &mut soa {
+        *ttl = (*ttl).min(*minimum);
+    }
+
+    Some(soa)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, net::Ipv4Addr};
+
+    use super::*;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+        static OFFSET: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+    }
+
+    // a clock that only moves when advance says so, every test runs on its own thread
+    fn now() -> Instant {
+        START.with(|start| *start) + OFFSET.with(Cell::get)
+    }
+
+    fn advance(secs: u64) {
+        OFFSET.with(|offset| offset.set(offset.get() + Duration::from_secs(secs)));
+    }
+
+    fn address(ttl: u32) -> DnsRecord {
+        DnsRecord::A {
+            domain: "example.com".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            ttl,
+        }
+    }
+
+    fn answer(records: Vec<DnsRecord>) -> DnsPacket {
+        let mut packet = DnsPacket::new_query(1, "example.com", QueryType::A).into_response();
+        packet.answers = records;
+        packet
+    }
+
+    #[test]
+    fn ttls_count_down_while_cached() {
+        let mut cache = DnsCache::new().with_clock(now);
+        cache.put(&answer(vec![address(60)]));
+
+        assert_eq!(cache.get("example.com", QueryType::A, 1), Some(vec![address(60)]));
+        advance(25);
+        assert_eq!(cache.get("EXAMPLE.com", QueryType::A, 1), Some(vec![address(35)]));
+    }
+
+    #[test]
+    fn expired_entries_are_evicted() {
+        let mut cache = DnsCache::new().with_clock(now);
+        cache.put(&answer(vec![address(30)]));
+
+        advance(29);
+        assert_eq!(cache.get("example.com", QueryType::A, 1), Some(vec![address(1)]));
+        advance(1);
+        assert_eq!(cache.get("example.com", QueryType::A, 1), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn answers_expire_with_their_shortest_record() {
+        let mut cache = DnsCache::new().with_clock(now);
+        let alias = |ttl| DnsRecord::CNAME {
+            domain: "example.com".to_string(),
+            host: "target.example.net".to_string(),
+            ttl,
+        };
+        let target = |ttl| DnsRecord::A {
+            domain: "target.example.net".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 2),
+            ttl,
+        };
+        cache.put(&answer(vec![alias(10), target(100)]));
+
+        advance(9);
+        assert_eq!(
+            cache.get("example.com", QueryType::A, 1),
+            Some(vec![alias(1), target(91)])
+        );
+        // without the CNAME the A record doesn't answer the question, so the whole chain goes
+        advance(1);
+        assert_eq!(cache.get("example.com", QueryType::A, 1), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn stale_answers_outlive_expiry_by_the_window() {
+        let mut cache = DnsCache::new()
+            .with_clock(now)
+            .with_stale_window(Duration::from_secs(60));
+        cache.put(&answer(vec![address(30)]));
+        let question = DnsQuestion::new("example.com".to_string(), QueryType::A);
+
+        advance(50);
+        assert_eq!(cache.get("example.com", QueryType::A, 1), None);
+        let stale = cache.stale_answer(&question, None).unwrap();
+        assert_eq!(stale.answers, [address(30)]);
+
+        advance(40);
+        assert!(cache.stale_answer(&question, None).is_none());
+    }
+
+    #[test]
+    fn the_least_recently_read_entry_is_evicted() {
+        let named = |name: &str| {
+            let mut packet = DnsPacket::new_query(1, name, QueryType::A).into_response();
+            packet.answers = vec![DnsRecord::A {
+                domain: name.to_string(),
+                address: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            }];
+            packet
+        };
+        let cached = |cache: &mut DnsCache, name: &str| cache.get(name, QueryType::A, 1).is_some();
+
+        let mut cache = DnsCache::with_capacity(3).with_clock(now);
+        for name in ["a.example", "b.example", "c.example"] {
+            cache.put(&named(name));
+        }
+        // a was put in first but read since, b is the one nobody has asked for the longest
+        assert!(cached(&mut cache, "a.example"));
+        cache.put(&named("d.example"));
+        assert_eq!(cache.len(), 3);
+        assert!(!cached(&mut cache, "b.example"));
+
+        // putting an entry that's there again only makes it the most recent
+        assert!(cached(&mut cache, "c.example"));
+        cache.put(&named("a.example"));
+        assert_eq!(cache.len(), 3);
+        cache.put(&named("e.example"));
+        assert!(!cached(&mut cache, "d.example"));
+        for name in ["a.example", "c.example", "e.example"] {
+            assert!(cached(&mut cache, name), "{}", name);
+        }
+    }
+}
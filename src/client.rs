@@ -0,0 +1,1718 @@
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tsig")]
+use crate::TsigKey;
+use crate::{
+    cache::{cache_key, lock, shared_cache, SharedCache, DEFAULT_CACHE_ENTRIES},
+    cookie::{Cookies, BADCOOKIE},
+    edns::{DEFAULT_PAYLOAD_SIZE, OPTION_CLIENT_SUBNET},
+    health::UpstreamHealth,
+    hints::RootHints,
+    inflight::InFlight,
+    llmnr::{is_llmnr_name, query_llmnr},
+    local::completed,
+    mdns::{query_mdns, MDNS_DOMAINS},
+    querylog::{EventLog, QueryLog, Trace},
+    question::reverse_name,
+    random_id,
+    ratelimit::{RateLimit, RateLimiter},
+    resolver::{in_zone, recursive_lookup_from},
+    rng::random_case,
+    rpz::{address_policy, name_policy, onward, Rpz},
+    server::{ForwardServer, Upstream},
+    view::view_for,
+    AccessControl, Blocklist, BytePacketBuffer, ClientSubnet, Dns64, DnsError, DnsPacket, DnsQuestion, Edns,
+    LocalRecords, QueryType, Result, ResultCode, Secondary, TcpClient, View, Zone,
+};
+#[cfg(feature = "tokio")]
+use crate::{inflight::AsyncInFlight, resolver::recursive_lookup_from_async, tcp::exchange_tcp_async};
+#[cfg(feature = "dnssec")]
+use crate::{Security, Validator};
+
+// builds a recursive query for a single question with a random id, ready to be sent, it advertises
+// EDNS so servers can answer with more than 512 bytes instead of truncating
+pub fn build_query(qname: &str, qtype: QueryType) -> DnsPacket {
+    build_query_with(qname, qtype, random_id)
+}
+
+// same as build_query, with the id taken from next_id, for callers that hand out ids their own way
+pub fn build_query_with(qname: &str, qtype: QueryType, next_id: impl FnOnce() -> u16) -> DnsPacket {
+    let mut query = DnsPacket::new_query(next_id(), qname, qtype);
+    query.set_edns(Edns::new(DEFAULT_PAYLOAD_SIZE));
+
+    query
+}
+
+pub(crate) fn encode(query: &DnsPacket) -> Result<BytePacketBuffer> {
+    let mut buffer = BytePacketBuffer::new();
+    query.write(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+// how long lookup_with_fallback and lookup_many wait on a server before giving up on it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// sends a single question to the server over udp and waits for its response, one with a result code
+// other than NOERROR comes back as the matching DnsError
+pub fn lookup(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16)) -> Result<DnsPacket> {
+    query_server(qname, qtype, server, None)
+}
+
+// looks up the PTR records of an address, building its in-addr.arpa or ip6.arpa name
+pub fn reverse_lookup(ip: IpAddr, server: (Ipv4Addr, u16)) -> Result<DnsPacket> {
+    lookup(&reverse_name(ip), QueryType::PTR, server)
+}
+
+// like lookup, but gives up with DnsError::Timeout if the server doesn't answer in time
+pub fn lookup_with_timeout(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    query_server(qname, qtype, server, Some(timeout))
+}
+
+// asks each server in turn until one answers without a SERVFAIL, the last error is returned if none do.
+// Any other result code is the answer, so it's returned without asking the rest
+pub fn lookup_with_fallback(qname: &str, qtype: QueryType, servers: &[(Ipv4Addr, u16)]) -> Result<DnsPacket> {
+    lookup_with_fallback_timeout(qname, qtype, servers, DEFAULT_TIMEOUT)
+}
+
+pub fn lookup_with_fallback_timeout(
+    qname: &str,
+    qtype: QueryType,
+    servers: &[(Ipv4Addr, u16)],
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    let mut last_error: DnsError = "No servers to query".into();
+
+    for server in servers {
+        match query_server(qname, qtype, *server, Some(timeout)) {
+            Ok(packet) => return Ok(packet),
+            Err(e) if e.is_result_code() && e != DnsError::ServerFailure => return Err(e),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// resolves every name using up to concurrency threads, each with its own socket, results come back in input order
+pub fn lookup_many(
+    names: &[String],
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    concurrency: usize,
+) -> Vec<(String, Result<DnsPacket>)> {
+    let next = AtomicUsize::new(0);
+    let workers = concurrency.clamp(1, names.len().max(1));
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let next = &next;
+
+            // every worker keeps taking the next unresolved name until there are none left
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(name) = names.get(index) else {
+                    break;
+                };
+
+                let result = query_server(name, qtype, server, Some(DEFAULT_TIMEOUT));
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    in_input_order(names, receiver)
+}
+
+// pairs every name with the result that came back for its index, in whatever order they finished
+fn in_input_order<T>(
+    names: &[String],
+    finished: impl IntoIterator<Item = (usize, Result<T>)>,
+) -> Vec<(String, Result<T>)> {
+    let mut results: Vec<Option<Result<T>>> = names.iter().map(|_| None).collect();
+    for (index, result) in finished {
+        results[index] = Some(result);
+    }
+
+    names
+        .iter()
+        .cloned()
+        .zip(results)
+        .map(|(name, result)| {
+            let result = result.unwrap_or_else(|| Err("Lookup worker stopped before resolving the name".into()));
+            (name, result)
+        })
+        .collect()
+}
+
+fn query_server(
+    qname: &str,
+    qtype: QueryType,
+    server: (Ipv4Addr, u16),
+    timeout: Option<Duration>,
+) -> Result<DnsPacket> {
+    let packet = exchange(&build_query(qname, qtype), server, timeout)?;
+    packet.check_response_code()?;
+
+    Ok(packet)
+}
+
+// sends an already built query over udp and waits for the response to it
+pub fn exchange(query: &DnsPacket, server: (Ipv4Addr, u16), timeout: Option<Duration>) -> Result<DnsPacket> {
+    exchange_wire(query, server, timeout).map(|(packet, _)| packet)
+}
+
+// exchange, along with the response exactly as it arrived, which is what signatures are checked over
+pub(crate) fn exchange_wire(
+    query: &DnsPacket,
+    server: (Ipv4Addr, u16),
+    timeout: Option<Duration>,
+) -> Result<(DnsPacket, Vec<u8>)> {
+    let request = encode(query)?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    // port 0 has the os pick a random ephemeral port, a new one for every query, so a spoofer has to
+    // guess it along with the id
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.send_to(&request.buffer[0..request.pos()], server)?;
+
+    let mut response = BytePacketBuffer::with_capacity(DEFAULT_PAYLOAD_SIZE as usize);
+    let packet = loop {
+        let remaining = match deadline {
+            Some(deadline) => Some(
+                deadline
+                    .checked_duration_since(Instant::now())
+                    .ok_or(DnsError::Timeout)?,
+            ),
+            None => None,
+        };
+        // a zero read timeout would mean blocking forever
+        socket.set_read_timeout(remaining.map(|remaining| remaining.max(Duration::from_millis(1))))?;
+
+        let (length, source) = socket.recv_from(&mut response.buffer).map_err(|e| match e.kind() {
+            // unix reports an expired read timeout as WouldBlock, windows as TimedOut
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => DnsError::Timeout,
+            _ => DnsError::from(e),
+        })?;
+
+        response.seek(0)?;
+        if let Some(packet) = response_to(query, server, source, &mut response) {
+            break (packet, response.buffer[..length].to_vec());
+        }
+    };
+
+    // a truncated answer is only part of the real one, the whole thing has to come over tcp
+    if packet.0.header.truncated_message {
+        return TcpClient::new(server)
+            .with_timeout(timeout.unwrap_or(DEFAULT_TIMEOUT))
+            .exchange_wire(query);
+    }
+
+    Ok(packet)
+}
+
+// the response in buffer if it's one to query, anything from another address, that doesn't parse or
+// has another id or question is dropped so the query keeps waiting for the real answer instead of
+// taking a spoofed one
+fn response_to(
+    query: &DnsPacket,
+    server: (Ipv4Addr, u16),
+    source: SocketAddr,
+    buffer: &mut BytePacketBuffer,
+) -> Option<DnsPacket> {
+    if source != SocketAddr::from(server) {
+        return None;
+    }
+    let packet = DnsPacket::from_buffer(buffer).ok()?;
+
+    // the case of the names is left to valid_answer, not every server copies it
+    let same_questions = packet.questions.len() == query.questions.len()
+        && packet.questions.iter().zip(&query.questions).all(|(answered, asked)| {
+            answered.name.eq_ignore_ascii_case(&asked.name)
+                && answered.qtype == asked.qtype
+                && answered.class == asked.class
+        });
+    if !packet.header.response || packet.header.id != query.header.id || !same_questions {
+        return None;
+    }
+
+    Some(packet)
+}
+
+// same as lookup, but awaits the socket instead of blocking the runtime
+#[cfg(feature = "tokio")]
+pub async fn lookup_async(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16)) -> Result<DnsPacket> {
+    let packet = exchange_async(&build_query(qname, qtype), server, None).await?;
+    packet.check_response_code()?;
+
+    Ok(packet)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn exchange_async(
+    query: &DnsPacket,
+    server: (Ipv4Addr, u16),
+    timeout: Option<Duration>,
+) -> Result<DnsPacket> {
+    let request = encode(query)?;
+
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.send_to(&request.buffer[0..request.pos()], server).await?;
+
+    let mut response = BytePacketBuffer::with_capacity(DEFAULT_PAYLOAD_SIZE as usize);
+    let receive = async {
+        loop {
+            let (_, source) = socket.recv_from(&mut response.buffer).await?;
+            response.seek(0)?;
+            if let Some(packet) = response_to(query, server, source, &mut response) {
+                return Ok::<_, DnsError>(packet);
+            }
+        }
+    };
+    let packet = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, receive)
+            .await
+            .map_err(|_| DnsError::Timeout)??,
+        None => receive.await?,
+    };
+
+    if packet.header.truncated_message {
+        return exchange_tcp_async(query, server, timeout.unwrap_or(DEFAULT_TIMEOUT)).await;
+    }
+
+    Ok(packet)
+}
+
+// one exchange with a forwarding server, how it went is recorded in health. The query carries the
+// server's cookies, a BADCOOKIE response came with the server cookie it wants so it's sent once more
+fn exchange_tracked(
+    query: &DnsPacket,
+    server: &ForwardServer,
+    timeout: Duration,
+    health: &UpstreamHealth,
+    cookies: &Cookies,
+) -> Result<DnsPacket> {
+    let (address, timeout) = (server.address, Some(server.timeout.unwrap_or(timeout)));
+    let started = Instant::now();
+
+    let attempt = exchange(&cookies.attached(query, address), address, timeout);
+    let mut outcome = cookies.checked(address, attempt);
+    if bad_cookie(&outcome) {
+        let attempt = exchange(&cookies.attached(query, address), address, timeout);
+        outcome = cookies.checked(address, attempt);
+    }
+
+    track(query, server, started, outcome, health)
+}
+
+#[cfg(feature = "tokio")]
+async fn exchange_tracked_async(
+    query: &DnsPacket,
+    server: &ForwardServer,
+    timeout: Duration,
+    health: &UpstreamHealth,
+    cookies: &Cookies,
+) -> Result<DnsPacket> {
+    let (address, timeout) = (server.address, Some(server.timeout.unwrap_or(timeout)));
+    let started = Instant::now();
+
+    let attempt = exchange_async(&cookies.attached(query, address), address, timeout).await;
+    let mut outcome = cookies.checked(address, attempt);
+    if bad_cookie(&outcome) {
+        let attempt = exchange_async(&cookies.attached(query, address), address, timeout).await;
+        outcome = cookies.checked(address, attempt);
+    }
+
+    track(query, server, started, outcome, health)
+}
+
+fn bad_cookie(outcome: &Result<DnsPacket>) -> bool {
+    outcome
+        .as_ref()
+        .is_ok_and(|packet| packet.extended_result_code() == BADCOOKIE)
+}
+
+fn track(
+    query: &DnsPacket,
+    server: &ForwardServer,
+    started: Instant,
+    outcome: Result<DnsPacket>,
+    health: &UpstreamHealth,
+) -> Result<DnsPacket> {
+    let outcome = valid_answer(query, outcome);
+    match outcome {
+        Ok(_) => health.record_success(server.address, started.elapsed()),
+        Err(_) => health.record_failure(server.address),
+    }
+
+    outcome
+}
+
+// sends query to each server in turn, retrying each as often as it allows, until one answers
+// without a SERVFAIL, the last error is returned if none do. The answer comes with the server it came
+// from
+pub(crate) fn exchange_failover(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    health: &UpstreamHealth,
+    cookies: &Cookies,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    let mut last_error: DnsError = "No servers to query".into();
+
+    for server in servers {
+        for _ in 0..=server.retries {
+            match exchange_tracked(query, server, timeout, health, cookies) {
+                Ok(packet) => return Ok((packet, server.address)),
+                Err(e) => last_error = e,
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn exchange_failover_async(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    health: &UpstreamHealth,
+    cookies: &Cookies,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    let mut last_error: DnsError = "No servers to query".into();
+
+    for server in servers {
+        for _ in 0..=server.retries {
+            match exchange_tracked_async(query, server, timeout, health, cookies).await {
+                Ok(packet) => return Ok((packet, server.address)),
+                Err(e) => last_error = e,
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+// asks the first race servers all at once and takes whichever valid answer comes first, only if none
+// of them gives one are the rest tried in turn. A race of 0 or 1 is plain failover
+pub(crate) fn exchange_forward(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    race: usize,
+    health: &Arc<UpstreamHealth>,
+    cookies: &Arc<Cookies>,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    if race < 2 {
+        return exchange_failover(query, servers, timeout, health, cookies);
+    }
+
+    let (racing, rest) = servers.split_at(race.min(servers.len()));
+    match exchange_race(query, racing, timeout, health, cookies) {
+        Err(_) if !rest.is_empty() => exchange_failover(query, rest, timeout, health, cookies),
+        outcome => outcome,
+    }
+}
+
+// every server is asked once on its own thread, the slower ones are left to finish in the background
+fn exchange_race(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    health: &Arc<UpstreamHealth>,
+    cookies: &Arc<Cookies>,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    let (sender, receiver) = mpsc::channel();
+    for server in servers {
+        let (sender, query, server) = (sender.clone(), query.clone(), *server);
+        let (health, cookies) = (health.clone(), cookies.clone());
+        thread::spawn(move || {
+            let outcome = exchange_tracked(&query, &server, timeout, &health, &cookies);
+            let _ = sender.send(outcome.map(|packet| (packet, server.address)));
+        });
+    }
+    drop(sender);
+
+    let mut last_error: DnsError = "No servers to query".into();
+    for outcome in receiver {
+        match outcome {
+            Ok(answer) => return Ok(answer),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn exchange_forward_async(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    race: usize,
+    health: &Arc<UpstreamHealth>,
+    cookies: &Arc<Cookies>,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    if race < 2 {
+        return exchange_failover_async(query, servers, timeout, health, cookies).await;
+    }
+
+    let (racing, rest) = servers.split_at(race.min(servers.len()));
+    match exchange_race_async(query, racing, timeout, health, cookies).await {
+        Err(_) if !rest.is_empty() => exchange_failover_async(query, rest, timeout, health, cookies).await,
+        outcome => outcome,
+    }
+}
+
+// the queries still running once an answer is in are aborted along with the set
+#[cfg(feature = "tokio")]
+async fn exchange_race_async(
+    query: &DnsPacket,
+    servers: &[ForwardServer],
+    timeout: Duration,
+    health: &Arc<UpstreamHealth>,
+    cookies: &Arc<Cookies>,
+) -> Result<(DnsPacket, (Ipv4Addr, u16))> {
+    let mut racing = tokio::task::JoinSet::new();
+    for server in servers {
+        let (query, server, health, cookies) = (query.clone(), *server, health.clone(), cookies.clone());
+        racing.spawn(async move {
+            let outcome = exchange_tracked_async(&query, &server, timeout, &health, &cookies).await;
+            outcome.map(|packet| (packet, server.address))
+        });
+    }
+
+    let mut last_error: DnsError = "No servers to query".into();
+    while let Some(joined) = racing.join_next().await {
+        match joined.map_err(DnsError::from).and_then(|outcome| outcome) {
+            Ok(answer) => return Ok(answer),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// only a response to this very query without a SERVFAIL counts as an answer, anything else could
+// be a stray packet or a server having trouble
+fn valid_answer(query: &DnsPacket, outcome: Result<DnsPacket>) -> Result<DnsPacket> {
+    let mut packet = outcome?;
+
+    if !matches_query(query, &packet) {
+        return Err("Response doesn't match the query".into());
+    }
+    // RFC 7871 has a response whose client subnet isn't the one asked about dropped, and one that
+    // wasn't asked about can't scope anything
+    let asked = query.edns().and_then(Edns::client_subnet);
+    match (asked, packet.edns_mut()) {
+        (Some(asked), Some(edns)) => {
+            if edns
+                .client_subnet()
+                .is_some_and(|answered| answered.with_scope(0) != asked)
+            {
+                return Err("Response is for another client subnet".into());
+            }
+        }
+        (None, Some(edns)) => edns.options.retain(|option| option.code != OPTION_CLIENT_SUBNET),
+        (_, None) => {}
+    }
+    if packet.header.result_code == ResultCode::SERVFAIL {
+        // with the reason if the server gave one
+        let errors = packet.edns().map(Edns::extended_errors).unwrap_or_default();
+        return Err(match errors.first() {
+            Some(error) => format!("{}, extended error {}", DnsError::ServerFailure, error).into(),
+            None => DnsError::ServerFailure,
+        });
+    }
+    // even after being sent the server cookie it asked for
+    if packet.extended_result_code() == BADCOOKIE {
+        return Err("Server keeps rejecting the cookie".into());
+    }
+
+    Ok(packet)
+}
+
+// whether response has the id and the questions of query, names down to the case of every letter
+// since that's part of what a spoofed response has to guess
+pub(crate) fn matches_query(query: &DnsPacket, response: &DnsPacket) -> bool {
+    response.header.id == query.header.id && response.questions == query.questions
+}
+
+// the query sent upstream for question, its class included and the case of its name randomized.
+// A resolver that validates asks for the signatures, and for the answer whether they check out or
+// not, so it's the one to decide
+fn query_for(question: &DnsQuestion, subnet: Option<&ClientSubnet>, dnssec_ok: bool) -> DnsPacket {
+    let mut query = build_query(&random_case(&question.name), question.qtype);
+    query.questions[0].class = question.class;
+    query.header.checking_disabled = dnssec_ok;
+    if let Some(edns) = query.edns_mut() {
+        edns.dnssec_ok = dnssec_ok;
+        if let Some(subnet) = subnet {
+            edns.set_client_subnet(subnet);
+        }
+    }
+
+    query
+}
+
+// puts name back the way it was asked for wherever the response has the randomized one
+pub(crate) fn restore_case(mut response: DnsPacket, name: &str) -> DnsPacket {
+    for question in &mut response.questions {
+        if question.name.eq_ignore_ascii_case(name) {
+            question.name = name.to_string();
+        }
+    }
+    for record in response
+        .answers
+        .iter_mut()
+        .chain(response.authorities.iter_mut())
+        .chain(response.resources.iter_mut())
+    {
+        if record.domain().eq_ignore_ascii_case(name) {
+            record.set_domain(name.to_string());
+        }
+    }
+
+    response
+}
+
+// caches what the upstream said, when it failed or couldn't be reached an expired answer still in
+// the cache's stale window is better than none
+fn settle(
+    cache: &SharedCache,
+    question: &DnsQuestion,
+    subnet: Option<&ClientSubnet>,
+    outcome: Result<DnsPacket>,
+) -> Result<DnsPacket> {
+    let mut cache = lock(cache);
+
+    match outcome {
+        Ok(packet) if packet.header.result_code != ResultCode::SERVFAIL => {
+            cache.put(&packet);
+            Ok(packet)
+        }
+        outcome => cache.stale_answer(question, subnet).map_or(outcome, Ok),
+    }
+}
+
+// where the client subnet sent to forwarding servers comes from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubnetSource {
+    // the same network for every query
+    Fixed(ClientSubnet),
+    // the address of whoever the server is answering, cut down to this many bits, RFC 7871 suggests
+    // 24 for ipv4 and 56 for ipv6 so single hosts can't be picked out
+    Client { ipv4_prefix: u8, ipv6_prefix: u8 },
+}
+
+impl SubnetSource {
+    // the subnet for a query from client, a subnet a downstream resolver asked on behalf of is
+    // passed on instead, no longer than the client's own would be
+    pub fn subnet_for(&self, client: Option<IpAddr>, asked: Option<ClientSubnet>) -> Option<ClientSubnet> {
+        match *self {
+            SubnetSource::Fixed(subnet) => Some(subnet),
+            SubnetSource::Client {
+                ipv4_prefix,
+                ipv6_prefix,
+            } => {
+                let (address, prefix) = match asked {
+                    Some(asked) => (asked.address, asked.source_prefix),
+                    None => (client?, u8::MAX),
+                };
+                let limit = if address.is_ipv4() { ipv4_prefix } else { ipv6_prefix };
+
+                Some(ClientSubnet::new(address, prefix.min(limit)))
+            }
+        }
+    }
+}
+
+// answers questions through its upstream, what it learns goes into a cache that can be shared
+// with other resolvers so every server in the process answers from the same one
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    pub upstream: Upstream,
+    pub timeout: Duration,
+    pub cache: SharedCache,
+    // forwarding servers asked at once, below 2 they're asked one after another
+    pub race: usize,
+    // whether forwarding servers are asked fastest and healthiest first instead of in their order
+    pub adaptive: bool,
+    // None sends forwarding servers no client subnet
+    pub subnet: Option<SubnetSource>,
+    // where recursion starts, primed in the background, shared between clones
+    pub root_hints: Arc<RootHints>,
+    // what the server answers queries asking for an NSID with, None leaves the option out
+    pub nsid: Option<Vec<u8>>,
+    // checks answers against the DNSSEC chain of trust, shared between clones like the cache
+    #[cfg(feature = "dnssec")]
+    pub validator: Option<Arc<Validator>>,
+    // the keys signed queries may be signed with, their answers are signed with the same one
+    #[cfg(feature = "tsig")]
+    pub tsig_keys: Arc<Vec<TsigKey>>,
+    // names in these domains are asked of the upstream given for the domain instead, like a corporate
+    // server for its internal names in front of a public resolver for the rest. The longest domain a
+    // name is in wins
+    pub domains: Arc<Vec<(String, Upstream)>>,
+    // the zones answered authoritatively instead of resolving, shared between clones
+    pub zones: Arc<Vec<Zone>>,
+    // the zones copied from their primaries, which a NOTIFY from the primary refreshes. They're answered
+    // authoritatively as well, after the zones of the server's own
+    pub secondary: Option<Secondary>,
+    // names from hosts and override files, answered before the cache is even asked
+    pub local: Arc<LocalRecords>,
+    // response policy zones, applied in order after the local names and before the blocklist
+    pub policies: Arc<Vec<Rpz>>,
+    // names that aren't resolved, checked after the local ones so those can unblock names
+    pub blocklist: Option<Arc<Blocklist>>,
+    // names without AAAA records get them made up from their A records, None leaves them without
+    pub dns64: Option<Dns64>,
+    // the clients in the networks of a view are answered by its resolver instead, the first one they're
+    // in wins and everyone else gets this one's answers
+    pub views: Arc<Vec<View<Resolver>>>,
+    // who may query, recurse and ask for transfers when serving, everyone by default
+    pub access: Arc<AccessControl>,
+    // where the server writes a line for every query it answers, None keeps no log
+    pub query_log: Option<Arc<QueryLog>>,
+    // what the server reports besides the queries, like the ones it couldn't answer
+    pub events: EventLog,
+    // shared between clones, like the cache
+    flights: Arc<InFlight>,
+    health: Arc<UpstreamHealth>,
+    pub(crate) cookies: Arc<Cookies>,
+    // udp clients past their limit are dropped when serving, None lets everyone through
+    pub(crate) limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Resolver {
+    pub fn new(upstream: Upstream) -> Resolver {
+        Resolver {
+            upstream,
+            timeout: DEFAULT_TIMEOUT,
+            cache: shared_cache(DEFAULT_CACHE_ENTRIES),
+            race: 0,
+            adaptive: false,
+            subnet: None,
+            root_hints: Arc::default(),
+            nsid: None,
+            #[cfg(feature = "dnssec")]
+            validator: None,
+            #[cfg(feature = "tsig")]
+            tsig_keys: Arc::default(),
+            domains: Arc::default(),
+            zones: Arc::default(),
+            secondary: None,
+            local: Arc::default(),
+            policies: Arc::default(),
+            blocklist: None,
+            dns64: None,
+            views: Arc::default(),
+            access: Arc::default(),
+            query_log: None,
+            events: EventLog::default(),
+            flights: Arc::default(),
+            health: Arc::default(),
+            cookies: Arc::default(),
+            limiter: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Resolver {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: SharedCache) -> Resolver {
+        self.cache = cache;
+        self
+    }
+
+    // how many forwarding servers to ask at once, the first valid answer is used
+    pub fn with_race(mut self, race: usize) -> Resolver {
+        self.race = race;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: bool) -> Resolver {
+        self.adaptive = adaptive;
+        self
+    }
+
+    // sends forwarding servers a client subnet so CDNs can answer for the network the query comes from
+    pub fn with_client_subnet(mut self, source: SubnetSource) -> Resolver {
+        self.subnet = Some(source);
+        self
+    }
+
+    // the name server identifier from RFC 5001 the server hands out when asked, to tell instances
+    // behind the same address apart
+    pub fn with_nsid(mut self, nsid: Vec<u8>) -> Resolver {
+        self.nsid = Some(nsid);
+        self
+    }
+
+    // the root servers to prime from when recursing, like the ones in a named.root file
+    pub fn with_root_hints(mut self, root_hints: RootHints) -> Resolver {
+        self.root_hints = Arc::new(root_hints);
+        self
+    }
+
+    // limits how many queries each address may send when the resolver serves them over udp
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Resolver {
+        self.limiter = Some(Arc::new(RateLimiter::new(limit)));
+        self
+    }
+
+    // refuses the clients the server doesn't let query, recurse or transfer zones
+    pub fn with_access_control(mut self, access: AccessControl) -> Resolver {
+        self.access = Arc::new(access);
+        self
+    }
+
+    // logs every query the server answers
+    pub fn with_query_log(mut self, log: QueryLog) -> Resolver {
+        self.query_log = Some(Arc::new(log));
+        self
+    }
+
+    pub fn with_event_log(mut self, events: EventLog) -> Resolver {
+        self.events = events;
+        self
+    }
+
+    // validates every answer before it's cached, those that check out get the AD bit and bogus ones
+    // fail with the extended error saying why
+    #[cfg(feature = "dnssec")]
+    pub fn with_dnssec(mut self, validator: Validator) -> Resolver {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    // a key clients may sign their queries with, queries signed with any other key are refused
+    #[cfg(feature = "tsig")]
+    pub fn with_tsig_key(mut self, key: TsigKey) -> Resolver {
+        Arc::make_mut(&mut self.tsig_keys).push(key);
+        self
+    }
+
+    // asks the local network over multicast DNS for .local names and the reverse names of link local
+    // addresses
+    pub fn with_mdns(mut self, timeout: Duration) -> Resolver {
+        for domain in MDNS_DOMAINS {
+            self = self.with_domain(domain, Upstream::Multicast(timeout));
+        }
+        self
+    }
+
+    // asks the local link over LLMNR for single label names, which aren't in any domain but the root
+    pub fn with_llmnr(mut self, timeout: Duration) -> Resolver {
+        Arc::make_mut(&mut self.domains).push((String::new(), Upstream::Llmnr(timeout)));
+        self
+    }
+
+    // sends the names in domain and below it to upstream, whatever the resolver's own upstream is
+    pub fn with_domain(mut self, domain: &str, upstream: Upstream) -> Resolver {
+        let domain = domain
+            .trim_start_matches("*.")
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        Arc::make_mut(&mut self.domains).push((domain, upstream));
+        self
+    }
+
+    pub fn with_zone(mut self, zone: Zone) -> Resolver {
+        Arc::make_mut(&mut self.zones).push(zone);
+        self
+    }
+
+    pub fn with_secondary(mut self, secondary: Secondary) -> Resolver {
+        self.secondary = Some(secondary);
+        self
+    }
+
+    pub fn with_local(mut self, local: LocalRecords) -> Resolver {
+        self.local = Arc::new(local);
+        self
+    }
+
+    pub fn with_rpz(mut self, rpz: Rpz) -> Resolver {
+        Arc::make_mut(&mut self.policies).push(rpz);
+        self
+    }
+
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Resolver {
+        self.blocklist = Some(Arc::new(blocklist));
+        self
+    }
+
+    // DNS64 with the NAT64 gateway's prefix, Dns64::default() for the well known one
+    pub fn with_dns64(mut self, dns64: Dns64) -> Resolver {
+        self.dns64 = Some(dns64);
+        self
+    }
+
+    pub fn with_view(mut self, view: View<Resolver>) -> Resolver {
+        Arc::make_mut(&mut self.views).push(view);
+        self
+    }
+
+    // the resolver that answers client, the one of the first view it's in or this one
+    pub fn view_for(&self, client: IpAddr) -> &Resolver {
+        view_for(&self.views, client).unwrap_or(self)
+    }
+
+    // round trip times and failures of the forwarding servers so far
+    pub fn health(&self) -> &UpstreamHealth {
+        &self.health
+    }
+
+    // the cookies sent to the forwarding servers and handed out to clients
+    pub fn cookies(&self) -> &Cookies {
+        &self.cookies
+    }
+
+    fn forwarding_order(&self, servers: &[ForwardServer]) -> Vec<ForwardServer> {
+        if self.adaptive {
+            self.health.order(servers)
+        } else {
+            servers.to_vec()
+        }
+    }
+
+    // the client subnet for a query from client, only ever sent to forwarding servers
+    pub(crate) fn subnet_for(&self, client: Option<IpAddr>, asked: Option<ClientSubnet>) -> Option<ClientSubnet> {
+        match self.upstream {
+            Upstream::Forward(_) => self.subnet?.subnet_for(client, asked),
+            Upstream::Recursive | Upstream::Multicast(_) | Upstream::Llmnr(_) => None,
+        }
+    }
+
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.resolve(&DnsQuestion::new(qname.to_string(), qtype))
+    }
+
+    // the cache is asked first, the network only when it has nothing for the question
+    pub fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.resolve_in(question, self.subnet_for(None, None))
+    }
+
+    // resolves question for clients in subnet, forwarding servers are told about it and answers
+    // they scope to it are only given to those clients. Local names come before all of that, a cname
+    // from one of them to a name that isn't is resolved onwards, then the policy zones and blocked names.
+    // With DNS64 names without AAAA records get them from their A records
+    pub fn resolve_in(&self, question: &DnsQuestion, subnet: Option<ClientSubnet>) -> Result<DnsPacket> {
+        self.resolve_traced(question, subnet, &Trace::default())
+    }
+
+    // resolve_in, noting down in trace whether the cache had the answer and which upstreams gave it
+    pub(crate) fn resolve_traced(
+        &self,
+        question: &DnsQuestion,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        let Some(dns64) = self.dns64 else {
+            return self.resolve_filtered(question, subnet, trace);
+        };
+        // the reverse names of synthesized addresses are those of the ipv4 addresses in them
+        if let Some(ipv4_name) = dns64.reverse(question) {
+            let answer = dns64.reverse_answer(question, &ipv4_name);
+            let rest = self.resolve_filtered(&DnsQuestion::new(ipv4_name, QueryType::PTR), subnet, trace)?;
+            return Ok(completed(answer, rest));
+        }
+
+        let answer = self.resolve_filtered(question, subnet, trace);
+        if answer.as_ref().is_ok_and(|answer| !dns64.applies(question, answer)) {
+            return answer;
+        }
+        let ipv4 = DnsQuestion::new(question.name.clone(), QueryType::A);
+        let ipv4 = self.resolve_filtered(&ipv4, subnet, trace);
+        match ipv4.ok().and_then(|ipv4| dns64.synthesized(answer.as_ref().ok(), ipv4)) {
+            Some(synthesized) => Ok(synthesized),
+            None => answer,
+        }
+    }
+
+    fn resolve_filtered(
+        &self,
+        question: &DnsQuestion,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        if let Some(answer) = self.local.answer(question) {
+            return match self.local.onward(&answer, question.qtype) {
+                Some(onward) => Ok(completed(answer, self.resolve_cached(&onward, subnet, trace)?)),
+                None => Ok(answer),
+            };
+        }
+
+        // a name with a policy, even PASSTHRU, isn't up to the blocklist anymore
+        let policy = name_policy(&self.policies, &question.name);
+        if let Some(answer) = policy.and_then(|policy| policy.rewrite(question)) {
+            return self.rewritten(answer, question.qtype, subnet, trace);
+        }
+        let blocklist = self.blocklist.as_ref().filter(|_| policy.is_none());
+        if let Some(blocked) = blocklist.and_then(|blocklist| blocklist.answer(question)) {
+            return Ok(blocked);
+        }
+
+        let answer = self.resolve_cached(question, subnet, trace)?;
+        if policy.is_none() {
+            if let Some(policy) = address_policy(&self.policies, &answer) {
+                return match policy.rewrite(question) {
+                    Some(rewritten) => self.rewritten(rewritten, question.qtype, subnet, trace),
+                    None => Ok(answer),
+                };
+            }
+        }
+
+        Ok(match blocklist {
+            Some(blocklist) => blocklist.filtered(question, answer),
+            None => answer,
+        })
+    }
+
+    // an answer a policy rewrote, a cname is resolved the rest of the way
+    fn rewritten(
+        &self,
+        answer: DnsPacket,
+        qtype: QueryType,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        match onward(&answer, qtype) {
+            Some(onward) => Ok(completed(answer, self.resolve_cached(&onward, subnet, trace)?)),
+            None => Ok(answer),
+        }
+    }
+
+    fn resolve_cached(&self, question: &DnsQuestion, subnet: Option<ClientSubnet>, trace: &Trace) -> Result<DnsPacket> {
+        let mut cache = lock(&self.cache);
+        let cached = cache.answer(question, subnet.as_ref());
+        trace.cache(cached.is_some());
+        if let Some(packet) = cached {
+            if cache.prefetch_due(question, subnet.as_ref()) {
+                let (resolver, question) = (self.clone(), question.clone());
+                thread::spawn(move || resolver.fetch(&question, subnet, &Trace::default()));
+            }
+            return Ok(packet);
+        }
+        drop(cache);
+
+        self.fetch(question, subnet, trace)
+    }
+
+    // asks the upstream, whatever it answers replaces what the cache had. Questions already on their
+    // way upstream aren't asked again, they get the answer of the query that's already out
+    fn fetch(&self, question: &DnsQuestion, subnet: Option<ClientSubnet>, trace: &Trace) -> Result<DnsPacket> {
+        self.flights.run(cache_key(question, subnet.as_ref()), || {
+            let packet = self.ask(question, subnet.as_ref(), trace);
+            #[cfg(feature = "dnssec")]
+            let packet = packet.and_then(|packet| self.validated(packet));
+
+            settle(&self.cache, question, subnet.as_ref(), packet)
+        })
+    }
+
+    // what the upstream answers, the cache left out of it
+    fn ask(&self, question: &DnsQuestion, subnet: Option<&ClientSubnet>, trace: &Trace) -> Result<DnsPacket> {
+        let upstream = upstream_for(&self.domains, &question.name, &self.upstream);
+        let answer = match upstream {
+            Upstream::Forward(servers) => {
+                let servers = self.forwarding_order(servers);
+                let query = query_for(question, subnet, self.validating());
+                let (packet, server) =
+                    exchange_forward(&query, &servers, self.timeout, self.race, &self.health, &self.cookies)?;
+                trace.upstream(format!("{}:{}", server.0, server.1));
+                return Ok(restore_case(packet, &question.name));
+            }
+            Upstream::Multicast(timeout) => query_mdns(question, *timeout, true),
+            Upstream::Llmnr(timeout) => query_llmnr(question, *timeout),
+            Upstream::Recursive => {
+                if self.root_hints.priming_due() {
+                    let root_hints = self.root_hints.clone();
+                    thread::spawn(move || root_hints.prime());
+                }
+                let roots = self.root_hints.servers();
+                recursive_lookup_from(&question.name, question.qtype, &roots, self.validating())
+            }
+        };
+        if answer.is_ok() {
+            trace.upstream(upstream.name().to_string());
+        }
+
+        answer
+    }
+
+    #[cfg(feature = "dnssec")]
+    fn validating(&self) -> bool {
+        self.validator.is_some()
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn validating(&self) -> bool {
+        false
+    }
+
+    // the keys and DS records the validator needs come straight from the upstream too
+    #[cfg(feature = "dnssec")]
+    fn validated(&self, packet: DnsPacket) -> Result<DnsPacket> {
+        let Some(validator) = self.validator.as_ref().filter(|_| !multicast(&self.domains, &packet)) else {
+            return Ok(packet);
+        };
+        let fetch = |name: &str, qtype| self.ask(&DnsQuestion::new(name.to_string(), qtype), None, &Trace::default());
+        let security = validator.validate(&packet, fetch)?;
+
+        secured(packet, security)
+    }
+}
+
+// the upstream of the longest of domains that name is in, otherwise the default one. LLMNR is only for
+// the single label names
+fn upstream_for<'a>(domains: &'a [(String, Upstream)], name: &str, default: &'a Upstream) -> &'a Upstream {
+    domains
+        .iter()
+        .filter(|(domain, upstream)| match upstream {
+            Upstream::Llmnr(_) => is_llmnr_name(name),
+            _ => in_zone(name, domain),
+        })
+        .max_by_key(|(domain, _)| domain.len())
+        .map_or(default, |(_, upstream)| upstream)
+}
+
+// whether packet answers a question that was asked over multicast DNS or LLMNR, which have no chain of
+// trust to check it against
+#[cfg(feature = "dnssec")]
+fn multicast(domains: &[(String, Upstream)], packet: &DnsPacket) -> bool {
+    packet.questions.first().is_some_and(|question| {
+        let default = Upstream::Recursive;
+        matches!(
+            upstream_for(domains, &question.name, &default),
+            Upstream::Multicast(_) | Upstream::Llmnr(_)
+        )
+    })
+}
+
+// packet with the AD bit saying whether it validated, bogus ones are an error
+#[cfg(feature = "dnssec")]
+fn secured(mut packet: DnsPacket, security: Security) -> Result<DnsPacket> {
+    match security {
+        Security::Bogus(error) => Err(error.into()),
+        security => {
+            packet.header.authed_data = security == Security::Secure;
+            Ok(packet)
+        }
+    }
+}
+
+// resolves queries without blocking, every lookup is its own future so any number of them can be
+// in flight on a single runtime
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct AsyncResolver {
+    pub upstream: Upstream,
+    pub timeout: Duration,
+    pub cache: SharedCache,
+    pub race: usize,
+    pub adaptive: bool,
+    pub subnet: Option<SubnetSource>,
+    pub root_hints: Arc<RootHints>,
+    pub nsid: Option<Vec<u8>>,
+    #[cfg(feature = "dnssec")]
+    pub validator: Option<Arc<Validator>>,
+    #[cfg(feature = "tsig")]
+    pub tsig_keys: Arc<Vec<TsigKey>>,
+    pub domains: Arc<Vec<(String, Upstream)>>,
+    pub zones: Arc<Vec<Zone>>,
+    pub secondary: Option<Secondary>,
+    pub local: Arc<LocalRecords>,
+    pub policies: Arc<Vec<Rpz>>,
+    pub blocklist: Option<Arc<Blocklist>>,
+    pub dns64: Option<Dns64>,
+    pub views: Arc<Vec<View<AsyncResolver>>>,
+    pub access: Arc<AccessControl>,
+    pub query_log: Option<Arc<QueryLog>>,
+    pub events: EventLog,
+    flights: Arc<AsyncInFlight>,
+    health: Arc<UpstreamHealth>,
+    pub(crate) cookies: Arc<Cookies>,
+    pub(crate) limiter: Option<Arc<RateLimiter>>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncResolver {
+    pub fn new(upstream: Upstream) -> AsyncResolver {
+        AsyncResolver {
+            upstream,
+            timeout: DEFAULT_TIMEOUT,
+            cache: shared_cache(DEFAULT_CACHE_ENTRIES),
+            race: 0,
+            adaptive: false,
+            subnet: None,
+            root_hints: Arc::default(),
+            nsid: None,
+            #[cfg(feature = "dnssec")]
+            validator: None,
+            #[cfg(feature = "tsig")]
+            tsig_keys: Arc::default(),
+            domains: Arc::default(),
+            zones: Arc::default(),
+            secondary: None,
+            local: Arc::default(),
+            policies: Arc::default(),
+            blocklist: None,
+            dns64: None,
+            views: Arc::default(),
+            access: Arc::default(),
+            query_log: None,
+            events: EventLog::default(),
+            flights: Arc::default(),
+            health: Arc::default(),
+            cookies: Arc::default(),
+            limiter: None,
+        }
+    }
+
+    // how long each forwarding server without a timeout of its own is waited for, when recursing it
+    // applies to each server asked
+    pub fn with_timeout(mut self, timeout: Duration) -> AsyncResolver {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: SharedCache) -> AsyncResolver {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_race(mut self, race: usize) -> AsyncResolver {
+        self.race = race;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: bool) -> AsyncResolver {
+        self.adaptive = adaptive;
+        self
+    }
+
+    pub fn with_client_subnet(mut self, source: SubnetSource) -> AsyncResolver {
+        self.subnet = Some(source);
+        self
+    }
+
+    pub fn with_nsid(mut self, nsid: Vec<u8>) -> AsyncResolver {
+        self.nsid = Some(nsid);
+        self
+    }
+
+    pub fn with_root_hints(mut self, root_hints: RootHints) -> AsyncResolver {
+        self.root_hints = Arc::new(root_hints);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> AsyncResolver {
+        self.limiter = Some(Arc::new(RateLimiter::new(limit)));
+        self
+    }
+
+    pub fn with_access_control(mut self, access: AccessControl) -> AsyncResolver {
+        self.access = Arc::new(access);
+        self
+    }
+
+    pub fn with_query_log(mut self, log: QueryLog) -> AsyncResolver {
+        self.query_log = Some(Arc::new(log));
+        self
+    }
+
+    pub fn with_event_log(mut self, events: EventLog) -> AsyncResolver {
+        self.events = events;
+        self
+    }
+
+    #[cfg(feature = "dnssec")]
+    pub fn with_dnssec(mut self, validator: Validator) -> AsyncResolver {
+        self.validator = Some(Arc::new(validator));
+        self
+    }
+
+    #[cfg(feature = "tsig")]
+    pub fn with_tsig_key(mut self, key: TsigKey) -> AsyncResolver {
+        Arc::make_mut(&mut self.tsig_keys).push(key);
+        self
+    }
+
+    pub fn with_mdns(mut self, timeout: Duration) -> AsyncResolver {
+        for domain in MDNS_DOMAINS {
+            self = self.with_domain(domain, Upstream::Multicast(timeout));
+        }
+        self
+    }
+
+    pub fn with_llmnr(mut self, timeout: Duration) -> AsyncResolver {
+        Arc::make_mut(&mut self.domains).push((String::new(), Upstream::Llmnr(timeout)));
+        self
+    }
+
+    pub fn with_domain(mut self, domain: &str, upstream: Upstream) -> AsyncResolver {
+        let domain = domain
+            .trim_start_matches("*.")
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+        Arc::make_mut(&mut self.domains).push((domain, upstream));
+        self
+    }
+
+    pub fn with_zone(mut self, zone: Zone) -> AsyncResolver {
+        Arc::make_mut(&mut self.zones).push(zone);
+        self
+    }
+
+    pub fn with_secondary(mut self, secondary: Secondary) -> AsyncResolver {
+        self.secondary = Some(secondary);
+        self
+    }
+
+    pub fn with_local(mut self, local: LocalRecords) -> AsyncResolver {
+        self.local = Arc::new(local);
+        self
+    }
+
+    pub fn with_rpz(mut self, rpz: Rpz) -> AsyncResolver {
+        Arc::make_mut(&mut self.policies).push(rpz);
+        self
+    }
+
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> AsyncResolver {
+        self.blocklist = Some(Arc::new(blocklist));
+        self
+    }
+
+    pub fn with_dns64(mut self, dns64: Dns64) -> AsyncResolver {
+        self.dns64 = Some(dns64);
+        self
+    }
+
+    pub fn with_view(mut self, view: View<AsyncResolver>) -> AsyncResolver {
+        Arc::make_mut(&mut self.views).push(view);
+        self
+    }
+
+    pub fn view_for(&self, client: IpAddr) -> &AsyncResolver {
+        view_for(&self.views, client).unwrap_or(self)
+    }
+
+    pub fn health(&self) -> &UpstreamHealth {
+        &self.health
+    }
+
+    pub fn cookies(&self) -> &Cookies {
+        &self.cookies
+    }
+
+    fn forwarding_order(&self, servers: &[ForwardServer]) -> Vec<ForwardServer> {
+        if self.adaptive {
+            self.health.order(servers)
+        } else {
+            servers.to_vec()
+        }
+    }
+
+    // the client subnet for a query from client, only ever sent to forwarding servers
+    pub(crate) fn subnet_for(&self, client: Option<IpAddr>, asked: Option<ClientSubnet>) -> Option<ClientSubnet> {
+        match self.upstream {
+            Upstream::Forward(_) => self.subnet?.subnet_for(client, asked),
+            Upstream::Recursive | Upstream::Multicast(_) | Upstream::Llmnr(_) => None,
+        }
+    }
+
+    pub async fn lookup(&self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.resolve(&DnsQuestion::new(qname.to_string(), qtype)).await
+    }
+
+    pub async fn resolve(&self, question: &DnsQuestion) -> Result<DnsPacket> {
+        self.resolve_in(question, self.subnet_for(None, None)).await
+    }
+
+    pub async fn resolve_in(&self, question: &DnsQuestion, subnet: Option<ClientSubnet>) -> Result<DnsPacket> {
+        self.resolve_traced(question, subnet, &Trace::default()).await
+    }
+
+    pub(crate) async fn resolve_traced(
+        &self,
+        question: &DnsQuestion,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        let Some(dns64) = self.dns64 else {
+            return self.resolve_filtered(question, subnet, trace).await;
+        };
+        if let Some(ipv4_name) = dns64.reverse(question) {
+            let answer = dns64.reverse_answer(question, &ipv4_name);
+            let ipv4_name = DnsQuestion::new(ipv4_name, QueryType::PTR);
+            let rest = self.resolve_filtered(&ipv4_name, subnet, trace).await?;
+            return Ok(completed(answer, rest));
+        }
+
+        let answer = self.resolve_filtered(question, subnet, trace).await;
+        if answer.as_ref().is_ok_and(|answer| !dns64.applies(question, answer)) {
+            return answer;
+        }
+        let ipv4 = DnsQuestion::new(question.name.clone(), QueryType::A);
+        let ipv4 = self.resolve_filtered(&ipv4, subnet, trace).await;
+        match ipv4.ok().and_then(|ipv4| dns64.synthesized(answer.as_ref().ok(), ipv4)) {
+            Some(synthesized) => Ok(synthesized),
+            None => answer,
+        }
+    }
+
+    async fn resolve_filtered(
+        &self,
+        question: &DnsQuestion,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        if let Some(answer) = self.local.answer(question) {
+            return match self.local.onward(&answer, question.qtype) {
+                Some(onward) => Ok(completed(answer, self.resolve_cached(&onward, subnet, trace).await?)),
+                None => Ok(answer),
+            };
+        }
+
+        let policy = name_policy(&self.policies, &question.name);
+        if let Some(answer) = policy.and_then(|policy| policy.rewrite(question)) {
+            return self.rewritten(answer, question.qtype, subnet, trace).await;
+        }
+        let blocklist = self.blocklist.as_ref().filter(|_| policy.is_none());
+        if let Some(blocked) = blocklist.and_then(|blocklist| blocklist.answer(question)) {
+            return Ok(blocked);
+        }
+
+        let answer = self.resolve_cached(question, subnet, trace).await?;
+        if policy.is_none() {
+            if let Some(policy) = address_policy(&self.policies, &answer) {
+                return match policy.rewrite(question) {
+                    Some(rewritten) => self.rewritten(rewritten, question.qtype, subnet, trace).await,
+                    None => Ok(answer),
+                };
+            }
+        }
+
+        Ok(match blocklist {
+            Some(blocklist) => blocklist.filtered(question, answer),
+            None => answer,
+        })
+    }
+
+    async fn rewritten(
+        &self,
+        answer: DnsPacket,
+        qtype: QueryType,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        match onward(&answer, qtype) {
+            Some(onward) => Ok(completed(answer, self.resolve_cached(&onward, subnet, trace).await?)),
+            None => Ok(answer),
+        }
+    }
+
+    async fn resolve_cached(
+        &self,
+        question: &DnsQuestion,
+        subnet: Option<ClientSubnet>,
+        trace: &Trace,
+    ) -> Result<DnsPacket> {
+        let cached = {
+            let mut cache = lock(&self.cache);
+            let subnet = subnet.as_ref();
+            cache
+                .answer(question, subnet)
+                .map(|packet| (packet, cache.prefetch_due(question, subnet)))
+        };
+        trace.cache(cached.is_some());
+        if let Some((packet, prefetch)) = cached {
+            if prefetch {
+                let (resolver, question) = (self.clone(), question.clone());
+                tokio::spawn(async move { resolver.fetch(&question, subnet, &Trace::default()).await });
+            }
+            return Ok(packet);
+        }
+
+        self.fetch(question, subnet, trace).await
+    }
+
+    async fn fetch(&self, question: &DnsQuestion, subnet: Option<ClientSubnet>, trace: &Trace) -> Result<DnsPacket> {
+        self.flights
+            .run(cache_key(question, subnet.as_ref()), async {
+                let packet = self.ask(question, subnet.as_ref(), trace).await;
+                #[cfg(feature = "dnssec")]
+                let packet = match packet {
+                    Ok(packet) => self.validated(packet).await,
+                    failed => failed,
+                };
+
+                settle(&self.cache, question, subnet.as_ref(), packet)
+            })
+            .await
+    }
+
+    async fn ask(&self, question: &DnsQuestion, subnet: Option<&ClientSubnet>, trace: &Trace) -> Result<DnsPacket> {
+        let (name, qtype) = (&question.name, question.qtype);
+        let upstream = upstream_for(&self.domains, name, &self.upstream);
+        let answer = match upstream {
+            Upstream::Forward(servers) => {
+                let servers = self.forwarding_order(servers);
+                let query = query_for(question, subnet, self.validating());
+                let (health, cookies) = (&self.health, &self.cookies);
+                let (packet, server) =
+                    exchange_forward_async(&query, &servers, self.timeout, self.race, health, cookies).await?;
+                trace.upstream(format!("{}:{}", server.0, server.1));
+                return Ok(restore_case(packet, name));
+            }
+            // the socket calls block, so they get a thread of their own
+            Upstream::Multicast(timeout) => {
+                let (question, timeout) = (question.clone(), *timeout);
+                tokio::task::spawn_blocking(move || query_mdns(&question, timeout, true)).await?
+            }
+            Upstream::Llmnr(timeout) => {
+                let (question, timeout) = (question.clone(), *timeout);
+                tokio::task::spawn_blocking(move || query_llmnr(&question, timeout)).await?
+            }
+            Upstream::Recursive => {
+                if self.root_hints.priming_due() {
+                    let (root_hints, timeout) = (self.root_hints.clone(), self.timeout);
+                    tokio::spawn(async move { root_hints.prime_async(timeout).await });
+                }
+                let roots = self.root_hints.servers();
+                recursive_lookup_from_async(name, qtype, &roots, self.validating(), self.timeout).await
+            }
+        };
+        if answer.is_ok() {
+            trace.upstream(upstream.name().to_string());
+        }
+
+        answer
+    }
+
+    #[cfg(feature = "dnssec")]
+    fn validating(&self) -> bool {
+        self.validator.is_some()
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn validating(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "dnssec")]
+    async fn validated(&self, packet: DnsPacket) -> Result<DnsPacket> {
+        let Some(validator) = self.validator.as_ref().filter(|_| !multicast(&self.domains, &packet)) else {
+            return Ok(packet);
+        };
+        let fetch = |name: String, qtype| async move {
+            self.ask(&DnsQuestion::new(name, qtype), None, &Trace::default()).await
+        };
+        let security = validator.validate_async(&packet, fetch).await?;
+
+        secured(packet, security)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::DnsRecord;
+
+    // a udp server on localhost answering the first query it gets with whatever respond makes of it,
+    // None leaves the query unanswered
+    fn stub(respond: impl FnOnce(DnsPacket) -> Option<DnsPacket> + Send + 'static) -> (Ipv4Addr, u16) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let mut buffer = BytePacketBuffer::new();
+            let (_, client) = socket.recv_from(&mut buffer.buffer).unwrap();
+            let query = DnsPacket::from_buffer(&mut buffer).unwrap();
+            if let Some(response) = respond(query) {
+                let response = encode(&response).unwrap();
+                socket.send_to(&response.buffer[..response.pos()], client).unwrap();
+            }
+            // hold on to the socket for a little while, so a silent stub times out instead of refusing
+            thread::sleep(Duration::from_secs(2));
+        });
+
+        (Ipv4Addr::LOCALHOST, port)
+    }
+
+    fn answering(code: ResultCode) -> impl FnOnce(DnsPacket) -> Option<DnsPacket> {
+        move |query| {
+            let mut response = DnsPacket::new_response(&query);
+            response.header.result_code = code;
+            if code == ResultCode::NOERROR {
+                response.answers.push(DnsRecord::A {
+                    domain: query.questions[0].name.clone(),
+                    address: Ipv4Addr::new(192, 0, 2, 1),
+                    ttl: 60,
+                });
+            }
+            Some(response)
+        }
+    }
+
+    #[test]
+    fn query_ids_come_from_the_generator() {
+        let counter = Cell::new(100);
+        let next_id = || {
+            counter.set(counter.get() + 1);
+            counter.get()
+        };
+
+        assert_eq!(build_query_with("example.com", QueryType::A, next_id).header.id, 101);
+        assert_eq!(build_query_with("example.com", QueryType::A, next_id).header.id, 102);
+        assert_eq!(build_query_with("example.com", QueryType::A, || 7).header.id, 7);
+    }
+
+    #[test]
+    fn explicit_ids_are_kept() {
+        let query = DnsPacket::new_query(0x0A0B, "example.com", QueryType::A);
+        let mut buffer = encode(&query).unwrap();
+        buffer.seek(0).unwrap();
+
+        assert_eq!(DnsPacket::from_buffer(&mut buffer).unwrap().header.id, 0x0A0B);
+    }
+
+    #[test]
+    fn random_ids_differ() {
+        // sixteen equal draws from a 16 bit generator would be a one in 2^240 chance
+        let first = random_id();
+        assert!((0..15).map(|_| random_id()).any(|id| id != first));
+        let first = build_query("example.com", QueryType::A).header.id;
+        assert!((0..15).any(|_| build_query("example.com", QueryType::A).header.id != first));
+    }
+
+    #[test]
+    fn fallback_moves_past_failing_servers() {
+        let servers = [
+            stub(answering(ResultCode::SERVFAIL)),
+            stub(|_| None),
+            stub(answering(ResultCode::NOERROR)),
+        ];
+        let timeout = Duration::from_millis(200);
+
+        let response = lookup_with_fallback_timeout("example.com", QueryType::A, &servers, timeout).unwrap();
+        assert_eq!(response.header.result_code, ResultCode::NOERROR);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn fallback_returns_the_last_error() {
+        let servers = [
+            stub(answering(ResultCode::NOERROR)),
+            stub(answering(ResultCode::SERVFAIL)),
+        ];
+        // the first server answers, so the second is never asked
+        assert!(lookup_with_fallback("example.com", QueryType::A, &servers).is_ok());
+
+        let servers = [stub(answering(ResultCode::SERVFAIL)), stub(|_| None)];
+        let timeout = Duration::from_millis(200);
+        let error = lookup_with_fallback_timeout("example.com", QueryType::A, &servers, timeout).unwrap_err();
+        assert_eq!(error, DnsError::Timeout);
+
+        let error = lookup_with_fallback("example.com", QueryType::A, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "No servers to query");
+    }
+
+    #[test]
+    fn lookups_return_error_result_codes() {
+        let error = lookup("example.com", QueryType::A, stub(answering(ResultCode::NXDOMAIN))).unwrap_err();
+        assert_eq!(error, DnsError::NameError);
+
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let error = reverse_lookup(ip, stub(answering(ResultCode::REFUSED))).unwrap_err();
+        assert_eq!(error, DnsError::Refused);
+
+        let server = stub(answering(ResultCode::SERVFAIL));
+        let error = lookup_with_timeout("example.com", QueryType::A, server, DEFAULT_TIMEOUT).unwrap_err();
+        assert_eq!(error, DnsError::ServerFailure);
+
+        // a name that doesn't exist is an answer, there's no use asking the next server
+        let servers = [
+            stub(answering(ResultCode::NXDOMAIN)),
+            stub(answering(ResultCode::NOERROR)),
+        ];
+        let error = lookup_with_fallback("example.com", QueryType::A, &servers).unwrap_err();
+        assert_eq!(error, DnsError::NameError);
+    }
+
+    #[test]
+    fn silent_servers_time_out() {
+        let started = Instant::now();
+        let timeout = Duration::from_millis(150);
+        let error = lookup_with_timeout("example.com", QueryType::A, stub(|_| None), timeout).unwrap_err();
+
+        assert_eq!(error, DnsError::Timeout);
+        assert!(started.elapsed() >= timeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    // nothing answers in TEST-NET-1, the packet just disappears wherever there's a route out
+    #[test]
+    #[ignore]
+    fn black_holes_time_out() {
+        let server = (Ipv4Addr::new(192, 0, 2, 1), 53);
+        let timeout = Duration::from_millis(500);
+        let error = lookup_with_timeout("example.com", QueryType::A, server, timeout).unwrap_err();
+
+        assert_eq!(error, DnsError::Timeout);
+    }
+
+    #[test]
+    fn batch_results_keep_the_input_order() {
+        let names = ["a.example", "b.example", "c.example", "d.example"].map(String::from);
+        let finished = [(2, Ok(2)), (0, Ok(0)), (3, Err("Lookup failed".into()))];
+
+        let results = in_input_order(&names, finished);
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.example", "b.example", "c.example", "d.example"]);
+        assert_eq!(results[0].1.as_ref().unwrap(), &0);
+        // b never came back
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2].1.as_ref().unwrap(), &2);
+        assert_eq!(results[3].1.as_ref().unwrap_err().to_string(), "Lookup failed");
+    }
+
+    #[test]
+    #[ignore]
+    fn resolves_a_batch_of_names() {
+        let names = ["example.com", "example.net", "example.org", "iana.org"].map(String::from);
+        let results = lookup_many(&names, QueryType::A, (Ipv4Addr::new(8, 8, 8, 8), 53), 3);
+
+        for ((name, result), asked) in results.iter().zip(&names) {
+            assert_eq!(name, asked);
+            assert!(!result.as_ref().unwrap().answers.is_empty(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    net::{IpAddr, Ipv4Addr},
+    sync::Mutex,
+};
+
+use crate::{cache::lock, edns::OPTION_COOKIE, record::unix_time, DnsPacket, Result};
+
+// the extended result code of a server that wants a valid cookie before it answers
+pub const BADCOOKIE: u16 = 23;
+
+pub const CLIENT_COOKIE_LEN: usize = 8;
+
+// RFC 9018 server cookies: a version, three reserved bytes, when it was made and an eight byte hash
+const SERVER_COOKIE_LEN: usize = 16;
+const COOKIE_VERSION: u8 = 1;
+
+// a server cookie is honoured for an hour, responses to queries with one older than half an hour get
+// a fresh one, and a timestamp a few minutes ahead is let through for the sake of the clock
+const COOKIE_LIFETIME: i64 = 60 * 60;
+const COOKIE_REFRESH: i64 = 30 * 60;
+const COOKIE_SKEW: i64 = 5 * 60;
+
+// how the cookie of a query checks out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CookieCheck {
+    // the query came without one
+    Missing,
+    // not a length a cookie can have, which RFC 7873 answers with FORMERR
+    Malformed,
+    // just a client cookie, or a server cookie that wasn't made here for this client or has expired
+    Unverified,
+    // a server cookie made here for the same client cookie and address, which a spoofer can't know
+    Valid,
+}
+
+// DNS cookies from RFC 7873, both sides of them. As a client every upstream gets a client cookie of
+// its own and the server cookie it answers with is sent back on the next query, as a server the
+// cookies handed out are keyed by a secret only this process knows, so they can't be forged
+#[derive(Debug, Default)]
+pub struct Cookies {
+    secret: RandomState,
+    // the server cookie each upstream sent last
+    servers: Mutex<HashMap<(Ipv4Addr, u16), Vec<u8>>>,
+}
+
+impl Cookies {
+    pub fn new() -> Cookies {
+        Cookies::default()
+    }
+
+    // derived from the secret and the server, so it stays the same for as long as this lives
+    pub fn client_cookie(&self, server: (Ipv4Addr, u16)) -> [u8; CLIENT_COOKIE_LEN] {
+        self.secret.hash_one(("client", server)).to_be_bytes()
+    }
+
+    pub fn server_cookie(&self, server: (Ipv4Addr, u16)) -> Option<Vec<u8>> {
+        lock(&self.servers).get(&server).cloned()
+    }
+
+    // query with the cookies for server in its OPT record, queries without EDNS are left as they are
+    pub(crate) fn attached(&self, query: &DnsPacket, server: (Ipv4Addr, u16)) -> DnsPacket {
+        let mut query = query.clone();
+        if let Some(edns) = query.edns_mut() {
+            let mut cookie = self.client_cookie(server).to_vec();
+            cookie.extend(self.server_cookie(server).unwrap_or_default());
+            edns.set_option(OPTION_COOKIE, cookie);
+        }
+
+        query
+    }
+
+    // the response from server, unless its cookie gives it away as spoofed. Servers that don't know
+    // cookies just leave the option out, those that do have their server cookie remembered
+    pub(crate) fn checked(&self, server: (Ipv4Addr, u16), outcome: Result<DnsPacket>) -> Result<DnsPacket> {
+        let packet = outcome?;
+        let Some(option) = packet.edns().and_then(|edns| edns.option(OPTION_COOKIE)) else {
+            return Ok(packet);
+        };
+
+        match split(&option.data) {
+            Some((client, cookie)) if !cookie.is_empty() && client == self.client_cookie(server) => {
+                lock(&self.servers).insert(server, cookie.to_vec());
+                Ok(packet)
+            }
+            _ => Err("Response cookie doesn't match the query".into()),
+        }
+    }
+
+    pub fn check(&self, query: &DnsPacket, client: IpAddr) -> CookieCheck {
+        let Some(option) = query.edns().and_then(|edns| edns.option(OPTION_COOKIE)) else {
+            return CookieCheck::Missing;
+        };
+
+        match split(&option.data) {
+            None => CookieCheck::Malformed,
+            Some((client_cookie, server_cookie)) => match self.age(client_cookie, server_cookie, client) {
+                Some(_) => CookieCheck::Valid,
+                None => CookieCheck::Unverified,
+            },
+        }
+    }
+
+    // what goes in the response to query: its client cookie, with the server cookie it came with if
+    // that's still fresh, a new one otherwise. None for queries without a well formed cookie
+    pub(crate) fn response_cookie(&self, query: &DnsPacket, client: IpAddr) -> Option<Vec<u8>> {
+        let option = query.edns()?.option(OPTION_COOKIE)?;
+        let (client_cookie, server_cookie) = split(&option.data)?;
+
+        let mut cookie = client_cookie.to_vec();
+        match self.age(client_cookie, server_cookie, client) {
+            Some(age) if age < COOKIE_REFRESH => cookie.extend_from_slice(server_cookie),
+            _ => cookie.extend(self.server_cookie_for(client_cookie, client, now())),
+        }
+
+        Some(cookie)
+    }
+
+    fn server_cookie_for(&self, client_cookie: &[u8], client: IpAddr, timestamp: u32) -> [u8; SERVER_COOKIE_LEN] {
+        let mut cookie = [0; SERVER_COOKIE_LEN];
+        cookie[0] = COOKIE_VERSION;
+        cookie[4..8].copy_from_slice(&timestamp.to_be_bytes());
+        let hash = self.secret.hash_one((client_cookie, &cookie[..8], client));
+        cookie[8..].copy_from_slice(&hash.to_be_bytes());
+
+        cookie
+    }
+
+    // seconds since the server cookie was made, if it was made here for this client and hasn't expired
+    fn age(&self, client_cookie: &[u8], server_cookie: &[u8], client: IpAddr) -> Option<i64> {
+        let timestamp = server_cookie.get(4..8)?;
+        let timestamp = u32::from_be_bytes(timestamp.try_into().ok()?);
+        if self.server_cookie_for(client_cookie, client, timestamp)[..] != *server_cookie {
+            return None;
+        }
+
+        // timestamps wrap around like serial numbers
+        let age = i64::from(now().wrapping_sub(timestamp) as i32);
+        (-COOKIE_SKEW..=COOKIE_LIFETIME).contains(&age).then_some(age)
+    }
+}
+
+// a client cookie and what follows it, which is nothing or a server cookie of 8 to 32 bytes
+fn split(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    match data.len() {
+        CLIENT_COOKIE_LEN | 16..=40 => Some(data.split_at(CLIENT_COOKIE_LEN)),
+        _ => None,
+    }
+}
+
+// cookie timestamps are serial numbers, so the clock wraps with them
+fn now() -> u32 {
+    unix_time() as u32
+}
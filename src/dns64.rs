@@ -0,0 +1,187 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::{question::reverse_name, DnsError, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode};
+
+// the well known prefix of RFC 6052, 64:ff9b::/96, for NAT64 gateways translating to public addresses
+pub const WELL_KNOWN_PREFIX: (Ipv6Addr, u8) = (Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0), 96);
+
+// what the cnames pointing reverse names of synthesized addresses at their ipv4 ones live for
+pub const REVERSE_TTL: u32 = 600;
+
+// the prefix lengths RFC 6052 has ipv4 addresses embedded after
+const PREFIX_LENGTHS: [u8; 6] = [32, 40, 48, 56, 64, 96];
+
+// the ipv4 mapped addresses, which RFC 6147 has an AAAA answer treated as empty for when they're all
+// there is in it
+const MAPPED: (Ipv6Addr, u8) = (Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96);
+
+// DNS64 like RFC 6147 has it, so clients that only have ipv6 can reach names that only have ipv4
+// addresses through a NAT64 gateway. Names without AAAA records of their own get them made up from
+// their A records, with the ipv4 address embedded in the gateway's prefix the way RFC 6052 does it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dns64 {
+    // only the first length bits are ever set
+    pub prefix: Ipv6Addr,
+    pub length: u8,
+}
+
+impl Dns64 {
+    // the prefix has to be one of the lengths RFC 6052 allows, 32, 40, 48, 56, 64 or 96 bits
+    pub fn new(prefix: Ipv6Addr, length: u8) -> Result<Dns64> {
+        if !PREFIX_LENGTHS.contains(&length) {
+            let lengths = "32, 40, 48, 56, 64 or 96";
+            return Err(format!("A NAT64 prefix is {} bits long, not {}", lengths, length).into());
+        }
+        let prefix = Ipv6Addr::from(network(prefix, length));
+
+        Ok(Dns64 { prefix, length })
+    }
+
+    // the ipv6 address the gateway translates to ipv4, its bits 64 to 71 are left 0 for every length
+    pub fn synthesize(&self, ipv4: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.octets();
+        let mut position = usize::from(self.length / 8);
+        for octet in ipv4.octets() {
+            if position == 8 {
+                position += 1;
+            }
+            octets[position] = octet;
+            position += 1;
+        }
+
+        Ipv6Addr::from(octets)
+    }
+
+    // the ipv4 address embedded in ipv6, None when it isn't in the prefix
+    pub fn extract(&self, ipv6: Ipv6Addr) -> Option<Ipv4Addr> {
+        if network(ipv6, self.length) != u128::from(self.prefix) {
+            return None;
+        }
+
+        let octets = ipv6.octets();
+        let mut ipv4 = [0; 4];
+        let mut position = usize::from(self.length / 8);
+        for octet in &mut ipv4 {
+            if position == 8 {
+                position += 1;
+            }
+            *octet = octets[position];
+            position += 1;
+        }
+
+        Some(Ipv4Addr::from(ipv4))
+    }
+
+    // whether question is an AAAA one that answer has nothing usable for, so what the name's A records
+    // say is worth a try. NXDOMAIN means there's no name to ask again, any other error is treated like
+    // an empty answer, and so is failing to resolve it at all
+    pub(crate) fn applies(&self, question: &DnsQuestion, answer: &DnsPacket) -> bool {
+        question.qtype == QueryType::AAAA
+            && answer.header.result_code != ResultCode::NXDOMAIN
+            && !answer.answers.iter().any(|record| match record {
+                DnsRecord::AAAA { address, .. } => network(*address, MAPPED.1) != u128::from(MAPPED.0),
+                _ => false,
+            })
+    }
+
+    // the answer to an AAAA question with the A records of ipv4 made into AAAA ones, None when it has
+    // none. Their TTL is capped at how long the AAAA answer may be cached, going by its SOA
+    pub(crate) fn synthesized(&self, answer: Option<&DnsPacket>, ipv4: DnsPacket) -> Option<DnsPacket> {
+        if !ipv4.answers.iter().any(|record| matches!(record, DnsRecord::A { .. })) {
+            return None;
+        }
+        let authorities = answer.map(|answer| answer.authorities.as_slice()).unwrap_or_default();
+        let negative_ttl = authorities.iter().find_map(|record| match record {
+            DnsRecord::SOA { ttl, minimum, .. } => Some((*ttl).min(*minimum)),
+            _ => None,
+        });
+
+        let mut synthesized = DnsPacket::new();
+        synthesized.header.result_code = ipv4.header.result_code;
+        for record in ipv4.answers {
+            synthesized.answers.push(match record {
+                DnsRecord::A { domain, address, ttl } => DnsRecord::AAAA {
+                    domain,
+                    address: self.synthesize(address),
+                    ttl: negative_ttl.map_or(ttl, |negative_ttl| ttl.min(negative_ttl)),
+                },
+                // the cnames that led to them stay, signatures over the A records don't fit anymore
+                DnsRecord::RRSIG { .. } => continue,
+                record => record,
+            });
+        }
+
+        Some(synthesized)
+    }
+
+    // for a PTR question about an address in the prefix, the reverse name of the ipv4 address in it
+    pub(crate) fn reverse(&self, question: &DnsQuestion) -> Option<String> {
+        if question.qtype != QueryType::PTR {
+            return None;
+        }
+        let ipv6 = ipv6_of(&question.name)?;
+
+        Some(reverse_name(IpAddr::V4(self.extract(ipv6)?)))
+    }
+
+    // a cname from the name asked for to the reverse name of the ipv4 address, which is resolved as
+    // usual the rest of the way
+    pub(crate) fn reverse_answer(&self, question: &DnsQuestion, ipv4_name: &str) -> DnsPacket {
+        let mut answer = DnsPacket::new();
+        answer.answers.push(DnsRecord::CNAME {
+            domain: question.name.trim_end_matches('.').to_string(),
+            host: ipv4_name.to_string(),
+            ttl: REVERSE_TTL,
+        });
+
+        answer
+    }
+}
+
+impl Default for Dns64 {
+    fn default() -> Dns64 {
+        Dns64 {
+            prefix: WELL_KNOWN_PREFIX.0,
+            length: WELL_KNOWN_PREFIX.1,
+        }
+    }
+}
+
+// a prefix like 64:ff9b::/96, the length defaults to 96
+impl FromStr for Dns64 {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Dns64> {
+        let (prefix, length) = s.split_once('/').unwrap_or((s, "96"));
+        let prefix: Ipv6Addr = prefix.parse().map_err(|_| format!("{} isn't an ipv6 prefix", s))?;
+        let length: u8 = length.parse().map_err(|_| format!("{} isn't an ipv6 prefix", s))?;
+
+        Dns64::new(prefix, length)
+    }
+}
+
+// the first length bits of address, for lengths of at least one bit
+fn network(address: Ipv6Addr, length: u8) -> u128 {
+    u128::from(address) & u128::MAX << (128 - u32::from(length))
+}
+
+// the address of an ip6.arpa name with all 32 nibbles, None for any other name
+fn ipv6_of(name: &str) -> Option<Ipv6Addr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let nibbles = name.strip_suffix(".ip6.arpa")?;
+
+    let mut address: u128 = 0;
+    let mut count = 0;
+    for nibble in nibbles.split('.').rev() {
+        let [digit] = nibble.as_bytes() else {
+            return None;
+        };
+        address = address << 4 | u128::from(char::from(*digit).to_digit(16)?);
+        count += 1;
+    }
+
+    (count == 32).then(|| Ipv6Addr::from(address))
+}
@@ -0,0 +1,291 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crypto_box::{
+    aead::{generic_array::GenericArray, AeadInPlace},
+    Nonce, PublicKey, SalsaBox, SecretKey,
+};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    client::{build_query, encode, exchange, DEFAULT_TIMEOUT},
+    parse_bytes,
+    record::{character_strings, unix_time},
+    tcp::{connect, read_framed, timeout_error, write_framed},
+    DnsError, DnsPacket, DnsRecord, QueryType, Result,
+};
+
+// where DNSCrypt servers usually listen, so it gets through networks that only let https out
+pub const DNSCRYPT_PORT: u16 = 443;
+
+// what every certificate starts with
+const CERT_MAGIC: [u8; 4] = *b"DNSC";
+
+// the es-version of X25519-XSalsa20Poly1305, the one construction supported
+const XSALSA20_POLY1305: [u8; 2] = [0, 1];
+
+// a certificate without extensions
+const CERT_SIZE: usize = 124;
+
+// what every response starts with
+const RESOLVER_MAGIC: [u8; 8] = [0x72, 0x36, 0x66, 0x6e, 0x76, 0x57, 0x6a, 0x38];
+
+// queries are padded to a multiple of 64 bytes, and over udp to at least 256 so a small query can't
+// get a larger response sent to a spoofed address
+const PADDING_BLOCK: usize = 64;
+const MIN_QUERY_SIZE: usize = 256;
+
+// the client's half of the nonce, the resolver's half follows it in the response
+const HALF_NONCE: usize = 12;
+
+const TAG_SIZE: usize = 16;
+
+// the part of a provider's certificate a client needs, which has been checked against the provider's
+// public key. Every query is encrypted to the resolver's short term key and starts with the client
+// magic, so the resolver knows which of its keys it was for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub resolver_key: [u8; 32],
+    pub client_magic: [u8; 8],
+    pub serial: u32,
+    // seconds since the unix epoch
+    pub valid_from: u32,
+    pub valid_until: u32,
+}
+
+impl Certificate {
+    // a certificate as it comes in the TXT record of the provider name, its signature checked with
+    // provider_key
+    pub fn parse(data: &[u8], provider_key: &[u8; 32]) -> Result<Certificate> {
+        if data.len() < CERT_SIZE || data[..4] != CERT_MAGIC {
+            return Err("Not a DNSCrypt certificate".into());
+        }
+        if data[4..6] != XSALSA20_POLY1305 {
+            let version = u16::from_be_bytes([data[4], data[5]]);
+            return Err(format!("DNSCrypt certificates for es-version {} aren't supported", version).into());
+        }
+
+        let (signature, signed) = (&data[8..72], &data[72..]);
+        UnparsedPublicKey::new(&ED25519, provider_key)
+            .verify(signed, signature)
+            .map_err(|_| "The DNSCrypt certificate's signature doesn't match the provider key")?;
+
+        let number = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        Ok(Certificate {
+            resolver_key: data[72..104].try_into()?,
+            client_magic: data[104..112].try_into()?,
+            serial: number(112),
+            valid_from: number(116),
+            valid_until: number(120),
+        })
+    }
+
+    pub fn is_current(&self) -> bool {
+        let now = now();
+        self.valid_from <= now && now <= self.valid_until
+    }
+}
+
+// queries a DNSCrypt v2 server, identified by the provider name its certificates are published under
+// and the provider's public key that signs them. The certificate is fetched with a TXT query for the
+// provider name before the first query and again once it has expired. Every query is encrypted with a
+// key pair of its own, so queries can't be linked to each other by the key. Truncated answers are asked
+// for again over tcp
+pub struct DnsCryptClient {
+    pub server: (Ipv4Addr, u16),
+    pub provider_name: String,
+    pub timeout: Duration,
+    provider_key: [u8; 32],
+    certificate: Option<Certificate>,
+}
+
+impl DnsCryptClient {
+    pub fn new(server: (Ipv4Addr, u16), provider_name: &str, provider_key: [u8; 32]) -> DnsCryptClient {
+        DnsCryptClient {
+            server,
+            provider_name: provider_name.trim_end_matches('.').to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            provider_key,
+            certificate: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> DnsCryptClient {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn lookup(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.exchange(&build_query(qname, qtype))
+    }
+
+    pub fn exchange(&mut self, query: &DnsPacket) -> Result<DnsPacket> {
+        let certificate = self.certificate()?.clone();
+        let request = encode(query)?;
+        let message = &request.buffer[..request.pos()];
+
+        let response = self.exchange_udp(&certificate, message)?;
+        if !response.header.truncated_message {
+            return Ok(response);
+        }
+        self.exchange_tcp(&certificate, message)
+    }
+
+    // the newest current certificate of the provider, the one kept from before while it lasts
+    pub fn certificate(&mut self) -> Result<&Certificate> {
+        if !self.certificate.as_ref().is_some_and(Certificate::is_current) {
+            self.certificate = Some(self.fetch_certificate()?);
+        }
+
+        Ok(self.certificate.as_ref().expect("The certificate was just fetched"))
+    }
+
+    // the provider's TXT records over plain DNS, a provider rotating its keys publishes a certificate
+    // for each of them and the highest serial wins
+    fn fetch_certificate(&self) -> Result<Certificate> {
+        let query = build_query(&self.provider_name, QueryType::TXT);
+        let response = exchange(&query, self.server, Some(self.timeout))?;
+
+        let mut newest: Option<Certificate> = None;
+        let mut rejected = None;
+        for record in response.answers {
+            let DnsRecord::TXT { raw, .. } = record else {
+                continue;
+            };
+            match Certificate::parse(&character_strings(&raw).concat(), &self.provider_key) {
+                Ok(certificate) if !certificate.is_current() => {
+                    rejected = Some("The certificate has expired".to_string())
+                }
+                Ok(certificate) => {
+                    if !newest
+                        .as_ref()
+                        .is_some_and(|newest| newest.serial >= certificate.serial)
+                    {
+                        newest = Some(certificate);
+                    }
+                }
+                Err(e) => rejected = Some(e.to_string()),
+            }
+        }
+
+        newest
+            .ok_or_else(|| match rejected {
+                Some(reason) => format!("No usable DNSCrypt certificate for {}: {}", self.provider_name, reason),
+                None => format!("{} has no DNSCrypt certificate", self.provider_name),
+            })
+            .map_err(Into::into)
+    }
+
+    fn exchange_udp(&self, certificate: &Certificate, message: &[u8]) -> Result<DnsPacket> {
+        let (encrypted, sealer) = seal(certificate, message, MIN_QUERY_SIZE)?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.send_to(&encrypted, self.server)?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut data = vec![0; MAX_BUFFER_SIZE];
+        loop {
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .ok_or(DnsError::Timeout)?;
+            socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+            let (length, source) = socket.recv_from(&mut data).map_err(timeout_error)?;
+
+            // anything that doesn't come from the server or doesn't decrypt is someone else's
+            if source != SocketAddr::from(self.server) {
+                continue;
+            }
+            if let Ok(response) = sealer.open(&data[..length]) {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn exchange_tcp(&self, certificate: &Certificate, message: &[u8]) -> Result<DnsPacket> {
+        let (encrypted, sealer) = seal(certificate, message, 0)?;
+        let mut stream = connect(self.server, self.timeout)?;
+        write_framed(&mut stream, &encrypted)?;
+        let response = read_framed(&mut stream)?;
+
+        sealer.open(&response.buffer)
+    }
+}
+
+// what decrypts the response to one query, the box shared with the resolver and the client's half of
+// the nonce the response has to start with
+struct Sealer {
+    shared: SalsaBox,
+    nonce: [u8; HALF_NONCE],
+}
+
+impl Sealer {
+    // the response in data, the magic then the whole nonce then the box of the padded message
+    fn open(&self, data: &[u8]) -> Result<DnsPacket> {
+        let start = RESOLVER_MAGIC.len() + 2 * HALF_NONCE;
+        if data.len() < start + TAG_SIZE || data[..8] != RESOLVER_MAGIC {
+            return Err("Not a DNSCrypt response".into());
+        }
+        let nonce = &data[8..start];
+        if nonce[..HALF_NONCE] != self.nonce {
+            return Err("The DNSCrypt response is for another query".into());
+        }
+
+        let tag = GenericArray::from_slice(&data[start..start + TAG_SIZE]);
+        let mut message = data[start + TAG_SIZE..].to_vec();
+        self.shared
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), b"", &mut message, tag)
+            .map_err(|_| "The DNSCrypt response doesn't decrypt")?;
+
+        parse_bytes(unpadded(&message).ok_or("The DNSCrypt response isn't padded")?)
+    }
+}
+
+// the query for message, the client magic, a new public key and half a nonce, then the box of the
+// message padded to a multiple of 64 bytes and at least minimum
+fn seal(certificate: &Certificate, message: &[u8], minimum: usize) -> Result<(Vec<u8>, Sealer)> {
+    let secret = SecretKey::from_bytes(random());
+    let shared = SalsaBox::new(&PublicKey::from_bytes(certificate.resolver_key), &secret);
+    let half: [u8; HALF_NONCE] = random();
+    let mut nonce = [0; 2 * HALF_NONCE];
+    nonce[..HALF_NONCE].copy_from_slice(&half);
+
+    // ISO/IEC 7816-4 padding, a 0x80 and then zeros
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    let length = padded.len().div_ceil(PADDING_BLOCK) * PADDING_BLOCK;
+    padded.resize(length.max(minimum), 0);
+
+    let tag = shared
+        .encrypt_in_place_detached(Nonce::from_slice(&nonce), b"", &mut padded)
+        .map_err(|_| "The query couldn't be encrypted")?;
+
+    let mut query = Vec::with_capacity(8 + 32 + HALF_NONCE + TAG_SIZE + padded.len());
+    query.extend_from_slice(&certificate.client_magic);
+    query.extend_from_slice(secret.public_key().as_bytes());
+    query.extend_from_slice(&half);
+    query.extend_from_slice(&tag);
+    query.extend_from_slice(&padded);
+
+    Ok((query, Sealer { shared, nonce: half }))
+}
+
+// message without its padding, None when it isn't padded the way it should be
+fn unpadded(message: &[u8]) -> Option<&[u8]> {
+    let end = message.iter().rposition(|byte| *byte != 0)?;
+    (message[end] == 0x80).then(|| &message[..end])
+}
+
+// keys and nonces come straight from the operating system's generator
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("The operating system's random number generator failed");
+
+    bytes
+}
+
+// certificate validity is in u32 seconds since the epoch, a clock past it gets the end of time
+fn now() -> u32 {
+    u32::try_from(unix_time()).unwrap_or(u32::MAX)
+}
@@ -0,0 +1,1482 @@
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ring::{digest, signature};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    cache::lock,
+    edns::{
+        EDE_DNSKEY_MISSING, EDE_DNSSEC_BOGUS, EDE_NSEC_MISSING, EDE_RRSIGS_MISSING, EDE_SIGNATURE_EXPIRED,
+        EDE_SIGNATURE_NOT_YET_VALID,
+    },
+    encoding::{base32hex_encode, hex_decode},
+    record::{name_wire, normal, unix_time},
+    resolver::{in_zone, label_count},
+    BytePacketBuffer, DnsPacket, DnsRecord, ExtendedError, QueryType, Result, ResultCode,
+};
+
+// the zone key bit of a DNSKEY's flags, only keys with it may sign a zone
+const ZONE_KEY: u16 = 0x0100;
+
+// DNSKEY's protocol field, RFC 4034 allows nothing else
+const DNSSEC_PROTOCOL: u8 = 3;
+
+// RFC 9276 lets validators treat zones that make every lookup hash the name this often as unsigned,
+// since checking their denials costs as much as an attacker wants it to
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+// keys and DS records a single response may need fetched before its validation is given up on
+const MAX_FETCHES: usize = 64;
+
+// zones whose keys are remembered at most, past that the expired ones are forgotten
+const MAX_ZONES: usize = 10_000;
+
+// the root zone's key signing keys, KSK-2017 and KSK-2024, as the DS records IANA publishes for them
+const ROOT_ANCHORS: [(u16, &str); 2] = [
+    (
+        20326,
+        "e06d44b80b8f1d39a95c0b0d7c65d08458e880409bbc683457104237c7f8ec8d",
+    ),
+    (
+        38696,
+        "683d2d0acb8c9b712a1948b27f741219298d0a450d612c483af444a4c0fb2b16",
+    ),
+];
+
+pub fn root_anchors() -> Vec<DnsRecord> {
+    ROOT_ANCHORS
+        .iter()
+        .map(|(key_tag, digest)| DnsRecord::DS {
+            domain: String::new(),
+            key_tag: *key_tag,
+            algorithm: 8,
+            digest_type: 2,
+            digest: hex_decode(digest).expect("Root anchor digests are valid hex"),
+            ttl: u32::MAX,
+        })
+        .collect()
+}
+
+// the DS records to trust from a file: RFC 7958's root-anchors.xml the way IANA publishes it, with
+// just the key digests that are valid right now, or DS records in zone file format
+pub fn parse_trust_anchors(text: &str) -> Result<Vec<DnsRecord>> {
+    let anchors = if text.contains("<TrustAnchor") {
+        xml_anchors(text)?
+    } else {
+        ds_anchors(text)?
+    };
+    if anchors.is_empty() {
+        return Err("No trust anchors that are valid now".into());
+    }
+
+    Ok(anchors)
+}
+
+fn xml_anchors(text: &str) -> Result<Vec<DnsRecord>> {
+    let zone = normal(element(text, "Zone").unwrap_or("."));
+    let now = unix_time();
+
+    let mut anchors = Vec::new();
+    for key_digest in text.split("<KeyDigest").skip(1) {
+        let (attributes, body) = key_digest.split_once('>').ok_or("A KeyDigest isn't closed")?;
+        let valid_from = attribute(attributes, "validFrom").map(xml_time).transpose()?;
+        let valid_until = attribute(attributes, "validUntil").map(xml_time).transpose()?;
+        if valid_from.is_some_and(|from| from > now) || valid_until.is_some_and(|until| until <= now) {
+            continue;
+        }
+
+        let field = |name: &str| element(body, name).ok_or(format!("A KeyDigest has no {}", name));
+        anchors.push(DnsRecord::DS {
+            domain: zone.clone(),
+            key_tag: field("KeyTag")?.parse()?,
+            algorithm: field("Algorithm")?.parse()?,
+            digest_type: field("DigestType")?.parse()?,
+            digest: hex_decode(field("Digest")?)?,
+            ttl: u32::MAX,
+        });
+    }
+
+    Ok(anchors)
+}
+
+// lines like ". IN DS 20326 8 2 E06D44B8...", the digest may be split up by spaces
+fn ds_anchors(text: &str) -> Result<Vec<DnsRecord>> {
+    let mut anchors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default();
+        let fields: Vec<&str> = line
+            .split_whitespace()
+            .filter(|field| !matches!(*field, "(" | ")"))
+            .collect();
+        let cut_short = || format!("Line {} of the trust anchors has a DS record cut short", number + 1);
+        let Some(index) = (1..fields.len()).find(|index| fields[*index].eq_ignore_ascii_case("DS")) else {
+            continue;
+        };
+        let [key_tag, algorithm, digest_type, digest @ ..] = &fields[index + 1..] else {
+            return Err(cut_short().into());
+        };
+        if digest.is_empty() {
+            return Err(cut_short().into());
+        }
+
+        anchors.push(DnsRecord::DS {
+            domain: normal(fields[0]),
+            key_tag: key_tag.parse()?,
+            algorithm: algorithm.parse()?,
+            digest_type: digest_type.parse()?,
+            digest: hex_decode(&digest.concat())?,
+            ttl: u32::MAX,
+        });
+    }
+
+    Ok(anchors)
+}
+
+// the text between <name> and </name>
+fn element<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let start = text.find(&format!("<{}>", name))? + name.len() + 2;
+    let length = text[start..].find(&format!("</{}>", name))?;
+
+    Some(text[start..start + length].trim())
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let start = attributes.find(&format!("{}=\"", name))? + name.len() + 2;
+    let length = attributes[start..].find('"')?;
+
+    Some(&attributes[start..start + length])
+}
+
+// seconds since the epoch for an xml dateTime like 2017-02-02T00:00:00+00:00
+fn xml_time(text: &str) -> Result<u64> {
+    let bad = || format!("Bad time {} in the trust anchors", text);
+    let number = |range: core::ops::Range<usize>| {
+        text.get(range)
+            .and_then(|digits| digits.parse::<i64>().ok())
+            .ok_or_else(bad)
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let seconds = number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+    // fractions of a second don't matter, the offset from utc does
+    let zone = text[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let zone_number = |range: core::ops::Range<usize>| {
+        zone.get(range)
+            .and_then(|digits| digits.parse::<i64>().ok())
+            .ok_or_else(bad)
+    };
+    let offset = match zone.as_bytes().first() {
+        None | Some(b'Z') => 0,
+        Some(sign @ (b'+' | b'-')) => {
+            let offset = zone_number(1..3)? * 3600 + zone_number(4..6)? * 60;
+            if *sign == b'+' {
+                offset
+            } else {
+                -offset
+            }
+        }
+        Some(_) => return Err(bad().into()),
+    };
+
+    let time = days_from_civil(year, month, day) * 86_400 + seconds - offset;
+    u64::try_from(time).map_err(|_| bad().into())
+}
+
+// days since 1970-01-01 in the proleptic gregorian calendar, Howard Hinnant's algorithm
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+// what validating a response found out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Security {
+    // every rrset and denial in it checks out all the way up to a trust anchor
+    Secure,
+    // the chain of trust ends at a delegation that's provably unsigned, or at algorithms this crate
+    // can't check, so there's nothing to validate
+    Insecure,
+    // should have been signed and isn't, or the signatures don't check out, the extended error says
+    // which
+    Bogus(ExtendedError),
+}
+
+// why a check couldn't finish, for now or for good
+enum Stop {
+    // the answer for this name and type is needed first
+    Fetch(String, QueryType),
+    // the rrset came without signatures, which only makes it bogus if its zone is signed
+    Unsigned(String),
+    Insecure,
+    Bogus(ExtendedError),
+}
+
+type Check<T> = core::result::Result<T, Stop>;
+
+// the answers fetched so far for the names and types a check asked for
+type Fetched = HashMap<(String, QueryType), DnsPacket>;
+
+fn bogus<T>(code: u16, text: String) -> Check<T> {
+    Err(Stop::Bogus(ExtendedError::new(code).with_text(&text)))
+}
+
+#[derive(Clone, Debug)]
+enum ZoneKeys {
+    Secure(Vec<DnsRecord>),
+    Insecure,
+}
+
+// how a denial of existence turned out
+enum Denial {
+    NxDomain,
+    // delegation is set when the name is the parent side of a zone cut
+    NoData { delegation: bool },
+    // an NSEC3 that skips unsigned delegations covers the name, so it may exist unsigned
+    OptOut,
+}
+
+// checks responses against the chain of trust from the anchors down, fetching the DNSKEY and DS
+// records on the way through whatever the caller resolves with. Keys that checked out are remembered
+// for as long as the records vouching for them live, shared between clones of a resolver
+#[derive(Debug)]
+pub struct Validator {
+    anchors: Vec<DnsRecord>,
+    zones: Mutex<HashMap<String, (ZoneKeys, Instant)>>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new()
+    }
+}
+
+impl Validator {
+    // anchored at the root zone's key signing keys
+    pub fn new() -> Validator {
+        Validator::with_anchors(root_anchors())
+    }
+
+    // DS records of the zones to trust, a zone without one above it is insecure
+    pub fn with_anchors(anchors: Vec<DnsRecord>) -> Validator {
+        Validator {
+            anchors: anchors
+                .into_iter()
+                .filter(|record| matches!(record, DnsRecord::DS { .. }))
+                .collect(),
+            zones: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // anchored at the DS records in a trust anchor file, see parse_trust_anchors
+    pub fn from_file(path: &Path) -> Result<Validator> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read trust anchors from {}: {}", path.display(), e))?;
+
+        Ok(Validator::with_anchors(parse_trust_anchors(&text)?))
+    }
+
+    // fetch looks up the DNSKEY and DS records the chain of trust needs, the way the response was
+    // looked up but with nothing validated
+    pub fn validate(
+        &self,
+        response: &DnsPacket,
+        mut fetch: impl FnMut(&str, QueryType) -> Result<DnsPacket>,
+    ) -> Result<Security> {
+        let mut fetched = Fetched::new();
+        for _ in 0..MAX_FETCHES {
+            match self.check(response, &fetched) {
+                Err(Stop::Fetch(name, qtype)) => {
+                    let packet = fetch(&name, qtype)?;
+                    fetched.insert((name, qtype), packet);
+                }
+                outcome => return Ok(security(outcome)),
+            }
+        }
+
+        Ok(Security::Bogus(too_many_fetches()))
+    }
+
+    #[cfg(feature = "tokio")]
+    pub async fn validate_async<F, T>(&self, response: &DnsPacket, mut fetch: F) -> Result<Security>
+    where
+        F: FnMut(String, QueryType) -> T,
+        T: Future<Output = Result<DnsPacket>>,
+    {
+        let mut fetched = Fetched::new();
+        for _ in 0..MAX_FETCHES {
+            match self.check(response, &fetched) {
+                Err(Stop::Fetch(name, qtype)) => {
+                    let packet = fetch(name.clone(), qtype).await?;
+                    fetched.insert((name, qtype), packet);
+                }
+                outcome => return Ok(security(outcome)),
+            }
+        }
+
+        Ok(Security::Bogus(too_many_fetches()))
+    }
+
+    // every rrset in the answer section, then the proof for the name at the end of the cname chain
+    // if it doesn't have the type. Any one insecure part makes the whole insecure
+    fn check(&self, response: &DnsPacket, fetched: &Fetched) -> Check<()> {
+        let Some(question) = response.questions.first() else {
+            return Err(Stop::Insecure);
+        };
+        // they only ever come along with the rrsets they sign
+        if question.qtype == QueryType::RRSIG {
+            return Err(Stop::Insecure);
+        }
+        // errors other than NXDOMAIN say nothing that could be signed
+        let result_code = response.header.result_code;
+        if !matches!(result_code, ResultCode::NOERROR | ResultCode::NXDOMAIN) {
+            return Err(Stop::Insecure);
+        }
+
+        let mut insecure = false;
+        for rrset in rrsets(&response.answers) {
+            let owner = rrset[0].domain().to_string();
+            match self.verify(&rrset, &response.answers, None, fetched) {
+                Ok(labels) if labels < label_count(&owner) => {
+                    self.check_expansion(&owner, labels, response, fetched)?
+                }
+                Ok(_) => {}
+                Err(Stop::Insecure) => insecure = true,
+                Err(Stop::Unsigned(name)) => match self.zone_security(&owner, fetched) {
+                    Err(Stop::Insecure) => insecure = true,
+                    Err(stop) => return Err(stop),
+                    Ok(()) => return bogus(EDE_RRSIGS_MISSING, format!("No signatures for {}", name)),
+                },
+                Err(stop) => return Err(stop),
+            }
+        }
+
+        let name = chain_end(response, &question.name, question.qtype);
+        let answered = response.answers.iter().any(|record| {
+            normal(record.domain()) == name
+                && (record.query_type() == question.qtype || question.qtype == QueryType::ANY)
+        });
+        if !answered {
+            let proven = response
+                .authorities
+                .iter()
+                .any(|record| matches!(record, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }));
+            if !proven {
+                self.zone_security(&name, fetched)?;
+                let text = format!("Nothing proves {} {} doesn't exist", name, question.qtype);
+                return bogus(EDE_NSEC_MISSING, text);
+            }
+
+            match (
+                result_code,
+                self.denial(&name, question.qtype, &response.authorities, fetched),
+            ) {
+                (ResultCode::NXDOMAIN, Ok(Denial::NxDomain)) => {}
+                (ResultCode::NOERROR, Ok(Denial::NoData { .. })) => {}
+                (_, Ok(Denial::OptOut)) | (_, Err(Stop::Insecure)) => insecure = true,
+                (_, Err(stop)) => return Err(stop),
+                (_, Ok(_)) => {
+                    let text = format!("The denial for {} doesn't match {:?}", name, result_code);
+                    return bogus(EDE_DNSSEC_BOGUS, text);
+                }
+            }
+        }
+
+        if insecure {
+            return Err(Stop::Insecure);
+        }
+
+        Ok(())
+    }
+
+    // checks rrset against a signature by a zone it's in, handing back the RRSIG's label count so
+    // wildcard expansions can be told apart. Signers have to be above below when it's given
+    fn verify(
+        &self,
+        rrset: &[DnsRecord],
+        section: &[DnsRecord],
+        below: Option<&str>,
+        fetched: &Fetched,
+    ) -> Check<usize> {
+        let (owner, qtype) = (normal(rrset[0].domain()), rrset[0].query_type());
+        // a DS rrset belongs to the parent, so it can't be signed by the zone it's for
+        let below = below.or((qtype == QueryType::DS).then_some(owner.as_str()));
+        let signatures = signatures(section, &owner, qtype);
+        if signatures.is_empty() {
+            return Err(Stop::Unsigned(format!("{} {}", owner, qtype)));
+        }
+
+        let mut signers: Vec<String> = signatures.iter().filter_map(|rrsig| signer(rrsig)).collect();
+        signers.sort();
+        signers.dedup();
+
+        let mut last = None;
+        for zone in signers {
+            if !in_zone(&owner, &zone) || below.is_some_and(|name| zone == name || !in_zone(name, &zone)) {
+                continue;
+            }
+
+            let keys = match self.keys_for(&zone, fetched) {
+                Ok(keys) => keys,
+                Err(Stop::Bogus(error)) => {
+                    last = Some(error);
+                    continue;
+                }
+                Err(stop) => return Err(stop),
+            };
+            let by_zone: Vec<&DnsRecord> = signatures
+                .iter()
+                .copied()
+                .filter(|rrsig| signer(rrsig).as_ref() == Some(&zone))
+                .collect();
+            let keys: Vec<&DnsRecord> = keys.iter().collect();
+            match verify_with(rrset, &by_zone, &keys, &zone) {
+                Ok(labels) => return Ok(labels),
+                Err(Stop::Bogus(error)) => last = Some(error),
+                Err(stop) => return Err(stop),
+            }
+        }
+
+        let text = format!("No valid signature for {} {}", owner, qtype);
+        Err(Stop::Bogus(last.unwrap_or_else(|| {
+            ExtendedError::new(EDE_DNSSEC_BOGUS).with_text(&text)
+        })))
+    }
+
+    // the validated DNSKEY rrset of zone: the parent's DS records, or the anchor's, vouch for a key
+    // that signs the rest
+    fn keys_for(&self, zone: &str, fetched: &Fetched) -> Check<Vec<DnsRecord>> {
+        if let Some(keys) = self.cached(zone) {
+            return match keys {
+                ZoneKeys::Secure(keys) => Ok(keys),
+                ZoneKeys::Insecure => Err(Stop::Insecure),
+            };
+        }
+
+        let anchored: Vec<DnsRecord> = self
+            .anchors
+            .iter()
+            .filter(|ds| normal(ds.domain()) == zone)
+            .cloned()
+            .collect();
+        let outcome = if anchored.is_empty() {
+            self.delegation(zone, fetched)
+                .and_then(|ds| self.zone_keys(zone, &ds, fetched))
+        } else {
+            self.zone_keys(zone, &anchored, fetched)
+        };
+
+        match &outcome {
+            Ok(keys) => self.remember(zone, ZoneKeys::Secure(keys.clone()), keys),
+            Err(Stop::Insecure) => self.remember(zone, ZoneKeys::Insecure, &[]),
+            Err(_) => {}
+        }
+
+        outcome
+    }
+
+    // the DS rrset for zone as its parent signed it, insecure when the parent proves there is none
+    fn delegation(&self, zone: &str, fetched: &Fetched) -> Check<Vec<DnsRecord>> {
+        if self.anchor_above(zone).is_none() {
+            return Err(Stop::Insecure);
+        }
+
+        let response = need(fetched, zone, QueryType::DS)?;
+        let ds = rrset(&response.answers, zone, QueryType::DS);
+        if ds.is_empty() {
+            return match self.denial(zone, QueryType::DS, &response.authorities, fetched)? {
+                Denial::NxDomain => bogus(EDE_DNSSEC_BOGUS, format!("The signer {} doesn't exist", zone)),
+                Denial::NoData { .. } | Denial::OptOut => Err(Stop::Insecure),
+            };
+        }
+
+        match self.verify(&ds, &response.answers, None, fetched) {
+            Ok(_) => Ok(ds),
+            // unsigned DS records are only fine below an unsigned parent
+            Err(Stop::Unsigned(name)) => {
+                self.zone_security(&parent(zone), fetched)?;
+                bogus(EDE_RRSIGS_MISSING, format!("No signatures for {}", name))
+            }
+            Err(stop) => Err(stop),
+        }
+    }
+
+    // the DNSKEY rrset of zone, as long as a key matching one of ds signed it
+    fn zone_keys(&self, zone: &str, ds: &[DnsRecord], fetched: &Fetched) -> Check<Vec<DnsRecord>> {
+        // RFC 4035 treats a zone whose DS records all use algorithms it can't check as unsigned
+        let usable: Vec<&DnsRecord> = ds.iter().filter(|ds| supported_ds(ds)).collect();
+        if usable.is_empty() {
+            return Err(Stop::Insecure);
+        }
+
+        let response = need(fetched, zone, QueryType::DNSKEY)?;
+        let keys = rrset(&response.answers, zone, QueryType::DNSKEY);
+        let trusted: Vec<&DnsRecord> = keys
+            .iter()
+            .filter(|key| usable.iter().any(|ds| ds_matches(ds, key, zone)))
+            .collect();
+        if trusted.is_empty() {
+            let text = format!("No DNSKEY of {} matches its DS records", zone_name(zone));
+            return bogus(EDE_DNSKEY_MISSING, text);
+        }
+
+        let signatures = signatures(&response.answers, zone, QueryType::DNSKEY);
+        verify_with(&keys, &signatures, &trusted, zone)?;
+
+        Ok(keys)
+    }
+
+    // whether name is in a signed zone, walking down from the anchor above it one label at a time
+    // and asking for DS records at each until a delegation turns out to be unsigned
+    fn zone_security(&self, name: &str, fetched: &Fetched) -> Check<()> {
+        let Some(anchor) = self.anchor_above(name) else {
+            return Err(Stop::Insecure);
+        };
+        self.keys_for(&anchor, fetched)?;
+
+        let name = normal(name);
+        for child in descendants(&anchor, &name) {
+            match self.cached(&child) {
+                Some(ZoneKeys::Insecure) => return Err(Stop::Insecure),
+                Some(ZoneKeys::Secure(_)) => continue,
+                None => {}
+            }
+
+            let response = need(fetched, &child, QueryType::DS)?;
+            if !rrset(&response.answers, &child, QueryType::DS).is_empty() {
+                self.keys_for(&child, fetched)?;
+                continue;
+            }
+            match self.denial(&child, QueryType::DS, &response.authorities, fetched)? {
+                Denial::NoData { delegation: true } | Denial::OptOut => {
+                    self.remember(&child, ZoneKeys::Insecure, &[]);
+                    return Err(Stop::Insecure);
+                }
+                // not a zone cut, the zone goes on below it
+                Denial::NoData { delegation: false } => {}
+                // nothing exists below a name that doesn't
+                Denial::NxDomain => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    // what the NSEC or NSEC3 records in authorities prove about name and qtype, each of them has to
+    // be signed
+    fn denial(&self, name: &str, qtype: QueryType, authorities: &[DnsRecord], fetched: &Fetched) -> Check<Denial> {
+        // the child's own records can't deny it has DS records, only its parent's
+        let below = (qtype == QueryType::DS).then_some(name);
+        let mut nsecs = Vec::new();
+        let mut nsec3s = Vec::new();
+        for record in authorities {
+            let list = match record {
+                DnsRecord::NSEC { .. } => &mut nsecs,
+                DnsRecord::NSEC3 { .. } => &mut nsec3s,
+                _ => continue,
+            };
+            match self.verify(core::slice::from_ref(record), authorities, below, fetched) {
+                Ok(_) => list.push(record),
+                Err(Stop::Unsigned(what)) => return bogus(EDE_RRSIGS_MISSING, format!("No signatures for {}", what)),
+                Err(stop) => return Err(stop),
+            }
+        }
+
+        let name = normal(name);
+        if !nsecs.is_empty() {
+            return nsec_denial(&name, qtype, &nsecs);
+        }
+        if !nsec3s.is_empty() {
+            return nsec3_denial(&name, qtype, &nsec3s);
+        }
+
+        bogus(
+            EDE_NSEC_MISSING,
+            format!("Nothing proves {} {} doesn't exist", name, qtype),
+        )
+    }
+
+    // an answer synthesized from a wildcard also needs proof that the name asked for doesn't exist,
+    // otherwise a wildcard could stand in for a real name
+    fn check_expansion(&self, owner: &str, labels: usize, response: &DnsPacket, fetched: &Fetched) -> Check<()> {
+        let owner = normal(owner);
+        let authorities = &response.authorities;
+        let mut proofs = Vec::new();
+        for record in authorities {
+            if !matches!(record, DnsRecord::NSEC { .. } | DnsRecord::NSEC3 { .. }) {
+                continue;
+            }
+            match self.verify(core::slice::from_ref(record), authorities, None, fetched) {
+                Ok(_) => proofs.push(record),
+                Err(Stop::Unsigned(_)) => {}
+                Err(stop) => return Err(stop),
+            }
+        }
+
+        let next_closer = suffix(&owner, labels + 1);
+        let proven = proofs.iter().any(|record| match record {
+            DnsRecord::NSEC {
+                domain, next_domain, ..
+            } => covers(&normal(domain), &normal(next_domain), &owner),
+            DnsRecord::NSEC3 { .. } => nsec3_covers(record, &next_closer).is_some(),
+            _ => false,
+        });
+        if !proven {
+            let text = format!("Nothing proves {} isn't there besides the wildcard", owner);
+            return bogus(EDE_NSEC_MISSING, text);
+        }
+
+        Ok(())
+    }
+
+    fn anchor_above(&self, name: &str) -> Option<String> {
+        self.anchors
+            .iter()
+            .map(|ds| normal(ds.domain()))
+            .filter(|zone| in_zone(name, zone))
+            .max_by_key(|zone| label_count(zone))
+    }
+
+    fn cached(&self, zone: &str) -> Option<ZoneKeys> {
+        let zones = lock(&self.zones);
+        let (keys, expires) = zones.get(zone)?;
+
+        (Instant::now() < *expires).then(|| keys.clone())
+    }
+
+    // until the shortest lived of records expires, an hour if there are none to go by
+    fn remember(&self, zone: &str, keys: ZoneKeys, records: &[DnsRecord]) {
+        let ttl = records.iter().map(DnsRecord::ttl).min().unwrap_or(3600);
+        let expires = Instant::now() + Duration::from_secs(u64::from(ttl.min(86_400)));
+
+        let mut zones = lock(&self.zones);
+        if zones.len() >= MAX_ZONES {
+            let now = Instant::now();
+            zones.retain(|_, (_, expires)| *expires > now);
+        }
+        zones.insert(zone.to_string(), (keys, expires));
+    }
+}
+
+fn security(outcome: Check<()>) -> Security {
+    match outcome {
+        Ok(()) => Security::Secure,
+        Err(Stop::Insecure) => Security::Insecure,
+        Err(Stop::Bogus(error)) => Security::Bogus(error),
+        Err(Stop::Unsigned(what)) => {
+            let text = format!("No signatures for {}", what);
+            Security::Bogus(ExtendedError::new(EDE_RRSIGS_MISSING).with_text(&text))
+        }
+        Err(Stop::Fetch(name, qtype)) => Security::Bogus(
+            ExtendedError::new(EDE_DNSKEY_MISSING).with_text(&format!("Couldn't fetch {} {}", name, qtype)),
+        ),
+    }
+}
+
+fn too_many_fetches() -> ExtendedError {
+    ExtendedError::new(EDE_DNSSEC_BOGUS).with_text("Too many keys to fetch")
+}
+
+fn need<'a>(fetched: &'a Fetched, name: &str, qtype: QueryType) -> Check<&'a DnsPacket> {
+    fetched
+        .get(&(name.to_string(), qtype))
+        .ok_or_else(|| Stop::Fetch(name.to_string(), qtype))
+}
+
+// the nsec that proves name doesn't exist has to show there's no matching wildcard either, since the
+// answer would have come from it
+fn nsec_denial(name: &str, qtype: QueryType, nsecs: &[&DnsRecord]) -> Check<Denial> {
+    let matching = nsecs.iter().find(|nsec| normal(nsec.domain()) == name);
+    if let Some(DnsRecord::NSEC { types, .. }) = matching {
+        return no_data(name, qtype, types);
+    }
+
+    // an nsec from the parent side of a delegation above name says nothing about what's below it
+    let usable = |nsec: &&&DnsRecord| match nsec {
+        DnsRecord::NSEC { domain, types, .. } => {
+            let owner = normal(domain);
+            owner == name || !in_zone(name, &owner) || !delegates(types)
+        }
+        _ => false,
+    };
+    let covering = nsecs.iter().filter(usable).find_map(|nsec| match nsec {
+        DnsRecord::NSEC {
+            domain, next_domain, ..
+        } => {
+            let (owner, next) = (normal(domain), normal(next_domain));
+            covers(&owner, &next, name).then_some((owner, next))
+        }
+        _ => None,
+    });
+    let Some((owner, next)) = covering else {
+        return bogus(EDE_NSEC_MISSING, format!("No NSEC covers {}", name));
+    };
+    // names only exist below an empty non-terminal, there's nothing at it to deny
+    if next != name && in_zone(&next, name) {
+        return Ok(Denial::NoData { delegation: false });
+    }
+
+    let encloser = [common_ancestor(name, &owner), common_ancestor(name, &next)]
+        .into_iter()
+        .max_by_key(|ancestor| label_count(ancestor))
+        .unwrap_or_default();
+    let wildcard = child(&encloser, "*");
+    for nsec in nsecs {
+        let DnsRecord::NSEC {
+            domain,
+            next_domain,
+            types,
+            ..
+        } = nsec
+        else {
+            continue;
+        };
+        if normal(domain) == wildcard {
+            return no_data(&wildcard, qtype, types);
+        }
+        if covers(&normal(domain), &normal(next_domain), &wildcard) {
+            return Ok(Denial::NxDomain);
+        }
+    }
+
+    bogus(EDE_NSEC_MISSING, format!("No NSEC covers the wildcard {}", wildcard))
+}
+
+// RFC 5155's closest encloser proof: the nearest ancestor of name that exists, the name one label
+// below it toward name covered so it doesn't, and its wildcard covered too
+fn nsec3_denial(name: &str, qtype: QueryType, nsec3s: &[&DnsRecord]) -> Check<Denial> {
+    for nsec3 in nsec3s {
+        let DnsRecord::NSEC3 {
+            hash_algorithm,
+            iterations,
+            ..
+        } = nsec3
+        else {
+            continue;
+        };
+        if *hash_algorithm != 1 || *iterations > MAX_NSEC3_ITERATIONS {
+            return Err(Stop::Insecure);
+        }
+    }
+
+    let matching = |name: &str| nsec3s.iter().copied().find(|nsec3| nsec3_matches(nsec3, name));
+    let covering = |name: &str| nsec3s.iter().find_map(|nsec3| nsec3_covers(nsec3, name));
+
+    if let Some(DnsRecord::NSEC3 { types, .. }) = matching(name) {
+        return no_data(name, qtype, types);
+    }
+
+    let ancestors = (0..label_count(name)).rev().map(|labels| suffix(name, labels));
+    for encloser in ancestors {
+        let Some(DnsRecord::NSEC3 { types, .. }) = matching(&encloser) else {
+            continue;
+        };
+        if delegates(types) {
+            return bogus(EDE_DNSSEC_BOGUS, format!("{} is delegated away", encloser));
+        }
+
+        let next_closer = suffix(name, label_count(&encloser) + 1);
+        let Some(opt_out) = covering(&next_closer) else {
+            return bogus(EDE_NSEC_MISSING, format!("No NSEC3 covers {}", next_closer));
+        };
+        if opt_out {
+            return Ok(Denial::OptOut);
+        }
+
+        let wildcard = child(&encloser, "*");
+        if covering(&wildcard).is_some() {
+            return Ok(Denial::NxDomain);
+        }
+        if let Some(DnsRecord::NSEC3 { types, .. }) = matching(&wildcard) {
+            return no_data(&wildcard, qtype, types);
+        }
+        return bogus(EDE_NSEC_MISSING, format!("No NSEC3 covers the wildcard {}", wildcard));
+    }
+
+    bogus(EDE_NSEC_MISSING, format!("No NSEC3 proves an encloser of {}", name))
+}
+
+// an nsec or nsec3 at name proves the type isn't there if neither it nor a cname is in its bitmap
+fn no_data(name: &str, qtype: QueryType, types: &[QueryType]) -> Check<Denial> {
+    if types.contains(&qtype) || (qtype != QueryType::CNAME && types.contains(&QueryType::CNAME)) {
+        return bogus(EDE_DNSSEC_BOGUS, format!("The NSEC for {} says {} exists", name, qtype));
+    }
+
+    Ok(Denial::NoData {
+        delegation: delegates(types),
+    })
+}
+
+// NS without SOA is the parent side of a zone cut
+fn delegates(types: &[QueryType]) -> bool {
+    types.contains(&QueryType::NS) && !types.contains(&QueryType::SOA)
+}
+
+fn nsec3_matches(nsec3: &DnsRecord, name: &str) -> bool {
+    match nsec3_hashes(nsec3, name) {
+        Some((owner, hash, _)) => owner == hash,
+        None => false,
+    }
+}
+
+// Some with the opt-out flag when nsec3 covers the hash of name
+fn nsec3_covers(nsec3: &DnsRecord, name: &str) -> Option<bool> {
+    let (owner, hash, next) = nsec3_hashes(nsec3, name)?;
+    let DnsRecord::NSEC3 { flags, .. } = nsec3 else {
+        return None;
+    };
+
+    let covered = if owner < next {
+        owner < hash && hash < next
+    } else {
+        owner < hash || hash < next
+    };
+    covered.then_some(flags & 1 == 1)
+}
+
+// the nsec3's own hash, the hash of name with its parameters and the next hash, all in base32hex,
+// whose order is the order of the hashes. None when name isn't in the nsec3's zone
+fn nsec3_hashes(nsec3: &DnsRecord, name: &str) -> Option<(String, String, String)> {
+    let DnsRecord::NSEC3 {
+        domain,
+        iterations,
+        salt,
+        next_hashed,
+        ..
+    } = nsec3
+    else {
+        return None;
+    };
+    let domain = normal(domain);
+    let (owner, zone) = domain.split_once('.').unwrap_or((&domain, ""));
+    if !in_zone(name, zone) {
+        return None;
+    }
+
+    let hash = nsec3_hash(name, salt, *iterations)?;
+    Some((
+        owner.to_string(),
+        hash,
+        base32hex_encode(next_hashed).to_ascii_lowercase(),
+    ))
+}
+
+// RFC 5155's iterated SHA-1 of the name's wire form, None for a name that has no wire form
+fn nsec3_hash(name: &str, salt: &[u8], iterations: u16) -> Option<String> {
+    let sha1 = &digest::SHA1_FOR_LEGACY_USE_ONLY;
+    let mut hash = digest::digest(sha1, &[name_wire(name).ok()?, salt.to_vec()].concat());
+    for _ in 0..iterations {
+        hash = digest::digest(sha1, &[hash.as_ref(), salt].concat());
+    }
+
+    Some(base32hex_encode(hash.as_ref()).to_ascii_lowercase())
+}
+
+// whether the nsec from owner to next covers name, the last one in a zone wraps around to its apex
+fn covers(owner: &str, next: &str, name: &str) -> bool {
+    let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+    let before_next = canonical_cmp(name, next) == Ordering::Less;
+
+    match canonical_cmp(owner, next) {
+        Ordering::Less => after_owner && before_next,
+        _ => after_owner || before_next,
+    }
+}
+
+// RFC 4034's canonical order, label by label from the right with lowercase bytes compared
+fn canonical_cmp(a: &str, b: &str) -> Ordering {
+    let labels = |name: &str| -> Vec<Vec<u8>> {
+        name.split('.')
+            .filter(|label| !label.is_empty())
+            .rev()
+            .map(|label| label.to_ascii_lowercase().into_bytes())
+            .collect()
+    };
+
+    labels(a).cmp(&labels(b))
+}
+
+// tries the signatures over rrset that one of keys made, the first one that checks out wins. Otherwise
+// the reason the closest one failed
+fn verify_with(rrset: &[DnsRecord], signatures: &[&DnsRecord], keys: &[&DnsRecord], zone: &str) -> Check<usize> {
+    let now = now();
+    let mut reason =
+        ExtendedError::new(EDE_DNSKEY_MISSING).with_text(&format!("No key of {} made the signatures", zone_name(zone)));
+
+    for rrsig in signatures {
+        let DnsRecord::RRSIG {
+            algorithm,
+            labels,
+            expiration,
+            inception,
+            key_tag,
+            signature,
+            ..
+        } = rrsig
+        else {
+            continue;
+        };
+        if (now.wrapping_sub(*inception) as i32) < 0 {
+            let text = format!("Signed for after {}", inception);
+            reason = ExtendedError::new(EDE_SIGNATURE_NOT_YET_VALID).with_text(&text);
+            continue;
+        }
+        if (expiration.wrapping_sub(now) as i32) < 0 {
+            let text = format!("Expired at {}", expiration);
+            reason = ExtendedError::new(EDE_SIGNATURE_EXPIRED).with_text(&text);
+            continue;
+        }
+        let Some(data) = signed_data(rrsig, rrset) else {
+            continue;
+        };
+
+        for key in keys {
+            let DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm: key_algorithm,
+                public_key,
+                ..
+            } = key
+            else {
+                continue;
+            };
+            if key_algorithm != algorithm || *protocol != DNSSEC_PROTOCOL || flags & ZONE_KEY == 0 {
+                continue;
+            }
+            if key_tag_of(key) != Some(*key_tag) {
+                continue;
+            }
+            if verify_signature(*algorithm, public_key, &data, signature) {
+                return Ok(usize::from(*labels));
+            }
+            reason = ExtendedError::new(EDE_DNSSEC_BOGUS).with_text(&format!(
+                "Bad signature over {} {}",
+                rrset[0].domain(),
+                rrset[0].query_type()
+            ));
+        }
+    }
+
+    Err(Stop::Bogus(reason))
+}
+
+fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let rsa = match algorithm {
+        5 | 7 => Some(&signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY),
+        8 => Some(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY),
+        10 => Some(&signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY),
+        _ => None,
+    };
+    if let Some(parameters) = rsa {
+        let Some((exponent, modulus)) = rsa_key(public_key) else {
+            return false;
+        };
+        let key = signature::RsaPublicKeyComponents {
+            n: modulus,
+            e: exponent,
+        };
+        return key.verify(parameters, data, signature).is_ok();
+    }
+
+    // ecdsa keys are the bare point, ring wants it uncompressed with the 0x04 prefix
+    let point = [&[4], public_key].concat();
+    let (parameters, key): (&dyn signature::VerificationAlgorithm, &[u8]) = match algorithm {
+        13 => (&signature::ECDSA_P256_SHA256_FIXED, &point),
+        14 => (&signature::ECDSA_P384_SHA384_FIXED, &point),
+        15 => (&signature::ED25519, public_key),
+        _ => return false,
+    };
+
+    signature::UnparsedPublicKey::new(parameters, key)
+        .verify(data, signature)
+        .is_ok()
+}
+
+// RFC 3110: the exponent's length in one byte, or in two after a zero, then the exponent and the
+// modulus. Leading zeros are left off the modulus since ring won't take them
+fn rsa_key(public_key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (length, rest) = match public_key {
+        [0, high, low, rest @ ..] => (usize::from(u16::from_be_bytes([*high, *low])), rest),
+        [length, rest @ ..] => (usize::from(*length), rest),
+        [] => return None,
+    };
+    if length == 0 || rest.len() <= length {
+        return None;
+    }
+    let (exponent, modulus) = rest.split_at(length);
+    let zeros = modulus.iter().take_while(|byte| **byte == 0).count();
+
+    Some((exponent, &modulus[zeros..]))
+}
+
+fn supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, 5 | 7 | 8 | 10 | 13 | 14 | 15)
+}
+
+fn supported_ds(ds: &DnsRecord) -> bool {
+    match ds {
+        DnsRecord::DS {
+            algorithm, digest_type, ..
+        } => supported_algorithm(*algorithm) && ds_digest(*digest_type).is_some(),
+        _ => false,
+    }
+}
+
+fn ds_digest(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        2 => Some(&digest::SHA256),
+        4 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+// a DS is the digest of the owner's name and the DNSKEY's rdata
+fn ds_matches(ds: &DnsRecord, key: &DnsRecord, zone: &str) -> bool {
+    let DnsRecord::DS {
+        key_tag,
+        algorithm,
+        digest_type,
+        digest,
+        ..
+    } = ds
+    else {
+        return false;
+    };
+    let (Some(rdata), Some(hash)) = (dnskey_rdata(key), ds_digest(*digest_type)) else {
+        return false;
+    };
+    let DnsRecord::DNSKEY {
+        algorithm: key_algorithm,
+        ..
+    } = key
+    else {
+        return false;
+    };
+    if algorithm != key_algorithm || key_tag_of(key) != Some(*key_tag) {
+        return false;
+    }
+
+    let Ok(owner) = name_wire(zone) else {
+        return false;
+    };
+
+    digest::digest(hash, &[owner, rdata].concat()).as_ref() == digest.as_slice()
+}
+
+fn dnskey_rdata(key: &DnsRecord) -> Option<Vec<u8>> {
+    let DnsRecord::DNSKEY {
+        flags,
+        protocol,
+        algorithm,
+        public_key,
+        ..
+    } = key
+    else {
+        return None;
+    };
+
+    Some([&flags.to_be_bytes()[..], &[*protocol, *algorithm], public_key].concat())
+}
+
+// the checksum from RFC 4034's appendix B that RRSIG and DS records pick their key by
+fn key_tag_of(key: &DnsRecord) -> Option<u16> {
+    let rdata = dnskey_rdata(key)?;
+    let mut sum: u32 = 0;
+    for (index, byte) in rdata.iter().enumerate() {
+        sum += if index % 2 == 0 {
+            u32::from(*byte) << 8
+        } else {
+            u32::from(*byte)
+        };
+    }
+    sum += (sum >> 16) & 0xFFFF;
+
+    Some((sum & 0xFFFF) as u16)
+}
+
+// what an RRSIG signs: its own rdata up to the signature, then every record of the rrset in
+// canonical form and order, with the original ttl and a wildcard owner if it was expanded from one
+fn signed_data(rrsig: &DnsRecord, rrset: &[DnsRecord]) -> Option<Vec<u8>> {
+    let DnsRecord::RRSIG {
+        type_covered,
+        algorithm,
+        labels,
+        original_ttl,
+        expiration,
+        inception,
+        key_tag,
+        signer_name,
+        ..
+    } = rrsig
+    else {
+        return None;
+    };
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&type_covered.to_num().to_be_bytes());
+    data.extend_from_slice(&[*algorithm, *labels]);
+    data.extend_from_slice(&original_ttl.to_be_bytes());
+    data.extend_from_slice(&expiration.to_be_bytes());
+    data.extend_from_slice(&inception.to_be_bytes());
+    data.extend_from_slice(&key_tag.to_be_bytes());
+    data.extend(name_wire(signer_name).ok()?);
+
+    let owner = normal(rrset.first()?.domain());
+    let labels = usize::from(*labels);
+    let owner = match label_count(&owner).cmp(&labels) {
+        Ordering::Less => return None,
+        Ordering::Equal => owner,
+        Ordering::Greater => child(&suffix(&owner, labels), "*"),
+    };
+
+    let mut rdatas = rrset.iter().map(canonical_rdata).collect::<Option<Vec<_>>>()?;
+    rdatas.sort();
+    rdatas.dedup();
+    let owner = name_wire(&owner).ok()?;
+    for rdata in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&type_covered.to_num().to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+
+    Some(data)
+}
+
+// the record's rdata written without compression, the names in it lowercased for the types RFC 4034
+// and 6840 say to
+fn canonical_rdata(record: &DnsRecord) -> Option<Vec<u8>> {
+    let mut record = record.clone();
+    match &mut record {
+        DnsRecord::NS { host, .. }
+        | DnsRecord::CNAME { host, .. }
+        | DnsRecord::PTR { host, .. }
+        | DnsRecord::MX { host, .. }
+        | DnsRecord::SRV { host, .. } => host.make_ascii_lowercase(),
+        DnsRecord::SOA { mname, rname, .. } => {
+            mname.make_ascii_lowercase();
+            rname.make_ascii_lowercase();
+        }
+        DnsRecord::NAPTR { replacement, .. } => replacement.make_ascii_lowercase(),
+        DnsRecord::RRSIG { signer_name, .. } => signer_name.make_ascii_lowercase(),
+        _ => {}
+    }
+
+    let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE).without_compression();
+    record.write(&mut buffer).ok()?;
+    // past the owner, type, class, ttl and rdata length
+    let start = name_wire(record.domain()).ok()?.len() + 10;
+
+    buffer.buffer.get(start..buffer.pos()).map(<[u8]>::to_vec)
+}
+
+// the records of section grouped into rrsets, signatures left out, in the order they first appear
+fn rrsets(section: &[DnsRecord]) -> Vec<Vec<DnsRecord>> {
+    let mut sets: Vec<Vec<DnsRecord>> = Vec::new();
+    for record in section {
+        let qtype = record.query_type();
+        if qtype == QueryType::RRSIG || qtype == QueryType::OPT {
+            continue;
+        }
+        let owner = normal(record.domain());
+        match sets
+            .iter_mut()
+            .find(|set| set[0].query_type() == qtype && normal(set[0].domain()) == owner)
+        {
+            Some(set) => set.push(record.clone()),
+            None => sets.push(vec![record.clone()]),
+        }
+    }
+
+    sets
+}
+
+fn rrset(section: &[DnsRecord], name: &str, qtype: QueryType) -> Vec<DnsRecord> {
+    section
+        .iter()
+        .filter(|record| record.query_type() == qtype && normal(record.domain()) == name)
+        .cloned()
+        .collect()
+}
+
+fn signatures<'a>(section: &'a [DnsRecord], name: &str, qtype: QueryType) -> Vec<&'a DnsRecord> {
+    section
+        .iter()
+        .filter(|record| match record {
+            DnsRecord::RRSIG {
+                domain, type_covered, ..
+            } => *type_covered == qtype && normal(domain) == name,
+            _ => false,
+        })
+        .collect()
+}
+
+fn signer(rrsig: &DnsRecord) -> Option<String> {
+    match rrsig {
+        DnsRecord::RRSIG { signer_name, .. } => Some(normal(signer_name)),
+        _ => None,
+    }
+}
+
+// follows the cnames from qname to the name that should have the type
+fn chain_end(response: &DnsPacket, qname: &str, qtype: QueryType) -> String {
+    let mut name = normal(qname);
+    if qtype == QueryType::CNAME {
+        return name;
+    }
+
+    for _ in 0..response.answers.len() {
+        let target = response.answers.iter().find_map(|record| match record {
+            DnsRecord::CNAME { domain, host, .. } if normal(domain) == name => Some(normal(host)),
+            _ => None,
+        });
+        match target {
+            Some(target) => name = target,
+            None => break,
+        }
+    }
+
+    name
+}
+
+fn zone_name(zone: &str) -> &str {
+    if zone.is_empty() {
+        "the root"
+    } else {
+        zone
+    }
+}
+
+fn parent(name: &str) -> String {
+    name.split_once('.')
+        .map(|(_, parent)| parent.to_string())
+        .unwrap_or_default()
+}
+
+fn child(name: &str, label: &str) -> String {
+    if name.is_empty() {
+        label.to_string()
+    } else {
+        format!("{}.{}", label, name)
+    }
+}
+
+// the last labels labels of name
+fn suffix(name: &str, labels: usize) -> String {
+    let all: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+    all[all.len().saturating_sub(labels)..].join(".")
+}
+
+fn common_ancestor(a: &str, b: &str) -> String {
+    let shared = (0..=label_count(a).min(label_count(b)))
+        .rev()
+        .find(|labels| suffix(a, *labels) == suffix(b, *labels))
+        .unwrap_or(0);
+
+    suffix(a, shared)
+}
+
+// the names between zone and name, name included, from the top down
+fn descendants(zone: &str, name: &str) -> Vec<String> {
+    (label_count(zone) + 1..=label_count(name))
+        .map(|labels| suffix(name, labels))
+        .collect()
+}
+
+// RRSIG times are serial numbers, so the clock wraps with them
+fn now() -> u32 {
+    unix_time() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::base64_decode;
+
+    #[test]
+    fn key_tags_match_the_published_ones() {
+        // the root zone's KSK-2017, which the root anchors list with key tag 20326
+        let public_key = base64_decode(concat!(
+            "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN",
+            "7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8",
+            "efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLY",
+            "A4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+        ))
+        .unwrap();
+        let ksk = DnsRecord::DNSKEY {
+            domain: String::new(),
+            flags: 257,
+            protocol: 3,
+            algorithm: 8,
+            public_key,
+            ttl: 172800,
+        };
+
+        assert_eq!(key_tag_of(&ksk), Some(20326));
+        let address = DnsRecord::A {
+            domain: String::new(),
+            address: [0; 4].into(),
+            ttl: 0,
+        };
+        assert_eq!(key_tag_of(&address), None);
+    }
+
+    #[test]
+    fn nsec3_hashes_match_rfc_5155() {
+        // appendix A's zone, hashed with salt aabbccdd and 12 more iterations
+        let salt = [0xaa, 0xbb, 0xcc, 0xdd];
+        for (name, hash) in [
+            ("example", "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"),
+            ("a.example", "35mthgpgcu1qg68fab165klnsnk3dpvl"),
+            ("ai.example", "gjeqe526plbf1g8mklp59enfd789njgi"),
+            ("ns1.example", "2t7b4g4vsa5smi47k61mv5bv1a22bojr"),
+            ("ns2.example", "q04jkcevqvmu85r014c7dkba38o0ji5r"),
+            ("w.example", "k8udemvp1j2f7eg6jebps17vp3n8i58h"),
+            ("*.w.example", "r53bq7cc2uvmubfu5ocmm6pers9tk9en"),
+            ("x.w.example", "b4um86eghhds6nea196smvmlo4ors995"),
+            ("y.w.example", "ji6neoaepv8b5o6k4ev33abha8ht9fgc"),
+            ("x.y.w.example", "2vptu5timamqttgl4luu9kg21e0aor3s"),
+            ("xx.example", "t644ebqk9bibcna874givr6joj62mlhv"),
+        ] {
+            assert_eq!(nsec3_hash(name, &salt, 12).as_deref(), Some(hash), "{}", name);
+            assert_eq!(nsec3_hash(&name.to_ascii_uppercase(), &salt, 12).as_deref(), Some(hash));
+        }
+        assert_eq!(nsec3_hash(&"a".repeat(64), &salt, 12), None);
+    }
+
+    #[test]
+    fn names_sort_the_way_rfc_4034_does() {
+        // section 6.1's example, in canonical order. \001 and \200 are the bytes, which come off the
+        // wire the way from_utf8_lossy reads them
+        let names = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "\u{1}.z.example",
+            "*.z.example",
+            "\u{fffd}.z.example",
+        ];
+        for (index, a) in names.iter().enumerate() {
+            for (other, b) in names.iter().enumerate() {
+                assert_eq!(canonical_cmp(a, b), index.cmp(&other), "{} against {}", a, b);
+            }
+        }
+        assert_eq!(canonical_cmp("Z.a.example.", "z.A.example"), Ordering::Equal);
+
+        assert!(covers("a.example", "z.example", "yljkjljk.a.example"));
+        assert!(covers("a.example", "z.example", "zABC.a.EXAMPLE"));
+        assert!(!covers("a.example", "z.example", "a.example"));
+        assert!(!covers("a.example", "z.example", "z.example"));
+        assert!(!covers("a.example", "z.example", "\u{1}.z.example"));
+        // the last nsec of the zone points back at the apex, covering everything after its owner
+        assert!(covers("z.example", "example", "\u{fffd}.z.example"));
+        assert!(!covers("z.example", "example", "a.example"));
+        assert!(!covers("z.example", "example", "example"));
+    }
+
+    // RFC 8080's first example, an MX rrset signed with Ed25519
+    fn rfc_8080() -> (DnsRecord, Vec<DnsRecord>, DnsRecord) {
+        let key = DnsRecord::DNSKEY {
+            domain: "example.com".to_string(),
+            flags: 257,
+            protocol: 3,
+            algorithm: 15,
+            public_key: base64_decode("l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=").unwrap(),
+            ttl: 3600,
+        };
+        let rrset = vec![DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 3600,
+        }];
+        let rrsig = DnsRecord::RRSIG {
+            domain: "example.com".to_string(),
+            type_covered: QueryType::MX,
+            algorithm: 15,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 1440021600,
+            inception: 1438207200,
+            key_tag: 3613,
+            signer_name: "example.com".to_string(),
+            signature: base64_decode(
+                "oL9krJun7xfBOIWcGHi7mag5/hdZrKWw15jPGrHpjQeRAvTdszaPD+QLs3fx8A4M3e23mRZ9VrbpMngwcrqNAg==",
+            )
+            .unwrap(),
+            ttl: 3600,
+        };
+
+        (key, rrset, rrsig)
+    }
+
+    #[test]
+    fn signed_rrsets_verify() {
+        let (key, rrset, rrsig) = rfc_8080();
+        assert_eq!(key_tag_of(&key), Some(3613));
+        let DnsRecord::DNSKEY { public_key, .. } = &key else {
+            unreachable!()
+        };
+        let DnsRecord::RRSIG { signature, .. } = &rrsig else {
+            unreachable!()
+        };
+
+        let data = signed_data(&rrsig, &rrset).unwrap();
+        assert!(verify_signature(15, public_key, &data, signature));
+        // names are signed lowercased and the ttl the rrset was signed with is what counts
+        let shouting = vec![DnsRecord::MX {
+            domain: "EXAMPLE.com".to_string(),
+            priority: 10,
+            host: "Mail.Example.COM".to_string(),
+            ttl: 1200,
+        }];
+        assert_eq!(signed_data(&rrsig, &shouting).unwrap(), data);
+
+        for index in 0..data.len() {
+            let mut flipped = data.clone();
+            flipped[index] ^= 1;
+            assert!(!verify_signature(15, public_key, &flipped, signature), "byte {}", index);
+        }
+        let mut flipped = signature.clone();
+        flipped[0] ^= 1;
+        assert!(!verify_signature(15, public_key, &data, &flipped));
+        let mut other = rrset.clone();
+        other.push(DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 20,
+            host: "backup.example.com".to_string(),
+            ttl: 3600,
+        });
+        assert!(!verify_signature(
+            15,
+            public_key,
+            &signed_data(&rrsig, &other).unwrap(),
+            signature
+        ));
+
+        // the signature ran out in 2015, which verify_with checks before the signature itself
+        match verify_with(&rrset, &[&rrsig], &[&key], "example.com") {
+            Err(Stop::Bogus(reason)) => assert_eq!(reason.info_code, EDE_SIGNATURE_EXPIRED),
+            _ => panic!("An expired signature was taken"),
+        }
+    }
+}
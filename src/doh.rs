@@ -0,0 +1,102 @@
+use std::{io::Read, time::Duration};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    client::{build_query, encode, DEFAULT_TIMEOUT},
+    encoding::base64url_encode,
+    parse_bytes,
+    tls::padded,
+    DnsPacket, QueryType, Result,
+};
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+// how RFC 8484 lets a query travel inside the request
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DohMethod {
+    // the query as the request body
+    Post,
+    // the query base64url encoded in the dns parameter, which http caches can work with
+    Get,
+}
+
+// queries a DNS over HTTPS endpoint like https://cloudflare-dns.com/dns-query, the agent keeps
+// connections alive between queries
+pub struct DohClient {
+    pub url: String,
+    pub method: DohMethod,
+    agent: ureq::Agent,
+}
+
+impl DohClient {
+    pub fn new(url: &str) -> DohClient {
+        DohClient::with_timeout(url, DEFAULT_TIMEOUT)
+    }
+
+    pub fn with_timeout(url: &str, timeout: Duration) -> DohClient {
+        DohClient {
+            url: url.to_string(),
+            method: DohMethod::Post,
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+
+    pub fn with_method(mut self, method: DohMethod) -> DohClient {
+        self.method = method;
+        self
+    }
+
+    // the id is zeroed as RFC 8484 suggests, so identical queries make identical urls for caches
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        let mut query = build_query(qname, qtype);
+        query.header.id = 0;
+
+        self.exchange(&query)
+    }
+
+    pub fn exchange(&self, query: &DnsPacket) -> Result<DnsPacket> {
+        let request = encode(&padded(query))?;
+        let message = &request.buffer[..request.pos()];
+
+        let response = match self.method {
+            DohMethod::Post => self
+                .agent
+                .post(&self.url)
+                .set("Content-Type", DNS_MESSAGE)
+                .set("Accept", DNS_MESSAGE)
+                .send_bytes(message)?,
+            DohMethod::Get => self
+                .agent
+                .get(&self.url)
+                .query("dns", &base64url_encode(message))
+                .set("Accept", DNS_MESSAGE)
+                .call()?,
+        };
+
+        if response.content_type() != DNS_MESSAGE {
+            return Err(format!(
+                "Endpoint answered with {} instead of {}",
+                response.content_type(),
+                DNS_MESSAGE
+            )
+            .into());
+        }
+
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(MAX_BUFFER_SIZE as u64 + 1)
+            .read_to_end(&mut body)?;
+
+        let packet = parse_bytes(&body)?;
+        if packet.header.id != query.header.id {
+            return Err(format!(
+                "Response id {} doesn't match query id {}",
+                packet.header.id, query.header.id
+            )
+            .into());
+        }
+
+        Ok(packet)
+    }
+}
@@ -0,0 +1,159 @@
+use std::{convert::Infallible, net::Ipv4Addr, sync::Arc};
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE},
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    encoding::base64_decode,
+    server::{encode_response, respond_async, Client, Transport},
+    AsyncResolver, DnsPacket, DnsRecord, Result,
+};
+
+// the path RFC 8484 uses in its examples, and what clients default to
+pub const DOH_PATH: &str = "/dns-query";
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+// answers RFC 8484 requests for DOH_PATH over https, clients that offer http/2 through ALPN get it,
+// the rest are served http/1.1
+pub async fn serve_doh(listen: (Ipv4Addr, u16), resolver: AsyncResolver, config: Arc<ServerConfig>) -> Result<()> {
+    let mut config = (*config).clone();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+
+    loop {
+        let (tcp, source) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                resolver
+                    .events
+                    .report(format_args!("Failed to accept a tcp connection: {}", e));
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let resolver = resolver.clone();
+
+        tokio::spawn(async move {
+            let tls = match acceptor.accept(tcp).await {
+                Ok(tls) => tls,
+                Err(e) => {
+                    resolver
+                        .events
+                        .report(format_args!("Tls handshake with {} failed: {}", source, e));
+                    return;
+                }
+            };
+
+            let (client, events) = (Client::new(source.ip(), Transport::Https), resolver.events.clone());
+            let service = service_fn(move |request| answer(request, client, resolver.clone()));
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(tls), service)
+                .await
+            {
+                events.report(format_args!("Https connection with {} failed: {}", source, e));
+            }
+        });
+    }
+}
+
+async fn answer(
+    request: Request<Incoming>,
+    client: Client,
+    resolver: AsyncResolver,
+) -> core::result::Result<Response<Full<Bytes>>, Infallible> {
+    let message = match query_of(request).await {
+        Ok(message) => message,
+        Err(status) => return Ok(status_response(status)),
+    };
+
+    let (response, limit) = match respond_async(&message, client, &resolver).await {
+        Some(answered) => answered,
+        None => return Ok(status_response(StatusCode::BAD_REQUEST)),
+    };
+    let buffer = match encode_response(&response, limit) {
+        Ok(buffer) => buffer,
+        Err(_) => return Ok(status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+    };
+
+    let mut http = Response::new(Full::new(Bytes::copy_from_slice(&buffer.buffer[..buffer.pos()])));
+    http.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(DNS_MESSAGE));
+    // http caches shouldn't keep the answer for longer than its records live
+    if let Some(ttl) = min_ttl(&response) {
+        if let Ok(value) = HeaderValue::from_str(&format!("max-age={}", ttl)) {
+            http.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+
+    Ok(http)
+}
+
+// the wire format query carried by the request, either base64url in the dns parameter of a GET or
+// the body of a POST
+async fn query_of(request: Request<Incoming>) -> core::result::Result<Vec<u8>, StatusCode> {
+    if request.uri().path() != DOH_PATH {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    match *request.method() {
+        Method::GET => {
+            let dns = request
+                .uri()
+                .query()
+                .unwrap_or("")
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("dns="))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+
+            base64_decode(dns).map_err(|_| StatusCode::BAD_REQUEST)
+        }
+        Method::POST => {
+            let content_type = request
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok());
+            if content_type != Some(DNS_MESSAGE) {
+                return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+
+            let body = Limited::new(request.into_body(), MAX_BUFFER_SIZE)
+                .collect()
+                .await
+                .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+
+            Ok(body.to_bytes().to_vec())
+        }
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+
+    response
+}
+
+fn min_ttl(packet: &DnsPacket) -> Option<u32> {
+    packet
+        .answers
+        .iter()
+        .chain(packet.authorities.iter())
+        .filter(|record| !matches!(record, DnsRecord::OPT { .. }))
+        .map(|record| record.ttl())
+        .min()
+}
@@ -0,0 +1,183 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    Connection, Endpoint, RecvStream, SendStream,
+};
+use rustls::{ClientConfig, ServerConfig};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    client::{build_query, encode, DEFAULT_TIMEOUT},
+    parse_bytes,
+    server::{encode_response, respond_async, Client, Transport},
+    tls::{client_config, padded},
+    AsyncResolver, DnsError, DnsPacket, QueryType, Result,
+};
+
+// RFC 9250 shares 853 with DNS over TLS, just on udp
+pub const DOQ_PORT: u16 = 853;
+
+const DOQ_ALPN: &[u8] = b"doq";
+
+// queries a server over QUIC, every query gets a stream of its own on one connection that's kept
+// open between them, the endpoint needs a tokio runtime to be created in
+pub struct DoqClient {
+    pub server: (Ipv4Addr, u16),
+    pub timeout: Duration,
+    server_name: String,
+    endpoint: Endpoint,
+    connection: Option<Connection>,
+}
+
+impl DoqClient {
+    // like TlsClient, server_name has to match the certificate and can be the ip itself
+    pub fn new(server: (Ipv4Addr, u16), server_name: &str) -> Result<DoqClient> {
+        DoqClient::with_config(server, server_name, client_config())
+    }
+
+    // for servers whose certificates come from somewhere other than the webpki roots, like a private ca
+    pub fn with_config(server: (Ipv4Addr, u16), server_name: &str, mut tls: ClientConfig) -> Result<DoqClient> {
+        tls.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+        let quic = QuicClientConfig::try_from(tls)?;
+
+        let mut endpoint = Endpoint::client((Ipv4Addr::UNSPECIFIED, 0).into())?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(quic)));
+
+        Ok(DoqClient {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            server_name: server_name.to_string(),
+            endpoint,
+            connection: None,
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> DoqClient {
+        self.timeout = timeout;
+        self
+    }
+
+    // the stream already tells responses apart, so RFC 9250 has the id always be 0
+    pub async fn lookup(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        let mut query = build_query(qname, qtype);
+        query.header.id = 0;
+
+        self.exchange(&query).await
+    }
+
+    pub async fn exchange(&mut self, query: &DnsPacket) -> Result<DnsPacket> {
+        let request = encode(&padded(query))?;
+        let message = framed(&request.buffer[..request.pos()])?;
+
+        tokio::time::timeout(self.timeout, self.send(&message))
+            .await
+            .map_err(|_| DnsError::Timeout)?
+    }
+
+    async fn send(&mut self, message: &[u8]) -> Result<DnsPacket> {
+        let connection = self.connection().await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+
+        send.write_all(message).await?;
+        // no more queries on this stream, which is how the server knows the query is complete
+        send.finish()?;
+
+        let response = recv.read_to_end(MAX_BUFFER_SIZE + 2).await?;
+        parse_bytes(unframed(&response)?)
+    }
+
+    // the open connection, or a new one if there's none or the server has closed it
+    async fn connection(&mut self) -> Result<Connection> {
+        if let Some(connection) = &self.connection {
+            if connection.close_reason().is_none() {
+                return Ok(connection.clone());
+            }
+        }
+
+        let address: SocketAddr = self.server.into();
+        let connection = self.endpoint.connect(address, &self.server_name)?.await?;
+        self.connection = Some(connection.clone());
+
+        Ok(connection)
+    }
+}
+
+// streams carry the same two byte length prefix as tcp
+fn framed(message: &[u8]) -> Result<Vec<u8>> {
+    let length = u16::try_from(message.len()).map_err(|_| "Message exceeds 65535 bytes")?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+
+    Ok(framed)
+}
+
+fn unframed(data: &[u8]) -> Result<&[u8]> {
+    let Some((length, message)) = data.split_first_chunk::<2>() else {
+        return Err("Stream ended before the length prefix".into());
+    };
+    if u16::from_be_bytes(*length) as usize != message.len() {
+        return Err("Stream length doesn't match its length prefix".into());
+    }
+
+    Ok(message)
+}
+
+// answers DNS over QUIC on listen, each stream is handled on its own task
+pub async fn serve_doq(listen: (Ipv4Addr, u16), resolver: AsyncResolver, config: Arc<ServerConfig>) -> Result<()> {
+    let mut tls = (*config).clone();
+    tls.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+    let quic = QuicServerConfig::try_from(tls)?;
+    let endpoint = Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(quic)), listen.into())?;
+
+    while let Some(incoming) = endpoint.accept().await {
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    resolver.events.report(format_args!("Quic handshake failed: {}", e));
+                    return;
+                }
+            };
+
+            // accepting fails once the client closes the connection, which ends it
+            let client = Client::new(connection.remote_address().ip(), Transport::Quic);
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                let resolver = resolver.clone();
+                tokio::spawn(async move {
+                    let events = resolver.events.clone();
+                    if let Err(e) = answer_stream(send, recv, client, resolver).await {
+                        events.report(format_args!("Quic stream failed: {}", e));
+                    }
+                });
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn answer_stream(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    client: Client,
+    resolver: AsyncResolver,
+) -> Result<()> {
+    let request = recv.read_to_end(MAX_BUFFER_SIZE + 2).await?;
+    let Some((response, limit)) = respond_async(unframed(&request)?, client, &resolver).await else {
+        return Ok(());
+    };
+
+    let buffer = encode_response(&response, limit)?;
+    send.write_all(&framed(&buffer.buffer[..buffer.pos()])?).await?;
+    send.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use crate::Result;
+
+// option codes this crate knows about, from the IANA EDNS0 option registry
+pub const OPTION_NSID: u16 = 3;
+pub const OPTION_CLIENT_SUBNET: u16 = 8;
+pub const OPTION_COOKIE: u16 = 10;
+pub const OPTION_PADDING: u16 = 12;
+pub const OPTION_EXTENDED_ERROR: u16 = 15;
+
+// the block sizes RFC 8467 recommends padding to, queries and responses get different ones since
+// responses vary a lot more in size
+pub const QUERY_PADDING_BLOCK: usize = 128;
+pub const RESPONSE_PADDING_BLOCK: usize = 468;
+
+// the Extended DNS Error info codes from RFC 8914 that this crate sends itself
+pub const EDE_STALE_ANSWER: u16 = 3;
+pub const EDE_DNSSEC_BOGUS: u16 = 6;
+pub const EDE_SIGNATURE_EXPIRED: u16 = 7;
+pub const EDE_SIGNATURE_NOT_YET_VALID: u16 = 8;
+pub const EDE_DNSKEY_MISSING: u16 = 9;
+pub const EDE_RRSIGS_MISSING: u16 = 10;
+pub const EDE_NSEC_MISSING: u16 = 12;
+pub const EDE_BLOCKED: u16 = 15;
+pub const EDE_NO_REACHABLE_AUTHORITY: u16 = 22;
+
+// what we advertise unless told otherwise, the commonly recommended size that avoids fragmentation
+pub const DEFAULT_PAYLOAD_SIZE: u16 = 1232;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+// the contents of an OPT pseudo-record, which reuses the class and ttl fields for its own purposes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Edns {
+    // largest udp response the sender can take, carried in the class field
+    pub payload_size: u16,
+    // upper eight bits of the twelve bit result code, the header holds the lower four
+    pub extended_rcode: u8,
+    pub version: u8,
+    // DO bit, asks for DNSSEC records
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Edns::new(DEFAULT_PAYLOAD_SIZE)
+    }
+}
+
+impl Edns {
+    pub fn new(payload_size: u16) -> Edns {
+        Edns {
+            payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    // the ttl field as it goes on the wire: extended rcode, version, then the DO bit and zeroes
+    pub fn packed_ttl(&self) -> u32 {
+        ((self.extended_rcode as u32) << 24) | ((self.version as u32) << 16) | if self.dnssec_ok { 0x8000 } else { 0 }
+    }
+
+    pub fn set_packed_ttl(&mut self, ttl: u32) {
+        self.extended_rcode = (ttl >> 24) as u8;
+        self.version = (ttl >> 16) as u8;
+        self.dnssec_ok = (ttl & 0x8000) != 0;
+    }
+
+    pub fn option(&self, code: u16) -> Option<&EdnsOption> {
+        self.options.iter().find(|option| option.code == code)
+    }
+
+    // replaces any option with the same code
+    pub fn set_option(&mut self, code: u16, data: Vec<u8>) {
+        self.options.retain(|option| option.code != code);
+        self.options.push(EdnsOption { code, data });
+    }
+
+    // None when there's no such option or it doesn't parse
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        ClientSubnet::from_data(&self.option(OPTION_CLIENT_SUBNET)?.data).ok()
+    }
+
+    pub fn set_client_subnet(&mut self, subnet: &ClientSubnet) {
+        self.set_option(OPTION_CLIENT_SUBNET, subnet.to_data());
+    }
+
+    // a response can carry several, the ones that don't parse are skipped
+    pub fn extended_errors(&self) -> Vec<ExtendedError> {
+        self.options
+            .iter()
+            .filter(|option| option.code == OPTION_EXTENDED_ERROR)
+            .filter_map(|option| ExtendedError::from_data(&option.data).ok())
+            .collect()
+    }
+
+    // unlike set_option this keeps the ones already there
+    pub fn add_extended_error(&mut self, error: &ExtendedError) {
+        self.options.push(EdnsOption {
+            code: OPTION_EXTENDED_ERROR,
+            data: error.to_data(),
+        });
+    }
+}
+
+// an Extended DNS Error from RFC 8914, why a server answered the way it did beyond what the result
+// code can say, like a stale answer or a DNSSEC failure
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExtendedError {
+    pub info_code: u16,
+    // free form and only meant for people, may be empty
+    pub extra_text: String,
+}
+
+impl ExtendedError {
+    pub fn new(info_code: u16) -> ExtendedError {
+        ExtendedError {
+            info_code,
+            extra_text: String::new(),
+        }
+    }
+
+    pub fn with_text(mut self, text: &str) -> ExtendedError {
+        self.extra_text = text.to_string();
+        self
+    }
+
+    // the info code, then utf-8 text that RFC 8914 says not to terminate with a nul, though some
+    // servers do anyway
+    pub fn from_data(data: &[u8]) -> Result<ExtendedError> {
+        let [high, low, text @ ..] = data else {
+            return Err("Extended error option is too short".into());
+        };
+        let text = text.strip_suffix(&[0]).unwrap_or(text);
+
+        Ok(ExtendedError {
+            info_code: u16::from_be_bytes([*high, *low]),
+            extra_text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let mut data = self.info_code.to_be_bytes().to_vec();
+        data.extend_from_slice(self.extra_text.as_bytes());
+
+        data
+    }
+
+    // the name the IANA registry gives the info code, None for unassigned ones
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self.info_code {
+            0 => "Other Error",
+            1 => "Unsupported DNSKEY Algorithm",
+            2 => "Unsupported DS Digest Type",
+            3 => "Stale Answer",
+            4 => "Forged Answer",
+            5 => "DNSSEC Indeterminate",
+            6 => "DNSSEC Bogus",
+            7 => "Signature Expired",
+            8 => "Signature Not Yet Valid",
+            9 => "DNSKEY Missing",
+            10 => "RRSIGs Missing",
+            11 => "No Zone Key Bit Set",
+            12 => "NSEC Missing",
+            13 => "Cached Error",
+            14 => "Not Ready",
+            15 => "Blocked",
+            16 => "Censored",
+            17 => "Filtered",
+            18 => "Prohibited",
+            19 => "Stale NXDOMAIN Answer",
+            20 => "Not Authoritative",
+            21 => "Not Supported",
+            22 => "No Reachable Authority",
+            23 => "Network Error",
+            24 => "Invalid Data",
+            25 => "Signature Expired before Valid",
+            26 => "Too Early",
+            27 => "Unsupported NSEC3 Iterations Value",
+            28 => "Unable to conform to policy",
+            29 => "Synthesized",
+            30 => "Invalid Query Type",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+// so a resolver can fail with one and the server answers with it
+impl core::error::Error for ExtendedError {}
+
+// the way dig shows it, code (name): text
+impl fmt::Display for ExtendedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.info_code)?;
+        if let Some(name) = self.name() {
+            write!(f, " ({})", name)?;
+        }
+        if !self.extra_text.is_empty() {
+            write!(f, ": {}", self.extra_text)?;
+        }
+
+        Ok(())
+    }
+}
+
+// the EDNS Client Subnet option from RFC 7871: the network a query is asked on behalf of, so servers
+// behind CDNs can answer with something close to it, and in responses how much of that network the
+// answer holds for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientSubnet {
+    // only the first source_prefix bits are ever set
+    pub address: IpAddr,
+    pub source_prefix: u8,
+    // 0 in queries, in responses the number of leading bits the answer depends on
+    pub scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    // the network of address that's prefix bits long, capped at the length of the address
+    pub fn new(address: IpAddr, prefix: u8) -> ClientSubnet {
+        let prefix = prefix.min(address_bits(address));
+
+        ClientSubnet {
+            address: masked(address, prefix),
+            source_prefix: prefix,
+            scope_prefix: 0,
+        }
+    }
+
+    pub fn with_scope(mut self, scope_prefix: u8) -> ClientSubnet {
+        self.scope_prefix = scope_prefix;
+        self
+    }
+
+    // whether address is in the network
+    pub fn contains(&self, address: IpAddr) -> bool {
+        address.is_ipv4() == self.address.is_ipv4() && masked(address, self.source_prefix) == self.address
+    }
+
+    // family, both prefixes, then only as many bytes of the address as the source prefix covers
+    pub fn from_data(data: &[u8]) -> Result<ClientSubnet> {
+        let [family_high, family_low, source_prefix, scope_prefix, address @ ..] = data else {
+            return Err("Client subnet option is too short".into());
+        };
+        let (source_prefix, scope_prefix) = (*source_prefix, *scope_prefix);
+        if address.len() != (source_prefix as usize).div_ceil(8) {
+            return Err("Client subnet address doesn't match its prefix".into());
+        }
+
+        let address = match u16::from_be_bytes([*family_high, *family_low]) {
+            1 if source_prefix <= 32 => {
+                let mut octets = [0; 4];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            2 if source_prefix <= 128 => {
+                let mut octets = [0; 16];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err("Unsupported client subnet family or prefix".into()),
+        };
+
+        Ok(ClientSubnet {
+            address: masked(address, source_prefix),
+            source_prefix,
+            scope_prefix,
+        })
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        let (family, octets): (u16, Vec<u8>) = match self.address {
+            IpAddr::V4(address) => (1, address.octets().to_vec()),
+            IpAddr::V6(address) => (2, address.octets().to_vec()),
+        };
+
+        let mut data = Vec::with_capacity(4 + octets.len());
+        data.extend_from_slice(&family.to_be_bytes());
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        data.extend_from_slice(&octets[..(self.source_prefix as usize).div_ceil(8)]);
+
+        data
+    }
+}
+
+// the way dig shows it, network/source/scope
+impl fmt::Display for ClientSubnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.address, self.source_prefix, self.scope_prefix)
+    }
+}
+
+fn address_bits(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+// address with everything past the first prefix bits cleared
+pub(crate) fn masked(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix.min(32))).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask))
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix.min(128))).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+        }
+    }
+}
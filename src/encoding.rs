@@ -0,0 +1,212 @@
+use alloc::{format, string::String, vec::Vec};
+
+use crate::Result;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// lowercase hex without separators, the form used for rdata in presentation format
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// reads pairs of hex digits, whitespace and colons between them are skipped so pasted dumps work as they are
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace() && *b != b':').collect();
+
+    if digits.len() % 2 != 0 {
+        return Err("Hex input has an odd number of digits".into());
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let text = core::str::from_utf8(pair)?;
+            // from_str_radix would take a sign as well
+            if !pair.iter().all(u8::is_ascii_hexdigit) {
+                return Err(format!("Invalid hex byte {}", text).into());
+            }
+            u8::from_str_radix(text, 16).map_err(|_| format!("Invalid hex byte {}", text).into())
+        })
+        .collect()
+}
+
+// standard padded base64, as dig prints key and signature material
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+
+        output.push(BASE64_ALPHABET[(triple >> 18) as usize & 0x3F] as char);
+        output.push(BASE64_ALPHABET[(triple >> 12) as usize & 0x3F] as char);
+        if chunk.len() > 1 {
+            output.push(BASE64_ALPHABET[(triple >> 6) as usize & 0x3F] as char);
+        } else {
+            output.push('=');
+        }
+        if chunk.len() > 2 {
+            output.push(BASE64_ALPHABET[triple as usize & 0x3F] as char);
+        } else {
+            output.push('=');
+        }
+    }
+
+    output
+}
+
+// the url safe alphabet without padding, which is how DNS over HTTPS puts queries in GET urls
+pub fn base64url_encode(data: &[u8]) -> String {
+    base64_encode(data)
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+// takes both the standard and the url safe alphabet, padding is optional and whitespace is skipped
+pub fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+
+    for c in text.bytes().filter(|b| !b.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(format!("Invalid base64 character {:?}", c as char).into()),
+        };
+
+        bits = (bits << 6) | value as u32;
+        count += 1;
+        if count == 4 {
+            output.extend_from_slice(&bits.to_be_bytes()[1..]);
+            bits = 0;
+            count = 0;
+        }
+    }
+
+    // a trailing group of two or three characters carries one or two more bytes
+    match count {
+        0 => {}
+        2 => output.push((bits >> 4) as u8),
+        3 => output.extend_from_slice(&((bits >> 2) as u16).to_be_bytes()),
+        _ => return Err("Base64 input ends in the middle of a byte".into()),
+    }
+
+    Ok(output)
+}
+
+const BASE32HEX_ALPHABET: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+// unpadded base32 with the extended hex alphabet, which is how NSEC3 prints hashed owner names
+pub fn base32hex_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity((data.len() * 8).div_ceil(5));
+
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for &byte in data {
+        bits = (bits << 8) | byte as u32;
+        bit_count += 8;
+        while bit_count >= 5 {
+            bit_count -= 5;
+            output.push(BASE32HEX_ALPHABET[((bits >> bit_count) & 0x1F) as usize] as char);
+        }
+    }
+    if bit_count > 0 {
+        output.push(BASE32HEX_ALPHABET[((bits << (5 - bit_count)) & 0x1F) as usize] as char);
+    }
+
+    output
+}
+
+// the inverse of base32hex_encode, taking either case. Leftover bits at the end are dropped
+pub fn base32hex_decode(text: &str) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(text.len() * 5 / 8);
+
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    for c in text.bytes().filter(|b| *b != b'=') {
+        let value = match c.to_ascii_lowercase() {
+            c @ b'0'..=b'9' => c - b'0',
+            c @ b'a'..=b'v' => c - b'a' + 10,
+            _ => return Err(format!("Invalid base32hex character {:?}", c as char).into()),
+        };
+
+        bits = (bits << 5) | value as u32;
+        bit_count += 5;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            output.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Ok(output)
+}
+
+// seconds since the epoch as YYYYMMDDHHmmSS, the form RRSIG timestamps are shown in
+pub fn format_timestamp(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds_of_day = timestamp % 86400;
+
+    // civil date from days since 1970-01-01, after Howard Hinnant's days_from_civil inverse
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day / 60) % 60,
+        seconds_of_day % 60
+    )
+}
+
+// reads an RRSIG timestamp back, either as YYYYMMDDHHmmSS or as plain seconds since the epoch
+pub fn parse_timestamp(text: &str) -> Result<u32> {
+    let invalid = || format!("Invalid timestamp {}", text);
+    if text.len() != 14 {
+        return text.parse().map_err(|_| invalid().into());
+    }
+
+    let field = |range: core::ops::Range<usize>| -> Result<i64> {
+        text.get(range)
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| invalid().into())
+    };
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid().into());
+    }
+
+    // days since 1970-01-01, Howard Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let timestamp = days * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(timestamp).map_err(|_| invalid().into())
+}
@@ -0,0 +1,217 @@
+use alloc::string::{FromUtf8Error, String, ToString};
+use core::{array::TryFromSliceError, fmt, net::AddrParseError, num::ParseIntError, str::Utf8Error};
+
+use crate::{ExtendedError, ResultCode};
+
+// everything that can go wrong: the failures a server reports through the result code of its response,
+// no response in time, input that can't be used and the layers underneath failing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsError {
+    FormatError,
+    ServerFailure,
+    NameError,
+    NotImplemented,
+    Refused,
+    NameExists,
+    RecordsExist,
+    RecordsMissing,
+    NotAuthoritative,
+    NotInZone,
+    // no response arrived before the read timeout ran out
+    Timeout,
+    // the answer failed DNSSEC validation, the extended error says why
+    Bogus(ExtendedError),
+    // a packet, name, record or piece of text that is malformed or can't be used, saying what's wrong
+    Invalid(String),
+    // a socket or file operation failed
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind, String),
+    // tls, quic, http, a cryptographic check or a task failed underneath
+    Failed(String),
+}
+
+impl DnsError {
+    // None for NOERROR, since that isn't a failure
+    pub fn from_result_code(code: ResultCode) -> Option<DnsError> {
+        match code {
+            ResultCode::NOERROR => None,
+            ResultCode::FORMERR => Some(DnsError::FormatError),
+            ResultCode::SERVFAIL => Some(DnsError::ServerFailure),
+            ResultCode::NXDOMAIN => Some(DnsError::NameError),
+            ResultCode::NOTIMP => Some(DnsError::NotImplemented),
+            ResultCode::REFUSED => Some(DnsError::Refused),
+            ResultCode::YXDOMAIN => Some(DnsError::NameExists),
+            ResultCode::YXRRSET => Some(DnsError::RecordsExist),
+            ResultCode::NXRRSET => Some(DnsError::RecordsMissing),
+            ResultCode::NOTAUTH => Some(DnsError::NotAuthoritative),
+            ResultCode::NOTZONE => Some(DnsError::NotInZone),
+        }
+    }
+
+    // whether a server answered with this as its result code, rather than something going wrong on the way
+    pub fn is_result_code(&self) -> bool {
+        matches!(
+            self,
+            DnsError::FormatError
+                | DnsError::ServerFailure
+                | DnsError::NameError
+                | DnsError::NotImplemented
+                | DnsError::Refused
+                | DnsError::NameExists
+                | DnsError::RecordsExist
+                | DnsError::RecordsMissing
+                | DnsError::NotAuthoritative
+                | DnsError::NotInZone
+        )
+    }
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::FormatError => write!(f, "Server could not interpret the query (FORMERR)"),
+            DnsError::ServerFailure => write!(f, "Server failed to process the query (SERVFAIL)"),
+            DnsError::NameError => write!(f, "Domain name does not exist (NXDOMAIN)"),
+            DnsError::NotImplemented => write!(f, "Server does not support the query (NOTIMP)"),
+            DnsError::Refused => write!(f, "Server refused the query (REFUSED)"),
+            DnsError::NameExists => write!(f, "Name exists when it should not (YXDOMAIN)"),
+            DnsError::RecordsExist => write!(f, "Records exist when they should not (YXRRSET)"),
+            DnsError::RecordsMissing => write!(f, "Records that should exist do not (NXRRSET)"),
+            DnsError::NotAuthoritative => write!(f, "Server is not authoritative for the zone (NOTAUTH)"),
+            DnsError::NotInZone => write!(f, "Name is not within the zone (NOTZONE)"),
+            DnsError::Timeout => write!(f, "Timed out waiting for a response"),
+            DnsError::Bogus(error) => write!(f, "{}", error),
+            DnsError::Invalid(message) | DnsError::Failed(message) => f.write_str(message),
+            #[cfg(feature = "std")]
+            DnsError::Io(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl core::error::Error for DnsError {}
+
+impl From<ExtendedError> for DnsError {
+    fn from(error: ExtendedError) -> DnsError {
+        DnsError::Bogus(error)
+    }
+}
+
+impl From<&str> for DnsError {
+    fn from(message: &str) -> DnsError {
+        DnsError::Invalid(message.to_string())
+    }
+}
+
+impl From<String> for DnsError {
+    fn from(message: String) -> DnsError {
+        DnsError::Invalid(message)
+    }
+}
+
+impl From<ParseIntError> for DnsError {
+    fn from(e: ParseIntError) -> DnsError {
+        DnsError::Invalid(e.to_string())
+    }
+}
+
+impl From<AddrParseError> for DnsError {
+    fn from(e: AddrParseError) -> DnsError {
+        DnsError::Invalid(e.to_string())
+    }
+}
+
+impl From<Utf8Error> for DnsError {
+    fn from(e: Utf8Error) -> DnsError {
+        DnsError::Invalid(e.to_string())
+    }
+}
+
+impl From<FromUtf8Error> for DnsError {
+    fn from(e: FromUtf8Error) -> DnsError {
+        DnsError::Invalid(e.to_string())
+    }
+}
+
+impl From<TryFromSliceError> for DnsError {
+    fn from(e: TryFromSliceError) -> DnsError {
+        DnsError::Invalid(e.to_string())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for DnsError {
+    fn from(e: std::io::Error) -> DnsError {
+        DnsError::Io(e.kind(), e.to_string())
+    }
+}
+
+// the libraries underneath fail in ways of their own, all that's kept of those is what they say
+macro_rules! failed_from {
+    ($($feature:literal => $error:ty,)*) => {
+        $(
+            #[cfg(feature = $feature)]
+            impl From<$error> for DnsError {
+                fn from(e: $error) -> DnsError {
+                    DnsError::Failed(e.to_string())
+                }
+            }
+        )*
+    };
+}
+
+failed_from! {
+    "tokio" => tokio::task::JoinError,
+    "tls" => rustls::Error,
+    "tls" => rustls::pki_types::InvalidDnsNameError,
+    "tls" => rustls::pki_types::pem::Error,
+    "doh" => ureq::Error,
+    "doq" => quinn::ConnectError,
+    "doq" => quinn::ConnectionError,
+    "doq" => quinn::WriteError,
+    "doq" => quinn::ReadToEndError,
+    "doq" => quinn::ClosedStream,
+    "doq" => quinn::crypto::rustls::NoInitialCipherSuite,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_every_result_code() {
+        let expected = [
+            None,
+            Some(DnsError::FormatError),
+            Some(DnsError::ServerFailure),
+            Some(DnsError::NameError),
+            Some(DnsError::NotImplemented),
+            Some(DnsError::Refused),
+            Some(DnsError::NameExists),
+            Some(DnsError::RecordsExist),
+            Some(DnsError::RecordsMissing),
+            Some(DnsError::NotAuthoritative),
+            Some(DnsError::NotInZone),
+        ];
+        for (num, error) in expected.into_iter().enumerate() {
+            assert_eq!(
+                DnsError::from_result_code(ResultCode::from_num(num as u8)),
+                error,
+                "rcode {}",
+                num
+            );
+        }
+    }
+
+    #[test]
+    fn only_result_codes_are_result_codes() {
+        assert!(DnsError::NameError.is_result_code());
+        assert!(!DnsError::Timeout.is_result_code());
+        assert!(!DnsError::from("Label too long").is_result_code());
+        assert_eq!(
+            DnsError::from("Label too long"),
+            DnsError::Invalid("Label too long".to_string())
+        );
+        let error = DnsError::from("x".parse::<u8>().unwrap_err());
+        assert_eq!(error.to_string(), "invalid digit found in string");
+    }
+}
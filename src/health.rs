@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{cache::lock, client::DEFAULT_TIMEOUT, server::ForwardServer};
+
+// failures in a row after which a server is only asked when the healthy ones can't answer
+pub const DEMOTE_AFTER: u32 = 3;
+
+// how often a demoted server gets a query first anyway, to find out whether it has recovered
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+// what's been seen of one upstream server
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ServerStats {
+    // smoothed round trip time of its answers, the way tcp smooths it
+    pub srtt: Option<Duration>,
+    // share of recent queries that failed, recent ones weigh more
+    pub failure_rate: f64,
+    pub consecutive_failures: u32,
+    pub queries: u64,
+    pub failures: u64,
+    last_attempt: Instant,
+}
+
+impl ServerStats {
+    fn new(now: Instant) -> ServerStats {
+        ServerStats {
+            srtt: None,
+            failure_rate: 0.0,
+            consecutive_failures: 0,
+            queries: 0,
+            failures: 0,
+            last_attempt: now,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures < DEMOTE_AFTER
+    }
+
+    // what asking the server can be expected to take: its round trip time, plus a timeout for the
+    // share of queries it fails
+    fn expected_rtt(&self) -> Option<Duration> {
+        self.srtt.map(|srtt| srtt + DEFAULT_TIMEOUT.mul_f64(self.failure_rate))
+    }
+}
+
+// round trip times and failures of the servers a resolver forwards to, used to ask the fastest
+// healthy one first
+#[derive(Debug)]
+pub struct UpstreamHealth {
+    stats: Mutex<HashMap<(Ipv4Addr, u16), ServerStats>>,
+    // where the time attempts are made at comes from
+    now: fn() -> Instant,
+}
+
+impl Default for UpstreamHealth {
+    fn default() -> Self {
+        UpstreamHealth::new()
+    }
+}
+
+impl UpstreamHealth {
+    pub fn new() -> UpstreamHealth {
+        UpstreamHealth {
+            stats: Mutex::new(HashMap::new()),
+            now: Instant::now,
+        }
+    }
+
+    // times attempts by what now returns instead of the system clock, so tests can move it along
+    #[cfg(test)]
+    fn with_clock(mut self, now: fn() -> Instant) -> UpstreamHealth {
+        self.now = now;
+        self
+    }
+
+    pub fn stats(&self, server: (Ipv4Addr, u16)) -> Option<ServerStats> {
+        lock(&self.stats).get(&server).copied()
+    }
+
+    pub(crate) fn record_success(&self, server: (Ipv4Addr, u16), rtt: Duration) {
+        let now = (self.now)();
+        let mut stats = lock(&self.stats);
+        let stats = stats.entry(server).or_insert_with(|| ServerStats::new(now));
+
+        stats.srtt = Some(match stats.srtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+        stats.failure_rate *= 0.9;
+        stats.consecutive_failures = 0;
+        stats.queries += 1;
+        stats.last_attempt = now;
+    }
+
+    pub(crate) fn record_failure(&self, server: (Ipv4Addr, u16)) {
+        let now = (self.now)();
+        let mut stats = lock(&self.stats);
+        let stats = stats.entry(server).or_insert_with(|| ServerStats::new(now));
+
+        stats.failure_rate = stats.failure_rate * 0.9 + 0.1;
+        stats.consecutive_failures += 1;
+        stats.queries += 1;
+        stats.failures += 1;
+        stats.last_attempt = now;
+    }
+
+    // servers that answered their last query by how long they can be expected to take, which counts
+    // the ones they failed lately against them, ones never asked yet first so they get measured. Then
+    // ones that failed their last query and last the demoted ones. A server that failed and hasn't been
+    // asked for PROBE_INTERVAL goes first, once, as a probe
+    pub(crate) fn order(&self, servers: &[ForwardServer]) -> Vec<ForwardServer> {
+        let now = (self.now)();
+        let mut stats = lock(&self.stats);
+
+        let mut ordered = servers.to_vec();
+        ordered.sort_by_key(|server| match stats.get(&server.address) {
+            None => (0, None),
+            Some(stats) if stats.consecutive_failures == 0 => (0, stats.expected_rtt()),
+            Some(stats) if stats.is_healthy() => (1, stats.expected_rtt()),
+            Some(stats) => (2, stats.expected_rtt()),
+        });
+
+        let probe = ordered.iter().skip(1).position(|server| {
+            stats.get(&server.address).is_some_and(|stats| {
+                stats.consecutive_failures > 0 && now.duration_since(stats.last_attempt) >= PROBE_INTERVAL
+            })
+        });
+        if let Some(index) = probe {
+            let server = ordered.remove(index + 1);
+            // so the queries right after this one don't all probe it too
+            if let Some(stats) = stats.get_mut(&server.address) {
+                stats.last_attempt = now;
+            }
+            ordered.insert(0, server);
+        }
+
+        ordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+        static OFFSET: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+    }
+
+    // a clock that only moves when advance says so, every test runs on its own thread
+    fn now() -> Instant {
+        START.with(|start| *start) + OFFSET.with(Cell::get)
+    }
+
+    fn advance(duration: Duration) {
+        OFFSET.with(|offset| offset.set(offset.get() + duration));
+    }
+
+    fn server(last: u8) -> ForwardServer {
+        ForwardServer::new((Ipv4Addr::new(192, 0, 2, last), 53))
+    }
+
+    fn order(health: &UpstreamHealth, servers: &[ForwardServer]) -> Vec<u8> {
+        health
+            .order(servers)
+            .iter()
+            .map(|server| server.address.0.octets()[3])
+            .collect()
+    }
+
+    #[test]
+    fn failures_count_against_fast_servers() {
+        let health = UpstreamHealth::new().with_clock(now);
+        let servers = [server(1), server(2), server(3)];
+        health.record_success(servers[0].address, Duration::from_millis(40));
+        health.record_success(servers[1].address, Duration::from_millis(10));
+        // the one never asked goes first so it gets measured
+        assert_eq!(order(&health, &servers), [3, 2, 1]);
+
+        // answering again doesn't make up for a failure this recent
+        health.record_failure(servers[1].address);
+        health.record_success(servers[1].address, Duration::from_millis(10));
+        health.record_success(servers[2].address, Duration::from_millis(20));
+        assert_eq!(order(&health, &servers), [3, 1, 2]);
+        assert!(health.stats(servers[1].address).unwrap().failure_rate > 0.0);
+
+        // but it's forgiven as it keeps answering
+        for _ in 0..40 {
+            health.record_success(servers[1].address, Duration::from_millis(10));
+        }
+        assert_eq!(order(&health, &servers), [2, 3, 1]);
+    }
+
+    #[test]
+    fn servers_are_demoted_after_failing_in_a_row() {
+        let health = UpstreamHealth::new().with_clock(now);
+        let servers = [server(1), server(2), server(3)];
+        health.record_success(servers[0].address, Duration::from_millis(10));
+        health.record_success(servers[1].address, Duration::from_millis(20));
+        health.record_success(servers[2].address, Duration::from_millis(300));
+
+        // a failure puts a server behind the ones that answered their last query, slow as they are
+        health.record_failure(servers[0].address);
+        assert_eq!(order(&health, &servers), [2, 3, 1]);
+        for _ in 1..DEMOTE_AFTER - 1 {
+            health.record_failure(servers[0].address);
+        }
+        health.record_failure(servers[1].address);
+        assert!(health.stats(servers[0].address).unwrap().is_healthy());
+        assert_eq!(order(&health, &servers), [3, 2, 1]);
+
+        // demoted ones come after those that only failed lately
+        health.record_failure(servers[0].address);
+        health.record_failure(servers[1].address);
+        let stats = health.stats(servers[0].address).unwrap();
+        assert!(!stats.is_healthy());
+        assert_eq!(stats.consecutive_failures, DEMOTE_AFTER);
+        assert_eq!(
+            (stats.failures, stats.queries),
+            (u64::from(DEMOTE_AFTER), u64::from(DEMOTE_AFTER) + 1)
+        );
+        assert_eq!(order(&health, &servers), [3, 2, 1]);
+        health.record_failure(servers[1].address);
+        assert_eq!(order(&health, &servers), [3, 1, 2]);
+
+        // one answer and it's back
+        health.record_success(servers[0].address, Duration::from_millis(10));
+        assert_eq!(health.stats(servers[0].address).unwrap().consecutive_failures, 0);
+        assert_eq!(order(&health, &servers)[..2], [3, 1]);
+    }
+
+    #[test]
+    fn demoted_servers_are_probed_once_an_interval() {
+        let health = UpstreamHealth::new().with_clock(now);
+        let servers = [server(1), server(2)];
+        health.record_success(servers[0].address, Duration::from_millis(10));
+        for _ in 0..DEMOTE_AFTER {
+            health.record_failure(servers[1].address);
+        }
+        assert_eq!(order(&health, &servers), [1, 2]);
+
+        advance(PROBE_INTERVAL - Duration::from_secs(1));
+        assert_eq!(order(&health, &servers), [1, 2]);
+        advance(Duration::from_secs(1));
+        assert_eq!(order(&health, &servers), [2, 1]);
+        // the probe counts as an attempt, the queries right after it don't probe again
+        assert_eq!(order(&health, &servers), [1, 2]);
+        advance(PROBE_INTERVAL / 2);
+        assert_eq!(order(&health, &servers), [1, 2]);
+
+        // a probe that fails waits out another interval
+        health.record_failure(servers[1].address);
+        advance(PROBE_INTERVAL - Duration::from_secs(1));
+        assert_eq!(order(&health, &servers), [1, 2]);
+        advance(Duration::from_secs(1));
+        assert_eq!(order(&health, &servers), [2, 1]);
+    }
+}
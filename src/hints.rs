@@ -0,0 +1,186 @@
+use std::{
+    net::Ipv4Addr,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tokio")]
+use crate::resolver::ask_any_async;
+use crate::{
+    cache::lock,
+    record::normal,
+    resolver::{ask_any, ROOT_SERVERS},
+    DnsPacket, DnsRecord, QueryType, Result,
+};
+
+// how long to keep going with the servers there are before priming again when it failed
+const PRIME_RETRY: Duration = Duration::from_secs(5 * 60);
+
+// the root NS records live for six days, but a list that's off for that long could be missing a
+// server that was renumbered
+const MAX_PRIMED: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+struct Primed {
+    servers: Vec<Ipv4Addr>,
+    // None until the first priming query is out
+    next: Option<Instant>,
+}
+
+// where recursion starts: the root servers from a named.root hints file, or the ones built in. The
+// hints are only used for the priming query from RFC 8109, which asks them for the current root
+// NS records and their addresses, and is asked again once those expire
+#[derive(Debug)]
+pub struct RootHints {
+    primed: Mutex<Primed>,
+}
+
+impl Default for RootHints {
+    fn default() -> Self {
+        RootHints::new()
+    }
+}
+
+impl RootHints {
+    pub fn new() -> RootHints {
+        RootHints::with_servers(ROOT_SERVERS.to_vec())
+    }
+
+    pub fn with_servers(servers: Vec<Ipv4Addr>) -> RootHints {
+        RootHints {
+            primed: Mutex::new(Primed { servers, next: None }),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<RootHints> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read root hints from {}: {}", path.display(), e))?;
+
+        RootHints::parse(&text)
+    }
+
+    // the addresses of the root's name servers in a hints file like IANA's named.root, which is in
+    // zone file format. The ipv6 ones are left out since lookups only go over ipv4
+    pub fn parse(text: &str) -> Result<RootHints> {
+        let mut nameservers = Vec::new();
+        let mut addresses = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // the owner, then an optional ttl and class before the type
+            let is_kind = |field: &str| matches!(field.to_ascii_uppercase().as_str(), "NS" | "A" | "AAAA");
+            let Some(index) = (1..fields.len()).find(|index| is_kind(fields[*index])) else {
+                continue;
+            };
+            let (owner, kind) = (normal(fields[0]), fields[index].to_ascii_uppercase());
+            let Some(data) = fields.get(index + 1) else {
+                return Err(format!("Line {} of the root hints has no {} data", number + 1, kind).into());
+            };
+
+            match kind.as_str() {
+                "NS" if owner.is_empty() => nameservers.push(normal(data)),
+                "A" => {
+                    let address = data
+                        .parse::<Ipv4Addr>()
+                        .map_err(|_| format!("Line {} of the root hints has a bad address {}", number + 1, data))?;
+                    addresses.push((owner, address));
+                }
+                _ => {}
+            }
+        }
+
+        let servers = root_addresses(&nameservers, &addresses);
+        if servers.is_empty() {
+            return Err("No root server addresses in the root hints".into());
+        }
+
+        Ok(RootHints::with_servers(servers))
+    }
+
+    // the root servers as the last priming query found them, the hints before that
+    pub fn servers(&self) -> Vec<Ipv4Addr> {
+        lock(&self.primed).servers.clone()
+    }
+
+    // asks the servers there are for the root NS records and takes the addresses that come with them,
+    // when that fails the servers stay as they were
+    pub fn prime(&self) -> Result<Vec<Ipv4Addr>> {
+        let response = ask_any("", QueryType::NS, false, &self.servers());
+        self.primed(response)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub async fn prime_async(&self, timeout: Duration) -> Result<Vec<Ipv4Addr>> {
+        let response = ask_any_async("", QueryType::NS, false, &self.servers(), timeout).await;
+        self.primed(response)
+    }
+
+    // whether it's time to prime, true only for the first to ask so just one of them does
+    pub(crate) fn priming_due(&self) -> bool {
+        let mut primed = lock(&self.primed);
+        let now = Instant::now();
+        if primed.next.is_some_and(|next| next > now) {
+            return false;
+        }
+
+        primed.next = Some(now + PRIME_RETRY);
+        true
+    }
+
+    fn primed(&self, response: Result<DnsPacket>) -> Result<Vec<Ipv4Addr>> {
+        let priming = response.and_then(|response| priming_answer(&response));
+
+        let mut primed = lock(&self.primed);
+        match priming {
+            Ok((servers, ttl)) => {
+                primed.servers = servers.clone();
+                primed.next = Some(Instant::now() + ttl.min(MAX_PRIMED));
+                Ok(servers)
+            }
+            Err(e) => {
+                primed.next = Some(Instant::now() + PRIME_RETRY);
+                Err(e)
+            }
+        }
+    }
+}
+
+// the addresses the root servers answered the priming query with and how long the NS records live
+fn priming_answer(response: &DnsPacket) -> Result<(Vec<Ipv4Addr>, Duration)> {
+    response.check_response_code()?;
+
+    let root_ns = response.answers.iter().filter_map(|record| match record {
+        DnsRecord::NS { domain, host, ttl } if normal(domain).is_empty() => Some((normal(host), *ttl)),
+        _ => None,
+    });
+    let (nameservers, ttls): (Vec<String>, Vec<u32>) = root_ns.unzip();
+    let addresses: Vec<(String, Ipv4Addr)> = response
+        .resources
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::A { domain, address, .. } => Some((normal(domain), *address)),
+            _ => None,
+        })
+        .collect();
+
+    let servers = root_addresses(&nameservers, &addresses);
+    if servers.is_empty() {
+        return Err("The priming response has no root server addresses".into());
+    }
+    let ttl = ttls.into_iter().min().unwrap_or_default();
+
+    Ok((servers, Duration::from_secs(u64::from(ttl))))
+}
+
+// the addresses of the root name servers among addresses, all of them if there are no NS records
+fn root_addresses(nameservers: &[String], addresses: &[(String, Ipv4Addr)]) -> Vec<Ipv4Addr> {
+    let mut servers: Vec<Ipv4Addr> = addresses
+        .iter()
+        .filter(|(owner, _)| nameservers.is_empty() || nameservers.contains(owner))
+        .map(|(_, address)| *address)
+        .collect();
+    servers.dedup();
+
+    servers
+}
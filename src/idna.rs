@@ -0,0 +1,138 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+// punycode parameters from RFC 3492
+const PUNY_BASE: u32 = 36;
+const PUNY_TMIN: u32 = 1;
+const PUNY_TMAX: u32 = 26;
+const PUNY_SKEW: u32 = 38;
+const PUNY_DAMP: u32 = 700;
+const PUNY_INITIAL_BIAS: u32 = 72;
+const PUNY_INITIAL_N: u32 = 128;
+
+// decodes every xn-- label of a dotted name, labels that aren't valid punycode are left as they are
+pub fn decode_idn(name: &str) -> String {
+    name.split('.')
+        .map(|label| match label.strip_prefix("xn--").and_then(decode_punycode) {
+            Some(decoded) => decoded,
+            None => label.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn punycode_adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / PUNY_DAMP } else { delta / 2 };
+    delta += delta / num_points;
+
+    let mut k = 0;
+    while delta > ((PUNY_BASE - PUNY_TMIN) * PUNY_TMAX) / 2 {
+        delta /= PUNY_BASE - PUNY_TMIN;
+        k += PUNY_BASE;
+    }
+
+    k + (((PUNY_BASE - PUNY_TMIN + 1) * delta) / (delta + PUNY_SKEW))
+}
+
+// decodes a single punycode label (without the xn-- prefix), None if it is malformed
+fn decode_punycode(input: &str) -> Option<String> {
+    // everything before the last delimiter is copied over literally
+    let (basic, extended) = match input.rfind('-') {
+        Some(idx) => (&input[..idx], &input[idx + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = PUNY_INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = PUNY_INITIAL_BIAS;
+
+    let mut digits = extended.bytes().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut weight: u32 = 1;
+        let mut k = PUNY_BASE;
+
+        loop {
+            let digit = match digits.next()? {
+                byte @ b'a'..=b'z' => byte - b'a',
+                byte @ b'A'..=b'Z' => byte - b'A',
+                byte @ b'0'..=b'9' => byte - b'0' + 26,
+                _ => return None,
+            } as u32;
+
+            i = i.checked_add(digit.checked_mul(weight)?)?;
+
+            let threshold = if k <= bias {
+                PUNY_TMIN
+            } else if k >= bias + PUNY_TMAX {
+                PUNY_TMAX
+            } else {
+                k - bias
+            };
+
+            if digit < threshold {
+                break;
+            }
+
+            weight = weight.checked_mul(PUNY_BASE - threshold)?;
+            k += PUNY_BASE;
+        }
+
+        let length = output.len() as u32 + 1;
+        bias = punycode_adapt(i - old_i, length, old_i == 0);
+        n = n.checked_add(i / length)?;
+        i %= length;
+
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_known_labels() {
+        assert_eq!(decode_punycode("bcher-kva").as_deref(), Some("bücher"));
+        assert_eq!(decode_punycode("mnchen-3ya").as_deref(), Some("münchen"));
+        // sample L of RFC 3492
+        assert_eq!(
+            decode_punycode("3B-ww4c5e180e575a65lsy2b").as_deref(),
+            Some("3年B組金八先生")
+        );
+    }
+
+    #[test]
+    fn decodes_every_xn_label_of_a_name() {
+        assert_eq!(decode_idn("xn--bcher-kva.example"), "bücher.example");
+        assert_eq!(
+            decode_idn("www.xn--mnchen-3ya.xn--bcher-kva.de"),
+            "www.münchen.bücher.de"
+        );
+        assert_eq!(decode_idn("plain.example.com"), "plain.example.com");
+    }
+
+    #[test]
+    fn rejects_malformed_labels() {
+        // not a base 36 digit
+        assert_eq!(decode_punycode("bcher-k!a"), None);
+        // the input stops in the middle of a variable length number
+        assert_eq!(decode_punycode("bcher-kv"), None);
+        // only ascii can come before the delimiter
+        assert_eq!(decode_punycode("bü-kva"), None);
+        // the code point runs past what a u32 can hold
+        assert_eq!(decode_punycode("99999999999999"), None);
+
+        assert_eq!(decode_idn("xn--bcher-kv.example"), "xn--bcher-kv.example");
+        assert_eq!(decode_idn("xn--a!.example"), "xn--a!.example");
+    }
+}
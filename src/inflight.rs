@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::{
+    cache::{lock, CacheKey},
+    DnsPacket, Result,
+};
+
+// the upstream queries currently being waited on, a question that's already being asked for waits
+// for that answer instead of asking again
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    flights: Mutex<HashMap<CacheKey, Arc<Flight>>>,
+}
+
+#[derive(Debug, Default)]
+struct Flight {
+    outcome: Mutex<Option<Result<DnsPacket>>>,
+    landed: Condvar,
+}
+
+// takes the flight off the board when the query that started it is done, even by panicking, so the
+// ones waiting on it aren't left hanging
+struct Landing<'a> {
+    flights: &'a Mutex<HashMap<CacheKey, Arc<Flight>>>,
+    key: CacheKey,
+    flight: Arc<Flight>,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        lock(self.flights).remove(&self.key);
+
+        let mut outcome = lock(&self.flight.outcome);
+        if outcome.is_none() {
+            *outcome = Some(Err("Query was abandoned".into()));
+        }
+        self.flight.landed.notify_all();
+    }
+}
+
+impl InFlight {
+    // runs fetch unless the same question is already being asked, in which case its answer is waited for
+    pub(crate) fn run(&self, key: CacheKey, fetch: impl FnOnce() -> Result<DnsPacket>) -> Result<DnsPacket> {
+        let (flight, leading) = {
+            let mut flights = lock(&self.flights);
+            match flights.get(&key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    flights.insert(key.clone(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if !leading {
+            let mut outcome = lock(&flight.outcome);
+            while outcome.is_none() {
+                outcome = flight
+                    .landed
+                    .wait(outcome)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            return outcome.clone().unwrap_or_else(|| Err("Query was abandoned".into()));
+        }
+
+        let landing = Landing {
+            flights: &self.flights,
+            key,
+            flight,
+        };
+        let outcome = fetch();
+        *lock(&landing.flight.outcome) = Some(outcome.clone());
+
+        outcome
+    }
+}
+
+// the outcome of every question being asked, None until its query is done
+#[cfg(feature = "tokio")]
+type AsyncFlights = Arc<Mutex<HashMap<CacheKey, tokio::sync::watch::Receiver<Option<Result<DnsPacket>>>>>>;
+
+// the same for async resolvers, where waiting mustn't block the thread
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default)]
+pub(crate) struct AsyncInFlight {
+    flights: AsyncFlights,
+}
+
+// removes the flight once its query is done or dropped, dropping the sender tells the waiting
+// ones that no answer is coming
+#[cfg(feature = "tokio")]
+struct AsyncLanding {
+    flights: AsyncFlights,
+    key: CacheKey,
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for AsyncLanding {
+    fn drop(&mut self) {
+        lock(&self.flights).remove(&self.key);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncInFlight {
+    pub(crate) async fn run(
+        &self,
+        key: CacheKey,
+        fetch: impl core::future::Future<Output = Result<DnsPacket>>,
+    ) -> Result<DnsPacket> {
+        let waiting = {
+            let mut flights = lock(&self.flights);
+            match flights.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = tokio::sync::watch::channel(None);
+                    flights.insert(key.clone(), receiver);
+                    Ok(sender)
+                }
+            }
+        };
+
+        let sender = match waiting {
+            Ok(sender) => sender,
+            Err(mut receiver) => {
+                let outcome = receiver
+                    .wait_for(Option::is_some)
+                    .await
+                    .map_err(|_| "Query was abandoned")?;
+                return outcome.clone().unwrap_or_else(|| Err("Query was abandoned".into()));
+            }
+        };
+
+        let _landing = AsyncLanding {
+            flights: self.flights.clone(),
+            key,
+        };
+        let outcome = fetch.await;
+        sender.send_replace(Some(outcome.clone()));
+
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        edns::{ExtendedError, EDE_SIGNATURE_EXPIRED},
+        DnsError, QueryType,
+    };
+
+    fn key() -> CacheKey {
+        ("example.com".to_string(), QueryType::A, 1, None)
+    }
+
+    fn bogus() -> DnsError {
+        DnsError::Bogus(ExtendedError::new(EDE_SIGNATURE_EXPIRED).with_text("RRSIG expired"))
+    }
+
+    #[test]
+    fn waiters_get_the_same_error() {
+        let flights = &InFlight::default();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+
+        std::thread::scope(|scope| {
+            let leader = scope.spawn(move || {
+                flights.run(key(), move || {
+                    released.recv().unwrap();
+                    Err(bogus())
+                })
+            });
+            // the map, the leader's landing and this clone hold the flight, each waiter on it adds one
+            let flight = loop {
+                if let Some(flight) = lock(&flights.flights).get(&key()) {
+                    break flight.clone();
+                }
+                std::thread::yield_now();
+            };
+            let waiters: Vec<_> = (0..2)
+                .map(|_| scope.spawn(|| flights.run(key(), || panic!("the question was asked twice"))))
+                .collect();
+            while Arc::strong_count(&flight) < 5 {
+                std::thread::yield_now();
+            }
+            release.send(()).unwrap();
+
+            assert_eq!(leader.join().unwrap(), Err(bogus()));
+            for waiter in waiters {
+                assert_eq!(waiter.join().unwrap(), Err(bogus()));
+            }
+        });
+        assert!(lock(&flights.flights).is_empty());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_waiters_get_the_same_error() {
+        let flights = AsyncInFlight::default();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let leader = flights.run(key(), async {
+            released.await.unwrap();
+            Err(bogus())
+        });
+        let waiter = || flights.run(key(), async { panic!("the question was asked twice") });
+        // join polls in order, so both waiters find the leader's flight before it's released
+        let releasing = async {
+            tokio::task::yield_now().await;
+            release.send(()).unwrap();
+        };
+
+        let (leader, first, second, ()) = tokio::join!(leader, waiter(), waiter(), releasing);
+        assert_eq!(leader, Err(bogus()));
+        assert_eq!(first, Err(bogus()));
+        assert_eq!(second, Err(bogus()));
+    }
+}
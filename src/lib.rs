@@ -0,0 +1,162 @@
+// without the std feature only the packet types and their parsing are available, which just need alloc
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod acl;
+#[cfg(feature = "std")]
+pub mod blocklist;
+pub mod buffer;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod client;
+#[cfg(feature = "std")]
+pub mod cookie;
+#[cfg(feature = "std")]
+pub mod dns64;
+#[cfg(feature = "dnscrypt")]
+pub mod dnscrypt;
+#[cfg(feature = "dnssec")]
+pub mod dnssec;
+#[cfg(feature = "doh")]
+pub mod doh;
+#[cfg(feature = "doh-server")]
+pub mod doh_server;
+#[cfg(feature = "doq")]
+pub mod doq;
+pub mod edns;
+pub mod encoding;
+pub mod error;
+pub mod header;
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod hints;
+pub mod idna;
+#[cfg(feature = "std")]
+mod inflight;
+#[cfg(feature = "std")]
+pub mod llmnr;
+#[cfg(feature = "std")]
+pub mod local;
+#[cfg(feature = "std")]
+pub mod mdns;
+#[cfg(feature = "std")]
+pub mod notify;
+pub mod packet;
+pub mod presentation;
+#[cfg(feature = "std")]
+pub mod querylog;
+pub mod question;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+pub mod record;
+#[cfg(feature = "std")]
+pub mod resolver;
+#[cfg(feature = "std")]
+pub mod rng;
+#[cfg(feature = "std")]
+pub mod rpz;
+#[cfg(feature = "std")]
+pub mod server;
+pub mod stamp;
+pub mod svcb;
+#[cfg(feature = "std")]
+pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "std")]
+pub mod transfer;
+#[cfg(feature = "tsig")]
+pub mod tsig;
+#[cfg(feature = "std")]
+pub mod update;
+#[cfg(feature = "std")]
+pub mod view;
+#[cfg(feature = "std")]
+pub mod zone;
+#[cfg(feature = "std")]
+mod zonefile;
+
+#[cfg(feature = "std")]
+pub use acl::{AccessControl, Acl};
+#[cfg(feature = "std")]
+pub use blocklist::{BlockMode, Blocklist};
+pub use buffer::BytePacketBuffer;
+#[cfg(feature = "std")]
+pub use cache::{shared_cache, DnsCache, SharedCache};
+#[cfg(feature = "std")]
+pub use client::{
+    lookup, lookup_many, lookup_with_fallback, lookup_with_fallback_timeout, lookup_with_timeout, reverse_lookup,
+    Resolver, SubnetSource,
+};
+#[cfg(feature = "tokio")]
+pub use client::{lookup_async, AsyncResolver};
+#[cfg(feature = "std")]
+pub use cookie::Cookies;
+#[cfg(feature = "std")]
+pub use dns64::Dns64;
+#[cfg(feature = "dnscrypt")]
+pub use dnscrypt::{Certificate, DnsCryptClient};
+#[cfg(feature = "dnssec")]
+pub use dnssec::{Security, Validator};
+#[cfg(feature = "doh")]
+pub use doh::{DohClient, DohMethod};
+#[cfg(feature = "doh-server")]
+pub use doh_server::serve_doh;
+#[cfg(feature = "doq")]
+pub use doq::{serve_doq, DoqClient};
+pub use edns::{ClientSubnet, Edns, EdnsOption, ExtendedError};
+pub use error::DnsError;
+pub use header::{DnsHeader, ResultCode};
+#[cfg(feature = "std")]
+pub use hints::RootHints;
+#[cfg(feature = "std")]
+pub use llmnr::{query_llmnr, LlmnrResponder};
+#[cfg(feature = "std")]
+pub use local::LocalRecords;
+#[cfg(feature = "std")]
+pub use mdns::{query_mdns, MdnsResponder};
+#[cfg(feature = "std")]
+pub use notify::{Notify, Secondary};
+pub use packet::{parse_bytes, DnsPacket, PacketSummary};
+pub use presentation::{parse_record, zone_file};
+#[cfg(feature = "std")]
+pub use querylog::{EventLog, LogFormat, QueryLog};
+pub use question::{reverse_name, DnsQuestion, QueryType};
+#[cfg(feature = "std")]
+pub use ratelimit::RateLimit;
+pub use record::DnsRecord;
+#[cfg(feature = "std")]
+pub use resolver::{recursive_lookup, recursive_lookup_from};
+#[cfg(feature = "tokio")]
+pub use resolver::{recursive_lookup_async, recursive_lookup_from_async};
+#[cfg(feature = "std")]
+pub use rng::random_id;
+#[cfg(feature = "std")]
+pub use rpz::{Policy, Rpz};
+#[cfg(feature = "tokio")]
+pub use server::serve_async;
+#[cfg(feature = "std")]
+pub use server::{serve, ForwardServer, Upstream};
+pub use stamp::{DnsStamp, StampProperties};
+pub use svcb::{SvcParam, SvcParams};
+#[cfg(feature = "std")]
+pub use tcp::{lookup_tcp, TcpClient};
+#[cfg(feature = "tls")]
+pub use tls::{serve_tls, TlsClient};
+#[cfg(feature = "std")]
+pub use transfer::{apply_changes, axfr, ZoneChanges, ZoneDiff, ZoneTransfer};
+#[cfg(feature = "tsig")]
+pub use tsig::{exchange_signed, TsigAlgorithm, TsigKey};
+#[cfg(feature = "std")]
+pub use update::Update;
+#[cfg(feature = "std")]
+pub use view::View;
+#[cfg(feature = "std")]
+pub use zone::Zone;
+
+// aliases for ease of coding
+pub type Result<T> = core::result::Result<T, DnsError>;
@@ -0,0 +1,266 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    header::OPCODE_QUERY,
+    mdns::{bind_group, encode, is_mdns_name, receive_until, same_data, MAX_MESSAGE_SIZE},
+    parse_bytes,
+    querylog::EventLog,
+    random_id, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode,
+};
+
+// where link local multicast name resolution goes, RFC 4795 has it on a port of its own next to
+// multicast DNS
+pub const LLMNR_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+pub const LLMNR_PORT: u16 = 5355;
+
+// how long answers live, the default RFC 4795 suggests
+pub const LLMNR_TTL: u32 = 30;
+
+// how long queries wait for answers unless told otherwise, LLMNR_TIMEOUT in RFC 4795
+pub const DEFAULT_LLMNR_TIMEOUT: Duration = Duration::from_secs(1);
+
+// whether name is one LLMNR answers for, a single label that isn't localhost and isn't left to
+// multicast DNS the way local is
+pub fn is_llmnr_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    !name.is_empty() && !name.contains('.') && !name.eq_ignore_ascii_case("localhost") && !is_mdns_name(name)
+}
+
+// asks the local link for question over LLMNR, the way Windows resolves the names of the hosts next to
+// it. Responders answer straight back to the port the query came from. The first answer that doesn't
+// have the C bit is the one, since only one host has the name. Names several hosts share have it, and
+// those get the whole timeout for everyone to answer. Answers with the T bit come from hosts that
+// haven't made sure the name is theirs yet and are only used when nobody else answers. Nobody answering
+// at all is NXDOMAIN
+pub fn query_llmnr(question: &DnsQuestion, timeout: Duration) -> Result<DnsPacket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // LLMNR never leaves the link
+    socket.set_multicast_ttl_v4(1)?;
+
+    let mut query = DnsPacket::new();
+    query.header.id = random_id();
+    query.questions.push(DnsQuestion::new(
+        question.name.trim_end_matches('.').to_string(),
+        question.qtype,
+    ));
+    socket.send_to(&encode(&query)?, (LLMNR_GROUP, LLMNR_PORT))?;
+
+    let mut answer = DnsPacket::new();
+    answer.header.response = true;
+    answer.header.id = query.header.id;
+    answer
+        .questions
+        .push(DnsQuestion::new(question.name.clone(), question.qtype));
+    let (mut answered, mut tentative) = (false, None);
+
+    let deadline = Instant::now() + timeout;
+    let mut data = vec![0; MAX_MESSAGE_SIZE];
+    while let Some((length, source)) = receive_until(&socket, deadline, &mut data)? {
+        let Ok(response) = parse_bytes(&data[..length]) else {
+            continue;
+        };
+        if source.port() != LLMNR_PORT || !answers(&query, &response) {
+            continue;
+        }
+
+        // the C and T bits are where AA and RD are in unicast DNS
+        let header = &response.header;
+        let (conflict, unverified) = (header.authoritative_answer, header.recursion_desired);
+        if unverified {
+            tentative.get_or_insert(response.answers);
+            continue;
+        }
+        answered = true;
+        if !conflict {
+            answer.answers = response.answers;
+            break;
+        }
+        for record in response.answers {
+            if !answer.answers.contains(&record) {
+                answer.answers.push(record);
+            }
+        }
+    }
+
+    match tentative {
+        Some(records) if !answered => answer.answers = records,
+        None if !answered => answer.header.result_code = ResultCode::NXDOMAIN,
+        _ => {}
+    }
+    // the counts it would have if it came off the wire in one piece
+    answer.header.questions = 1;
+    answer.header.answers = answer.answers.len() as u16;
+
+    Ok(answer)
+}
+
+// answers for this host's name on the local link the way Windows does, its single label name with its
+// addresses. Before answering it makes sure no other host on the link already answers for the name, and
+// queries are only ever answered straight back to whoever asked
+#[derive(Clone, Debug, Default)]
+pub struct LlmnrResponder {
+    records: Vec<DnsRecord>,
+    events: EventLog,
+}
+
+impl LlmnrResponder {
+    pub fn new() -> LlmnrResponder {
+        LlmnrResponder::default()
+    }
+
+    pub fn with_host(mut self, name: &str, addresses: &[IpAddr]) -> LlmnrResponder {
+        let domain = name.trim_end_matches('.').to_string();
+        for address in addresses {
+            self = self.with_record(match *address {
+                IpAddr::V4(address) => DnsRecord::A {
+                    domain: domain.clone(),
+                    address,
+                    ttl: LLMNR_TTL,
+                },
+                IpAddr::V6(address) => DnsRecord::AAAA {
+                    domain: domain.clone(),
+                    address,
+                    ttl: LLMNR_TTL,
+                },
+            });
+        }
+
+        self
+    }
+
+    pub fn with_record(mut self, record: DnsRecord) -> LlmnrResponder {
+        if !self.records.contains(&record) {
+            self.records.push(record);
+        }
+        self
+    }
+
+    // where other hosts answering for our names and queries it couldn't answer are reported
+    pub fn with_event_log(mut self, events: EventLog) -> LlmnrResponder {
+        self.events = events;
+        self
+    }
+
+    pub fn records(&self) -> &[DnsRecord] {
+        &self.records
+    }
+
+    // makes sure the names are ours, then answers queries on port 5355 until receiving fails. Another
+    // host answering for one of the names is an error, it's theirs
+    pub fn run(&self) -> Result<()> {
+        let socket = bind_group(LLMNR_GROUP, LLMNR_PORT)?;
+        socket.set_ttl(1)?;
+        socket.set_multicast_ttl_v4(1)?;
+        self.verify(&socket)?;
+
+        let mut data = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            let (length, source) = socket.recv_from(&mut data)?;
+            let Ok(query) = parse_bytes(&data[..length]) else {
+                continue;
+            };
+            // a query with the C bit tells the responders that more than one of them answered it
+            if !query.header.response && query.header.authoritative_answer {
+                let names: Vec<&str> = query.questions.iter().map(|question| &question.name[..]).collect();
+                self.events.report(format_args!(
+                    "{} says another host on the link answers for {} too",
+                    source,
+                    names.join(", ")
+                ));
+                continue;
+            }
+            let Some(response) = self.response(&query) else {
+                continue;
+            };
+            if let Err(e) = socket.send_to(&encode(&response)?, source) {
+                self.events
+                    .report(format_args!("Couldn't answer {} over LLMNR: {}", source, e));
+            }
+        }
+    }
+
+    // the response to query, None when it isn't for one of our names. RFC 4795 has queries with more
+    // than one question or with records in them thrown away. A name of ours without the type asked for
+    // gets an empty answer, so the querier doesn't wait for one that won't come
+    pub(crate) fn response(&self, query: &DnsPacket) -> Option<DnsPacket> {
+        let header = &query.header;
+        let plain = !header.response && header.opcode == OPCODE_QUERY && header.result_code == ResultCode::NOERROR;
+        let [question] = query.questions.as_slice() else {
+            return None;
+        };
+        let records = !query.answers.is_empty() || !query.authorities.is_empty();
+        if !plain || header.authoritative_answer || records {
+            return None;
+        }
+
+        let named = self
+            .records
+            .iter()
+            .filter(|record| record.domain().eq_ignore_ascii_case(&question.name));
+        let mut named = named.peekable();
+        named.peek()?;
+
+        let mut response = DnsPacket::new();
+        response.header.id = header.id;
+        response.header.response = true;
+        response.questions.push(question.clone());
+        response.answers = named
+            .filter(|record| question.qtype == QueryType::ANY || record.query_type() == question.qtype)
+            .cloned()
+            .collect();
+
+        Some(response)
+    }
+
+    // asks the link for every one of our names and types, any host answering with other records for
+    // them within LLMNR_TIMEOUT has the name already (RFC 4795 section 4)
+    fn verify(&self, socket: &UdpSocket) -> Result<()> {
+        let mut queries = Vec::new();
+        for record in &self.records {
+            let mut query = DnsPacket::new();
+            query.header.id = random_id();
+            query
+                .questions
+                .push(DnsQuestion::new(record.domain().to_string(), record.query_type()));
+            socket.send_to(&encode(&query)?, (LLMNR_GROUP, LLMNR_PORT))?;
+            queries.push(query);
+        }
+
+        let deadline = Instant::now() + DEFAULT_LLMNR_TIMEOUT;
+        let mut data = vec![0; MAX_MESSAGE_SIZE];
+        while let Some((length, source)) = receive_until(socket, deadline, &mut data)? {
+            let Ok(response) = parse_bytes(&data[..length]) else {
+                continue;
+            };
+            if !queries.iter().any(|query| answers(query, &response)) {
+                continue;
+            }
+            let theirs = response
+                .answers
+                .iter()
+                .any(|record| !self.records.iter().any(|own| same_data(own, record)));
+            if theirs {
+                let name = &response.questions[0].name;
+                return Err(format!("{} on the link already answers for {}", source.ip(), name).into());
+            }
+        }
+        socket.set_read_timeout(None)?;
+
+        Ok(())
+    }
+}
+
+// whether response is an answer to query, with its id and question. Answers with an error code are
+// thrown away like RFC 4795 has it
+fn answers(query: &DnsPacket, response: &DnsPacket) -> bool {
+    let header = &response.header;
+    let question = match (query.questions.first(), response.questions.as_slice()) {
+        (Some(asked), [question]) => question.name.eq_ignore_ascii_case(&asked.name) && question.qtype == asked.qtype,
+        _ => false,
+    };
+
+    header.response && header.id == query.header.id && header.result_code == ResultCode::NOERROR && question
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashMap, net::IpAddr, path::Path};
+
+use crate::{
+    question::reverse_name, zone::MAX_CNAME_CHAIN, zonefile::parse_records, DnsPacket, DnsQuestion, DnsRecord,
+    QueryType, Result,
+};
+
+// what hosts file entries are answered with, they're looked up locally anyway so there's nothing to
+// gain from clients caching them
+pub const HOSTS_TTL: u32 = 0;
+
+// names answered locally, before the cache and the upstreams are asked, from hosts files like
+// /etc/hosts and from override files of records written the way zone files have them. Only names that
+// are in them are answered, everything else is resolved as usual
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LocalRecords {
+    // by lowercased name, the records of each in the order they were added
+    names: HashMap<String, Vec<DnsRecord>>,
+}
+
+impl LocalRecords {
+    pub fn new() -> LocalRecords {
+        LocalRecords::default()
+    }
+
+    pub fn read_hosts(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the hosts file {}: {}", path.display(), e))?;
+
+        self.parse_hosts(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    // the entries of a hosts file, an address followed by its names on every line, with # starting a
+    // comment. Names get an A or AAAA record, and the address a PTR record for the first name it's
+    // given. Link local addresses with a zone like fe80::1%eth0 mean nothing to others and are skipped
+    pub fn parse_hosts(&mut self, text: &str) -> Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(address) = fields.next() else {
+                continue;
+            };
+            if address.contains('%') {
+                continue;
+            }
+
+            let address: IpAddr = address
+                .parse()
+                .map_err(|_| format!("Line {}: Invalid address {}", number + 1, address))?;
+            let names: Vec<&str> = fields.map(|name| name.trim_end_matches('.')).collect();
+            let Some(first) = names.first() else {
+                return Err(format!("Line {}: {} has no names", number + 1, address).into());
+            };
+
+            for name in &names {
+                let domain = name.to_string();
+                self.add(match address {
+                    IpAddr::V4(address) => DnsRecord::A {
+                        domain,
+                        address,
+                        ttl: HOSTS_TTL,
+                    },
+                    IpAddr::V6(address) => DnsRecord::AAAA {
+                        domain,
+                        address,
+                        ttl: HOSTS_TTL,
+                    },
+                });
+            }
+            // an address listed again keeps the name it was given first
+            let reverse = reverse_name(address);
+            if !self.contains(&reverse) {
+                self.add(DnsRecord::PTR {
+                    domain: reverse,
+                    host: first.to_string(),
+                    ttl: HOSTS_TTL,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_records(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the override file {}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or(Path::new("."));
+
+        for record in parse_records(&text, &path.display().to_string(), directory)? {
+            self.add(record);
+        }
+
+        Ok(())
+    }
+
+    // the records of an override file, written like a zone file but with any owners and no SOA needed.
+    // Names that don't end in a dot are absolute too, unless an $ORIGIN says otherwise
+    pub fn parse_records(&mut self, text: &str) -> Result<()> {
+        for record in parse_records(text, "the override file", Path::new("."))? {
+            self.add(record);
+        }
+
+        Ok(())
+    }
+
+    pub fn add(&mut self, record: DnsRecord) {
+        let records = self.names.entry(record.domain().to_ascii_lowercase()).or_default();
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names
+            .contains_key(&name.trim_end_matches('.').to_ascii_lowercase())
+    }
+
+    // how many names there are records for
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // the answer to question when its name is one of the local ones, None when it has to be resolved.
+    // A name without the type asked for is NODATA, and cnames are followed while they lead to other
+    // local names, the rest of the way is what onward asks for
+    pub(crate) fn answer(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let mut name = question.name.trim_end_matches('.').to_ascii_lowercase();
+        self.names.get(&name)?;
+
+        let mut answer = DnsPacket::new();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let Some(records) = self.names.get(&name) else {
+                break;
+            };
+
+            let matching = records
+                .iter()
+                .filter(|record| question.qtype == QueryType::ANY || record.query_type() == question.qtype);
+            let count = answer.answers.len();
+            answer.answers.extend(matching.cloned());
+            if answer.answers.len() > count {
+                break;
+            }
+
+            let Some(cname @ DnsRecord::CNAME { host, .. }) =
+                records.iter().find(|record| matches!(record, DnsRecord::CNAME { .. }))
+            else {
+                break;
+            };
+            // a loop ends where it comes around again
+            if answer.answers.contains(cname) {
+                break;
+            }
+            answer.answers.push(cname.clone());
+            name = host.to_ascii_lowercase();
+        }
+
+        Some(answer)
+    }
+
+    // the question left for the upstreams when answer ends in a cname out of the local names
+    pub(crate) fn onward(&self, answer: &DnsPacket, qtype: QueryType) -> Option<DnsQuestion> {
+        match answer.answers.last() {
+            Some(DnsRecord::CNAME { host, .. }) if qtype != QueryType::CNAME && !self.contains(host) => {
+                Some(DnsQuestion::new(host.clone(), qtype))
+            }
+            _ => None,
+        }
+    }
+}
+
+// answer with the rest of the chain resolved from the upstreams added to it, and their result code
+pub(crate) fn completed(mut answer: DnsPacket, rest: DnsPacket) -> DnsPacket {
+    answer.header.result_code = rest.header.result_code;
+    answer.answers.extend(rest.answers);
+    answer.authorities = rest.authorities;
+
+    answer
+}
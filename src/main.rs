@@ -1,412 +1,1525 @@
-use std::{fs::File, io::Read, net::Ipv4Addr};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use dns_learning::{
+    cache::DEFAULT_CACHE_ENTRIES,
+    client::{build_query, exchange},
+    edns::OPTION_NSID,
+    llmnr::DEFAULT_LLMNR_TIMEOUT,
+    mdns::{DEFAULT_MDNS_TIMEOUT, MDNS_GROUP, MDNS_PORT},
+    parse_record,
+    presentation::absolute_name,
+    query_llmnr, query_mdns, serve,
+    server::DEFAULT_LISTEN,
+    AccessControl, Acl, BlockMode, Blocklist, ClientSubnet, Dns64, DnsCache, DnsPacket, DnsQuestion, DnsStamp,
+    EventLog, ForwardServer, LlmnrResponder, LocalRecords, LogFormat, MdnsResponder, Notify, QueryLog, QueryType,
+    RateLimit, Resolver, Result, RootHints, Rpz, Secondary, SubnetSource, TcpClient, Update, Upstream, View, Zone,
+    ZoneChanges, ZoneTransfer,
+};
+
+const DEFAULT_SERVER: (Ipv4Addr, u16) = (Ipv4Addr::new(8, 8, 8, 8), 53);
+
+/// A small dig-like DNS client
+#[derive(Parser, Debug)]
+#[command(name = "dnslearning", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a single query and print the response
+    Query(QueryArgs),
+    /// Answer queries over UDP by forwarding them or resolving them recursively
+    Serve(Box<ServeArgs>),
+    /// Change records in a zone with a dynamic update sent to its primary server
+    Update(UpdateArgs),
+    /// Tell a secondary server that a zone changed, so it transfers the zone again
+    Notify(NotifyArgs),
+}
+
+#[derive(Args, Debug)]
+struct QueryArgs {
+    /// Name to look up
+    name: String,
+
+    /// Record type (defaults to A) and @server[:port] (defaults to @8.8.8.8),
+    /// @dot://server[:port][#name], @doq://server[:port][#name], @dnscrypt://server[:port]#provider,
+    /// @https://url, @sdns://stamp, @mdns or @llmnr, in any order
+    #[arg(value_name = "TYPE|@SERVER")]
+    extra: Vec<String>,
+
+    /// How long to wait for the response, e.g. 2s or 500ms
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Send the query over TCP instead of UDP
+    #[arg(long)]
+    tcp: bool,
+
+    /// Use GET instead of POST for DNS over HTTPS
+    #[arg(long)]
+    get: bool,
 
+    /// The public key of the @dnscrypt:// provider, which its certificates are signed with, in hex
+    #[arg(long)]
+    provider_key: Option<String>,
 
-// aliases for ease of coding
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+    /// Have responders answer an @mdns query to the whole group (QM) instead of straight back (QU)
+    #[arg(long)]
+    qm: bool,
 
+    /// Ask on behalf of this network with the EDNS client subnet option, like 192.0.2.0/24
+    #[arg(long, value_parser = parse_subnet)]
+    subnet: Option<ClientSubnet>,
 
-pub struct BytePacketBuffer {
-    pub buffer: [u8; 512],
-    pub position: usize,
+    /// Ask the server for its NSID, which tells apart the instances behind an anycast address
+    #[arg(long)]
+    nsid: bool,
+
+    /// Set the DO bit so the answer comes with its DNSSEC signatures
+    #[arg(long)]
+    dnssec: bool,
+
+    /// Sign the query with this TSIG key, as [algorithm:]name:secret like dig -y
+    #[arg(long, conflicts_with = "tsig_file")]
+    tsig: Option<String>,
+
+    /// Sign the query with the first key in this BIND key file, like dig -k
+    #[arg(long)]
+    tsig_file: Option<PathBuf>,
+
+    /// Serial of the copy of the zone you have, for an IXFR of what changed since
+    #[arg(long)]
+    serial: Option<u32>,
+
+    /// Write the zone an AXFR brings to this zone file, which serve --zone can answer from
+    #[arg(long)]
+    save: Option<PathBuf>,
 }
 
-impl BytePacketBuffer {
-    pub fn new() -> BytePacketBuffer {
-        BytePacketBuffer{
-            buffer: [0;512],
-            position: 0,
-        }
-    }
+#[derive(Args, Debug)]
+struct UpdateArgs {
+    /// Zone to change
+    zone: String,
+
+    /// Primary server of the zone, as @server[:port]
+    #[arg(value_name = "@SERVER")]
+    server: String,
+
+    /// Record to add, like "www 300 A 192.0.2.1" with names relative to the zone, can be repeated
+    #[arg(long)]
+    add: Vec<String>,
+
+    /// Record to delete, given the same way as for --add, can be repeated
+    #[arg(long)]
+    delete: Vec<String>,
+
+    /// All records of a type at a name to delete, as "name type", can be repeated
+    #[arg(long)]
+    delete_rrset: Vec<String>,
+
+    /// Name to delete every record at, can be repeated
+    #[arg(long)]
+    delete_name: Vec<String>,
+
+    /// Only make the changes if this exists, as "name" for any record or "name type" for records of a type
+    #[arg(long)]
+    exists: Vec<String>,
+
+    /// Only make the changes if this doesn't exist, given the same way as for --exists
+    #[arg(long)]
+    absent: Vec<String>,
+
+    /// Only make the changes if the records of this one's type at its name are exactly the ones given
+    #[arg(long)]
+    require: Vec<String>,
+
+    /// TTL of added records that don't give one
+    #[arg(long, default_value_t = 3600)]
+    ttl: u32,
+
+    /// How long to wait for the response, e.g. 2s or 500ms
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Sign the update with this TSIG key, as [algorithm:]name:secret like nsupdate -y
+    #[arg(long, conflicts_with = "tsig_file")]
+    tsig: Option<String>,
+
+    /// Sign the update with the first key in this BIND key file, like nsupdate -k
+    #[arg(long)]
+    tsig_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct NotifyArgs {
+    /// Zone that changed
+    zone: String,
 
-    fn pos(&self) -> usize {
-        self.position
+    /// Secondary server to tell, as @server[:port]
+    #[arg(value_name = "@SERVER")]
+    server: String,
+
+    /// Serial the zone is at now, so a secondary that already has it can skip the transfer
+    #[arg(long)]
+    serial: Option<u32>,
+
+    /// How long to wait for the response, e.g. 2s or 500ms
+    #[arg(long, default_value = "5s", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Sign the NOTIFY with this TSIG key, as [algorithm:]name:secret
+    #[arg(long, conflicts_with = "tsig_file")]
+    tsig: Option<String>,
+
+    /// Sign the NOTIFY with the first key in this BIND key file
+    #[arg(long)]
+    tsig_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// Address and port to listen on
+    #[arg(long, default_value_t = SocketAddrV4::new(DEFAULT_LISTEN.0, DEFAULT_LISTEN.1))]
+    listen: SocketAddrV4,
+
+    /// Server to forward queries to, as ip[:port] or the sdns:// stamp of a plain DNS server, repeat it
+    /// for servers to fail over to in order
+    #[arg(long, default_value = "8.8.8.8", value_parser = parse_server, conflicts_with = "recursive")]
+    forward: Vec<(Ipv4Addr, u16)>,
+
+    /// Forward the names in a domain to other servers than everything else, as domain=ip[:port][,...]
+    /// like corp.internal=10.0.0.53. The longest domain a name is in wins. Can be repeated
+    #[arg(long)]
+    forward_zone: Vec<String>,
+
+    /// How long to wait for a forwarding server before trying again or moving on
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    upstream_timeout: Duration,
+
+    /// How many times to ask a forwarding server again before moving on to the next
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Ask this many of the forwarding servers at once and use the first valid answer
+    #[arg(long, default_value_t = 0)]
+    race: usize,
+
+    /// Ask the fastest healthy forwarding server first instead of going in order
+    #[arg(long)]
+    adaptive: bool,
+
+    /// Resolve queries from the root servers instead of forwarding them
+    #[arg(long)]
+    recursive: bool,
+
+    /// Start recursion from the root servers in this named.root file instead of the built in ones
+    #[arg(long, requires = "recursive")]
+    root_hints: Option<PathBuf>,
+
+    /// How many questions to keep cached answers for
+    #[arg(long, default_value_t = DEFAULT_CACHE_ENTRIES)]
+    cache_size: usize,
+
+    /// Answer from expired cache entries up to this long past their TTL when upstreams fail, like 1d
+    #[arg(long, value_parser = parse_duration)]
+    serve_stale: Option<Duration>,
+
+    /// Refresh answers asked for this many times shortly before they expire
+    #[arg(long)]
+    prefetch: Option<u32>,
+
+    /// Send forwarding servers this client subnet, like 192.0.2.0/24, or "client" for the network of
+    /// whoever asked
+    #[arg(long, value_parser = parse_subnet_source)]
+    client_subnet: Option<SubnetSource>,
+
+    /// What to answer queries asking for the server's NSID with
+    #[arg(long)]
+    nsid: Option<String>,
+
+    /// Validate answers with DNSSEC from the root trust anchor, bogus ones are answered with SERVFAIL
+    #[arg(long)]
+    dnssec: bool,
+
+    /// Trust the anchors in this root-anchors.xml or DS record file instead of the built in ones
+    #[arg(long, requires = "dnssec")]
+    trust_anchors: Option<PathBuf>,
+
+    /// A TSIG key clients may sign queries with, as [algorithm:]name:secret, can be repeated
+    #[arg(long)]
+    tsig: Vec<String>,
+
+    /// A BIND key file with TSIG keys clients may sign queries with, can be repeated
+    #[arg(long)]
+    tsig_file: Vec<PathBuf>,
+
+    /// Answer authoritatively for a zone from a zone file, as origin=path like example.com=db.example.com,
+    /// can be repeated
+    #[arg(long)]
+    zone: Vec<String>,
+
+    /// Keep a copy of a zone from its primary, as zone@primary[:port], it's transferred again whenever
+    /// the primary sends a NOTIFY. Transfers are signed with the first --tsig key. Can be repeated
+    #[arg(long)]
+    secondary: Vec<String>,
+
+    /// Answer the names in a hosts file like /etc/hosts before asking the cache or upstreams, can be
+    /// repeated
+    #[arg(long)]
+    hosts: Vec<PathBuf>,
+
+    /// Answer the names in a file of records written like a zone file's, with names absolute unless
+    /// there's an $ORIGIN, before asking the cache or upstreams. Can be repeated
+    #[arg(long)]
+    overrides: Vec<PathBuf>,
+
+    /// Apply the policies of a response policy zone from a zone file, as origin=path like
+    /// rpz.example=db.rpz, after the local names and before the blocklists. Can be repeated, the first
+    /// zone with a policy for a name wins
+    #[arg(long)]
+    rpz: Vec<String>,
+
+    /// Don't resolve the names in this list or anything below them, the list is in hosts format or one
+    /// name a line. Can be repeated
+    #[arg(long)]
+    blocklist: Vec<PathBuf>,
+
+    /// What blocked names are answered with, nxdomain or null for 0.0.0.0 and ::
+    #[arg(long, default_value = "nxdomain")]
+    block_mode: BlockMode,
+
+    /// Answer the clients in some networks from a view of their own, as name=network[,network...] like
+    /// internal=10.0.0.0/8,192.168.0.0/16. The first view a client is in answers it, everyone else gets
+    /// the server's own answers. Can be repeated
+    #[arg(long)]
+    view: Vec<String>,
+
+    /// Answer a zone from a zone file to the clients of a view, as view:origin=path like
+    /// internal:example.com=db.internal. A view only answers its own zones. Can be repeated
+    #[arg(long)]
+    view_zone: Vec<String>,
+
+    /// Server for a view to forward queries to, as view:ip[:port]. Views without one resolve the way the
+    /// server does. Can be repeated
+    #[arg(long)]
+    view_forward: Vec<String>,
+
+    /// Resolve .local names and the reverse names of link local addresses over multicast DNS
+    #[arg(long)]
+    mdns: bool,
+
+    /// Answer for NAME.local over multicast DNS with --mdns-address, after making sure no other host
+    /// on the network already does
+    #[arg(long, value_name = "NAME")]
+    mdns_host: Option<String>,
+
+    /// An address of --mdns-host, by default the one this host sends multicast from. Can be repeated
+    #[arg(long, requires = "mdns_host")]
+    mdns_address: Vec<IpAddr>,
+
+    /// Resolve single label names over LLMNR, the way Windows hosts find each other
+    #[arg(long)]
+    llmnr: bool,
+
+    /// Answer for NAME over LLMNR with --llmnr-address, after making sure no other host on the link
+    /// already does
+    #[arg(long, value_name = "NAME")]
+    llmnr_host: Option<String>,
+
+    /// An address of --llmnr-host, by default the one this host sends multicast from. Can be repeated
+    #[arg(long, requires = "llmnr_host")]
+    llmnr_address: Vec<IpAddr>,
+
+    /// Make AAAA records up for names that only have A records, with their ipv4 addresses in the
+    /// --dns64-prefix of a NAT64 gateway like RFC 6147 has it
+    #[arg(long)]
+    dns64: bool,
+
+    /// The NAT64 prefix for --dns64, 32, 40, 48, 56, 64 or 96 bits long
+    #[arg(long, default_value = "64:ff9b::/96", requires = "dns64")]
+    dns64_prefix: Dns64,
+
+    /// Queries a second one network may send over UDP before the rest are dropped
+    #[arg(long)]
+    rate_limit: Option<u32>,
+
+    /// The limit for queries that come with a valid server cookie, ten times --rate-limit by default
+    #[arg(long, requires = "rate_limit")]
+    cookie_rate_limit: Option<u32>,
+
+    /// How many bits of an IPv4 and an IPv6 address make up the network --rate-limit counts it in
+    #[arg(long, num_args = 2, value_names = ["IPV4", "IPV6"], default_values_t = [24, 56])]
+    #[arg(requires = "rate_limit")]
+    rate_limit_prefix: Vec<u8>,
+
+    /// Every how many dropped queries one is answered with the TC bit instead, 0 drops them all
+    #[arg(long, default_value_t = 2, requires = "rate_limit")]
+    rate_limit_slip: u32,
+
+    /// Only answer clients in these networks, as network[,network...]. Can be repeated
+    #[arg(long)]
+    allow_query: Vec<String>,
+
+    /// Refuse the clients in these networks, even when --allow-query has them. Can be repeated
+    #[arg(long)]
+    deny_query: Vec<String>,
+
+    /// Only resolve names for clients in these networks, everyone else just gets answers from the
+    /// server's own zones. Can be repeated
+    #[arg(long)]
+    allow_recursion: Vec<String>,
+
+    /// Don't resolve names for the clients in these networks. Can be repeated
+    #[arg(long)]
+    deny_recursion: Vec<String>,
+
+    /// Only let clients in these networks ask for zone transfers. Can be repeated
+    #[arg(long)]
+    allow_transfer: Vec<String>,
+
+    /// Refuse zone transfers to the clients in these networks. Can be repeated
+    #[arg(long)]
+    deny_transfer: Vec<String>,
+
+    /// PEM certificate chain, enables DNS over TLS together with --tls-key
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Address and port to answer DNS over TLS on
+    #[arg(long, default_value = "0.0.0.0:8853")]
+    tls_listen: SocketAddrV4,
+
+    /// Also answer DNS over HTTPS on /dns-query at this address, with the --tls-cert certificate
+    #[arg(long, requires = "tls_cert")]
+    doh_listen: Option<SocketAddrV4>,
+
+    /// Also answer DNS over QUIC at this address, with the --tls-cert certificate
+    #[arg(long, requires = "tls_cert")]
+    doq_listen: Option<SocketAddrV4>,
+
+    /// Write a line for every query answered to this file, or to stdout for -
+    #[arg(long)]
+    query_log: Option<String>,
+
+    /// What the query log lines look like, text or json for one JSON object per line
+    #[arg(long, default_value = "text", requires = "query_log")]
+    query_log_format: LogFormat,
+
+    /// Rotate the query log file once it's this big, like 10M. Takes k, M and G or a number of bytes
+    #[arg(long, value_parser = parse_size, requires = "query_log")]
+    query_log_max_size: Option<u64>,
+
+    /// How many rotated query log files to keep, as path.1, path.2 and so on
+    #[arg(long, default_value_t = 5, requires = "query_log_max_size")]
+    query_log_keep: usize,
+}
+
+// a number followed by ms, s, m, h or d, a bare number is taken as seconds
+fn parse_duration(text: &str) -> std::result::Result<Duration, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration {}", text))?;
+
+    let seconds = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown duration unit {} in {}", unit, text)),
+    };
+    let seconds = number
+        .checked_mul(seconds)
+        .ok_or_else(|| format!("{} is too big", text))?;
+
+    Ok(Duration::from_secs(seconds))
+}
+
+// a number of bytes followed by k, M or G for 1024 of the one before, a bare number is bytes
+fn parse_size(text: &str) -> std::result::Result<u64, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid size {}", text))?;
+
+    let shift = match unit {
+        "" => 0,
+        "k" | "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        _ => return Err(format!("unknown size unit {} in {}", unit, text)),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("{} is too big", text))
+}
+
+// an address with an optional /prefix, without one it's the whole address
+fn parse_subnet(subnet: &str) -> Result<ClientSubnet> {
+    let (address, prefix) = subnet.split_once('/').unwrap_or((subnet, "128"));
+    let address: IpAddr = address.parse()?;
+
+    Ok(ClientSubnet::new(address, prefix.parse()?))
+}
+
+// the access list of the networks given with an --allow and a --deny option
+fn acl(allow: &[String], deny: &[String]) -> Result<Acl> {
+    let mut acl = Acl::new();
+    for network in allow.iter().flat_map(|networks| networks.split(',')) {
+        acl = acl.with_allowed(parse_subnet(network)?);
+    }
+    for network in deny.iter().flat_map(|networks| networks.split(',')) {
+        acl = acl.with_denied(parse_subnet(network)?);
     }
 
-    fn step(&mut self, steps: usize) -> Result<()> {
-        self.position += steps;
+    Ok(acl)
+}
 
-        Ok(())
+// "client" takes the subnet from the client's address like RFC 7871 suggests, /24 for ipv4 and /56
+// for ipv6, anything else is a fixed network
+fn parse_subnet_source(source: &str) -> Result<SubnetSource> {
+    match source {
+        "client" => Ok(SubnetSource::Client {
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+        }),
+        subnet => Ok(SubnetSource::Fixed(parse_subnet(subnet)?)),
     }
+}
 
-    fn seek(&mut self, pos: usize) -> Result<()> {
-        self.position = pos;
-        Ok(())
+// an ip with an optional port, which defaults to 53, or the sdns:// stamp of a plain DNS server
+fn parse_server(address: &str) -> Result<(Ipv4Addr, u16)> {
+    if address.starts_with("sdns://") {
+        return match address.parse()? {
+            DnsStamp::Plain { address, .. } => ipv4_server(address),
+            _ => Err(format!("{} isn't the stamp of a plain DNS server", address).into()),
+        };
     }
 
-    fn read(&mut self) -> Result<u8> {
-        if self.position >= 512 {
-            return Err("End of buffer".into());
-        }
-        let result = self.buffer[self.position];
-        self.position+=1;
+    parse_address(address, 53)
+}
 
-        Ok(result)
+fn ipv4_server(address: SocketAddr) -> Result<(Ipv4Addr, u16)> {
+    match address {
+        SocketAddr::V4(address) => Ok((*address.ip(), address.port())),
+        SocketAddr::V6(_) => Err(format!("Only ipv4 servers are supported, not {}", address).into()),
     }
+}
+
+fn parse_address(address: &str, default_port: u16) -> Result<(Ipv4Addr, u16)> {
+    match address.split_once(':') {
+        Some((ip, port)) => Ok((ip.parse()?, port.parse()?)),
+        None => Ok((address.parse()?, default_port)),
+    }
+}
+
+// where a query goes and how it gets there
+enum Target {
+    // udp, or tcp with --tcp
+    Plain((Ipv4Addr, u16)),
+    // along with the name the certificate is checked against
+    Tls((Ipv4Addr, u16), String),
+    Https(String),
+    // like Tls, over QUIC
+    Quic((Ipv4Addr, u16), String),
+    // along with the provider name its certificates are published under and the provider key when a
+    // stamp has it
+    Crypt((Ipv4Addr, u16), String, Option<[u8; 32]>),
+    // the local network, over multicast DNS
+    Mdns,
+    // the local link, over LLMNR
+    Llmnr,
+}
+
+// ip[:port][#name] for the encrypted transports, the port defaults to 853 and the name the
+// certificate is checked against to the ip
+fn parse_named(target: &str) -> Result<((Ipv4Addr, u16), String)> {
+    let (address, name) = target.split_once('#').unwrap_or((target, ""));
+    let server = parse_address(address, 853)?;
+    let name = if name.is_empty() {
+        server.0.to_string()
+    } else {
+        name.to_string()
+    };
+
+    Ok((server, name))
+}
 
-    fn get(&mut self, pos: usize) -> Result<u8> {
-        if pos >= 512 {
-            return Err("End of buffer".into());
+// where a stamp says queries go. DNS over HTTPS resolves the hostname as usual, the other transports
+// connect to the address in the stamp. The hashes aren't pinned, certificates are checked against the
+// webpki roots like always. Relays and oblivious targets can't be asked directly
+fn stamp_target(stamp: DnsStamp) -> Result<Target> {
+    match stamp {
+        DnsStamp::Plain { address, .. } => Ok(Target::Plain(ipv4_server(address)?)),
+        DnsStamp::DnsCrypt {
+            address,
+            provider_key,
+            provider_name,
+            ..
+        } => Ok(Target::Crypt(ipv4_server(address)?, provider_name, Some(provider_key))),
+        DnsStamp::Https { hostname, path, .. } => Ok(Target::Https(format!("https://{}{}", hostname, path))),
+        DnsStamp::Tls {
+            address: Some(address),
+            hostname,
+            ..
+        } => Ok(Target::Tls(ipv4_server(address)?, hostname)),
+        DnsStamp::Quic {
+            address: Some(address),
+            hostname,
+            ..
+        } => Ok(Target::Quic(ipv4_server(address)?, hostname)),
+        DnsStamp::Tls { hostname, .. } | DnsStamp::Quic { hostname, .. } => {
+            Err(format!("The stamp of {} has no address to connect to", hostname).into())
         }
-        Ok(self.buffer[pos])
+        _ => Err("Relays and oblivious DNS over HTTPS targets can't be queried directly".into()),
     }
+}
 
-    fn get_range(&mut self, start: usize, length: usize) -> Result<&[u8]> {
-        if start + length >= 512 {
-            return Err("End of buffer exceeded".into());
+// the extra positionals are either a record type or an @server, where @dot://... picks DNS over TLS,
+// @doq://... DNS over QUIC, @dnscrypt://... DNSCrypt, @https://... DNS over HTTPS, @mdns multicast DNS and
+// @llmnr LLMNR
+fn parse_extra(extra: &[String]) -> Result<(QueryType, Target)> {
+    let mut qtype = QueryType::A;
+    let mut target = Target::Plain(DEFAULT_SERVER);
+
+    for arg in extra {
+        if let Some(rest) = arg.strip_prefix("@dot://") {
+            let (server, name) = parse_named(rest)?;
+            target = Target::Tls(server, name);
+        } else if let Some(rest) = arg.strip_prefix("@doq://") {
+            let (server, name) = parse_named(rest)?;
+            target = Target::Quic(server, name);
+        } else if let Some(rest) = arg.strip_prefix("@dnscrypt://") {
+            // DNSCrypt servers usually listen on 443
+            let (address, provider) = rest.split_once('#').ok_or("@dnscrypt:// needs the #provider name")?;
+            target = Target::Crypt(parse_address(address, 443)?, provider.to_string(), None);
+        } else if let Some(stamp) = arg.strip_prefix('@').filter(|stamp| stamp.starts_with("sdns://")) {
+            target = stamp_target(stamp.parse()?)?;
+        } else if let Some(url) = arg.strip_prefix('@').filter(|url| url.starts_with("https://")) {
+            target = Target::Https(url.to_string());
+        } else if arg == "@mdns" {
+            target = Target::Mdns;
+        } else if arg == "@llmnr" {
+            target = Target::Llmnr;
+        } else if let Some(address) = arg.strip_prefix('@') {
+            target = Target::Plain(parse_server(address)?);
+        } else {
+            qtype = arg.parse()?;
         }
-        Ok(&self.buffer[start .. start+length as usize])
     }
 
-    fn read_u16(&mut self) -> Result<u16> {
-        let result = ((self.read()? as u16)<< 8) | (self.read()? as u16);
+    Ok((qtype, target))
+}
 
-        Ok(result)
+fn query(args: QueryArgs) -> Result<()> {
+    let (qtype, target) = parse_extra(&args.extra)?;
+    let mut query = build_query(&args.name, qtype);
+    if let Some(edns) = query.edns_mut() {
+        if let Some(subnet) = &args.subnet {
+            edns.set_client_subnet(subnet);
+        }
+        if args.nsid {
+            edns.set_option(OPTION_NSID, Vec::new());
+        }
+        edns.dnssec_ok = args.dnssec;
     }
 
-    fn read_u32(&mut self) -> Result<u32> {
-        let result = ((self.read()? as u32) << 24) 
-        | ((self.read()? as u32) << 16)
-        | ((self.read()? as u32) << 8)
-        | (self.read()? as u32);
+    // stamps come with the provider key, @dnscrypt:// takes it from --provider-key
+    let target = match (target, &args.provider_key) {
+        (Target::Crypt(server, provider, None), Some(key)) => {
+            let key = dns_learning::encoding::hex_decode(key)?;
+            Target::Crypt(
+                server,
+                provider,
+                Some(key.try_into().map_err(|_| "A provider key is 32 bytes")?),
+            )
+        }
+        (target, _) => target,
+    };
 
-        Ok(result)
+    let signing = args.tsig.is_some() || args.tsig_file.is_some();
+    if signing && !matches!(target, Target::Plain(_)) {
+        return Err("TSIG signed queries only go over plain udp and tcp".into());
     }
-    
-    fn read_q_name(&mut self, outstring: &mut String) -> Result<()> {
-        // tracking position in case there are jumps
-        let mut pos = self.pos();
-        
-        // tracking whether there's been jumps and how many
-        let mut jumped = false;
-        let max_jumps = 5;
-        let mut jumps_performed = 0;
 
-        let mut delimiter = "";
-        loop {
-            // in case there is a malicious loop in the packet
-            if jumps_performed > max_jumps {
-                return Err(format!("Limit of {} jumps was exceeded",max_jumps).into());
-            }
+    if args.save.is_some() && qtype != QueryType::AXFR {
+        return Err("Only the zone of an AXFR can be saved with --save".into());
+    }
+    // a whole zone comes as a stream of messages rather than one response
+    if qtype == QueryType::AXFR {
+        let Target::Plain(server) = target else {
+            return Err("Zone transfers only go over plain tcp".into());
+        };
+        return transfer(&args.name, server, &args);
+    }
+    if qtype == QueryType::IXFR {
+        let Target::Plain(server) = target else {
+            return Err("Zone transfers only go over plain tcp".into());
+        };
+        let serial = args.serial.ok_or("IXFR needs the --serial of the copy you have")?;
+        return incremental_transfer(&args.name, serial, server, &args);
+    }
 
-            // labels always begin with a length byte by spec
-            let len = self.get(pos)?;
+    let packet = match target {
+        Target::Plain(server) if signing => exchange_tsig(&query, server, &args)?,
+        Target::Plain(server) if args.tcp => TcpClient::new(server).with_timeout(args.timeout).exchange(&query)?,
+        Target::Plain(server) => exchange(&query, server, Some(args.timeout))?,
+        #[cfg(feature = "tls")]
+        Target::Tls(server, name) => dns_learning::TlsClient::new(server, &name)?
+            .with_timeout(args.timeout)
+            .exchange(&query)?,
+        #[cfg(not(feature = "tls"))]
+        Target::Tls(server, name) => {
+            return Err(format!("DNS over TLS to {} ({}) needs the tls feature", name, server.0).into())
+        }
+        #[cfg(feature = "doh")]
+        Target::Https(url) => {
+            let method = if args.get {
+                dns_learning::DohMethod::Get
+            } else {
+                dns_learning::DohMethod::Post
+            };
+            // zeroed like DohClient::lookup does, so caches see the same url for the same question
+            query.header.id = 0;
+            dns_learning::DohClient::with_timeout(&url, args.timeout)
+                .with_method(method)
+                .exchange(&query)?
+        }
+        #[cfg(not(feature = "doh"))]
+        Target::Https(url) => return Err(format!("DNS over HTTPS to {} needs the doh feature", url).into()),
+        #[cfg(feature = "doq")]
+        Target::Quic(server, name) => {
+            // RFC 9250 has the id always be 0
+            query.header.id = 0;
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(async {
+                dns_learning::DoqClient::new(server, &name)?
+                    .with_timeout(args.timeout)
+                    .exchange(&query)
+                    .await
+            })?
+        }
+        #[cfg(not(feature = "doq"))]
+        Target::Quic(server, name) => {
+            return Err(format!("DNS over QUIC to {} ({}) needs the doq feature", name, server.0).into())
+        }
+        #[cfg(feature = "dnscrypt")]
+        Target::Crypt(server, provider, key) => {
+            let key = key.ok_or("@dnscrypt:// needs the --provider-key")?;
+            dns_learning::DnsCryptClient::new(server, &provider, key)
+                .with_timeout(args.timeout)
+                .exchange(&query)?
+        }
+        #[cfg(not(feature = "dnscrypt"))]
+        Target::Crypt(server, provider, _) => {
+            return Err(format!("DNSCrypt to {} ({}) needs the dnscrypt feature", provider, server.0).into())
+        }
+        Target::Mdns => query_mdns(&DnsQuestion::new(args.name.clone(), qtype), args.timeout, !args.qm)?,
+        Target::Llmnr => query_llmnr(&DnsQuestion::new(args.name.clone(), qtype), args.timeout)?,
+    };
+    print!("{}", packet);
 
-            // check if the next byte needs to be read as well
-            if (len & 0xC0) == 0xC0 {
-                if !jumped {
-                    self.seek(pos+2)?;
-                }
+    Ok(())
+}
 
-                // read another byte
-                let len_second = self.get(pos+1)? as u16;
-                let offset = (((len as u16)^0xC0) << 8) | len_second;
-                pos = offset as usize;
+// sends the query signed with the --tsig key, the response has to be signed with it as well
+#[cfg(feature = "tsig")]
+fn exchange_tsig(query: &DnsPacket, server: (Ipv4Addr, u16), args: &QueryArgs) -> Result<DnsPacket> {
+    let keys = tsig_keys(args.tsig.as_slice(), args.tsig_file.as_slice())?;
+    let key = keys.first().ok_or("No TSIG key to sign the query with")?;
+
+    if args.tcp {
+        TcpClient::new(server)
+            .with_timeout(args.timeout)
+            .exchange_signed(query, key)
+    } else {
+        dns_learning::exchange_signed(query, server, Some(args.timeout), key)
+    }
+}
 
-                // note that there was a jump performed
-                jumped = true;
-                jumps_performed += 1;
+#[cfg(not(feature = "tsig"))]
+fn exchange_tsig(_query: &DnsPacket, _server: (Ipv4Addr, u16), _args: &QueryArgs) -> Result<DnsPacket> {
+    Err("Signing queries with --tsig needs the tsig feature".into())
+}
 
-                continue;
-            }
-            // base scenario where there is a single label read and then appended to the output
-            else {
-                pos += 1; // move a single byte forward past the length byte
-
-                // domain names are terminated by an empty label with length 0
-                // if length is 0 then we are done
-                if len == 0 {
-                    break;
-                }
+// prints the records of the zone as they arrive, one per line like a zone file, and with --save keeps
+// them to write the zone out once it's complete
+fn transfer(zone: &str, server: (Ipv4Addr, u16), args: &QueryArgs) -> Result<()> {
+    let transfer = sign_transfer(ZoneTransfer::new(server).with_timeout(args.timeout), args)?;
+
+    let mut count = 0;
+    let mut records = Vec::new();
+    transfer.axfr_each(zone, |record| {
+        println!("{}", record);
+        count += 1;
+        if args.save.is_some() {
+            records.push(record);
+        }
+        Ok(())
+    })?;
+    println!(";; {} records", count);
 
-                outstring.push_str(delimiter);
+    if let Some(path) = &args.save {
+        Zone::from_records(zone, records)?.save(path)?;
+        println!(";; saved to {}", path.display());
+    }
 
-                // Get the actual ASCII bytes for the label
-                let string_buffer = self.get_range(pos,len as usize)?;
-                outstring.push_str(&String::from_utf8_lossy(string_buffer).to_lowercase());
+    Ok(())
+}
 
-                delimiter = ".";
+// prints what changed since serial, as records deleted and added for every version in between, or the
+// whole zone when the server sends that instead
+fn incremental_transfer(zone: &str, serial: u32, server: (Ipv4Addr, u16), args: &QueryArgs) -> Result<()> {
+    let transfer = sign_transfer(ZoneTransfer::new(server).with_timeout(args.timeout), args)?;
 
-                pos += len as usize;
+    match transfer.ixfr(zone, serial)? {
+        ZoneChanges::UpToDate => println!(";; {} is still at serial {}", zone, serial),
+        ZoneChanges::Full(records) => {
+            for record in &records {
+                println!("{}", record);
             }
+            println!(";; {} records", records.len());
         }
-
-        if !jumped {
-            self.seek(pos)?;
+        ZoneChanges::Incremental(diffs) => {
+            for diff in diffs {
+                println!(";; serial {} to {}", diff.from_serial, diff.to_serial);
+                for record in &diff.deleted {
+                    println!("-{}", record);
+                }
+                for record in &diff.added {
+                    println!("+{}", record);
+                }
+            }
         }
+    }
 
-        Ok(())
+    Ok(())
+}
+
+#[cfg(feature = "tsig")]
+fn sign_transfer(transfer: ZoneTransfer, args: &QueryArgs) -> Result<ZoneTransfer> {
+    let keys = tsig_keys(args.tsig.as_slice(), args.tsig_file.as_slice())?;
+    match keys.into_iter().next() {
+        Some(key) => Ok(transfer.with_key(key)),
+        None => Ok(transfer),
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum ResultCode {
-    NOERROR = 0,
-    FORMERR = 1,
-    SERVFAIL = 2,
-    NXDOMAIN = 3,
-    NOTIMP = 4,
-    REFUSED = 5,
+#[cfg(not(feature = "tsig"))]
+fn sign_transfer(transfer: ZoneTransfer, args: &QueryArgs) -> Result<ZoneTransfer> {
+    if args.tsig.is_some() || args.tsig_file.is_some() {
+        return Err("Signing zone transfers with --tsig needs the tsig feature".into());
+    }
+
+    Ok(transfer)
 }
 
-impl ResultCode {
-    pub fn from_num(num:u8) -> ResultCode {
-        match num {
-            1 =>ResultCode::FORMERR,
-            2 => ResultCode::SERVFAIL,
-            3 => ResultCode::NXDOMAIN,
-            4 => ResultCode::NOTIMP,
-            5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
-        }
+#[cfg(feature = "tsig")]
+fn tsig_keys(keys: &[String], files: &[PathBuf]) -> Result<Vec<dns_learning::TsigKey>> {
+    let mut parsed = keys
+        .iter()
+        .map(|key| dns_learning::TsigKey::parse(key))
+        .collect::<Result<Vec<_>>>()?;
+    for path in files {
+        parsed.extend(dns_learning::TsigKey::from_file(path)?);
     }
+
+    Ok(parsed)
 }
 
-#[derive(Clone, Debug)]
-pub struct DnsHeader {
-    pub id: u16, // 16 bits
+// a name relative to the zone, optionally followed by a type
+fn parse_rrset(text: &str, zone: &str) -> Result<(String, Option<QueryType>)> {
+    match text.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [name] => Ok((absolute_name(name, zone)?, None)),
+        [name, qtype] => Ok((absolute_name(name, zone)?, Some(qtype.parse()?))),
+        _ => Err(format!("{} isn't a name with an optional type", text).into()),
+    }
+}
 
-    pub recursion_desired: bool, // 1 bit
-    pub truncated_message: bool,
-    pub authoritative_answer: bool,
-    pub opcode: u8, // 4 bits actually
-    pub response: bool,
+fn update(args: UpdateArgs) -> Result<()> {
+    let server = parse_server(args.server.strip_prefix('@').unwrap_or(&args.server))?;
+    let zone = &args.zone;
+    let mut update = Update::new(zone);
 
-    pub result_code: ResultCode, // 4 bits actually
-    pub checking_disabled: bool,
-    pub authed_data: bool,
-    pub z: bool,
-    pub recursion_available: bool, 
+    for text in &args.exists {
+        update = match parse_rrset(text, zone)? {
+            (name, Some(qtype)) => update.require_exists(&name, qtype),
+            (name, None) => update.require_name(&name),
+        };
+    }
+    for text in &args.absent {
+        update = match parse_rrset(text, zone)? {
+            (name, Some(qtype)) => update.require_absent(&name, qtype),
+            (name, None) => update.require_no_name(&name),
+        };
+    }
+    for text in &args.require {
+        update = update.require_record(parse_record(text, zone, 0)?);
+    }
 
-    pub questions: u16,
-    pub answers: u16,
-    pub authoritative_entries: u16,
-    pub resource_entries: u16,
-}
+    // deletions go first, so an rrset can be replaced by deleting it and adding the new records
+    for text in &args.delete_name {
+        update = update.delete_name(&absolute_name(text, zone)?);
+    }
+    for text in &args.delete_rrset {
+        let (name, qtype) = parse_rrset(text, zone)?;
+        update = update.delete_rrset(&name, qtype.ok_or_else(|| format!("{} has no type to delete", text))?);
+    }
+    for text in &args.delete {
+        update = update.delete_record(parse_record(text, zone, 0)?)?;
+    }
+    for text in &args.add {
+        update = update.add_record(parse_record(text, zone, args.ttl)?);
+    }
 
-impl DnsHeader {
-    pub fn new() -> DnsHeader {
-        DnsHeader{
-            id: 0,
+    if update.updates.is_empty() {
+        return Err("The update doesn't change anything, give it --add or one of the --delete options".into());
+    }
 
-            recursion_desired: false,
-            truncated_message: false,
-            authoritative_answer: false,
-            opcode: 0,
-            response: false,
+    print!("{}", send_update(&update, server, &args)?);
 
-            result_code: ResultCode::NOERROR,
-            checking_disabled: false,
-            authed_data: false,
-            z: false,
-            recursion_available: false,
+    Ok(())
+}
 
-            questions: 0,
-            answers: 0,
-            authoritative_entries: 0,
-            resource_entries: 0,
-        }
+// sends the update, signed when there's a --tsig key
+#[cfg(feature = "tsig")]
+fn send_update(update: &Update, server: (Ipv4Addr, u16), args: &UpdateArgs) -> Result<DnsPacket> {
+    if args.tsig.is_none() && args.tsig_file.is_none() {
+        return update.send(server, Some(args.timeout));
     }
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        self.id = buffer.read_u16()?;
+    let keys = tsig_keys(args.tsig.as_slice(), args.tsig_file.as_slice())?;
+    let key = keys.first().ok_or("No TSIG key to sign the update with")?;
+    update.send_signed(server, Some(args.timeout), key)
+}
+
+#[cfg(not(feature = "tsig"))]
+fn send_update(update: &Update, server: (Ipv4Addr, u16), args: &UpdateArgs) -> Result<DnsPacket> {
+    if args.tsig.is_some() || args.tsig_file.is_some() {
+        return Err("Signing updates with --tsig needs the tsig feature".into());
+    }
 
-        let flags = buffer.read_u16()?;
-        let a = (flags >> 8) as u8;
-        let b = (flags & 0xFF) as u8;
-        
-        self.recursion_desired = (a & (1 << 0)) > 0;
-        self.truncated_message = (a & (1 << 1)) > 0;
-        self.authoritative_answer = (a & (1 << 2)) > 0;
-        self.opcode = (a >> 3) & 0x0F;
-        self.response = (a & (1 << 7)) > 0;
+    update.send(server, Some(args.timeout))
+}
 
-        self.result_code = ResultCode::from_num(b&0x0F);
-        self.checking_disabled = (b & (1 << 4)) > 0;
-        self.authed_data = (b & (1 << 5)) > 0;
-        self.z = (b & (1 << 6)) > 0;
-        self.recursion_available = (b & (1 << 7)) > 0;
+fn notify(args: NotifyArgs) -> Result<()> {
+    let server = parse_server(args.server.strip_prefix('@').unwrap_or(&args.server))?;
+    let mut notify = Notify::new(&args.zone);
+    if let Some(serial) = args.serial {
+        notify = notify.with_serial(serial);
+    }
 
-        self.questions = buffer.read_u16()?;
-        self.answers = buffer.read_u16()?;
-        self.authoritative_entries = buffer.read_u16()?;
-        self.resource_entries = buffer.read_u16()?;
+    print!("{}", send_notify(&notify, server, &args)?);
 
-        Ok(())
+    Ok(())
+}
+
+// sends the NOTIFY, signed when there's a --tsig key
+#[cfg(feature = "tsig")]
+fn send_notify(notify: &Notify, server: (Ipv4Addr, u16), args: &NotifyArgs) -> Result<DnsPacket> {
+    if args.tsig.is_none() && args.tsig_file.is_none() {
+        return notify.send(server, Some(args.timeout));
     }
+
+    let keys = tsig_keys(args.tsig.as_slice(), args.tsig_file.as_slice())?;
+    let key = keys.first().ok_or("No TSIG key to sign the NOTIFY with")?;
+    notify.send_signed(server, Some(args.timeout), key)
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
-pub enum QueryType {
-    UNKNOWN(u16),
-    A, // 1
+#[cfg(not(feature = "tsig"))]
+fn send_notify(notify: &Notify, server: (Ipv4Addr, u16), args: &NotifyArgs) -> Result<DnsPacket> {
+    if args.tsig.is_some() || args.tsig_file.is_some() {
+        return Err("Signing NOTIFY messages with --tsig needs the tsig feature".into());
+    }
+
+    notify.send(server, Some(args.timeout))
 }
 
-impl QueryType {
-    pub fn to_num(&self) -> u16 {
-        match *self {
-            QueryType::UNKNOWN(x) => x,
-            QueryType::A => 1,
+fn run_server(args: ServeArgs) -> Result<()> {
+    let upstream = if args.recursive {
+        Upstream::Recursive
+    } else {
+        Upstream::Forward(
+            args.forward
+                .iter()
+                .map(|server| ForwardServer::new(*server).with_retries(args.retries))
+                .collect(),
+        )
+    };
+    // every transport answers from the same cache
+    let cache = DnsCache::with_capacity(args.cache_size)
+        .with_stale_window(args.serve_stale.unwrap_or_default())
+        .with_prefetch(args.prefetch.unwrap_or(0));
+    let mut resolver = Resolver::new(upstream)
+        .with_timeout(args.upstream_timeout)
+        .with_race(args.race)
+        .with_adaptive(args.adaptive)
+        .with_cache(cache.into_shared())
+        .with_event_log(stderr_log());
+    if let Some(path) = &args.root_hints {
+        resolver = resolver.with_root_hints(RootHints::from_file(path)?);
+    }
+    if let Some(source) = args.client_subnet {
+        resolver = resolver.with_client_subnet(source);
+    }
+    if let Some(nsid) = &args.nsid {
+        resolver = resolver.with_nsid(nsid.as_bytes().to_vec());
+    }
+    if args.dnssec {
+        resolver = enable_dnssec(resolver, args.trust_anchors.as_deref())?;
+    }
+    if !args.tsig.is_empty() || !args.tsig_file.is_empty() {
+        resolver = enable_tsig(resolver, &args.tsig, &args.tsig_file)?;
+    }
+    for text in &args.forward_zone {
+        let (domain, servers) = text
+            .split_once('=')
+            .ok_or_else(|| format!("{} isn't domain=servers", text))?;
+        let servers: Vec<ForwardServer> = servers
+            .split(',')
+            .map(|server| Ok(ForwardServer::new(parse_server(server)?).with_retries(args.retries)))
+            .collect::<Result<_>>()?;
+        resolver = resolver.with_domain(domain, Upstream::Forward(servers));
+    }
+    for text in &args.zone {
+        let (origin, path) = text
+            .split_once('=')
+            .ok_or_else(|| format!("{} isn't origin=path", text))?;
+        let zone = Zone::from_file(Path::new(path), origin)?;
+        let (count, serial) = (zone.records().len(), zone.serial().unwrap_or_default());
+        eprintln!("Loaded {} records of {} at serial {}", count, zone.origin, serial);
+        resolver = resolver.with_zone(zone);
+    }
+    if !args.secondary.is_empty() {
+        resolver = resolver.with_secondary(secondary_zones(&args)?);
+    }
+    if !args.hosts.is_empty() || !args.overrides.is_empty() {
+        let mut local = LocalRecords::new();
+        for path in &args.overrides {
+            local.read_records(path)?;
+        }
+        for path in &args.hosts {
+            local.read_hosts(path)?;
+        }
+        eprintln!("Loaded {} local names", local.len());
+        resolver = resolver.with_local(local);
+    }
+    for text in &args.rpz {
+        let (origin, path) = text
+            .split_once('=')
+            .ok_or_else(|| format!("{} isn't origin=path", text))?;
+        let rpz = Rpz::from_file(Path::new(path), origin)?;
+        eprintln!("Loaded {} policies of {}", rpz.len(), rpz.origin);
+        resolver = resolver.with_rpz(rpz);
+    }
+    if !args.blocklist.is_empty() {
+        resolver = resolver.with_blocklist(blocklist(&args)?);
+        log_blocked(&resolver);
+    }
+    if args.dns64 {
+        resolver = resolver.with_dns64(args.dns64_prefix);
+    }
+    if args.mdns {
+        resolver = resolver.with_mdns(DEFAULT_MDNS_TIMEOUT);
+    }
+    if let Some(name) = &args.mdns_host {
+        start_mdns(name, &args.mdns_address)?;
+    }
+    if args.llmnr {
+        resolver = resolver.with_llmnr(DEFAULT_LLMNR_TIMEOUT);
+    }
+    if let Some(name) = &args.llmnr_host {
+        start_llmnr(name, &args.llmnr_address)?;
+    }
+    for view in views(&args, &resolver)? {
+        let networks = view.networks.iter();
+        let networks: Vec<String> = networks
+            .map(|network| format!("{}/{}", network.address, network.source_prefix))
+            .collect();
+        eprintln!("Answering {} from the view {}", networks.join(", "), view.name);
+        resolver = resolver.with_view(view);
+    }
+    if let Some(per_second) = args.rate_limit {
+        let limit = RateLimit::new(per_second)
+            .with_prefixes(args.rate_limit_prefix[0], args.rate_limit_prefix[1])
+            .with_slip(args.rate_limit_slip);
+        resolver = resolver.with_rate_limit(match args.cookie_rate_limit {
+            Some(per_second) => limit.with_cookie_limit(per_second),
+            None => limit,
+        });
+    }
+    let access = AccessControl::new()
+        .with_query(acl(&args.allow_query, &args.deny_query)?)
+        .with_recursion(acl(&args.allow_recursion, &args.deny_recursion)?)
+        .with_transfer(acl(&args.allow_transfer, &args.deny_transfer)?);
+    resolver = resolver.with_access_control(access);
+    if let Some(path) = &args.query_log {
+        let log = match &path[..] {
+            "-" => QueryLog::stdout(args.query_log_format),
+            path => QueryLog::file(Path::new(path), args.query_log_format)?,
+        };
+        resolver = resolver.with_query_log(match args.query_log_max_size {
+            Some(max_size) => log.with_rotation(max_size, args.query_log_keep),
+            None => log,
+        });
+    }
+
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        start_tls(cert, key, args.tls_listen, &resolver)?;
+
+        if let Some(listen) = args.doh_listen {
+            start_doh(cert, key, listen, &resolver)?;
+        }
+        if let Some(listen) = args.doq_listen {
+            start_doq(cert, key, listen, &resolver)?;
         }
     }
 
-    pub fn from_num(num: u16) -> QueryType {
-        match num {
-            1 => QueryType::A,
-            _ => QueryType::UNKNOWN(num),
+    // the hints may be out of date, so the servers in them are asked for the current ones first
+    if args.recursive {
+        match resolver.root_hints.prime() {
+            Ok(servers) => eprintln!("Primed {} root servers", servers.len()),
+            Err(e) => eprintln!("Priming the root servers failed, starting from the hints: {}", e),
         }
     }
+
+    eprintln!("Listening on {}", args.listen);
+    serve((*args.listen.ip(), args.listen.port()), resolver)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DnsQuestion {
-    pub name: String, 
-    pub qtype: QueryType,
+// the responder runs on a thread of its own, a name another host has already is only logged so the
+// rest of the server keeps going
+fn start_mdns(name: &str, addresses: &[IpAddr]) -> Result<()> {
+    let addresses = match addresses.is_empty() {
+        true => vec![multicast_address()?],
+        false => addresses.to_vec(),
+    };
+    let responder = MdnsResponder::new()
+        .with_host(name, &addresses)
+        .with_event_log(stderr_log());
+    let shown: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+
+    let name = name.trim_end_matches(".local");
+    eprintln!(
+        "Answering for {}.local at {} over multicast DNS",
+        name,
+        shown.join(", ")
+    );
+    std::thread::spawn(move || {
+        if let Err(e) = responder.run() {
+            eprintln!("Multicast DNS stopped: {}", e);
+        }
+    });
+
+    Ok(())
 }
 
-impl DnsQuestion {
-    pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
-        DnsQuestion {
-            name: name,
-            qtype: qtype,
+fn start_llmnr(name: &str, addresses: &[IpAddr]) -> Result<()> {
+    let addresses = match addresses.is_empty() {
+        true => vec![multicast_address()?],
+        false => addresses.to_vec(),
+    };
+    let responder = LlmnrResponder::new()
+        .with_host(name, &addresses)
+        .with_event_log(stderr_log());
+    let shown: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+
+    eprintln!("Answering for {} at {} over LLMNR", name, shown.join(", "));
+    std::thread::spawn(move || {
+        if let Err(e) = responder.run() {
+            eprintln!("LLMNR stopped: {}", e);
         }
+    });
+
+    Ok(())
+}
+
+// what the library reports while serving goes to stderr, like everything else the server says
+fn stderr_log() -> EventLog {
+    EventLog::new(|message| eprintln!("{}", message))
+}
+
+// the address this host sends multicast from, the one it has on the local network. Connecting a udp
+// socket sends nothing, it only picks the route
+fn multicast_address() -> Result<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((MDNS_GROUP, MDNS_PORT))?;
+
+    Ok(socket.local_addr()?.ip())
+}
+
+// the views given with --view, with the zones and forwarding servers given for them. Each one has a
+// cache of its own so answers don't leak from one view into another, the local names, policies and
+// blocklist are the server's
+fn views(args: &ServeArgs, resolver: &Resolver) -> Result<Vec<View<Resolver>>> {
+    let mut views = Vec::new();
+    for text in &args.view {
+        let (name, networks) = text
+            .split_once('=')
+            .ok_or_else(|| format!("{} isn't name=networks", text))?;
+        let forward: Vec<ForwardServer> = view_options(&args.view_forward, name)
+            .map(|server| Ok(ForwardServer::new(parse_server(server)?).with_retries(args.retries)))
+            .collect::<Result<_>>()?;
+        let upstream = match forward.is_empty() {
+            true => resolver.upstream.clone(),
+            false => Upstream::Forward(forward),
+        };
+
+        let cache = DnsCache::with_capacity(args.cache_size)
+            .with_stale_window(args.serve_stale.unwrap_or_default())
+            .with_prefetch(args.prefetch.unwrap_or(0));
+        let mut view_resolver = Resolver::new(upstream)
+            .with_timeout(resolver.timeout)
+            .with_race(resolver.race)
+            .with_adaptive(resolver.adaptive)
+            .with_cache(cache.into_shared());
+        view_resolver.subnet = resolver.subnet;
+        view_resolver.domains = resolver.domains.clone();
+        view_resolver.root_hints = resolver.root_hints.clone();
+        view_resolver.local = resolver.local.clone();
+        view_resolver.policies = resolver.policies.clone();
+        view_resolver.blocklist = resolver.blocklist.clone();
+        view_resolver.dns64 = resolver.dns64;
+        view_resolver.events = resolver.events.clone();
+        #[cfg(feature = "dnssec")]
+        {
+            view_resolver.validator = resolver.validator.clone();
+        }
+        for text in view_options(&args.view_zone, name) {
+            let (origin, path) = text
+                .split_once('=')
+                .ok_or_else(|| format!("{} isn't origin=path", text))?;
+            let zone = Zone::from_file(Path::new(path), origin)?;
+            eprintln!(
+                "Loaded {} records of {} for the view {}",
+                zone.records().len(),
+                zone.origin,
+                name
+            );
+            view_resolver = view_resolver.with_zone(zone);
+        }
+
+        let mut view = View::new(name, view_resolver);
+        for network in networks.split(',') {
+            view = view.with_network(parse_subnet(network)?);
+        }
+        views.push(view);
     }
 
-    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        buffer.read_q_name(&mut self.name)?;
-        self.qtype = QueryType::from_num(buffer.read_u16()?); // qtype
-        let _ = buffer.read_u16()?; // class
+    Ok(views)
+}
 
-        Ok(())
+// the values of a view:value option that are for the view name
+fn view_options<'a>(options: &'a [String], name: &'a str) -> impl Iterator<Item = &'a str> {
+    options.iter().filter_map(move |text| match text.split_once(':') {
+        Some((view, value)) if view == name => Some(value),
+        _ => None,
+    })
+}
+
+// the zones given with --secondary, transferred from their primaries in the background so serving
+// starts right away
+fn secondary_zones(args: &ServeArgs) -> Result<Secondary> {
+    let mut secondary = Secondary::new().with_event_log(stderr_log());
+    for text in &args.secondary {
+        let (zone, primary) = text
+            .split_once('@')
+            .ok_or_else(|| format!("{} isn't zone@primary", text))?;
+        let transfer = sign_secondary(ZoneTransfer::new(parse_server(primary)?), args)?;
+        secondary = secondary.with_zone(zone, transfer);
     }
+
+    let refreshing = secondary.clone();
+    std::thread::spawn(move || refreshing.refresh_all());
+
+    Ok(secondary)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-#[allow(dead_code)]
-pub enum DnsRecord {
-    UNKNOWN {
-        domain: String,
-        qtype: u16, 
-        data_len: u16,
-        ttl: u32,
-    },
-    A {
-        domain: String,
-        address: Ipv4Addr,
-        ttl: u32,
-    },
-}
-
-impl DnsRecord {
-    pub fn read(buffer: &mut BytePacketBuffer) -> Result<DnsRecord> {
-        let mut domain = String::new();
-
-        let qtype_number = buffer.read_u16()?;
-        let qtype = QueryType::from_num(qtype_number);
-        let _ = buffer.read_u16()?;
-        let ttl = buffer.read_u32()?;
-        let data_length = buffer.read_u16()?;
-
-        match qtype {
-            QueryType::A => {
-                let raw_address = buffer.read_u32()?;
-                let addr = Ipv4Addr::new(
-                    ((raw_address >> 24) & 0xFF) as u8,
-                    ((raw_address >> 16) & 0xFF) as u8,
-                    ((raw_address >> 8) & 0xFF) as u8,
-                    (raw_address & 0xFF) as u8,
-                );
-
-                Ok(DnsRecord::A {
-                    domain: domain,
-                    address: addr,
-                    ttl: ttl,
-                })
-            }
-            QueryType::UNKNOWN(_) => {
-                buffer.step(data_length as usize)?;
-
-                Ok(DnsRecord::UNKNOWN { 
-                    domain: domain,
-                    qtype: qtype_number,
-                    data_len: data_length,
-                    ttl: ttl
-                })
-            }
-        }
+#[cfg(feature = "tsig")]
+fn sign_secondary(transfer: ZoneTransfer, args: &ServeArgs) -> Result<ZoneTransfer> {
+    match tsig_keys(&args.tsig, &args.tsig_file)?.into_iter().next() {
+        Some(key) => Ok(transfer.with_key(key)),
+        None => Ok(transfer),
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct DnsPacket {
-    pub header: DnsHeader,
-    pub questions: Vec<DnsQuestion>,
-    pub answers: Vec<DnsRecord>,
-    pub authorities: Vec<DnsRecord>,
-    pub resources: Vec<DnsRecord>,
+// without the feature there are no keys, enable_tsig already refuses them
+#[cfg(not(feature = "tsig"))]
+fn sign_secondary(transfer: ZoneTransfer, _args: &ServeArgs) -> Result<ZoneTransfer> {
+    Ok(transfer)
 }
 
-impl DnsPacket {
-    pub fn new() -> DnsPacket {
-        DnsPacket {
-            header: DnsHeader::new(),
-            questions: Vec::new(),
-            answers: Vec::new(),
-            authorities: Vec::new(),
-            resources: Vec::new(),
-        }
+fn blocklist(args: &ServeArgs) -> Result<Blocklist> {
+    let mut blocklist = Blocklist::new().with_mode(args.block_mode);
+    for path in &args.blocklist {
+        let added = blocklist.read(path)?;
+        eprintln!("Loaded {} blocked names from {}", added, path.display());
     }
 
-    pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<DnsPacket> {
-        let mut result = DnsPacket::new();
+    Ok(blocklist)
+}
 
-        result.header.read(buffer)?;
+// says how many queries were blocked once a minute, when that changed
+fn log_blocked(resolver: &Resolver) {
+    let Some(blocklist) = resolver.blocklist.clone() else {
+        return;
+    };
 
-        for _ in 0..result.header.questions {
-            let mut question = DnsQuestion::new("".to_string(), QueryType::UNKNOWN(0));
-            question.read(buffer)?;
-            result.questions.push(question);
+    std::thread::spawn(move || {
+        let mut last = 0;
+        loop {
+            std::thread::sleep(Duration::from_secs(60));
+            let blocked = blocklist.blocked();
+            if blocked == last {
+                continue;
+            }
+            last = blocked;
+
+            let top: Vec<String> = blocklist
+                .top(3)
+                .iter()
+                .map(|(name, hits)| format!("{} ({})", name, hits))
+                .collect();
+            let queries = blocklist.queries();
+            eprintln!(
+                "Blocked {} of {} queries, most often {}",
+                blocked,
+                queries,
+                top.join(", ")
+            );
         }
+    });
+}
 
-        for _ in 0..result.header.answers {
-            let answer = DnsRecord::read(buffer)?;
-            result.answers.push(answer);
+// DNS over TLS runs on its own thread next to the plain udp and tcp server
+#[cfg(feature = "tls")]
+fn start_tls(cert: &Path, key: &Path, listen: SocketAddrV4, resolver: &Resolver) -> Result<()> {
+    let config = dns_learning::tls::server_config(cert, key)?;
+    let resolver = resolver.clone();
+
+    eprintln!("Listening for DNS over TLS on {}", listen);
+    std::thread::spawn(move || {
+        if let Err(e) = dns_learning::serve_tls((*listen.ip(), listen.port()), resolver, config) {
+            eprintln!("DNS over TLS stopped: {}", e);
         }
+    });
 
-        for _ in 0..result.header.authoritative_entries {
-            let authorities = DnsRecord::read(buffer)?;
-            result.authorities.push(authorities);
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+fn start_tls(cert: &Path, _key: &Path, _listen: SocketAddrV4, _resolver: &Resolver) -> Result<()> {
+    Err(format!("Serving DNS over TLS with {} needs the tls feature", cert.display()).into())
+}
+
+#[cfg(feature = "dnssec")]
+fn enable_dnssec(resolver: Resolver, anchors: Option<&Path>) -> Result<Resolver> {
+    let validator = match anchors {
+        Some(path) => dns_learning::Validator::from_file(path)?,
+        None => dns_learning::Validator::new(),
+    };
+
+    Ok(resolver.with_dnssec(validator))
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn enable_dnssec(_resolver: Resolver, _anchors: Option<&Path>) -> Result<Resolver> {
+    Err("Validating answers with --dnssec needs the dnssec feature".into())
+}
+
+#[cfg(feature = "tsig")]
+fn enable_tsig(mut resolver: Resolver, keys: &[String], files: &[PathBuf]) -> Result<Resolver> {
+    for key in tsig_keys(keys, files)? {
+        resolver = resolver.with_tsig_key(key);
+    }
+
+    Ok(resolver)
+}
+
+#[cfg(not(feature = "tsig"))]
+fn enable_tsig(_resolver: Resolver, _keys: &[String], _files: &[PathBuf]) -> Result<Resolver> {
+    Err("Checking TSIG signatures needs the tsig feature".into())
+}
+
+// the async servers share the cache of the sync ones
+#[cfg(any(feature = "doh-server", feature = "doq"))]
+fn async_resolver(resolver: &Resolver) -> dns_learning::AsyncResolver {
+    let mut async_resolver = dns_learning::AsyncResolver::new(resolver.upstream.clone())
+        .with_timeout(resolver.timeout)
+        .with_race(resolver.race)
+        .with_adaptive(resolver.adaptive)
+        .with_cache(resolver.cache.clone());
+    async_resolver.subnet = resolver.subnet;
+    async_resolver.nsid = resolver.nsid.clone();
+    async_resolver.root_hints = resolver.root_hints.clone();
+    async_resolver.secondary = resolver.secondary.clone();
+    async_resolver.local = resolver.local.clone();
+    async_resolver.policies = resolver.policies.clone();
+    async_resolver.blocklist = resolver.blocklist.clone();
+    async_resolver.dns64 = resolver.dns64;
+    async_resolver.domains = resolver.domains.clone();
+    async_resolver.zones = resolver.zones.clone();
+    async_resolver.views = std::sync::Arc::new(async_views(resolver));
+    async_resolver.access = resolver.access.clone();
+    async_resolver.query_log = resolver.query_log.clone();
+    async_resolver.events = resolver.events.clone();
+    #[cfg(feature = "dnssec")]
+    {
+        async_resolver.validator = resolver.validator.clone();
+    }
+    #[cfg(feature = "tsig")]
+    {
+        async_resolver.tsig_keys = resolver.tsig_keys.clone();
+    }
+
+    async_resolver
+}
+
+#[cfg(any(feature = "doh-server", feature = "doq"))]
+fn async_views(resolver: &Resolver) -> Vec<View<dns_learning::AsyncResolver>> {
+    let views = resolver.views.iter().map(|view| View {
+        name: view.name.clone(),
+        networks: view.networks.clone(),
+        resolver: async_resolver(&view.resolver),
+    });
+
+    views.collect()
+}
+
+// the https endpoint is async, so it gets a runtime of its own on a separate thread
+#[cfg(feature = "doh-server")]
+fn start_doh(cert: &Path, key: &Path, listen: SocketAddrV4, resolver: &Resolver) -> Result<()> {
+    let config = dns_learning::tls::server_config(cert, key)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let resolver = async_resolver(resolver);
+
+    eprintln!("Listening for DNS over HTTPS on https://{}/dns-query", listen);
+    std::thread::spawn(move || {
+        let serving = dns_learning::serve_doh((*listen.ip(), listen.port()), resolver, config);
+        if let Err(e) = runtime.block_on(serving) {
+            eprintln!("DNS over HTTPS stopped: {}", e);
         }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(feature = "doh-server"))]
+fn start_doh(_cert: &Path, _key: &Path, listen: SocketAddrV4, _resolver: &Resolver) -> Result<()> {
+    Err(format!("Serving DNS over HTTPS on {} needs the doh-server feature", listen).into())
+}
 
-        for _ in 0..result.header.resource_entries {
-            let entries = DnsRecord::read(buffer)?;
-            result.resources.push(entries);
+#[cfg(feature = "doq")]
+fn start_doq(cert: &Path, key: &Path, listen: SocketAddrV4, resolver: &Resolver) -> Result<()> {
+    let config = dns_learning::tls::server_config(cert, key)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let resolver = async_resolver(resolver);
+
+    eprintln!("Listening for DNS over QUIC on {}", listen);
+    std::thread::spawn(move || {
+        let serving = dns_learning::serve_doq((*listen.ip(), listen.port()), resolver, config);
+        if let Err(e) = runtime.block_on(serving) {
+            eprintln!("DNS over QUIC stopped: {}", e);
         }
+    });
+
+    Ok(())
+}
 
-        Ok(result)
+#[cfg(not(feature = "doq"))]
+fn start_doq(_cert: &Path, _key: &Path, listen: SocketAddrV4, _resolver: &Resolver) -> Result<()> {
+    Err(format!("Serving DNS over QUIC on {} needs the doq feature", listen).into())
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Query(args) => query(args),
+        Command::Serve(args) => run_server(*args),
+        Command::Update(args) => update(args),
+        Command::Notify(args) => notify(args),
     }
 }
 
-fn main() -> Result<()>{
-    let mut f = File::open("response_packet.txt")?;
-    let mut buffer = BytePacketBuffer::new();
-    f.read(&mut buffer.buffer)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let packet = DnsPacket::from_buffer(&mut buffer)?;
-    println!("{:#?}", packet.header);
-    
-    for questions in packet.questions {
-        println!("{:#?}", questions);
+    fn query_args(args: &[&str]) -> QueryArgs {
+        let cli = Cli::try_parse_from(["dnslearning", "query"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Query(args) => args,
+            command => panic!("not a query: {:?}", command),
+        }
     }
-    for answers in packet.answers {
-        println!("{:#?}", answers);
+
+    #[test]
+    fn query_defaults_to_a_records_from_the_default_server() {
+        let args = query_args(&["example.com"]);
+        assert_eq!(args.name, "example.com");
+        assert_eq!(args.timeout, Duration::from_secs(5));
+
+        let (qtype, target) = parse_extra(&args.extra).unwrap();
+        assert_eq!(qtype, QueryType::A);
+        assert!(matches!(target, Target::Plain(DEFAULT_SERVER)));
+    }
+
+    #[test]
+    fn type_and_server_come_in_any_order() {
+        for extra in [["MX", "@1.1.1.1:5353"], ["@1.1.1.1:5353", "mx"]] {
+            let args = query_args(&["example.com", extra[0], extra[1], "--timeout", "500ms"]);
+            assert_eq!(args.timeout, Duration::from_millis(500));
+
+            let (qtype, target) = parse_extra(&args.extra).unwrap();
+            assert_eq!(qtype, QueryType::MX);
+            assert!(matches!(target, Target::Plain((ip, 5353)) if ip == Ipv4Addr::new(1, 1, 1, 1)));
+        }
+
+        let (_, target) = parse_extra(&["@9.9.9.9".to_string()]).unwrap();
+        assert!(matches!(target, Target::Plain((ip, 53)) if ip == Ipv4Addr::new(9, 9, 9, 9)));
     }
-    for auths in packet.authorities {
-        println!("{:#?}", auths);
+
+    #[test]
+    fn picks_the_transport_from_the_server() {
+        let target = |arg: &str| parse_extra(&[arg.to_string()]).unwrap().1;
+
+        let tls = target("@dot://1.1.1.1#one.one.one.one");
+        assert!(matches!(tls, Target::Tls((_, 853), name) if name == "one.one.one.one"));
+        // without a #name the certificate is checked against the address
+        let quic = target("@doq://94.140.14.14:8853");
+        assert!(matches!(quic, Target::Quic((_, 8853), name) if name == "94.140.14.14"));
+        let https = target("@https://dns.google/dns-query");
+        assert!(matches!(https, Target::Https(url) if url == "https://dns.google/dns-query"));
+        assert!(matches!(target("@mdns"), Target::Mdns));
+        assert!(matches!(target("@llmnr"), Target::Llmnr));
     }
-    for resources in packet.resources {
-        println!("{:#?}", resources);
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_extra(&["NOTATYPE".to_string()]).is_err());
+        assert!(parse_extra(&["@not-an-ip".to_string()]).is_err());
+        assert!(parse_extra(&["@dnscrypt://1.2.3.4".to_string()]).is_err());
+        assert!(Cli::try_parse_from(["dnslearning", "query"]).is_err());
+        assert!(Cli::try_parse_from(["dnslearning", "query", "example.com", "--timeout", "5x"]).is_err());
     }
 
-    Ok(())
+    #[test]
+    fn parses_durations_and_sizes() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("ms").is_err());
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("18446744073709551615m").is_err());
+        assert_eq!(parse_size("10M"), Ok(10 << 20));
+        assert!(parse_size("1T").is_err());
+    }
 }
@@ -0,0 +1,546 @@
+use std::{
+    io::ErrorKind,
+    mem,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    header::OPCODE_QUERY, parse_bytes, querylog::EventLog, question::reverse_name, random_id, record::CLASS_IN,
+    BytePacketBuffer, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode,
+};
+
+// where multicast DNS goes, RFC 6762 has it on its own port so it doesn't get in the way of unicast DNS
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+// the top bit of a record's class in a response, telling the ones that cache it to throw out the
+// records of the name and type they had from before. Only unique records ever have it
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+// the top bit of a question's class, asking for the answer straight back instead of to the group
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+
+// how long records with host names in them live, what Avahi and Bonjour give them too
+pub const HOST_TTL: u32 = 120;
+
+// how long multicast DNS queries wait for answers unless told otherwise
+pub const DEFAULT_MDNS_TIMEOUT: Duration = Duration::from_secs(1);
+
+// the names multicast DNS is for, .local and the reverse names of link local addresses
+pub const MDNS_DOMAINS: [&str; 6] = [
+    "local",
+    "254.169.in-addr.arpa",
+    "8.e.f.ip6.arpa",
+    "9.e.f.ip6.arpa",
+    "a.e.f.ip6.arpa",
+    "b.e.f.ip6.arpa",
+];
+
+// what the answers to queries that didn't come from port 5353 may be cached for, those come from
+// ordinary resolvers that don't know about cache flushing
+const LEGACY_TTL: u32 = 10;
+
+// the biggest message multicast DNS sends, what fits in a jumbo ethernet frame
+pub(crate) const MAX_MESSAGE_SIZE: usize = 9000;
+
+// how a responder probes for its names before it answers for them, RFC 6762 section 8.1
+const PROBES: usize = 3;
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+// whether name is one multicast DNS answers for rather than unicast DNS
+pub fn is_mdns_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    MDNS_DOMAINS
+        .iter()
+        .any(|domain| name == *domain || name.ends_with(&format!(".{}", domain)))
+}
+
+// asks the local network for question and merges what every responder answers within timeout, or
+// until one gives unique records with the cache flush bit or an NSEC. With unicast responders answer
+// straight back instead of to the group, and nobody answering is NXDOMAIN
+pub fn query_mdns(question: &DnsQuestion, timeout: Duration, unicast: bool) -> Result<DnsPacket> {
+    let (socket, legacy) = match bind_group(MDNS_GROUP, MDNS_PORT) {
+        Ok(socket) => (socket, false),
+        Err(_) => (UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?, true),
+    };
+
+    let mut query = DnsPacket::new();
+    // multicast queries have id 0, one from another port gets its id echoed back
+    query.header.id = if legacy { random_id() } else { 0 };
+    let mut asked = DnsQuestion::new(question.name.trim_end_matches('.').to_string(), question.qtype);
+    if unicast {
+        asked.class |= UNICAST_RESPONSE;
+    }
+    query.questions.push(asked);
+    socket.send_to(&encode(&query)?, (MDNS_GROUP, MDNS_PORT))?;
+
+    let mut answer = DnsPacket::new();
+    answer.header.response = true;
+    answer.header.id = query.header.id;
+    answer
+        .questions
+        .push(DnsQuestion::new(question.name.clone(), question.qtype));
+    let (mut done, mut denied) = (false, false);
+
+    let deadline = Instant::now() + timeout;
+    let mut data = vec![0; MAX_MESSAGE_SIZE];
+    while !done {
+        let Some((length, source)) = receive_until(&socket, deadline, &mut data)? else {
+            break;
+        };
+
+        // responders always send from 5353, and messages with an error code are ignored
+        let Ok((response, flushes)) = parse_message(&data[..length]) else {
+            continue;
+        };
+        let header = &response.header;
+        if source.port() != MDNS_PORT || !header.response || header.result_code != ResultCode::NOERROR {
+            continue;
+        }
+        let records = response
+            .answers
+            .into_iter()
+            .chain(response.authorities)
+            .chain(response.resources);
+
+        for (record, flush) in records.zip(flushes) {
+            let ours = record
+                .domain()
+                .eq_ignore_ascii_case(question.name.trim_end_matches('.'));
+            let matching = question.qtype == QueryType::ANY
+                || record.query_type() == question.qtype
+                || matches!(record, DnsRecord::CNAME { .. });
+            match record {
+                DnsRecord::OPT { .. } => {}
+                DnsRecord::NSEC { .. } if ours => denied = true,
+                record if ours && matching => {
+                    // what a cache flush replaces is whatever came from the responders before
+                    if flush {
+                        answer.answers.retain(|other| {
+                            other.query_type() != record.query_type()
+                                || !other.domain().eq_ignore_ascii_case(record.domain())
+                        });
+                        done = true;
+                    }
+                    if !answer.answers.contains(&record) {
+                        answer.answers.push(record);
+                    }
+                    // an answer straight back to a query from another port is all there's going to be
+                    done |= legacy;
+                }
+                record => {
+                    if !answer.resources.contains(&record) {
+                        answer.resources.push(record);
+                    }
+                }
+            }
+        }
+        done |= denied;
+    }
+
+    if answer.answers.is_empty() && !denied {
+        answer.header.result_code = ResultCode::NXDOMAIN;
+    }
+    // the counts it would have if it came off the wire in one piece
+    answer.header.questions = 1;
+    answer.header.answers = answer.answers.len() as u16;
+    answer.header.resource_entries = answer.resources.len() as u16;
+
+    Ok(answer)
+}
+
+// answers for this host on the local network the way Avahi and Bonjour do. Its unique records, the ones
+// no other host should have, are probed for before they're announced, shared ones aren't
+#[derive(Clone, Debug, Default)]
+pub struct MdnsResponder {
+    records: Vec<DnsRecord>,
+    // like the PTRs of services, which every host offering the service has
+    shared: Vec<DnsRecord>,
+    events: EventLog,
+}
+
+impl MdnsResponder {
+    pub fn new() -> MdnsResponder {
+        MdnsResponder::default()
+    }
+
+    // name.local with addresses, and a PTR for each of their reverse names
+    pub fn with_host(mut self, name: &str, addresses: &[IpAddr]) -> MdnsResponder {
+        let name = name.trim_end_matches('.').trim_end_matches(".local");
+        let domain = format!("{}.local", name);
+        for address in addresses {
+            self = self.with_record(match *address {
+                IpAddr::V4(address) => DnsRecord::A {
+                    domain: domain.clone(),
+                    address,
+                    ttl: HOST_TTL,
+                },
+                IpAddr::V6(address) => DnsRecord::AAAA {
+                    domain: domain.clone(),
+                    address,
+                    ttl: HOST_TTL,
+                },
+            });
+            self = self.with_record(DnsRecord::PTR {
+                domain: reverse_name(*address),
+                host: domain.clone(),
+                ttl: HOST_TTL,
+            });
+        }
+
+        self
+    }
+
+    pub fn with_record(mut self, record: DnsRecord) -> MdnsResponder {
+        if !self.records.contains(&record) {
+            self.records.push(record);
+        }
+        self
+    }
+
+    pub fn with_shared_record(mut self, record: DnsRecord) -> MdnsResponder {
+        if !self.shared.contains(&record) {
+            self.shared.push(record);
+        }
+        self
+    }
+
+    // where queries it couldn't answer are reported
+    pub fn with_event_log(mut self, events: EventLog) -> MdnsResponder {
+        self.events = events;
+        self
+    }
+
+    pub fn records(&self) -> &[DnsRecord] {
+        &self.records
+    }
+
+    pub fn shared_records(&self) -> &[DnsRecord] {
+        &self.shared
+    }
+
+    // probes, announces and then answers queries on port 5353 until receiving fails. Another host
+    // answering for one of the names is an error, it's theirs
+    pub fn run(&self) -> Result<()> {
+        let socket = bind_group(MDNS_GROUP, MDNS_PORT)?;
+        self.probe(&socket)?;
+        self.announce(&socket)?;
+
+        let mut data = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            let (length, source) = socket.recv_from(&mut data)?;
+            let Ok((query, _)) = parse_message(&data[..length]) else {
+                continue;
+            };
+            let Some((response, destination)) = self.response(&query, source) else {
+                continue;
+            };
+            if let Err(e) = socket.send_to(&encode(&response)?, destination) {
+                self.events
+                    .report(format_args!("Couldn't answer {} over multicast DNS: {}", source, e));
+            }
+        }
+    }
+
+    // the response to query from source and where it goes. Known answers aren't sent again, and a name
+    // of ours without the type gets an NSEC of the ones it has
+    pub(crate) fn response(&self, query: &DnsPacket, source: SocketAddr) -> Option<(DnsPacket, SocketAddr)> {
+        let header = &query.header;
+        if header.response || header.opcode != OPCODE_QUERY || header.result_code != ResultCode::NOERROR {
+            return None;
+        }
+        let legacy = source.port() != MDNS_PORT;
+
+        let mut response = DnsPacket::new();
+        let mut unicast = true;
+        for question in &query.questions {
+            let named: Vec<&DnsRecord> = self
+                .records
+                .iter()
+                .chain(&self.shared)
+                .filter(|record| record.domain().eq_ignore_ascii_case(&question.name))
+                .collect();
+            if named.is_empty() {
+                continue;
+            }
+            unicast &= question.class & UNICAST_RESPONSE != 0;
+
+            let matching = named
+                .iter()
+                .filter(|record| question.qtype == QueryType::ANY || record.query_type() == question.qtype)
+                .filter(|record| !known(query, record));
+            let count = response.answers.len();
+            response.answers.extend(matching.map(|record| (*record).clone()));
+            let missing =
+                question.qtype != QueryType::ANY && !named.iter().any(|record| record.query_type() == question.qtype);
+            if response.answers.len() == count && missing {
+                let mut types: Vec<QueryType> = named.iter().map(|record| record.query_type()).collect();
+                types.sort_by_key(|qtype| qtype.to_num());
+                types.dedup();
+                let (domain, next_domain) = (question.name.clone(), question.name.clone());
+                response.answers.push(DnsRecord::NSEC {
+                    domain,
+                    next_domain,
+                    types,
+                    ttl: HOST_TTL,
+                });
+            }
+        }
+        if response.answers.is_empty() {
+            return None;
+        }
+
+        // the other addresses of the names answered, and those of the names PTRs point at
+        for record in &response.answers {
+            let name = match record {
+                DnsRecord::PTR { host, .. } => host,
+                DnsRecord::A { domain, .. } | DnsRecord::AAAA { domain, .. } => domain,
+                _ => continue,
+            };
+            for address in self.addresses(name) {
+                if !response.answers.contains(address) && !response.resources.contains(address) {
+                    response.resources.push(address.clone());
+                }
+            }
+        }
+
+        response.header.response = true;
+        response.header.authoritative_answer = true;
+        if !legacy {
+            for record in response.answers.iter_mut().chain(response.resources.iter_mut()) {
+                if !self.shared.contains(record) {
+                    *record = cache_flush(record.clone());
+                }
+            }
+            let destination = if unicast {
+                source
+            } else {
+                SocketAddr::from((MDNS_GROUP, MDNS_PORT))
+            };
+            return Some((response, destination));
+        }
+
+        // an ordinary resolver gets its id and question back, and short TTLs since it can't be told
+        // to flush them
+        response.header.id = query.header.id;
+        response.questions = query.questions.clone();
+        for record in response.answers.iter_mut().chain(response.resources.iter_mut()) {
+            record.set_ttl(record.ttl().min(LEGACY_TTL));
+        }
+
+        Some((response, source))
+    }
+
+    fn addresses<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a DnsRecord> {
+        self.records.iter().filter(move |record| {
+            let address = matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. });
+            address && record.domain().eq_ignore_ascii_case(name)
+        })
+    }
+
+    // asks for every unique name three times a quarter of a second apart, with the records it's going
+    // to have in the authority section. Any host answering with other records for them has them already
+    fn probe(&self, socket: &UdpSocket) -> Result<()> {
+        let mut probe = DnsPacket::new();
+        for record in &self.records {
+            if !probe
+                .questions
+                .iter()
+                .any(|question| question.name.eq_ignore_ascii_case(record.domain()))
+            {
+                let mut question = DnsQuestion::new(record.domain().to_string(), QueryType::ANY);
+                question.class |= UNICAST_RESPONSE;
+                probe.questions.push(question);
+            }
+        }
+        probe.authorities = self.records.clone();
+        let probe = encode(&probe)?;
+
+        let mut data = vec![0; MAX_MESSAGE_SIZE];
+        for _ in 0..PROBES {
+            socket.send_to(&probe, (MDNS_GROUP, MDNS_PORT))?;
+            let deadline = Instant::now() + PROBE_INTERVAL;
+            while let Some((length, _)) = receive_until(socket, deadline, &mut data)? {
+                let Ok((response, _)) = parse_message(&data[..length]) else {
+                    continue;
+                };
+                if let Some(name) = self.conflict(&response) {
+                    return Err(format!("Another host on the network answers for {}", name).into());
+                }
+            }
+        }
+        socket.set_read_timeout(None)?;
+
+        Ok(())
+    }
+
+    // the first of our names a response has other records for than ours
+    fn conflict(&self, response: &DnsPacket) -> Option<String> {
+        if !response.header.response {
+            return None;
+        }
+        response.answers.iter().chain(&response.resources).find_map(|record| {
+            let ours = self
+                .records
+                .iter()
+                .any(|own| own.domain().eq_ignore_ascii_case(record.domain()));
+            let same = self.records.iter().any(|own| same_data(own, record));
+            (ours && !same && !matches!(record, DnsRecord::NSEC { .. })).then(|| record.domain().to_string())
+        })
+    }
+
+    // every record sent unasked to the whole group twice, a second apart, the unique ones with the cache
+    // flush bit so whatever anyone had for them before is replaced
+    fn announce(&self, socket: &UdpSocket) -> Result<()> {
+        let mut announcement = DnsPacket::new();
+        announcement.header.response = true;
+        announcement.header.authoritative_answer = true;
+        announcement.answers = self.records.iter().cloned().map(cache_flush).collect();
+        announcement.answers.extend(self.shared.iter().cloned());
+        let announcement = encode(&announcement)?;
+
+        socket.send_to(&announcement, (MDNS_GROUP, MDNS_PORT))?;
+        thread::sleep(Duration::from_secs(1));
+        socket.send_to(&announcement, (MDNS_GROUP, MDNS_PORT))?;
+
+        Ok(())
+    }
+}
+
+// a socket on port that's in group, which only one program on the host can have without SO_REUSEADDR.
+// What it sends to the group has a TTL of 255, so receivers can tell it came from the local link
+pub(crate) fn bind_group(group: Ipv4Addr, port: u16) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+
+    Ok(socket)
+}
+
+// the next message socket receives into data, with who sent it, None once deadline has passed
+pub(crate) fn receive_until(
+    socket: &UdpSocket,
+    deadline: Instant,
+    data: &mut [u8],
+) -> Result<Option<(usize, SocketAddr)>> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Ok(None);
+    }
+    socket.set_read_timeout(Some(left))?;
+    match socket.recv_from(data) {
+        Ok(received) => Ok(Some(received)),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// whether the query already has record among its known answers, with at least half of its TTL left
+fn known(query: &DnsPacket, record: &DnsRecord) -> bool {
+    query
+        .answers
+        .iter()
+        .any(|answer| same_data(answer, record) && answer.ttl() >= record.ttl() / 2)
+}
+
+// whether two records are the same apart from their TTLs and the case of their owners
+pub(crate) fn same_data(a: &DnsRecord, b: &DnsRecord) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    a.set_ttl(0);
+    b.set_ttl(0);
+    a.set_domain(a.domain().to_ascii_lowercase());
+    b.set_domain(b.domain().to_ascii_lowercase());
+
+    a == b
+}
+
+pub(crate) fn encode(packet: &DnsPacket) -> Result<Vec<u8>> {
+    let mut buffer = BytePacketBuffer::with_capacity(MAX_MESSAGE_SIZE);
+    packet.write(&mut buffer)?;
+
+    Ok(buffer.buffer[..buffer.pos()].to_vec())
+}
+
+// record with the cache flush bit in its class
+fn cache_flush(record: DnsRecord) -> DnsRecord {
+    DnsRecord::CLASS {
+        class: CLASS_IN | CACHE_FLUSH,
+        record: Box::new(record),
+    }
+}
+
+// message with the cache flush bit taken off the classes of its records, which are then IN ones, and
+// whether each of them had it in the order of their sections
+fn parse_message(message: &[u8]) -> Result<(DnsPacket, Vec<bool>)> {
+    let mut packet = parse_bytes(message)?;
+    let mut flushes = Vec::new();
+    for section in [&mut packet.answers, &mut packet.authorities, &mut packet.resources] {
+        *section = mem::take(section)
+            .into_iter()
+            .map(|record| match record {
+                DnsRecord::CLASS { class, record } if class == CLASS_IN | CACHE_FLUSH => {
+                    flushes.push(true);
+                    *record
+                }
+                record => {
+                    flushes.push(false);
+                    record
+                }
+            })
+            .collect();
+    }
+
+    Ok((packet, flushes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responder() -> MdnsResponder {
+        let service = DnsRecord::PTR {
+            domain: "_http._tcp.local".to_string(),
+            host: "mybox._http._tcp.local".to_string(),
+            ttl: HOST_TTL,
+        };
+        MdnsResponder::new()
+            .with_host("mybox", &[IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))])
+            .with_shared_record(service)
+    }
+
+    fn ask(name: &str, qtype: QueryType, source: SocketAddr) -> (Vec<u8>, SocketAddr) {
+        let mut query = DnsPacket::new();
+        query.questions.push(DnsQuestion::new(name.to_string(), qtype));
+        let (response, destination) = responder().response(&query, source).unwrap();
+
+        (encode(&response).unwrap(), destination)
+    }
+
+    #[test]
+    fn only_unique_records_flush() {
+        let source = SocketAddr::from((Ipv4Addr::new(192, 168, 1, 7), MDNS_PORT));
+        let (data, destination) = ask("mybox.local", QueryType::A, source);
+        assert_eq!(destination, SocketAddr::from((MDNS_GROUP, MDNS_PORT)));
+        let (response, flushes) = parse_message(&data).unwrap();
+        assert!(matches!(response.answers[..], [DnsRecord::A { .. }]));
+        assert_eq!(flushes, [true]);
+
+        let (data, _) = ask("_http._tcp.local", QueryType::PTR, source);
+        let (response, flushes) = parse_message(&data).unwrap();
+        let instance = "mybox._http._tcp.local";
+        assert!(matches!(&response.answers[..], [DnsRecord::PTR { host, .. }] if host == instance));
+        assert_eq!(flushes, [false]);
+    }
+
+    #[test]
+    fn ordinary_resolvers_get_no_flushes() {
+        let source = SocketAddr::from((Ipv4Addr::new(192, 168, 1, 7), 40000));
+        let (data, destination) = ask("mybox.local", QueryType::A, source);
+        assert_eq!(destination, source);
+        let (response, flushes) = parse_message(&data).unwrap();
+        assert_eq!(response.answers[0].ttl(), LEGACY_TTL);
+        assert!(flushes.iter().all(|flush| !flush));
+    }
+}
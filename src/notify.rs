@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    cache::lock,
+    client::exchange,
+    header::OPCODE_NOTIFY,
+    querylog::EventLog,
+    record::{fqdn, normal},
+    rng::random_id,
+    transfer::serial_newer,
+    zone::closest_zone,
+    DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode, Zone, ZoneTransfer,
+};
+#[cfg(feature = "tsig")]
+use crate::{tsig::exchange_signed, TsigKey};
+
+// an RFC 1996 NOTIFY, which a primary sends the secondaries of a zone when it changes so they don't
+// have to wait out the refresh interval of its SOA to find out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notify {
+    pub zone: String,
+    pub serial: Option<u32>,
+}
+
+impl Notify {
+    pub fn new(zone: &str) -> Notify {
+        Notify {
+            zone: zone.trim_end_matches('.').to_string(),
+            serial: None,
+        }
+    }
+
+    // the serial the zone is at now, secondaries that already have it can skip the transfer
+    pub fn with_serial(mut self, serial: u32) -> Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    // the message, with the zone's SOA as its question and, when there's a serial, an SOA in the
+    // answers that only the serial of means anything
+    pub fn to_packet(&self, id: u16) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.id = id;
+        packet.header.opcode = OPCODE_NOTIFY;
+        packet.header.authoritative_answer = true;
+        packet
+            .questions
+            .push(DnsQuestion::new(self.zone.clone(), QueryType::SOA));
+        if let Some(serial) = self.serial {
+            packet.answers.push(DnsRecord::SOA {
+                domain: self.zone.clone(),
+                mname: String::new(),
+                rname: String::new(),
+                serial,
+                refresh: 0,
+                retry: 0,
+                expire: 0,
+                minimum: 0,
+                ttl: 0,
+            });
+        }
+        packet.header = packet.synced_header();
+
+        packet
+    }
+
+    // tells the secondary at server about the change, it acknowledges with a response of its own
+    pub fn send(&self, server: (Ipv4Addr, u16), timeout: Option<Duration>) -> Result<DnsPacket> {
+        acknowledged(exchange(&self.to_packet(random_id()), server, timeout)?)
+    }
+
+    // send, signed with key, secondaries may only take a NOTIFY they can be sure came from the primary
+    #[cfg(feature = "tsig")]
+    pub fn send_signed(&self, server: (Ipv4Addr, u16), timeout: Option<Duration>, key: &TsigKey) -> Result<DnsPacket> {
+        acknowledged(exchange_signed(&self.to_packet(random_id()), server, timeout, key)?)
+    }
+}
+
+fn acknowledged(response: DnsPacket) -> Result<DnsPacket> {
+    if response.header.opcode != OPCODE_NOTIFY {
+        return Err(format!("The response has opcode {} instead of NOTIFY", response.header.opcode).into());
+    }
+    response.check_response_code()?;
+
+    Ok(response)
+}
+
+// the zones kept as copies of the ones on their primaries, each one is transferred again when its
+// primary sends a NOTIFY. Clones share the copies, which are answered from like the server's own zones
+#[derive(Clone, Debug, Default)]
+pub struct Secondary {
+    zones: Arc<Mutex<HashMap<String, SecondaryZone>>>,
+    // where transfers and notifies that aren't from a primary are reported
+    events: EventLog,
+}
+
+#[derive(Debug)]
+struct SecondaryZone {
+    transfer: ZoneTransfer,
+    // without an SOA until the first refresh
+    zone: Zone,
+    // so a burst of notifies doesn't start a transfer for each of them
+    refreshing: bool,
+}
+
+impl Secondary {
+    pub fn new() -> Secondary {
+        Secondary::default()
+    }
+
+    // keeps a copy of zone from the primary transfer asks, it's empty until the first refresh
+    pub fn with_zone(self, zone: &str, transfer: ZoneTransfer) -> Self {
+        let copy = SecondaryZone {
+            transfer,
+            zone: Zone::new(zone),
+            refreshing: false,
+        };
+        lock(&self.zones).insert(normal(zone), copy);
+        self
+    }
+
+    pub fn with_event_log(mut self, events: EventLog) -> Secondary {
+        self.events = events;
+        self
+    }
+
+    pub fn zones(&self) -> Vec<String> {
+        lock(&self.zones).keys().cloned().collect()
+    }
+
+    // the records of zone as of its last refresh, SOA first, None when it isn't one of the zones
+    pub fn records(&self, zone: &str) -> Option<Vec<DnsRecord>> {
+        lock(&self.zones).get(&normal(zone)).map(|copy| copy.zone.records())
+    }
+
+    pub fn serial(&self, zone: &str) -> Option<u32> {
+        lock(&self.zones).get(&normal(zone)).and_then(|copy| copy.zone.serial())
+    }
+
+    // the authoritative answer to question from the closest of the copies that has one, None when
+    // it's in none of them
+    pub(crate) fn answer(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let zones = lock(&self.zones);
+        let loaded = zones
+            .values()
+            .map(|copy| &copy.zone)
+            .filter(|zone| zone.soa().is_some());
+
+        closest_zone(loaded, &question.name).map(|zone| zone.answer(&question.name, question.qtype))
+    }
+
+    // brings the copy of zone up to date with an IXFR from its primary, or the whole zone when there's
+    // no copy yet. Returns whether it changed, which it doesn't while another refresh is under way
+    pub fn refresh(&self, zone: &str) -> Result<bool> {
+        let key = normal(zone);
+        let (transfer, mut records) = {
+            let mut zones = lock(&self.zones);
+            let copy = zones
+                .get_mut(&key)
+                .ok_or_else(|| format!("{} is not a secondary zone", fqdn(&key)))?;
+            if copy.refreshing {
+                return Ok(false);
+            }
+            copy.refreshing = true;
+            (copy.transfer.clone(), copy.zone.records())
+        };
+
+        // the lock isn't held for the transfer, so the old copy is still answered from meanwhile
+        let refreshed = transfer.refresh(&key, &mut records).and_then(|changed| match changed {
+            true => Zone::from_records(&key, records).map(Some),
+            false => Ok(None),
+        });
+        let mut zones = lock(&self.zones);
+        if let Some(copy) = zones.get_mut(&key) {
+            copy.refreshing = false;
+            if let Ok(Some(zone)) = &refreshed {
+                copy.zone = zone.clone();
+            }
+        }
+
+        refreshed.map(|zone| zone.is_some())
+    }
+
+    // refreshes every zone one after another, failures are logged rather than stopping the rest
+    pub fn refresh_all(&self) {
+        for zone in self.zones() {
+            self.refresh_logged(&zone);
+        }
+    }
+
+    fn refresh_logged(&self, zone: &str) {
+        match self.refresh(zone) {
+            Ok(true) => {
+                let serial = self.serial(zone).unwrap_or_default();
+                self.events
+                    .report(format_args!("Transferred {} at serial {}", fqdn(zone), serial));
+            }
+            Ok(false) => {}
+            Err(e) => self
+                .events
+                .report(format_args!("Transferring {} failed: {}", fqdn(zone), e)),
+        }
+    }
+
+    // what a NOTIFY for zone from source is answered with. Only the zone's primary may send one, and
+    // unless it names a serial the copy already has the zone is refreshed in the background
+    pub(crate) fn notified(&self, zone: &str, source: IpAddr, serial: Option<u32>) -> ResultCode {
+        let key = normal(zone);
+        let current = {
+            let zones = lock(&self.zones);
+            let Some(copy) = zones.get(&key) else {
+                return ResultCode::NOTAUTH;
+            };
+            if source != IpAddr::V4(copy.transfer.server.0) {
+                self.events.report(format_args!(
+                    "Ignored a NOTIFY for {} from {}, which isn't its primary",
+                    fqdn(&key),
+                    source
+                ));
+                return ResultCode::REFUSED;
+            }
+            copy.zone.serial()
+        };
+
+        if let (Some(serial), Some(current)) = (serial, current) {
+            if !serial_newer(serial, current) {
+                return ResultCode::NOERROR;
+            }
+        }
+        let secondary = self.clone();
+        thread::spawn(move || secondary.refresh_logged(&key));
+
+        ResultCode::NOERROR
+    }
+}
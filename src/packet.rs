@@ -0,0 +1,708 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    edns::{Edns, OPTION_PADDING},
+    encoding::hex_decode,
+    header::opcode_name,
+    record::fqdn,
+    BytePacketBuffer, DnsError, DnsHeader, DnsQuestion, DnsRecord, QueryType, Result, ResultCode,
+};
+
+// parses a packet straight from raw bytes, any input gives either a packet or an error, never a panic
+pub fn parse_bytes(data: &[u8]) -> Result<DnsPacket> {
+    let mut buffer = BytePacketBuffer::from_bytes(data)?;
+
+    DnsPacket::from_buffer(&mut buffer)
+}
+
+// per section record counts of a packet, handy for one line of stats per query
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PacketSummary {
+    pub id: u16,
+    pub result_code: ResultCode,
+    pub questions: usize,
+    pub answers: usize,
+    pub authorities: usize,
+    pub resources: usize,
+}
+
+impl fmt::Display for PacketSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "id={} rcode={:?} questions={} answers={} authorities={} resources={}",
+            self.id, self.result_code, self.questions, self.answers, self.authorities, self.resources
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsPacket {
+    pub header: DnsHeader,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub resources: Vec<DnsRecord>,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        DnsPacket::new()
+    }
+}
+
+impl DnsPacket {
+    pub fn new() -> DnsPacket {
+        DnsPacket {
+            header: DnsHeader::new(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            resources: Vec::new(),
+        }
+    }
+
+    // parses a hex dump of a packet, like the ones copied out of wireshark
+    pub fn from_hex(hex: &str) -> Result<DnsPacket> {
+        parse_bytes(&hex_decode(hex)?)
+    }
+
+    // a recursive query for a single question, the id is left to the caller so tests stay deterministic
+    pub fn new_query(id: u16, qname: &str, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.id = id;
+        packet.header.recursion_desired = true;
+        packet.header.questions = 1;
+        packet.questions.push(DnsQuestion::new(qname.to_string(), qtype));
+
+        packet
+    }
+
+    pub fn from_buffer(buffer: &mut BytePacketBuffer) -> Result<DnsPacket> {
+        let mut result = DnsPacket::new();
+
+        result.header.read(buffer)?;
+
+        for _ in 0..result.header.questions {
+            let mut question = DnsQuestion::new("".to_string(), QueryType::UNKNOWN(0));
+            question.read(buffer)?;
+            result.questions.push(question);
+        }
+
+        for _ in 0..result.header.answers {
+            let answer = DnsRecord::read(buffer)?;
+            result.answers.push(answer);
+        }
+
+        for _ in 0..result.header.authoritative_entries {
+            let authorities = DnsRecord::read(buffer)?;
+            result.authorities.push(authorities);
+        }
+
+        for _ in 0..result.header.resource_entries {
+            let entries = DnsRecord::read(buffer)?;
+            result.resources.push(entries);
+        }
+
+        Ok(result)
+    }
+
+    // serializes the packet, the header counts always come from the sections themselves
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.synced_header().write(buffer)?;
+
+        for question in &self.questions {
+            question.write(buffer)?;
+        }
+        for record in self.all_records() {
+            record.write(buffer)?;
+        }
+
+        Ok(())
+    }
+
+    // turns a query into an empty response for it, answers can then be pushed onto it
+    pub fn into_response(self) -> DnsPacket {
+        let mut response = DnsPacket::new();
+
+        response.header.id = self.header.id;
+        response.header.opcode = self.header.opcode;
+        response.header.recursion_desired = self.header.recursion_desired;
+        response.header.recursion_available = true;
+        response.header.response = true;
+
+        response.header.questions = self.questions.len() as u16;
+        response.questions = self.questions;
+
+        response
+    }
+
+    // same as into_response, for when the query is still needed afterwards
+    pub fn new_response(query: &DnsPacket) -> DnsPacket {
+        let skeleton = DnsPacket {
+            header: query.header.clone(),
+            questions: query.questions.clone(),
+            ..DnsPacket::new()
+        };
+
+        skeleton.into_response()
+    }
+
+    // turns a non NOERROR result code into the matching DnsError, the header is left untouched
+    pub fn check_response_code(&self) -> Result<()> {
+        match DnsError::from_result_code(self.header.result_code) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    // the header with its four counts taken from the sections, which is what gets written to the wire
+    pub fn synced_header(&self) -> DnsHeader {
+        let mut header = self.header.clone();
+        header.questions = self.questions.len() as u16;
+        header.answers = self.answers.len() as u16;
+        header.authoritative_entries = self.authorities.len() as u16;
+        header.resource_entries = self.resources.len() as u16;
+
+        header
+    }
+
+    // flags header counts that don't match the sections they describe
+    pub fn validate(&self) -> Result<()> {
+        let (questions, answers, authorities, resources) = self.counts();
+
+        for (section, claimed, actual) in [
+            ("question", self.header.questions, questions),
+            ("answer", self.header.answers, answers),
+            ("authority", self.header.authoritative_entries, authorities),
+            ("additional", self.header.resource_entries, resources),
+        ] {
+            if claimed as usize != actual {
+                return Err(format!(
+                    "Header claims {} {} entries but the packet has {}",
+                    claimed, section, actual
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // (questions, answers, authorities, resources) taken from the sections themselves, not the header
+    pub fn counts(&self) -> (usize, usize, usize, usize) {
+        (
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.resources.len(),
+        )
+    }
+
+    pub fn summary(&self) -> PacketSummary {
+        let (questions, answers, authorities, resources) = self.counts();
+
+        PacketSummary {
+            id: self.header.id,
+            result_code: self.header.result_code,
+            questions,
+            answers,
+            authorities,
+            resources,
+        }
+    }
+
+    // the answers in master file format, one `name TTL IN TYPE rdata` line each
+    pub fn to_zone_format(&self) -> String {
+        self.answers.iter().map(|record| format!("{}\n", record)).collect()
+    }
+
+    // appends the records of other that this packet doesn't have yet, cname chains often repeat them
+    pub fn merge(&mut self, other: &DnsPacket) {
+        for (section, incoming) in [
+            (&mut self.answers, &other.answers),
+            (&mut self.authorities, &other.authorities),
+            (&mut self.resources, &other.resources),
+        ] {
+            for record in incoming {
+                if !section.contains(record) {
+                    section.push(record.clone());
+                }
+            }
+        }
+
+        self.header = self.synced_header();
+    }
+
+    // the EDNS information of the OPT record in the additional section, if there is one
+    pub fn edns(&self) -> Option<&Edns> {
+        self.resources.iter().find_map(|record| match record {
+            DnsRecord::OPT { edns } => Some(edns),
+            _ => None,
+        })
+    }
+
+    pub fn edns_mut(&mut self) -> Option<&mut Edns> {
+        self.resources.iter_mut().find_map(|record| match record {
+            DnsRecord::OPT { edns } => Some(edns),
+            _ => None,
+        })
+    }
+
+    // adds an OPT record, replacing the one already there since a packet can only carry one
+    pub fn set_edns(&mut self, edns: Edns) {
+        self.resources.retain(|record| !matches!(record, DnsRecord::OPT { .. }));
+        self.resources.push(DnsRecord::OPT { edns });
+        self.header.resource_entries = self.resources.len() as u16;
+    }
+
+    // RFC 7830 padding, grows the OPT record with a padding option until the encoded packet is a
+    // multiple of block bytes long. Packets without EDNS can't carry it and are left alone, as are
+    // ones that padding would push past the largest message there can be
+    pub fn pad(&mut self, block: usize) {
+        let Some(edns) = self.edns_mut() else {
+            return;
+        };
+        edns.options.retain(|option| option.code != OPTION_PADDING);
+
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+        if block == 0 || self.write(&mut buffer).is_err() {
+            return;
+        }
+        // the option's own code and length take four bytes
+        let length = buffer.pos() + 4;
+        let padding = (block - length % block) % block;
+        if length + padding > MAX_BUFFER_SIZE {
+            return;
+        }
+
+        if let Some(edns) = self.edns_mut() {
+            edns.set_option(OPTION_PADDING, vec![0; padding]);
+        }
+    }
+
+    // the full twelve bit result code, combining the header with the EDNS extended bits
+    pub fn extended_result_code(&self) -> u16 {
+        let upper = self.edns().map(|edns| edns.extended_rcode).unwrap_or(0) as u16;
+        (upper << 4) | self.header.result_code as u16
+    }
+
+    // walks the answer, authority and additional sections in that order
+    pub fn all_records(&self) -> impl Iterator<Item = &DnsRecord> {
+        self.answers
+            .iter()
+            .chain(self.authorities.iter())
+            .chain(self.resources.iter())
+    }
+
+    pub fn records_of_type(&self, qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        self.all_records().filter(move |record| record.query_type() == qtype)
+    }
+}
+
+// compact dig-like output: a header summary, then one line per question and record
+impl fmt::Display for DnsPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+
+        let mut flags = Vec::new();
+        if header.response {
+            flags.push("qr");
+        }
+        if header.authoritative_answer {
+            flags.push("aa");
+        }
+        if header.truncated_message {
+            flags.push("tc");
+        }
+        if header.recursion_desired {
+            flags.push("rd");
+        }
+        if header.recursion_available {
+            flags.push("ra");
+        }
+        if header.authed_data {
+            flags.push("ad");
+        }
+        if header.checking_disabled {
+            flags.push("cd");
+        }
+
+        writeln!(
+            f,
+            ";; id: {}, opcode: {}, status: {:?}, flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            header.id,
+            opcode_name(header.opcode),
+            header.result_code,
+            flags.join(" "),
+            header.questions,
+            header.answers,
+            header.authoritative_entries,
+            header.resource_entries,
+        )?;
+
+        for question in &self.questions {
+            writeln!(f, ";{} IN {}", fqdn(&question.name), question.qtype)?;
+        }
+
+        for (title, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.resources),
+        ] {
+            if records.is_empty() {
+                continue;
+            }
+
+            writeln!(f, ";; {} SECTION:", title)?;
+            for record in records {
+                writeln!(f, "{}", record)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::*;
+
+    // a response with a record in every section, names repeated so they get compressed
+    fn sample_response() -> DnsPacket {
+        let mut packet = DnsPacket::new_query(0x1234, "example.com", QueryType::MX).into_response();
+        packet.answers.push(DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        });
+        let txt = DnsRecord::new_txt("example.com".to_string(), vec!["v=spf1 -all".to_string()], 300);
+        packet.answers.push(txt.unwrap());
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: "ns1.example.com".to_string(),
+            ttl: 3600,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: "mail.example.com".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 25),
+            ttl: 300,
+        });
+        packet.header = packet.synced_header();
+
+        packet
+    }
+
+    fn round_trip(packet: &DnsPacket) -> DnsPacket {
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+
+        parse_bytes(&buffer.buffer[..buffer.pos()]).unwrap()
+    }
+
+    #[test]
+    fn write_then_parse_gives_the_same_packet() {
+        let packet = sample_response();
+
+        assert_eq!(round_trip(&packet), packet);
+    }
+
+    #[test]
+    fn all_records_walks_the_sections_in_order() {
+        let packet = sample_response();
+        let types: Vec<QueryType> = packet.all_records().map(|record| record.query_type()).collect();
+
+        assert_eq!(types, [QueryType::MX, QueryType::TXT, QueryType::NS, QueryType::A]);
+    }
+
+    #[test]
+    fn records_of_type_counts_across_sections() {
+        let mut packet = sample_response();
+        packet.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+
+        let owners: Vec<&str> = packet
+            .records_of_type(QueryType::A)
+            .map(|record| record.domain())
+            .collect();
+        assert_eq!(owners, ["example.com", "mail.example.com"]);
+        assert_eq!(packet.records_of_type(QueryType::NS).count(), 1);
+        assert_eq!(packet.records_of_type(QueryType::AAAA).count(), 0);
+    }
+
+    #[test]
+    fn responses_echo_the_query() {
+        let mut query = DnsPacket::new_query(0xBEEF, "example.com", QueryType::AAAA);
+        query.header.opcode = 2;
+        query.answers.push(DnsRecord::A {
+            domain: "stray.example.com".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 9),
+            ttl: 1,
+        });
+
+        let response = DnsPacket::new_response(&query);
+        assert_eq!(response, query.clone().into_response());
+
+        assert_eq!(response.header.id, 0xBEEF);
+        assert_eq!(response.header.opcode, 2);
+        assert!(response.header.recursion_desired);
+        assert!(response.header.response);
+        assert!(response.header.recursion_available);
+        assert_eq!(response.questions, query.questions);
+        assert_eq!(response.header.questions, 1);
+        assert!(response.answers.is_empty());
+
+        query.header.recursion_desired = false;
+        assert!(!query.into_response().header.recursion_desired);
+    }
+
+    #[test]
+    fn check_response_code_leaves_the_header() {
+        let mut response = sample_response();
+        assert!(response.check_response_code().is_ok());
+
+        response.header.result_code = ResultCode::NXDOMAIN;
+        let error = response.check_response_code().unwrap_err();
+        assert_eq!(error, DnsError::NameError);
+        assert_eq!(response.header.result_code, ResultCode::NXDOMAIN);
+    }
+
+    #[test]
+    fn displays_like_dig() {
+        let expected = "\
+;; id: 4660, opcode: QUERY, status: NOERROR, flags: qr rd ra; \
+QUERY: 1, ANSWER: 2, AUTHORITY: 1, ADDITIONAL: 1
+;example.com. IN MX
+;; ANSWER SECTION:
+example.com. 300 IN MX 10 mail.example.com.
+example.com. 300 IN TXT \"v=spf1 -all\"
+;; AUTHORITY SECTION:
+example.com. 3600 IN NS ns1.example.com.
+;; ADDITIONAL SECTION:
+mail.example.com. 300 IN A 192.0.2.25
+";
+
+        assert_eq!(sample_response().to_string(), expected);
+    }
+
+    #[test]
+    fn zone_format_has_a_line_per_answer() {
+        let mut packet = sample_response();
+        packet.answers.push(DnsRecord::AAAA {
+            domain: "example.com".to_string(),
+            address: "2001:db8::1".parse().unwrap(),
+            ttl: 60,
+        });
+        packet.answers.push(DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024050101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        });
+
+        let expected = "\
+example.com. 300 IN MX 10 mail.example.com.
+example.com. 300 IN TXT \"v=spf1 -all\"
+example.com. 60 IN AAAA 2001:db8::1
+example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 2024050101 7200 3600 1209600 300
+";
+        assert_eq!(packet.to_zone_format(), expected);
+        assert_eq!(DnsPacket::new().to_zone_format(), "");
+    }
+
+    #[test]
+    fn counts_come_from_the_sections() {
+        let mut packet = sample_response();
+        // a header that lies about its counts doesn't change them
+        packet.header.answers = 9;
+        packet.header.result_code = ResultCode::NXDOMAIN;
+
+        assert_eq!(packet.counts(), (1, 2, 1, 1));
+        let summary = packet.summary();
+        assert_eq!(
+            summary,
+            PacketSummary {
+                id: 0x1234,
+                result_code: ResultCode::NXDOMAIN,
+                questions: 1,
+                answers: 2,
+                authorities: 1,
+                resources: 1,
+            }
+        );
+        let line = "id=4660 rcode=NXDOMAIN questions=1 answers=2 authorities=1 resources=1";
+        assert_eq!(summary.to_string(), line);
+        assert_eq!(DnsPacket::new().counts(), (0, 0, 0, 0));
+    }
+
+    // a response for google.com A as wireshark's "copy as hex stream" gives it
+    const GOOGLE_A: &str = concat!(
+        "8c1f8180000100010000000006676f6f676c6503636f6d0000010001",
+        "c00c000100010000012c00048efab52e",
+    );
+
+    #[test]
+    fn parses_a_wireshark_hex_stream() {
+        let packet = DnsPacket::from_hex(GOOGLE_A).unwrap();
+
+        assert_eq!(packet.header.id, 0x8c1f);
+        assert!(packet.header.response && packet.header.recursion_available);
+        assert_eq!(
+            packet.questions,
+            [DnsQuestion::new("google.com".to_string(), QueryType::A)]
+        );
+        assert_eq!(
+            packet.answers,
+            [DnsRecord::A {
+                domain: "google.com".to_string(),
+                address: Ipv4Addr::new(142, 250, 181, 46),
+                ttl: 300,
+            }]
+        );
+
+        // bytes can be spaced out and broken over lines the way hex dumps show them
+        let dump = "8c 1f 81 80 00 01 00 01 00 00 00 00 06 67 6f 6f
+                    67 6c 65 03 63 6f 6d 00 00 01 00 01 c0 0c 00 01
+                    00 01 00 00 01 2c 00 04 8e fa b5 2e";
+        assert_eq!(DnsPacket::from_hex(dump).unwrap(), packet);
+    }
+
+    #[test]
+    fn from_hex_rejects_bad_dumps() {
+        // odd number of digits
+        assert!(DnsPacket::from_hex(&GOOGLE_A[..GOOGLE_A.len() - 1]).is_err());
+        // not hex at all
+        assert!(DnsPacket::from_hex("8c1f81g0").is_err());
+        assert!(DnsPacket::from_hex("+1+2").is_err());
+        // valid hex, but cut off in the middle of the answer
+        assert!(DnsPacket::from_hex(&GOOGLE_A[..GOOGLE_A.len() - 8]).is_err());
+        assert!(DnsPacket::from_hex("").is_err());
+    }
+
+    #[test]
+    fn write_corrects_header_counts() {
+        let mut packet = sample_response();
+        packet
+            .questions
+            .push(DnsQuestion::new("example.net".to_string(), QueryType::AAAA));
+        packet.header.answers = 0;
+        assert!(packet.validate().is_err());
+
+        let written = round_trip(&packet);
+        assert_eq!(written.header.questions, 2);
+        assert_eq!(written.header.answers, 2);
+        assert_eq!(written.questions, packet.questions);
+        assert!(written.validate().is_ok());
+        assert_eq!(written.header, packet.synced_header());
+    }
+
+    fn written(packet: &DnsPacket) -> Option<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+        packet.write(&mut buffer).ok()?;
+
+        Some(buffer.buffer[..buffer.pos()].to_vec())
+    }
+
+    // what the fuzz target checks: a packet that parses and can be written parses back, and from then on
+    // writing and parsing it again changes nothing. The first write can still change it, a label with a
+    // dot in it becomes two and names sharing a suffix share its case once compressed
+    fn assert_round_trips(data: &[u8]) {
+        let Some(first) = parse_bytes(data).ok().as_ref().and_then(written) else {
+            return;
+        };
+        let packet = parse_bytes(&first).unwrap_or_else(|e| panic!("{} in {:02x?}", e, data));
+
+        let second = written(&packet).unwrap();
+        assert_eq!(parse_bytes(&second).ok(), Some(packet), "{:02x?}", data);
+        assert_eq!(second, first);
+    }
+
+    // a small xorshift, the same inputs every run
+    fn garbage(seed: &mut u32) -> u8 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        *seed as u8
+    }
+
+    #[test]
+    fn truncated_packets_are_errors() {
+        let mut buffer = BytePacketBuffer::new();
+        sample_response().write(&mut buffer).unwrap();
+        let data = &buffer.buffer[..buffer.pos()];
+
+        for end in 0..data.len() {
+            assert!(parse_bytes(&data[..end]).is_err(), "{} of {} bytes", end, data.len());
+        }
+        assert!(parse_bytes(data).is_ok());
+    }
+
+    #[test]
+    fn garbage_never_panics() {
+        let mut seed = 0x2545F491;
+        for length in 0..2000 {
+            let data: Vec<u8> = (0..length % 300).map(|_| garbage(&mut seed)).collect();
+            assert_round_trips(&data);
+        }
+
+        // mostly valid packets with a few bytes changed get much further into the parser
+        let mut packet = sample_response();
+        packet.set_edns(Edns::new(1232));
+        let data = written(&packet).unwrap();
+        for _ in 0..20000 {
+            let mut mutated = data.to_vec();
+            for _ in 0..1 + garbage(&mut seed) % 4 {
+                let index = usize::from(garbage(&mut seed)) % mutated.len();
+                mutated[index] = garbage(&mut seed);
+            }
+            assert_round_trips(&mutated);
+        }
+    }
+
+    #[test]
+    fn merge_skips_records_already_there() {
+        let mut packet = sample_response();
+        let glue = packet.resources[0].clone();
+
+        let mut other = DnsPacket::new();
+        other.answers.push(DnsRecord::A {
+            domain: "example.com".to_string(),
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+        other.resources.push(glue.clone());
+        // the same address with another ttl is a record of its own
+        let mut refreshed = glue.clone();
+        refreshed.set_ttl(60);
+        other.resources.push(refreshed.clone());
+
+        packet.merge(&other);
+        assert_eq!(packet.answers.len(), 3);
+        assert_eq!(packet.resources, [glue, refreshed]);
+        assert_eq!(packet.header.answers, 3);
+        assert_eq!(packet.header.resource_entries, 2);
+
+        // merging the same packet again adds nothing
+        let before = packet.clone();
+        packet.merge(&other);
+        assert_eq!(packet, before);
+    }
+}
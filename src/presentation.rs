@@ -0,0 +1,846 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    encoding::{base32hex_decode, base64_decode, hex_decode, parse_timestamp},
+    record::fqdn,
+    svcb::{
+        key_number, SvcParam, SvcParams, KEY_ALPN, KEY_ECH, KEY_IPV4HINT, KEY_IPV6HINT, KEY_MANDATORY,
+        KEY_NO_DEFAULT_ALPN, KEY_PORT,
+    },
+    BytePacketBuffer, DnsError, DnsRecord, QueryType, Result,
+};
+
+// one field of a line in presentation format. Quotes are taken off but escapes are left in, since
+// what they mean depends on the field, a `\#` at the start of the record data isn't just a `#`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Field {
+    pub text: String,
+    pub quoted: bool,
+}
+
+// splits text into fields at whitespace outside of quotes, stopping at a semicolon that starts a comment
+pub(crate) fn tokenize(text: &str) -> Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    let mut current: Option<Field> = None;
+    let mut in_quotes = false;
+
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let field = current.get_or_insert_with(Field::default);
+                field.text.push('\\');
+                field
+                    .text
+                    .push(chars.next().ok_or("Text ends in the middle of an escape")?);
+            }
+            '"' => {
+                current.get_or_insert_with(Field::default).quoted = true;
+                in_quotes = !in_quotes;
+            }
+            ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => fields.extend(current.take()),
+            c => current.get_or_insert_with(Field::default).text.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("Quoted string is never closed".into());
+    }
+    fields.extend(current);
+
+    Ok(fields)
+}
+
+// decodes \X to X and \DDD to the byte with that decimal value
+pub(crate) fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(text.len());
+
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            output.push(byte);
+            continue;
+        }
+
+        let next = bytes.next().ok_or("Text ends in the middle of an escape")?;
+        if !next.is_ascii_digit() {
+            output.push(next);
+            continue;
+        }
+
+        let digits = [Some(next), bytes.next(), bytes.next()];
+        let value = digits.iter().try_fold(0u32, |value, digit| match digit {
+            Some(digit) if digit.is_ascii_digit() => Some(value * 10 + (digit - b'0') as u32),
+            _ => None,
+        });
+        match value {
+            Some(value) if value <= 0xFF => output.push(value as u8),
+            _ => return Err(format!("Invalid escape in {}", text).into()),
+        }
+    }
+
+    Ok(output)
+}
+
+fn character_string(text: &str) -> Result<String> {
+    Ok(String::from_utf8_lossy(&unescape(text)?).to_string())
+}
+
+// names ending in a dot are absolute, anything else is relative to origin and @ is origin itself
+pub fn absolute_name(text: &str, origin: &str) -> Result<String> {
+    if text == "@" {
+        return Ok(origin.to_string());
+    }
+
+    let absolute = text.ends_with('.') && !text.ends_with("\\.");
+    let name = character_string(text)?;
+    match name.strip_suffix('.') {
+        Some(name) if absolute => Ok(name.to_string()),
+        _ if origin.is_empty() => Ok(name),
+        _ => Ok(format!("{}.{}", name, origin)),
+    }
+}
+
+// a TTL or SOA timer, either plain seconds or with units the way BIND takes them, like 1h30m
+pub(crate) fn seconds(text: &str) -> Result<u32> {
+    if let Ok(seconds) = text.parse() {
+        return Ok(seconds);
+    }
+
+    let invalid = || -> DnsError { format!("Invalid TTL {}", text).into() };
+    let mut total: u32 = 0;
+    let mut number: Option<u32> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            let value = number
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(digit));
+            number = Some(value.ok_or_else(invalid)?);
+            continue;
+        }
+
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(invalid()),
+        };
+        let value = number.take().ok_or_else(invalid)?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|value| total.checked_add(value))
+            .ok_or_else(invalid)?;
+    }
+
+    match number {
+        Some(_) => Err(invalid()),
+        None => Ok(total),
+    }
+}
+
+// a record in presentation format, `name [ttl] [class] type data`, with relative names completed
+// from origin and default_ttl for records that don't give one. Only class IN is supported
+pub fn parse_record(text: &str, origin: &str, default_ttl: u32) -> Result<DnsRecord> {
+    let fields = tokenize(text)?;
+    let origin = origin.trim_end_matches('.');
+
+    let (owner, rest) = fields.split_first().ok_or("The record is empty")?;
+    let owner = absolute_name(&owner.text, origin)?;
+    let (ttl, rest) = ttl_and_class(rest)?;
+
+    let (qtype, rdata) = rest.split_first().ok_or("The record has no type")?;
+    let qtype = QueryType::from_str(&qtype.text)?;
+
+    record_from_fields(owner, ttl.unwrap_or(default_ttl), qtype, rdata, origin)
+}
+
+// records as the text of a zone file for origin, which parse_record and Zone::parse read back. Owners
+// in the zone are written relative to the $ORIGIN line it starts with, the origin itself as @, and
+// everything else is written the way Display prints it
+pub fn zone_file(origin: &str, records: &[DnsRecord]) -> String {
+    let origin = origin.trim_end_matches('.');
+    let mut text = format!("$ORIGIN {}\n", fqdn(origin));
+    for record in records {
+        let line = record.to_string();
+        let owner = fqdn(record.domain());
+        match line.strip_prefix(&owner) {
+            Some(rest) => {
+                text.push_str(&relative_owner(&owner, origin));
+                text.push_str(rest);
+            }
+            None => text.push_str(&line),
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+// owner, printed, relative to origin where it's in it. A leading $ is escaped, it would make the line
+// a directive
+fn relative_owner(owner: &str, origin: &str) -> String {
+    let apex = fqdn(origin);
+    let suffix = format!(".{}", apex);
+    let lowercase = owner.to_ascii_lowercase();
+    let relative = if owner.eq_ignore_ascii_case(&apex) {
+        "@"
+    } else if !origin.is_empty() && lowercase.ends_with(&suffix.to_ascii_lowercase()) {
+        &owner[..owner.len() - suffix.len()]
+    } else {
+        owner
+    };
+
+    match relative.starts_with('$') {
+        true => format!("\\{}", relative),
+        false => relative.to_string(),
+    }
+}
+
+// the TTL, if there is one, from the fields between the owner and the type, along with the fields
+// from the type on. The TTL and the class can come in either order, and both can be left out
+pub(crate) fn ttl_and_class(mut rest: &[Field]) -> Result<(Option<u32>, &[Field])> {
+    let mut ttl = None;
+    let mut class = false;
+    while let Some((field, after)) = rest.split_first() {
+        let upper = field.text.to_ascii_uppercase();
+        if ttl.is_none() && upper.starts_with(|c: char| c.is_ascii_digit()) {
+            ttl = Some(seconds(&field.text)?);
+        } else if !class && upper == "IN" {
+            class = true;
+        } else if matches!(upper.as_str(), "CH" | "CHAOS" | "HS" | "HESIOD") || upper.starts_with("CLASS") {
+            return Err(format!("Only class IN records are supported, not {}", field.text).into());
+        } else {
+            break;
+        }
+        rest = after;
+    }
+
+    Ok((ttl, rest))
+}
+
+// a record of the given type from the fields of its record data, which can always be in the generic
+// form from RFC 3597 and otherwise have to be in the form DnsRecord prints them in
+pub(crate) fn record_from_fields(
+    domain: String,
+    ttl: u32,
+    qtype: QueryType,
+    rdata: &[Field],
+    origin: &str,
+) -> Result<DnsRecord> {
+    let meta = [
+        QueryType::OPT,
+        QueryType::TSIG,
+        QueryType::IXFR,
+        QueryType::AXFR,
+        QueryType::ANY,
+    ];
+    if meta.contains(&qtype) {
+        return Err(format!("{} isn't a type records can be written down as", qtype).into());
+    }
+    if let Some((first, rest)) = rdata.split_first() {
+        if first.text == "\\#" && !first.quoted {
+            return generic(domain, ttl, qtype, rest);
+        }
+    }
+
+    let mut fields = Fields { rest: rdata, qtype };
+    let record = match qtype {
+        QueryType::A => DnsRecord::A {
+            domain,
+            address: fields.parse::<Ipv4Addr>("address")?,
+            ttl,
+        },
+        QueryType::AAAA => DnsRecord::AAAA {
+            domain,
+            address: fields.parse::<Ipv6Addr>("address")?,
+            ttl,
+        },
+        QueryType::HINFO => DnsRecord::HINFO {
+            domain,
+            cpu: fields.string("cpu")?,
+            os: fields.string("os")?,
+            ttl,
+        },
+        QueryType::NS => DnsRecord::NS {
+            domain,
+            host: fields.name("host", origin)?,
+            ttl,
+        },
+        QueryType::CNAME => DnsRecord::CNAME {
+            domain,
+            host: fields.name("target", origin)?,
+            ttl,
+        },
+        QueryType::PTR => DnsRecord::PTR {
+            domain,
+            host: fields.name("host", origin)?,
+            ttl,
+        },
+        QueryType::MX => DnsRecord::MX {
+            domain,
+            priority: fields.parse("priority")?,
+            host: fields.name("exchange", origin)?,
+            ttl,
+        },
+        QueryType::TXT => {
+            let strings = fields.remaining().iter().map(|field| unescape(&field.text));
+            let strings = strings.collect::<Result<Vec<Vec<u8>>>>()?;
+            if strings.is_empty() {
+                return Err("The TXT record is missing its text".into());
+            }
+            DnsRecord::new_txt(domain, strings, ttl)?
+        }
+        QueryType::SOA => DnsRecord::SOA {
+            domain,
+            mname: fields.name("primary server", origin)?,
+            rname: fields.name("mailbox", origin)?,
+            serial: fields.parse("serial")?,
+            refresh: fields.seconds("refresh")?,
+            retry: fields.seconds("retry")?,
+            expire: fields.seconds("expire")?,
+            minimum: fields.seconds("minimum")?,
+            ttl,
+        },
+        QueryType::SRV => DnsRecord::SRV {
+            domain,
+            priority: fields.parse("priority")?,
+            weight: fields.parse("weight")?,
+            port: fields.parse("port")?,
+            host: fields.name("target", origin)?,
+            ttl,
+        },
+        QueryType::CAA => DnsRecord::CAA {
+            domain,
+            flags: fields.parse("flags")?,
+            tag: fields.next("tag")?.text.clone(),
+            value: fields.bytes("value")?,
+            ttl,
+        },
+        QueryType::TLSA => DnsRecord::TLSA {
+            domain,
+            usage: fields.parse("usage")?,
+            selector: fields.parse("selector")?,
+            matching_type: fields.parse("matching type")?,
+            certificate: hex_decode(&fields.joined("certificate data")?)?,
+            ttl,
+        },
+        QueryType::NAPTR => DnsRecord::NAPTR {
+            domain,
+            order: fields.parse("order")?,
+            preference: fields.parse("preference")?,
+            flags: fields.string("flags")?,
+            services: fields.string("services")?,
+            regexp: fields.string("regexp")?,
+            replacement: fields.name("replacement", origin)?,
+            ttl,
+        },
+        QueryType::SVCB => DnsRecord::SVCB {
+            domain,
+            priority: fields.parse("priority")?,
+            target: fields.name("target", origin)?,
+            params: svc_params(fields.remaining())?,
+            ttl,
+        },
+        QueryType::HTTPS => DnsRecord::HTTPS {
+            domain,
+            priority: fields.parse("priority")?,
+            target: fields.name("target", origin)?,
+            params: svc_params(fields.remaining())?,
+            ttl,
+        },
+        QueryType::DS => DnsRecord::DS {
+            domain,
+            key_tag: fields.parse("key tag")?,
+            algorithm: fields.parse("algorithm")?,
+            digest_type: fields.parse("digest type")?,
+            digest: hex_decode(&fields.joined("digest")?)?,
+            ttl,
+        },
+        QueryType::DNSKEY => DnsRecord::DNSKEY {
+            domain,
+            flags: fields.parse("flags")?,
+            protocol: fields.parse("protocol")?,
+            algorithm: fields.parse("algorithm")?,
+            public_key: base64_decode(&fields.joined("public key")?)?,
+            ttl,
+        },
+        QueryType::RRSIG => DnsRecord::RRSIG {
+            domain,
+            type_covered: fields.parse("type covered")?,
+            algorithm: fields.parse("algorithm")?,
+            labels: fields.parse("labels")?,
+            original_ttl: fields.parse("original TTL")?,
+            expiration: parse_timestamp(&fields.next("expiration")?.text)?,
+            inception: parse_timestamp(&fields.next("inception")?.text)?,
+            key_tag: fields.parse("key tag")?,
+            signer_name: fields.name("signer name", origin)?,
+            signature: base64_decode(&fields.joined("signature")?)?,
+            ttl,
+        },
+        QueryType::NSEC => DnsRecord::NSEC {
+            domain,
+            next_domain: fields.name("next domain", origin)?,
+            types: fields.types()?,
+            ttl,
+        },
+        QueryType::NSEC3 => DnsRecord::NSEC3 {
+            domain,
+            hash_algorithm: fields.parse("hash algorithm")?,
+            flags: fields.parse("flags")?,
+            iterations: fields.parse("iterations")?,
+            salt: match fields.next("salt")?.text.as_str() {
+                "-" => Vec::new(),
+                salt => hex_decode(salt)?,
+            },
+            next_hashed: base32hex_decode(&fields.next("next hashed owner")?.text)?,
+            types: fields.types()?,
+            ttl,
+        },
+        _ => return Err(format!("{} records can only be given in the generic \\# form", qtype).into()),
+    };
+
+    fields.finish()?;
+    Ok(record)
+}
+
+// `\# length hex`, which is put on the wire and read back so known types still come out as their own
+// variant
+fn generic(domain: String, ttl: u32, qtype: QueryType, fields: &[Field]) -> Result<DnsRecord> {
+    let (length, hex) = fields
+        .split_first()
+        .ok_or("Generic record data is missing its length")?;
+    let length: u16 = length
+        .text
+        .parse()
+        .map_err(|_| format!("Invalid generic record data length {}", length.text))?;
+
+    let data = hex_decode(&hex.iter().map(|field| field.text.as_str()).collect::<String>())?;
+    if data.len() != length as usize {
+        return Err(format!("Generic record data is {} bytes instead of {}", data.len(), length).into());
+    }
+
+    let raw = DnsRecord::UNKNOWN {
+        domain,
+        qtype: qtype.to_num(),
+        data_len: length,
+        data,
+        ttl,
+    };
+    let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+    raw.write(&mut buffer)?;
+    buffer.seek(0)?;
+
+    DnsRecord::read(&mut buffer)
+}
+
+// the parameters of SVCB and HTTPS records, key=value with lists separated by commas, or the bare key
+// for ones without a value
+fn svc_params(fields: &[Field]) -> Result<SvcParams> {
+    let mut params: Vec<SvcParam> = Vec::new();
+    for field in fields {
+        let (key, value) = field.text.split_once('=').unwrap_or((&field.text, ""));
+        let number = key_number(key)?;
+        let invalid = || -> DnsError { format!("Invalid {} value {}", key, value).into() };
+
+        let value = match number {
+            KEY_MANDATORY => {
+                let keys = value.split(',').map(key_number).collect::<Result<Vec<u16>>>()?;
+                keys.iter().flat_map(|key| key.to_be_bytes()).collect()
+            }
+            KEY_ALPN => {
+                let mut data = Vec::new();
+                for id in value.split(',') {
+                    let id = unescape(id)?;
+                    if id.is_empty() || id.len() > 0xFF {
+                        return Err(invalid());
+                    }
+                    data.push(id.len() as u8);
+                    data.extend_from_slice(&id);
+                }
+                data
+            }
+            KEY_NO_DEFAULT_ALPN if value.is_empty() => Vec::new(),
+            KEY_NO_DEFAULT_ALPN => return Err(invalid()),
+            KEY_PORT => value.parse::<u16>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            KEY_IPV4HINT => {
+                let hints = value
+                    .split(',')
+                    .map(|hint| hint.parse::<Ipv4Addr>().map_err(|_| invalid()));
+                hints
+                    .map(|hint| hint.map(|hint| hint.octets()))
+                    .collect::<Result<Vec<_>>>()?
+                    .concat()
+            }
+            KEY_ECH => base64_decode(value)?,
+            KEY_IPV6HINT => {
+                let hints = value
+                    .split(',')
+                    .map(|hint| hint.parse::<Ipv6Addr>().map_err(|_| invalid()));
+                hints
+                    .map(|hint| hint.map(|hint| hint.octets()))
+                    .collect::<Result<Vec<_>>>()?
+                    .concat()
+            }
+            _ => unescape(value)?,
+        };
+
+        if params.iter().any(|param| param.key == number) {
+            return Err(format!("SvcParam {} is given twice", key).into());
+        }
+        params.push(SvcParam { key: number, value });
+    }
+
+    // they have to go on the wire in increasing order of their keys
+    params.sort_by_key(|param| param.key);
+
+    Ok(SvcParams(params))
+}
+
+// the record data fields still to be read, along with the type for error messages
+struct Fields<'a> {
+    rest: &'a [Field],
+    qtype: QueryType,
+}
+
+impl<'a> Fields<'a> {
+    fn next(&mut self, what: &str) -> Result<&'a Field> {
+        let (field, rest) = self
+            .rest
+            .split_first()
+            .ok_or_else(|| format!("The {} record is missing its {}", self.qtype, what))?;
+        self.rest = rest;
+
+        Ok(field)
+    }
+
+    fn parse<T: FromStr>(&mut self, what: &str) -> Result<T> {
+        let field = self.next(what)?;
+        field
+            .text
+            .parse()
+            .map_err(|_| format!("Invalid {} {} in the {} record", what, field.text, self.qtype).into())
+    }
+
+    fn seconds(&mut self, what: &str) -> Result<u32> {
+        seconds(&self.next(what)?.text)
+    }
+
+    fn name(&mut self, what: &str, origin: &str) -> Result<String> {
+        absolute_name(&self.next(what)?.text, origin)
+    }
+
+    fn string(&mut self, what: &str) -> Result<String> {
+        character_string(&self.next(what)?.text)
+    }
+
+    fn bytes(&mut self, what: &str) -> Result<Vec<u8>> {
+        unescape(&self.next(what)?.text)
+    }
+
+    // everything left run together, for base64 and hex that may be broken up with spaces
+    fn joined(&mut self, what: &str) -> Result<String> {
+        if self.rest.is_empty() {
+            return Err(format!("The {} record is missing its {}", self.qtype, what).into());
+        }
+
+        Ok(self.remaining().iter().map(|field| field.text.as_str()).collect())
+    }
+
+    fn types(&mut self) -> Result<Vec<QueryType>> {
+        self.remaining().iter().map(|field| field.text.parse()).collect()
+    }
+
+    fn remaining(&mut self) -> &'a [Field] {
+        core::mem::take(&mut self.rest)
+    }
+
+    fn finish(&self) -> Result<()> {
+        match self.rest.first() {
+            Some(field) => Err(format!("Unexpected {} at the end of the {} record", field.text, self.qtype).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+// the inverse of Display, with every name taken as absolute and a TTL of 0 if there's none
+impl FromStr for DnsRecord {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<DnsRecord> {
+        parse_record(s, "", 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoding::hex_encode, svcb::SvcParams};
+
+    // a record of every type presentation format has, with names and strings that need escaping
+    fn every_type() -> Vec<DnsRecord> {
+        let domain = || "example.com".to_string();
+        let svc_params = SvcParams(vec![
+            SvcParam {
+                key: KEY_MANDATORY,
+                value: vec![0, KEY_ALPN as u8],
+            },
+            SvcParam {
+                key: KEY_ALPN,
+                value: b"\x02h2\x02h3".to_vec(),
+            },
+            SvcParam {
+                key: KEY_NO_DEFAULT_ALPN,
+                value: Vec::new(),
+            },
+            SvcParam {
+                key: KEY_PORT,
+                value: 8443u16.to_be_bytes().to_vec(),
+            },
+            SvcParam {
+                key: KEY_IPV4HINT,
+                value: vec![192, 0, 2, 1, 192, 0, 2, 2],
+            },
+            SvcParam {
+                key: KEY_ECH,
+                value: vec![0xfe, 0x0d, 0x00, 0x01],
+            },
+            SvcParam {
+                key: KEY_IPV6HINT,
+                value: Ipv6Addr::LOCALHOST.octets().to_vec(),
+            },
+        ]);
+
+        vec![
+            DnsRecord::UNKNOWN {
+                domain: domain(),
+                qtype: 65280,
+                data_len: 3,
+                data: vec![0xde, 0xad, 0],
+                ttl: 60,
+            },
+            DnsRecord::A {
+                domain: "a\u{7}b.example.com".to_string(),
+                address: Ipv4Addr::new(192, 0, 2, 1),
+                ttl: 300,
+            },
+            DnsRecord::HINFO {
+                domain: domain(),
+                cpu: "Intel Xeon".to_string(),
+                os: "Linux \"6\"".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::DNSKEY {
+                domain: domain(),
+                flags: 257,
+                protocol: 3,
+                algorithm: 15,
+                public_key: (0..32).collect(),
+                ttl: 3600,
+            },
+            DnsRecord::AAAA {
+                domain: "with space.example.com".to_string(),
+                address: Ipv6Addr::LOCALHOST,
+                ttl: 300,
+            },
+            DnsRecord::NS {
+                domain: domain(),
+                host: "ns1.example.net".to_string(),
+                ttl: 86400,
+            },
+            DnsRecord::CNAME {
+                domain: "semi;colon.example.com".to_string(),
+                host: domain(),
+                ttl: 300,
+            },
+            DnsRecord::MX {
+                domain: domain(),
+                priority: 10,
+                host: "mail.example.com".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::new_txt(
+                domain(),
+                vec![
+                    &b"v=spf1 -all"[..],
+                    b"say \"hi\"; not a comment",
+                    b"back\\slash",
+                    b"\x00\x07\xe9\xff",
+                    b"",
+                ],
+                300,
+            )
+            .unwrap(),
+            DnsRecord::SOA {
+                domain: domain(),
+                mname: "ns1.example.com".to_string(),
+                rname: "host\\.master.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+                ttl: 3600,
+            },
+            DnsRecord::SRV {
+                domain: "_sip._udp.example.com".to_string(),
+                priority: 10,
+                weight: 60,
+                port: 5060,
+                host: "sip.example.com".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::PTR {
+                domain: "1.2.0.192.in-addr.arpa".to_string(),
+                host: domain(),
+                ttl: 300,
+            },
+            DnsRecord::CAA {
+                domain: domain(),
+                flags: 128,
+                tag: "issue".to_string(),
+                value: b"ca; x=\"y\"".to_vec(),
+                ttl: 300,
+            },
+            DnsRecord::TLSA {
+                domain: "_443._tcp.example.com".to_string(),
+                usage: 3,
+                selector: 1,
+                matching_type: 1,
+                certificate: (0..32).collect(),
+                ttl: 300,
+            },
+            DnsRecord::NAPTR {
+                domain: domain(),
+                order: 100,
+                preference: 10,
+                flags: "S".to_string(),
+                services: "SIP+D2U".to_string(),
+                regexp: "!^.*$!sip:info@example.com!".to_string(),
+                replacement: "_sip._udp.example.com".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::SVCB {
+                domain: "_dns.example.com".to_string(),
+                priority: 1,
+                target: "dns.example.com".to_string(),
+                params: svc_params.clone(),
+                ttl: 300,
+            },
+            DnsRecord::HTTPS {
+                domain: domain(),
+                priority: 1,
+                target: String::new(),
+                params: svc_params,
+                ttl: 300,
+            },
+            DnsRecord::DS {
+                domain: domain(),
+                key_tag: 3613,
+                algorithm: 15,
+                digest_type: 2,
+                digest: vec![0xab; 32],
+                ttl: 3600,
+            },
+            DnsRecord::RRSIG {
+                domain: domain(),
+                type_covered: QueryType::MX,
+                algorithm: 15,
+                labels: 2,
+                original_ttl: 3600,
+                expiration: 1440021600,
+                inception: 1438207200,
+                key_tag: 3613,
+                signer_name: domain(),
+                signature: (0..64).collect(),
+                ttl: 3600,
+            },
+            DnsRecord::NSEC {
+                domain: domain(),
+                next_domain: "\u{1}.example.com".to_string(),
+                types: vec![
+                    QueryType::A,
+                    QueryType::MX,
+                    QueryType::RRSIG,
+                    QueryType::NSEC,
+                    QueryType::UNKNOWN(1234),
+                ],
+                ttl: 300,
+            },
+            DnsRecord::NSEC3 {
+                domain: "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom.example".to_string(),
+                hash_algorithm: 1,
+                flags: 1,
+                iterations: 12,
+                salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+                next_hashed: (0..20).collect(),
+                types: vec![QueryType::A, QueryType::RRSIG],
+                ttl: 300,
+            },
+            DnsRecord::NSEC3 {
+                domain: "35mthgpgcu1qg68fab165klnsnk3dpvl.example".to_string(),
+                hash_algorithm: 1,
+                flags: 0,
+                iterations: 0,
+                salt: Vec::new(),
+                next_hashed: (20..40).collect(),
+                types: Vec::new(),
+                ttl: 300,
+            },
+        ]
+    }
+
+    #[test]
+    fn records_read_back_the_way_they_print() {
+        for record in every_type() {
+            let text = record.to_string();
+            assert_eq!(parse_record(&text, "", 0).unwrap(), record, "{}", text);
+
+            // and in the generic form any type can be given in, from the rdata past the owner, type,
+            // class, ttl and rdata length
+            let mut owner = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+            owner.write_q_name_uncompressed(record.domain()).unwrap();
+            let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE).without_compression();
+            record.write(&mut buffer).unwrap();
+            let rdata = &buffer.buffer[owner.pos() + 10..buffer.pos()];
+            let (owner, qtype) = (fqdn(record.domain()), record.query_type());
+            let generic = format!(
+                "{} {} {} \\# {} {}",
+                owner,
+                record.ttl(),
+                qtype,
+                rdata.len(),
+                hex_encode(rdata)
+            );
+            assert_eq!(parse_record(&generic, "", 0).unwrap(), record, "{}", generic);
+        }
+
+        // \DDD escapes a byte, \X the character itself
+        let text = r#"a\007b.example.com. 300 IN TXT "\"quoted\" \\ \233" plain\;text"#;
+        let DnsRecord::TXT { domain, raw, .. } = parse_record(text, "", 0).unwrap() else {
+            panic!("{} isn't a TXT record", text);
+        };
+        assert_eq!(domain, "a\u{7}b.example.com");
+        assert_eq!(raw, b"\x0c\"quoted\" \\ \xe9\x0aplain;text");
+    }
+
+    #[test]
+    fn zone_files_read_back() {
+        let records = every_type();
+        let text = zone_file("example.com", &records);
+        let zone = crate::zonefile::parse_records(&text, "test.zone", std::path::Path::new(".")).unwrap();
+        assert_eq!(zone, records);
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{cache::lock, encoding::format_timestamp, server::Transport, DnsError, DnsQuestion, Result, ResultCode};
+
+// what query log lines look like, text for reading them and json lines for feeding them to something
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<LogFormat> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("{} isn't a log format, text or json", s).into()),
+        }
+    }
+}
+
+// a line for every query the server answers: when, who asked over what, the question, the result code,
+// how long answering took, whether the cache had the answer and which upstreams gave it. Lines go to
+// stdout or get appended to a file, which with a rotation is moved to path.1 once it would grow past
+// its maximum size, the one before that to path.2 and so on, and only as many old ones are kept
+#[derive(Debug)]
+pub struct QueryLog {
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+#[derive(Debug)]
+enum Output {
+    Stdout,
+    File(LogFile),
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    // None never rotates
+    max_size: Option<u64>,
+    keep: usize,
+}
+
+impl QueryLog {
+    pub fn stdout(format: LogFormat) -> QueryLog {
+        QueryLog {
+            format,
+            output: Mutex::new(Output::Stdout),
+        }
+    }
+
+    // appends to the file at path, which is created when there isn't one
+    pub fn file(path: &Path, format: LogFormat) -> Result<QueryLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let file = LogFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size: None,
+            keep: 0,
+        };
+
+        Ok(QueryLog {
+            format,
+            output: Mutex::new(Output::File(file)),
+        })
+    }
+
+    // rotates the file once it's max_size bytes, keeping keep old ones. Stdout isn't rotated
+    pub fn with_rotation(mut self, max_size: u64, keep: usize) -> QueryLog {
+        if let Output::File(file) = self.output.get_mut().unwrap_or_else(PoisonError::into_inner) {
+            file.max_size = Some(max_size);
+            file.keep = keep;
+        }
+        self
+    }
+
+    pub(crate) fn record(&self, entry: &LogEntry) -> io::Result<()> {
+        let line = match self.format {
+            LogFormat::Text => entry.text(),
+            LogFormat::Json => entry.json(),
+        };
+        match &mut *lock(&self.output) {
+            Output::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Output::File(file) => file.write_line(&line),
+        }
+    }
+}
+
+// where servers and responders report what happens besides answering, like a client they couldn't
+// answer or a zone that was transferred. The default one drops everything
+#[derive(Clone)]
+pub struct EventLog {
+    write: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl EventLog {
+    pub fn new(write: impl Fn(&str) + Send + Sync + 'static) -> EventLog {
+        EventLog { write: Arc::new(write) }
+    }
+
+    pub(crate) fn report(&self, message: fmt::Arguments) {
+        (self.write)(&message.to_string());
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> EventLog {
+        EventLog::new(|_| {})
+    }
+}
+
+impl fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+impl LogFile {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + length > max_size)
+        {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+
+        Ok(())
+    }
+
+    // path.1 becomes path.2 and so on, the oldest is overwritten, and a new file takes path's place
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |number: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", number));
+            PathBuf::from(path)
+        };
+        for number in (1..self.keep).rev() {
+            match fs::rename(numbered(number), numbered(number + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, numbered(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+// what answering one query came down to, filled in while it's resolved. A question answered in parts,
+// like a local cname resolved onwards or a synthesized AAAA answer, is a cache hit only when every part
+// came from the cache
+#[derive(Debug, Default)]
+pub(crate) struct Trace {
+    traced: Mutex<Traced>,
+}
+
+#[derive(Debug, Default)]
+struct Traced {
+    // None when the cache was never asked
+    cache_hit: Option<bool>,
+    upstreams: Vec<String>,
+}
+
+impl Trace {
+    pub(crate) fn cache(&self, hit: bool) {
+        let mut traced = lock(&self.traced);
+        traced.cache_hit = Some(traced.cache_hit.unwrap_or(true) && hit);
+    }
+
+    // an upstream that answered
+    pub(crate) fn upstream(&self, upstream: String) {
+        let mut traced = lock(&self.traced);
+        if !traced.upstreams.contains(&upstream) {
+            traced.upstreams.push(upstream);
+        }
+    }
+}
+
+// one line of the log
+pub(crate) struct LogEntry<'a> {
+    pub client: IpAddr,
+    pub transport: Transport,
+    // None for queries without a single question
+    pub question: Option<&'a DnsQuestion>,
+    pub result_code: ResultCode,
+    pub latency: Duration,
+    pub trace: &'a Trace,
+}
+
+impl LogEntry<'_> {
+    fn text(&self) -> String {
+        let traced = lock(&self.trace.traced);
+        let question = match self.question {
+            Some(question) => format!("{}. {}", question.name.trim_end_matches('.'), question.qtype),
+            None => "-".to_string(),
+        };
+        let cache = match traced.cache_hit {
+            Some(true) => "hit",
+            Some(false) => "miss",
+            None => "-",
+        };
+        let upstreams = match traced.upstreams.is_empty() {
+            true => "-".to_string(),
+            false => traced.upstreams.join(","),
+        };
+
+        format!(
+            "{} {} {} {} {:?} {:.1}ms cache={} upstream={}",
+            timestamp(),
+            self.client,
+            transport_name(self.transport),
+            question,
+            self.result_code,
+            self.latency.as_secs_f64() * 1000.0,
+            cache,
+            upstreams
+        )
+    }
+
+    fn json(&self) -> String {
+        let traced = lock(&self.trace.traced);
+        let (name, qtype) = match self.question {
+            Some(question) => {
+                let name = json_string(question.name.trim_end_matches('.'));
+                (name, json_string(&question.qtype.to_string()))
+            }
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let cache = match traced.cache_hit {
+            Some(true) => "\"hit\"",
+            Some(false) => "\"miss\"",
+            None => "null",
+        };
+        let upstreams: Vec<String> = traced.upstreams.iter().map(|upstream| json_string(upstream)).collect();
+
+        format!(
+            "{{\"time\":\"{}\",\"client\":\"{}\",\"transport\":\"{}\",\"name\":{},\"type\":{},\
+             \"rcode\":\"{:?}\",\"latency_ms\":{:.3},\"cache\":{},\"upstream\":[{}]}}",
+            timestamp(),
+            self.client,
+            transport_name(self.transport),
+            name,
+            qtype,
+            self.result_code,
+            self.latency.as_secs_f64() * 1000.0,
+            cache,
+            upstreams.join(",")
+        )
+    }
+}
+
+fn transport_name(transport: Transport) -> &'static str {
+    match transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+        Transport::Tls => "tls",
+        Transport::Https => "https",
+        Transport::Quic => "quic",
+    }
+}
+
+// now in UTC as 2024-05-01T12:30:00.250Z
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let digits = format_timestamp(u32::try_from(now.as_secs()).unwrap_or(u32::MAX));
+    let field = |range: core::ops::Range<usize>| &digits[range];
+
+    format!(
+        "{}-{}-{}T{}:{}:{}.{:03}Z",
+        field(0..4),
+        field(4..6),
+        field(6..8),
+        field(8..10),
+        field(10..12),
+        field(12..14),
+        now.subsec_millis()
+    )
+}
+
+// text as a json string, with its quotes
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of its own for each test, gone once the test is done with it
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("dnslearning-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // nine bytes and a newline each
+    fn write_lines(log: &mut QueryLog, lines: std::ops::RangeInclusive<usize>) {
+        let Output::File(file) = log.output.get_mut().unwrap() else {
+            panic!("The log isn't written to a file");
+        };
+        for number in lines {
+            file.write_line(&format!("line {:04}", number)).unwrap();
+        }
+    }
+
+    // the lines in the file, None when there's no such file
+    fn lines_in(path: &Path, suffix: &str) -> Option<Vec<String>> {
+        let mut path = path.to_path_buf().into_os_string();
+        path.push(suffix);
+        let text = fs::read_to_string(PathBuf::from(path)).ok()?;
+        Some(text.lines().map(|line| line[5..].to_string()).collect())
+    }
+
+    #[test]
+    fn full_files_are_rotated_keeping_the_newest() {
+        let directory = TempDir::new("rotation");
+        let path = directory.0.join("queries.log");
+        let mut log = QueryLog::file(&path, LogFormat::Text).unwrap().with_rotation(25, 2);
+
+        // two lines fit in 25 bytes, the third moves them to .1
+        write_lines(&mut log, 1..=3);
+        assert_eq!(lines_in(&path, ""), Some(vec!["0003".to_string()]));
+        assert_eq!(
+            lines_in(&path, ".1"),
+            Some(vec!["0001".to_string(), "0002".to_string()])
+        );
+
+        write_lines(&mut log, 4..=7);
+        assert_eq!(lines_in(&path, ""), Some(vec!["0007".to_string()]));
+        assert_eq!(
+            lines_in(&path, ".1"),
+            Some(vec!["0005".to_string(), "0006".to_string()])
+        );
+        assert_eq!(
+            lines_in(&path, ".2"),
+            Some(vec!["0003".to_string(), "0004".to_string()])
+        );
+        assert_eq!(lines_in(&path, ".3"), None);
+    }
+
+    #[test]
+    fn files_without_old_ones_to_keep_start_over() {
+        let directory = TempDir::new("rotation-keep-0");
+        let path = directory.0.join("queries.log");
+        let mut log = QueryLog::file(&path, LogFormat::Text).unwrap().with_rotation(25, 0);
+
+        write_lines(&mut log, 1..=5);
+        assert_eq!(lines_in(&path, ""), Some(vec!["0005".to_string()]));
+        assert_eq!(lines_in(&path, ".1"), None);
+    }
+
+    #[test]
+    fn rotation_counts_what_the_file_already_had() {
+        let directory = TempDir::new("rotation-existing");
+        let path = directory.0.join("queries.log");
+        fs::write(&path, "line 0001\nline 0002\n").unwrap();
+
+        let mut log = QueryLog::file(&path, LogFormat::Text).unwrap().with_rotation(25, 1);
+        write_lines(&mut log, 3..=3);
+        assert_eq!(lines_in(&path, ""), Some(vec!["0003".to_string()]));
+        assert_eq!(
+            lines_in(&path, ".1"),
+            Some(vec!["0001".to_string(), "0002".to_string()])
+        );
+
+        // a line longer than the maximum still goes into an empty file, it's the next one that rotates
+        let long = directory.0.join("long.log");
+        let mut log = QueryLog::file(&long, LogFormat::Text).unwrap().with_rotation(5, 1);
+        write_lines(&mut log, 1..=2);
+        assert_eq!(lines_in(&long, ""), Some(vec!["0002".to_string()]));
+        assert_eq!(lines_in(&long, ".1"), Some(vec!["0001".to_string()]));
+
+        // and without a rotation the file only grows
+        let mut log = QueryLog::file(&path, LogFormat::Text).unwrap();
+        write_lines(&mut log, 4..=9);
+        assert_eq!(lines_in(&path, "").map(|lines| lines.len()), Some(7));
+        assert_eq!(lines_in(&path, ".2"), None);
+    }
+}
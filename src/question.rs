@@ -0,0 +1,331 @@
+use alloc::{format, string::String};
+use core::{fmt, net::IpAddr, str::FromStr};
+
+use crate::{BytePacketBuffer, DnsError, Result};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash, Copy)]
+pub enum QueryType {
+    UNKNOWN(u16),
+    A,      // 1
+    NS,     // 2
+    CNAME,  // 5
+    SOA,    // 6
+    PTR,    // 12
+    HINFO,  // 13
+    MX,     // 15
+    TXT,    // 16
+    AAAA,   // 28
+    SRV,    // 33
+    NAPTR,  // 35
+    OPT,    // 41
+    DS,     // 43
+    RRSIG,  // 46
+    NSEC,   // 47
+    DNSKEY, // 48
+    NSEC3,  // 50
+    TLSA,   // 52
+    SVCB,   // 64
+    HTTPS,  // 65
+    TSIG,   // 250, only ever the last record of a message
+    IXFR,   // 251, asks for the changes to a zone since a serial
+    AXFR,   // 252, asks for a whole zone over tcp
+    ANY,    // 255, only valid in questions
+    CAA,    // 257
+}
+
+impl QueryType {
+    pub fn to_num(&self) -> u16 {
+        match *self {
+            QueryType::UNKNOWN(x) => x,
+            QueryType::A => 1,
+            QueryType::NS => 2,
+            QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
+            QueryType::HINFO => 13,
+            QueryType::MX => 15,
+            QueryType::TXT => 16,
+            QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::NAPTR => 35,
+            QueryType::OPT => 41,
+            QueryType::DS => 43,
+            QueryType::RRSIG => 46,
+            QueryType::NSEC => 47,
+            QueryType::DNSKEY => 48,
+            QueryType::NSEC3 => 50,
+            QueryType::TLSA => 52,
+            QueryType::SVCB => 64,
+            QueryType::HTTPS => 65,
+            QueryType::TSIG => 250,
+            QueryType::IXFR => 251,
+            QueryType::AXFR => 252,
+            QueryType::ANY => 255,
+            QueryType::CAA => 257,
+        }
+    }
+
+    pub fn from_num(num: u16) -> QueryType {
+        match num {
+            1 => QueryType::A,
+            2 => QueryType::NS,
+            5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
+            13 => QueryType::HINFO,
+            15 => QueryType::MX,
+            16 => QueryType::TXT,
+            28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            35 => QueryType::NAPTR,
+            41 => QueryType::OPT,
+            43 => QueryType::DS,
+            46 => QueryType::RRSIG,
+            47 => QueryType::NSEC,
+            48 => QueryType::DNSKEY,
+            50 => QueryType::NSEC3,
+            52 => QueryType::TLSA,
+            64 => QueryType::SVCB,
+            65 => QueryType::HTTPS,
+            250 => QueryType::TSIG,
+            251 => QueryType::IXFR,
+            252 => QueryType::AXFR,
+            255 => QueryType::ANY,
+            257 => QueryType::CAA,
+            _ => QueryType::UNKNOWN(num),
+        }
+    }
+}
+
+// the name PTR records for an address live under, e.g. 4.3.2.1.in-addr.arpa for 1.2.3.4,
+// ipv6 addresses are spelled out one reversed nibble per label under ip6.arpa
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// mnemonic as used by dig and zone files, unknown types use the TYPEn form from RFC 3597
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            QueryType::UNKNOWN(x) => write!(f, "TYPE{}", x),
+            QueryType::A => write!(f, "A"),
+            QueryType::HINFO => write!(f, "HINFO"),
+            QueryType::DNSKEY => write!(f, "DNSKEY"),
+            QueryType::AAAA => write!(f, "AAAA"),
+            QueryType::NS => write!(f, "NS"),
+            QueryType::CNAME => write!(f, "CNAME"),
+            QueryType::MX => write!(f, "MX"),
+            QueryType::TXT => write!(f, "TXT"),
+            QueryType::SOA => write!(f, "SOA"),
+            QueryType::SRV => write!(f, "SRV"),
+            QueryType::PTR => write!(f, "PTR"),
+            QueryType::CAA => write!(f, "CAA"),
+            QueryType::TLSA => write!(f, "TLSA"),
+            QueryType::NAPTR => write!(f, "NAPTR"),
+            QueryType::SVCB => write!(f, "SVCB"),
+            QueryType::HTTPS => write!(f, "HTTPS"),
+            QueryType::DS => write!(f, "DS"),
+            QueryType::RRSIG => write!(f, "RRSIG"),
+            QueryType::NSEC => write!(f, "NSEC"),
+            QueryType::NSEC3 => write!(f, "NSEC3"),
+            QueryType::OPT => write!(f, "OPT"),
+            QueryType::TSIG => write!(f, "TSIG"),
+            QueryType::IXFR => write!(f, "IXFR"),
+            QueryType::AXFR => write!(f, "AXFR"),
+            QueryType::ANY => write!(f, "ANY"),
+        }
+    }
+}
+
+// accepts the mnemonic in any case, the TYPEn form, or a bare type number
+impl FromStr for QueryType {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<QueryType> {
+        let upper = s.to_ascii_uppercase();
+        match upper.as_str() {
+            "A" => Ok(QueryType::A),
+            "HINFO" => Ok(QueryType::HINFO),
+            "DNSKEY" => Ok(QueryType::DNSKEY),
+            "AAAA" => Ok(QueryType::AAAA),
+            "NS" => Ok(QueryType::NS),
+            "CNAME" => Ok(QueryType::CNAME),
+            "MX" => Ok(QueryType::MX),
+            "TXT" => Ok(QueryType::TXT),
+            "SOA" => Ok(QueryType::SOA),
+            "SRV" => Ok(QueryType::SRV),
+            "PTR" => Ok(QueryType::PTR),
+            "CAA" => Ok(QueryType::CAA),
+            "TLSA" => Ok(QueryType::TLSA),
+            "NAPTR" => Ok(QueryType::NAPTR),
+            "SVCB" => Ok(QueryType::SVCB),
+            "HTTPS" => Ok(QueryType::HTTPS),
+            "DS" => Ok(QueryType::DS),
+            "RRSIG" => Ok(QueryType::RRSIG),
+            "NSEC" => Ok(QueryType::NSEC),
+            "NSEC3" => Ok(QueryType::NSEC3),
+            "OPT" => Ok(QueryType::OPT),
+            "TSIG" => Ok(QueryType::TSIG),
+            "IXFR" => Ok(QueryType::IXFR),
+            "AXFR" => Ok(QueryType::AXFR),
+            "ANY" | "*" => Ok(QueryType::ANY),
+            _ => {
+                let number = upper.strip_prefix("TYPE").unwrap_or(&upper);
+                match number.parse::<u16>() {
+                    Ok(num) => Ok(QueryType::from_num(num)),
+                    Err(_) => Err(format!("Unknown record type {}", s).into()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: QueryType,
+    // almost always 1 for IN, CH (3) still turns up for things like version.bind
+    pub class: u16,
+}
+
+// the internet class, which is what every question is for unless said otherwise
+pub const CLASS_IN: u16 = 1;
+
+impl DnsQuestion {
+    pub fn new(name: String, qtype: QueryType) -> DnsQuestion {
+        DnsQuestion {
+            name,
+            qtype,
+            class: CLASS_IN,
+        }
+    }
+
+    // same as new, but refuses names that would turn into a malformed packet
+    pub fn new_checked(name: String, qtype: QueryType) -> Result<DnsQuestion> {
+        validate_name(&name)?;
+
+        Ok(DnsQuestion::new(name, qtype))
+    }
+
+    pub fn read(&mut self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        self.name = buffer.read_qname()?;
+        self.qtype = QueryType::from_num(buffer.read_u16()?); // qtype
+        self.class = buffer.read_u16()?;
+
+        Ok(())
+    }
+
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_q_name(&self.name)?;
+        buffer.write_u16(self.qtype.to_num())?;
+        buffer.write_u16(self.class)?;
+
+        Ok(())
+    }
+}
+
+// a single trailing dot (or just "." for the root) is fine, empty labels, whitespace and non ascii aren't
+fn validate_name(name: &str) -> Result<()> {
+    if name == "." {
+        return Ok(());
+    }
+
+    let relative = name.strip_suffix('.').unwrap_or(name);
+    if relative.is_empty() {
+        return Err("Domain name is empty".into());
+    }
+
+    // every label costs its length plus a length byte, and the root label another byte
+    if relative.len() + 2 > 255 {
+        return Err(format!("Domain name {} exceeds 255 bytes", name).into());
+    }
+
+    for label in relative.split('.') {
+        if label.is_empty() {
+            return Err(format!("Domain name {} contains an empty label", name).into());
+        }
+        if label.len() > 63 {
+            return Err(format!("Label {} exceeds 63 characters of length", label).into());
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !c.is_ascii() || c.is_ascii_whitespace() || c.is_ascii_control())
+        {
+            return Err(format!("Label {} contains the invalid character {:?}", label, c).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::DnsPacket;
+
+    #[test]
+    fn any_is_type_255() {
+        assert_eq!(QueryType::from_num(255), QueryType::ANY);
+        assert_eq!(QueryType::ANY.to_num(), 255);
+        assert_eq!(QueryType::ANY.to_string(), "ANY");
+        for text in ["ANY", "any", "*", "TYPE255", "255"] {
+            assert_eq!(text.parse::<QueryType>().unwrap(), QueryType::ANY, "{}", text);
+        }
+    }
+
+    #[test]
+    fn any_queries_go_out_as_255() {
+        let query = DnsPacket::new_query(1, "example.com", QueryType::ANY);
+        let mut buffer = BytePacketBuffer::new();
+        query.write(&mut buffer).unwrap();
+
+        // the header, then example.com in 13 bytes, then the type and class
+        assert_eq!(buffer.get_range(12 + 13, 4).unwrap(), [0, 255, 0, 1]);
+
+        buffer.seek(12).unwrap();
+        let mut question = DnsQuestion::new(String::new(), QueryType::A);
+        question.read(&mut buffer).unwrap();
+        assert_eq!(question, DnsQuestion::new("example.com".to_string(), QueryType::ANY));
+    }
+
+    #[test]
+    fn new_checked_refuses_malformed_names() {
+        let checked = |name: &str| DnsQuestion::new_checked(name.to_string(), QueryType::A);
+
+        for name in ["example.com", "example.com.", "_sip._tcp.example.com", ".", "localhost"] {
+            assert!(checked(name).is_ok(), "{}", name);
+        }
+        assert_eq!(checked("example.com.").unwrap().name, "example.com.");
+
+        let long_label = format!("{}.com", "a".repeat(64));
+        let long_name = ["abcdefghi"; 26].join(".");
+        for name in [
+            "",
+            "a..b",
+            ".example.com",
+            "example.com..",
+            "bücher.de",
+            "exa mple.com",
+            "tab\t.com",
+        ] {
+            assert!(checked(name).is_err(), "{:?}", name);
+        }
+        assert!(checked(&long_label).is_err());
+        assert!(checked(&long_name).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{cache::lock, edns::masked};
+
+// clients tracked at most, past that the ones that have been quiet for a second are forgotten, which
+// loses nothing since their buckets are full again by then
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// how often a full map of clients is swept at most, a flood of new ones would have every query walk
+// all of them otherwise. Whatever arrives in between is tracked on top of the maximum
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// how many queries a second one network may send over udp before the rest are dropped, anyone can
+// put someone else's address on a udp packet so this keeps the server from being used to flood them.
+// Addresses share a bucket with the rest of their /24, or /56 for ipv6, since whoever spoofs one of them
+// can spoof its neighbours just as well. Every slip-th dropped query gets an empty truncated answer
+// instead, which is no bigger than the query but sends a real client behind the address to tcp, where
+// it can't be spoofed and isn't limited. A slip of 0 drops them all
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    // the limit for queries with a valid server cookie, which can't come from a spoofed address
+    pub cookie_per_second: u32,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub slip: u32,
+}
+
+impl RateLimit {
+    // clients with a cookie get ten times as many, and every second dropped query slips through
+    // truncated like BIND does it
+    pub fn new(per_second: u32) -> RateLimit {
+        RateLimit {
+            per_second,
+            cookie_per_second: per_second.saturating_mul(10),
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+            slip: 2,
+        }
+    }
+
+    pub fn with_cookie_limit(mut self, per_second: u32) -> RateLimit {
+        self.cookie_per_second = per_second;
+        self
+    }
+
+    // how many bits of an address pick its bucket, 32 and 128 give every address one of its own
+    pub fn with_prefixes(mut self, ipv4_prefix: u8, ipv6_prefix: u8) -> RateLimit {
+        self.ipv4_prefix = ipv4_prefix.min(32);
+        self.ipv6_prefix = ipv6_prefix.min(128);
+        self
+    }
+
+    pub fn with_slip(mut self, slip: u32) -> RateLimit {
+        self.slip = slip;
+        self
+    }
+}
+
+// what happens to a query once it has been counted against its network's limit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Allow,
+    Drop,
+    // answered with nothing but the TC bit
+    Slip,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // queries over the limit since the last one that slipped
+    dropped: u32,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    // by network and whether its queries had a valid cookie
+    networks: HashMap<(IpAddr, bool), Bucket>,
+    // when the quiet ones were last forgotten
+    swept: Option<Instant>,
+}
+
+// a token bucket per network and whether its queries had a valid cookie, each holds a second's worth
+// of queries and refills at the limit's rate
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+    // where the time buckets refill by comes from
+    now: fn() -> Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(Buckets::default()),
+            now: Instant::now,
+        }
+    }
+
+    // refills buckets by the time now returns instead of the system clock, so tests can move it along
+    #[cfg(test)]
+    fn with_clock(mut self, now: fn() -> Instant) -> RateLimiter {
+        self.now = now;
+        self
+    }
+
+    // takes a token from the bucket of the client's network, once it's empty the query is dropped or
+    // slips
+    pub(crate) fn allow(&self, client: IpAddr, cookie: bool) -> Verdict {
+        let rate = f64::from(if cookie {
+            self.limit.cookie_per_second
+        } else {
+            self.limit.per_second
+        });
+        let now = (self.now)();
+
+        let mut buckets = lock(&self.buckets);
+        let due = match buckets.swept {
+            Some(swept) => now.duration_since(swept) >= SWEEP_INTERVAL,
+            None => true,
+        };
+        if buckets.networks.len() >= MAX_TRACKED_CLIENTS && due {
+            buckets
+                .networks
+                .retain(|_, bucket| now.duration_since(bucket.updated) < Duration::from_secs(1));
+            buckets.swept = Some(now);
+        }
+
+        let prefix = if client.is_ipv4() {
+            self.limit.ipv4_prefix
+        } else {
+            self.limit.ipv6_prefix
+        };
+        let bucket = buckets
+            .networks
+            .entry((masked(client, prefix), cookie))
+            .or_insert(Bucket {
+                tokens: rate,
+                updated: now,
+                dropped: 0,
+            });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(rate);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        if self.limit.slip == 0 {
+            return Verdict::Drop;
+        }
+        bucket.dropped += 1;
+        if bucket.dropped < self.limit.slip {
+            return Verdict::Drop;
+        }
+        bucket.dropped = 0;
+
+        Verdict::Slip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, net::Ipv4Addr};
+
+    use super::*;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+        static OFFSET: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+    }
+
+    // a clock that only moves when advance says so, every test runs on its own thread
+    fn now() -> Instant {
+        START.with(|start| *start) + OFFSET.with(Cell::get)
+    }
+
+    fn advance(millis: u64) {
+        OFFSET.with(|offset| offset.set(offset.get() + Duration::from_millis(millis)));
+    }
+
+    fn address(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    fn verdicts(limiter: &RateLimiter, client: IpAddr, cookie: bool, queries: usize) -> Vec<Verdict> {
+        (0..queries).map(|_| limiter.allow(client, cookie)).collect()
+    }
+
+    #[test]
+    fn every_slip_th_dropped_query_is_truncated() {
+        use Verdict::{Allow, Drop, Slip};
+        let client = address(192, 0, 2, 1);
+
+        let limiter = RateLimiter::new(RateLimit::new(2)).with_clock(now);
+        assert_eq!(
+            verdicts(&limiter, client, false, 7),
+            [Allow, Allow, Drop, Slip, Drop, Slip, Drop]
+        );
+        // a second later the bucket is full again, and the count of dropped ones carries on
+        advance(1000);
+        assert_eq!(verdicts(&limiter, client, false, 4), [Allow, Allow, Slip, Drop]);
+        // half a second is half the tokens
+        advance(500);
+        assert_eq!(verdicts(&limiter, client, false, 2), [Allow, Slip]);
+
+        let limiter = RateLimiter::new(RateLimit::new(1).with_slip(3)).with_clock(now);
+        assert_eq!(
+            verdicts(&limiter, client, false, 7),
+            [Allow, Drop, Drop, Slip, Drop, Drop, Slip]
+        );
+        let limiter = RateLimiter::new(RateLimit::new(1).with_slip(1)).with_clock(now);
+        assert_eq!(verdicts(&limiter, client, false, 3), [Allow, Slip, Slip]);
+        let limiter = RateLimiter::new(RateLimit::new(1).with_slip(0)).with_clock(now);
+        assert_eq!(verdicts(&limiter, client, false, 3), [Allow, Drop, Drop]);
+    }
+
+    #[test]
+    fn networks_and_cookies_have_buckets_of_their_own() {
+        let limiter = RateLimiter::new(RateLimit::new(1).with_cookie_limit(3).with_slip(0)).with_clock(now);
+        assert_eq!(limiter.allow(address(192, 0, 2, 1), false), Verdict::Allow);
+        // the rest of the /24 shares the bucket, the next /24 doesn't
+        assert_eq!(limiter.allow(address(192, 0, 2, 200), false), Verdict::Drop);
+        assert_eq!(limiter.allow(address(192, 0, 3, 1), false), Verdict::Allow);
+
+        let with_cookie = verdicts(&limiter, address(192, 0, 2, 1), true, 4);
+        assert_eq!(
+            with_cookie,
+            [Verdict::Allow, Verdict::Allow, Verdict::Allow, Verdict::Drop]
+        );
+
+        let v6 = |last| IpAddr::V6([0x2001, 0xdb8, 0, last, 0, 0, 0, 1].into());
+        assert_eq!(limiter.allow(v6(0x01), false), Verdict::Allow);
+        assert_eq!(limiter.allow(v6(0xff), false), Verdict::Drop);
+        assert_eq!(limiter.allow(v6(0x100), false), Verdict::Allow);
+    }
+
+    #[test]
+    fn quiet_clients_are_swept_at_most_once_a_second() {
+        let limiter = RateLimiter::new(RateLimit::new(10).with_prefixes(32, 128)).with_clock(now);
+        let tracked = || lock(&limiter.buckets).networks.len();
+        let fill = |group: u8| {
+            for i in 0..MAX_TRACKED_CLIENTS / 2 {
+                limiter.allow(address(10, group, (i >> 8) as u8, i as u8), false);
+            }
+        };
+
+        fill(1);
+        advance(900);
+        fill(2);
+        assert_eq!(tracked(), MAX_TRACKED_CLIENTS);
+
+        // full, so the first group goes, the second hasn't been quiet for a second yet
+        advance(600);
+        limiter.allow(address(192, 0, 2, 1), false);
+        assert_eq!(tracked(), MAX_TRACKED_CLIENTS / 2 + 1);
+        fill(3);
+
+        // the second group has been quiet long enough now, but the last sweep was only half a second ago
+        advance(500);
+        limiter.allow(address(192, 0, 2, 2), false);
+        assert_eq!(tracked(), MAX_TRACKED_CLIENTS + 2);
+
+        advance(500);
+        limiter.allow(address(192, 0, 2, 3), false);
+        assert_eq!(tracked(), 2);
+    }
+}
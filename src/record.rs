@@ -0,0 +1,1814 @@
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    edns::{
+        ClientSubnet, Edns, EdnsOption, ExtendedError, OPTION_CLIENT_SUBNET, OPTION_COOKIE, OPTION_EXTENDED_ERROR,
+        OPTION_NSID, OPTION_PADDING,
+    },
+    encoding::{base32hex_encode, base64_encode, format_timestamp, hex_encode},
+    svcb::{SvcParam, SvcParams},
+    BytePacketBuffer, QueryType, Result,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[allow(dead_code)]
+pub enum DnsRecord {
+    UNKNOWN {
+        domain: String,
+        qtype: u16,
+        data_len: u16,
+        data: Vec<u8>,
+        ttl: u32,
+    },
+    A {
+        domain: String,
+        address: Ipv4Addr,
+        ttl: u32,
+    },
+    HINFO {
+        domain: String,
+        cpu: String,
+        os: String,
+        ttl: u32,
+    },
+    DNSKEY {
+        domain: String,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    },
+    AAAA {
+        domain: String,
+        address: Ipv6Addr,
+        ttl: u32,
+    },
+    NS {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    CNAME {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    MX {
+        domain: String,
+        priority: u16,
+        host: String,
+        ttl: u32,
+    },
+    // the character-strings as they are on the wire, each one with its length in front
+    TXT {
+        domain: String,
+        raw: Vec<u8>,
+        ttl: u32,
+    },
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    },
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        host: String,
+        ttl: u32,
+    },
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    },
+    CAA {
+        domain: String,
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+        ttl: u32,
+    },
+    TLSA {
+        domain: String,
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        certificate: Vec<u8>,
+        ttl: u32,
+    },
+    NAPTR {
+        domain: String,
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: String,
+        ttl: u32,
+    },
+    SVCB {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: SvcParams,
+        ttl: u32,
+    },
+    HTTPS {
+        domain: String,
+        priority: u16,
+        target: String,
+        params: SvcParams,
+        ttl: u32,
+    },
+    DS {
+        domain: String,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    },
+    RRSIG {
+        domain: String,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+        ttl: u32,
+    },
+    NSEC {
+        domain: String,
+        next_domain: String,
+        types: Vec<QueryType>,
+        ttl: u32,
+    },
+    NSEC3 {
+        domain: String,
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed: Vec<u8>,
+        types: Vec<QueryType>,
+        ttl: u32,
+    },
+    // EDNS pseudo-record, only ever found in the additional section
+    OPT {
+        edns: Edns,
+    },
+    // a transaction signature from RFC 8945, the last record of a signed message. The owner is the name
+    // of the key, the class is always ANY and the TTL 0
+    TSIG {
+        domain: String,
+        algorithm: String,
+        // seconds since the epoch, 48 bits on the wire
+        time_signed: u64,
+        fudge: u16,
+        mac: Vec<u8>,
+        original_id: u16,
+        error: u16,
+        other: Vec<u8>,
+    },
+    // a prerequisite or deletion from an RFC 2136 update, which says what it means through the class,
+    // ANY or NONE. The data is the record data of a single record to delete, empty for whole rrsets
+    UPDATE {
+        domain: String,
+        qtype: QueryType,
+        class: u16,
+        data: Vec<u8>,
+    },
+    // a record of a class other than IN, like the CH TXT records servers answer version.bind with. The
+    // record is read the way it would be in IN
+    CLASS {
+        class: u16,
+        record: Box<DnsRecord>,
+    },
+}
+
+// the error field of a TSIG record when the other side didn't accept the signature
+pub const TSIG_BADSIG: u16 = 16;
+pub const TSIG_BADKEY: u16 = 17;
+pub const TSIG_BADTIME: u16 = 18;
+pub const TSIG_BADTRUNC: u16 = 22;
+
+// the class of TSIG records, and of questions for every class
+pub const CLASS_ANY: u16 = 255;
+// only used by updates, for records that must not exist or are to be deleted
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+pub const CLASS_HS: u16 = 4;
+
+impl DnsRecord {
+    // builds a TXT record, filling in the raw rdata the strings encode to
+    pub fn new_txt<T: AsRef<[u8]>>(domain: String, strings: Vec<T>, ttl: u32) -> Result<DnsRecord> {
+        let mut raw = Vec::new();
+        for text in strings.iter().map(AsRef::as_ref) {
+            if text.len() > 0xFF {
+                return Err("Character-string exceeds 255 bytes".into());
+            }
+            raw.push(text.len() as u8);
+            raw.extend_from_slice(text);
+        }
+
+        Ok(DnsRecord::TXT { domain, raw, ttl })
+    }
+
+    // the character-strings of a TXT record as text, bytes that aren't utf-8 are replaced. None for
+    // records that aren't TXT
+    pub fn txt_strings(&self) -> Option<Vec<String>> {
+        match self {
+            DnsRecord::TXT { raw, .. } => Some(
+                character_strings(raw)
+                    .into_iter()
+                    .map(|text| String::from_utf8_lossy(text).into_owned())
+                    .collect(),
+            ),
+            DnsRecord::CLASS { record, .. } => record.txt_strings(),
+            _ => None,
+        }
+    }
+
+    pub fn query_type(&self) -> QueryType {
+        match *self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::UNKNOWN(qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::HINFO { .. } => QueryType::HINFO,
+            DnsRecord::DNSKEY { .. } => QueryType::DNSKEY,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::CAA { .. } => QueryType::CAA,
+            DnsRecord::TLSA { .. } => QueryType::TLSA,
+            DnsRecord::NAPTR { .. } => QueryType::NAPTR,
+            DnsRecord::SVCB { .. } => QueryType::SVCB,
+            DnsRecord::HTTPS { .. } => QueryType::HTTPS,
+            DnsRecord::DS { .. } => QueryType::DS,
+            DnsRecord::RRSIG { .. } => QueryType::RRSIG,
+            DnsRecord::NSEC { .. } => QueryType::NSEC,
+            DnsRecord::NSEC3 { .. } => QueryType::NSEC3,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+            DnsRecord::TSIG { .. } => QueryType::TSIG,
+            DnsRecord::UPDATE { qtype, .. } => qtype,
+            DnsRecord::CLASS { ref record, .. } => record.query_type(),
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::TSIG { domain, .. }
+            | DnsRecord::UPDATE { domain, .. } => domain,
+            DnsRecord::CLASS { record, .. } => record.domain(),
+            // OPT always belongs to the root
+            DnsRecord::OPT { .. } => "",
+        }
+    }
+
+    pub fn set_domain(&mut self, new_domain: String) {
+        match self {
+            DnsRecord::UNKNOWN { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::HINFO { domain, .. }
+            | DnsRecord::AAAA { domain, .. }
+            | DnsRecord::NS { domain, .. }
+            | DnsRecord::CNAME { domain, .. }
+            | DnsRecord::MX { domain, .. }
+            | DnsRecord::TXT { domain, .. }
+            | DnsRecord::SOA { domain, .. }
+            | DnsRecord::SRV { domain, .. }
+            | DnsRecord::PTR { domain, .. }
+            | DnsRecord::CAA { domain, .. }
+            | DnsRecord::TLSA { domain, .. }
+            | DnsRecord::NAPTR { domain, .. }
+            | DnsRecord::SVCB { domain, .. }
+            | DnsRecord::HTTPS { domain, .. }
+            | DnsRecord::DS { domain, .. }
+            | DnsRecord::RRSIG { domain, .. }
+            | DnsRecord::NSEC { domain, .. }
+            | DnsRecord::NSEC3 { domain, .. }
+            | DnsRecord::DNSKEY { domain, .. }
+            | DnsRecord::TSIG { domain, .. }
+            | DnsRecord::UPDATE { domain, .. } => *domain = new_domain,
+            DnsRecord::CLASS { record, .. } => record.set_domain(new_domain),
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. } => *ttl,
+            DnsRecord::OPT { edns } => edns.packed_ttl(),
+            DnsRecord::TSIG { .. } | DnsRecord::UPDATE { .. } => 0,
+            DnsRecord::CLASS { record, .. } => record.ttl(),
+        }
+    }
+
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::HINFO { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::CAA { ttl, .. }
+            | DnsRecord::TLSA { ttl, .. }
+            | DnsRecord::NAPTR { ttl, .. }
+            | DnsRecord::SVCB { ttl, .. }
+            | DnsRecord::HTTPS { ttl, .. }
+            | DnsRecord::DS { ttl, .. }
+            | DnsRecord::RRSIG { ttl, .. }
+            | DnsRecord::NSEC { ttl, .. }
+            | DnsRecord::NSEC3 { ttl, .. }
+            | DnsRecord::DNSKEY { ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { edns } => edns.set_packed_ttl(new_ttl),
+            DnsRecord::CLASS { record, .. } => record.set_ttl(new_ttl),
+            DnsRecord::TSIG { .. } | DnsRecord::UPDATE { .. } => {}
+        }
+    }
+
+    // IN, except for records of other classes, OPT which puts its udp payload size here, TSIG which is
+    // ANY and the prerequisites and deletions of updates
+    pub fn class(&self) -> u16 {
+        match self {
+            DnsRecord::OPT { edns } => edns.payload_size,
+            DnsRecord::TSIG { .. } => CLASS_ANY,
+            DnsRecord::UPDATE { class, .. } | DnsRecord::CLASS { class, .. } => *class,
+            _ => CLASS_IN,
+        }
+    }
+
+    // the record data on its own with no names compressed, as it is given in the generic form from
+    // RFC 3597 and when an update deletes a single record
+    pub fn rdata(&self) -> Result<Vec<u8>> {
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE).without_compression();
+        let end = self.write(&mut buffer)?;
+
+        // past the owner, type, class, ttl and rdata length
+        buffer.seek(0)?;
+        buffer.read_qname()?;
+        let start = buffer.pos() + 10;
+
+        Ok(buffer.get_range(start, end - start)?.to_vec())
+    }
+
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<DnsRecord> {
+        let domain = buffer.read_qname()?;
+
+        let qtype_number = buffer.read_u16()?;
+        let qtype = QueryType::from_num(qtype_number);
+        let class = buffer.read_u16()?;
+        let ttl = buffer.read_u32()?;
+        let data_length = buffer.read_u16()?;
+
+        // OPT puts the payload size in the class and TSIG is always ANY, anything else in the update
+        // classes is a prerequisite or a deletion
+        let update = (class == CLASS_ANY || class == CLASS_NONE) && !matches!(qtype, QueryType::OPT | QueryType::TSIG);
+
+        let record = buffer.read_rdata(data_length as usize, |buffer| match qtype {
+            _ if update => {
+                let data = buffer.read_bytes(data_length as usize)?;
+
+                Ok(DnsRecord::UPDATE {
+                    domain,
+                    qtype,
+                    class,
+                    data,
+                })
+            }
+            QueryType::A => {
+                let addr = Ipv4Addr::from(buffer.read_u32()?);
+
+                Ok(DnsRecord::A {
+                    domain,
+                    address: addr,
+                    ttl,
+                })
+            }
+            QueryType::HINFO => {
+                let cpu = buffer.read_character_string()?;
+                let os = buffer.read_character_string()?;
+
+                Ok(DnsRecord::HINFO { domain, cpu, os, ttl })
+            }
+            QueryType::DNSKEY => {
+                // flags, protocol and algorithm take up the first four bytes, the rest is the key
+                if data_length < 4 {
+                    return Err("DNSKEY record data is too short".into());
+                }
+
+                let flags = buffer.read_u16()?;
+                let protocol = buffer.read()?;
+                let algorithm = buffer.read()?;
+
+                let key_length = data_length as usize - 4;
+                let public_key = buffer.read_bytes(key_length)?;
+
+                Ok(DnsRecord::DNSKEY {
+                    domain,
+                    flags,
+                    protocol,
+                    algorithm,
+                    public_key,
+                    ttl,
+                })
+            }
+            QueryType::AAAA => {
+                let addr = Ipv6Addr::from(buffer.read_array::<16>()?);
+
+                Ok(DnsRecord::AAAA {
+                    domain,
+                    address: addr,
+                    ttl,
+                })
+            }
+            QueryType::NS => {
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::NS { domain, host, ttl })
+            }
+            QueryType::CNAME => {
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::CNAME { domain, host, ttl })
+            }
+            QueryType::MX => {
+                let priority = buffer.read_u16()?;
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::MX {
+                    domain,
+                    priority,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::TXT => {
+                let raw = buffer.get_range(buffer.pos(), data_length as usize)?.to_vec();
+
+                // one or more character-strings filling up the record data
+                let end = buffer.pos() + data_length as usize;
+                while buffer.pos() < end {
+                    let length = buffer.read()?;
+                    buffer.step(usize::from(length))?;
+                }
+
+                Ok(DnsRecord::TXT { domain, raw, ttl })
+            }
+            QueryType::SOA => {
+                let mname = buffer.read_qname()?;
+                let rname = buffer.read_qname()?;
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl,
+                })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    host,
+                    ttl,
+                })
+            }
+            QueryType::PTR => {
+                let host = buffer.read_qname()?;
+
+                Ok(DnsRecord::PTR { domain, host, ttl })
+            }
+            QueryType::CAA => {
+                let end = buffer.pos() + data_length as usize;
+                let flags = buffer.read()?;
+                let tag = buffer.read_character_string()?;
+
+                // the value has no length of its own, it runs to the end of the record data
+                if buffer.pos() > end {
+                    return Err("CAA tag runs past the record data".into());
+                }
+                let value = buffer.read_bytes(end - buffer.pos())?;
+
+                Ok(DnsRecord::CAA {
+                    domain,
+                    flags,
+                    tag,
+                    value,
+                    ttl,
+                })
+            }
+            QueryType::TLSA => {
+                // three single byte fields, then the certificate association data
+                if data_length < 3 {
+                    return Err("TLSA record data is too short".into());
+                }
+
+                let usage = buffer.read()?;
+                let selector = buffer.read()?;
+                let matching_type = buffer.read()?;
+                let certificate = buffer.read_bytes(data_length as usize - 3)?;
+
+                Ok(DnsRecord::TLSA {
+                    domain,
+                    usage,
+                    selector,
+                    matching_type,
+                    certificate,
+                    ttl,
+                })
+            }
+            QueryType::NAPTR => {
+                let order = buffer.read_u16()?;
+                let preference = buffer.read_u16()?;
+                let flags = buffer.read_character_string()?;
+                let services = buffer.read_character_string()?;
+                let regexp = buffer.read_character_string()?;
+                let replacement = buffer.read_qname()?;
+
+                Ok(DnsRecord::NAPTR {
+                    domain,
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                    ttl,
+                })
+            }
+            QueryType::SVCB => {
+                let end = buffer.pos() + data_length as usize;
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_qname()?;
+                let params = read_svc_params(buffer, end)?;
+
+                Ok(DnsRecord::SVCB {
+                    domain,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                })
+            }
+            QueryType::HTTPS => {
+                let end = buffer.pos() + data_length as usize;
+                let priority = buffer.read_u16()?;
+                let target = buffer.read_qname()?;
+                let params = read_svc_params(buffer, end)?;
+
+                Ok(DnsRecord::HTTPS {
+                    domain,
+                    priority,
+                    target,
+                    params,
+                    ttl,
+                })
+            }
+            QueryType::DS => {
+                if data_length < 4 {
+                    return Err("DS record data is too short".into());
+                }
+
+                let key_tag = buffer.read_u16()?;
+                let algorithm = buffer.read()?;
+                let digest_type = buffer.read()?;
+                let digest = buffer.read_bytes(data_length as usize - 4)?;
+
+                Ok(DnsRecord::DS {
+                    domain,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    digest,
+                    ttl,
+                })
+            }
+            QueryType::RRSIG => {
+                let end = buffer.pos() + data_length as usize;
+                let type_covered = QueryType::from_num(buffer.read_u16()?);
+                let algorithm = buffer.read()?;
+                let labels = buffer.read()?;
+                let original_ttl = buffer.read_u32()?;
+                let expiration = buffer.read_u32()?;
+                let inception = buffer.read_u32()?;
+                let key_tag = buffer.read_u16()?;
+                let signer_name = buffer.read_qname()?;
+
+                // the signature fills whatever is left of the record data
+                if buffer.pos() > end {
+                    return Err("RRSIG signer name runs past the record data".into());
+                }
+                let signature = buffer.read_bytes(end - buffer.pos())?;
+
+                Ok(DnsRecord::RRSIG {
+                    domain,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature,
+                    ttl,
+                })
+            }
+            QueryType::NSEC => {
+                let end = buffer.pos() + data_length as usize;
+                let next_domain = buffer.read_qname()?;
+                let types = read_type_bitmap(buffer, end)?;
+
+                Ok(DnsRecord::NSEC {
+                    domain,
+                    next_domain,
+                    types,
+                    ttl,
+                })
+            }
+            QueryType::NSEC3 => {
+                let end = buffer.pos() + data_length as usize;
+                let hash_algorithm = buffer.read()?;
+                let flags = buffer.read()?;
+                let iterations = buffer.read_u16()?;
+                let salt_length = buffer.read()?;
+                let salt = buffer.read_bytes(salt_length as usize)?;
+                let hash_length = buffer.read()?;
+                let next_hashed = buffer.read_bytes(hash_length as usize)?;
+                let types = read_type_bitmap(buffer, end)?;
+
+                Ok(DnsRecord::NSEC3 {
+                    domain,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed,
+                    types,
+                    ttl,
+                })
+            }
+            QueryType::OPT => {
+                let end = buffer.pos() + data_length as usize;
+
+                let mut edns = Edns::new(class);
+                edns.set_packed_ttl(ttl);
+                while buffer.pos() < end {
+                    let code = buffer.read_u16()?;
+                    let length = buffer.read_u16()?;
+                    let data = buffer.read_bytes(length as usize)?;
+                    edns.options.push(EdnsOption { code, data });
+                }
+
+                Ok(DnsRecord::OPT { edns })
+            }
+            QueryType::TSIG => {
+                let algorithm = buffer.read_qname()?;
+                let time_signed = u64::from(buffer.read_u16()?) << 32 | u64::from(buffer.read_u32()?);
+                let fudge = buffer.read_u16()?;
+                let mac_length = buffer.read_u16()?;
+                let mac = buffer.read_bytes(mac_length as usize)?;
+                let original_id = buffer.read_u16()?;
+                let error = buffer.read_u16()?;
+                let other_length = buffer.read_u16()?;
+                let other = buffer.read_bytes(other_length as usize)?;
+
+                Ok(DnsRecord::TSIG {
+                    domain,
+                    algorithm,
+                    time_signed,
+                    fudge,
+                    mac,
+                    original_id,
+                    error,
+                    other,
+                })
+            }
+            // ANY and the transfer types only make sense in a question, a record claiming them is kept
+            // as raw data
+            QueryType::UNKNOWN(_) | QueryType::ANY | QueryType::IXFR | QueryType::AXFR => {
+                // keep the raw bytes around so the record can still be inspected or passed on
+                let data = buffer.read_bytes(data_length as usize)?;
+
+                Ok(DnsRecord::UNKNOWN {
+                    domain,
+                    qtype: qtype_number,
+                    data_len: data_length,
+                    data,
+                    ttl,
+                })
+            }
+        })?;
+
+        match class {
+            CLASS_IN => Ok(record),
+            _ if update || matches!(qtype, QueryType::OPT | QueryType::TSIG) => Ok(record),
+            _ => Ok(DnsRecord::CLASS {
+                class,
+                record: Box::new(record),
+            }),
+        }
+    }
+
+    // writes the whole record and returns how many bytes it took up
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<usize> {
+        let start_pos = buffer.pos();
+
+        buffer.write_q_name(self.domain())?;
+        buffer.write_u16(self.query_type().to_num())?;
+        buffer.write_u16(self.class())?;
+        buffer.write_u32(self.ttl())?;
+
+        buffer.write_rdata(|buffer| self.write_data(buffer))?;
+
+        Ok(buffer.pos() - start_pos)
+    }
+
+    // the record data, without its length
+    fn write_data(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        match self {
+            DnsRecord::CLASS { record, .. } => record.write_data(buffer),
+            DnsRecord::UNKNOWN { data, .. } | DnsRecord::UPDATE { data, .. } => buffer.write_bytes(data),
+            DnsRecord::A { address, .. } => buffer.write_bytes(&address.octets()),
+            DnsRecord::HINFO { cpu, os, .. } => {
+                buffer.write_character_string(cpu)?;
+                buffer.write_character_string(os)
+            }
+            DnsRecord::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ..
+            } => {
+                buffer.write_u16(*flags)?;
+                buffer.write_u8(*protocol)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_bytes(public_key)
+            }
+            DnsRecord::AAAA { address, .. } => buffer.write_bytes(&address.octets()),
+            DnsRecord::NS { host, .. } => buffer.write_q_name(host),
+            DnsRecord::CNAME { host, .. } => buffer.write_q_name(host),
+            DnsRecord::MX { priority, host, .. } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_q_name(host)
+            }
+            DnsRecord::TXT { raw, .. } => buffer.write_bytes(raw),
+            DnsRecord::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => {
+                buffer.write_q_name(mname)?;
+                buffer.write_q_name(rname)?;
+                buffer.write_u32(*serial)?;
+                buffer.write_u32(*refresh)?;
+                buffer.write_u32(*retry)?;
+                buffer.write_u32(*expire)?;
+                buffer.write_u32(*minimum)
+            }
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                host,
+                ..
+            } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_u16(*weight)?;
+                buffer.write_u16(*port)?;
+                buffer.write_q_name_uncompressed(host)
+            }
+            DnsRecord::PTR { host, .. } => buffer.write_q_name(host),
+            DnsRecord::CAA { flags, tag, value, .. } => {
+                buffer.write_u8(*flags)?;
+                buffer.write_character_string(tag)?;
+                buffer.write_bytes(value)
+            }
+            DnsRecord::TLSA {
+                usage,
+                selector,
+                matching_type,
+                certificate,
+                ..
+            } => {
+                buffer.write_u8(*usage)?;
+                buffer.write_u8(*selector)?;
+                buffer.write_u8(*matching_type)?;
+                buffer.write_bytes(certificate)
+            }
+            DnsRecord::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ..
+            } => {
+                buffer.write_u16(*order)?;
+                buffer.write_u16(*preference)?;
+                buffer.write_character_string(flags)?;
+                buffer.write_character_string(services)?;
+                buffer.write_character_string(regexp)?;
+                buffer.write_q_name_uncompressed(replacement)
+            }
+            DnsRecord::SVCB {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_q_name_uncompressed(target)?;
+                write_svc_params(buffer, params)
+            }
+            DnsRecord::HTTPS {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                buffer.write_u16(*priority)?;
+                buffer.write_q_name_uncompressed(target)?;
+                write_svc_params(buffer, params)
+            }
+            DnsRecord::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ..
+            } => {
+                buffer.write_u16(*key_tag)?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*digest_type)?;
+                buffer.write_bytes(digest)
+            }
+            DnsRecord::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+                ..
+            } => {
+                buffer.write_u16(type_covered.to_num())?;
+                buffer.write_u8(*algorithm)?;
+                buffer.write_u8(*labels)?;
+                buffer.write_u32(*original_ttl)?;
+                buffer.write_u32(*expiration)?;
+                buffer.write_u32(*inception)?;
+                buffer.write_u16(*key_tag)?;
+                buffer.write_q_name_uncompressed(signer_name)?;
+                buffer.write_bytes(signature)
+            }
+            DnsRecord::NSEC { next_domain, types, .. } => {
+                buffer.write_q_name_uncompressed(next_domain)?;
+                write_type_bitmap(buffer, types)
+            }
+            DnsRecord::NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+                ..
+            } => {
+                if salt.len() > 0xFF || next_hashed.len() > 0xFF {
+                    return Err("NSEC3 salt and hash can't exceed 255 bytes".into());
+                }
+                buffer.write_u8(*hash_algorithm)?;
+                buffer.write_u8(*flags)?;
+                buffer.write_u16(*iterations)?;
+                buffer.write_u8(salt.len() as u8)?;
+                buffer.write_bytes(salt)?;
+                buffer.write_u8(next_hashed.len() as u8)?;
+                buffer.write_bytes(next_hashed)?;
+                write_type_bitmap(buffer, types)
+            }
+            DnsRecord::OPT { edns } => {
+                for option in &edns.options {
+                    if option.data.len() > 0xFFFF {
+                        return Err("EDNS option exceeds 65535 bytes".into());
+                    }
+                    buffer.write_u16(option.code)?;
+                    buffer.write_u16(option.data.len() as u16)?;
+                    buffer.write_bytes(&option.data)?;
+                }
+                Ok(())
+            }
+            DnsRecord::TSIG {
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+                ..
+            } => {
+                if mac.len() > 0xFFFF || other.len() > 0xFFFF {
+                    return Err("TSIG MAC and other data can't exceed 65535 bytes".into());
+                }
+                // RFC 8945 doesn't allow the algorithm name to be compressed
+                buffer.write_q_name_uncompressed(algorithm)?;
+                buffer.write_u16((time_signed >> 32) as u16)?;
+                buffer.write_u32(*time_signed as u32)?;
+                buffer.write_u16(*fudge)?;
+                buffer.write_u16(mac.len() as u16)?;
+                buffer.write_bytes(mac)?;
+                buffer.write_u16(*original_id)?;
+                buffer.write_u16(*error)?;
+                buffer.write_u16(other.len() as u16)?;
+                buffer.write_bytes(other)
+            }
+        }
+    }
+}
+
+// the NSEC/NSEC3 type bitmap: window blocks of a window number, a bitmap length and up to 32 bytes
+// where bit n (most significant first) of the block marks type window * 256 + n
+fn read_type_bitmap(buffer: &mut BytePacketBuffer, end: usize) -> Result<Vec<QueryType>> {
+    let mut types = Vec::new();
+    while buffer.pos() < end {
+        let window = buffer.read()? as u16;
+        let length = buffer.read()? as usize;
+        if length == 0 || length > 32 {
+            return Err(format!("Invalid type bitmap length {}", length).into());
+        }
+
+        let bitmap = buffer.read_bytes(length)?;
+        for (index, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(QueryType::from_num(window * 256 + (index * 8 + bit) as u16));
+                }
+            }
+        }
+    }
+
+    Ok(types)
+}
+
+fn write_type_bitmap(buffer: &mut BytePacketBuffer, types: &[QueryType]) -> Result<()> {
+    let mut numbers: Vec<u16> = types.iter().map(|qtype| qtype.to_num()).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    for window in 0..=255u16 {
+        let in_window: Vec<u16> = numbers
+            .iter()
+            .filter(|num| *num >> 8 == window)
+            .map(|num| num & 0xFF)
+            .collect();
+        let Some(highest) = in_window.last() else {
+            continue;
+        };
+
+        let mut bitmap = vec![0u8; *highest as usize / 8 + 1];
+        for num in &in_window {
+            bitmap[*num as usize / 8] |= 0x80 >> (num % 8);
+        }
+
+        buffer.write_u8(window as u8)?;
+        buffer.write_u8(bitmap.len() as u8)?;
+        buffer.write_bytes(&bitmap)?;
+    }
+
+    Ok(())
+}
+
+// SvcParams run until the end of the record data, each a key, a length and that many bytes of value
+fn read_svc_params(buffer: &mut BytePacketBuffer, end: usize) -> Result<SvcParams> {
+    let mut params = Vec::new();
+    while buffer.pos() < end {
+        let key = buffer.read_u16()?;
+        let length = buffer.read_u16()?;
+        let value = buffer.read_bytes(length as usize)?;
+        params.push(SvcParam { key, value });
+    }
+
+    Ok(SvcParams(params))
+}
+
+fn write_svc_params(buffer: &mut BytePacketBuffer, params: &SvcParams) -> Result<()> {
+    for param in &params.0 {
+        if param.value.len() > 0xFFFF {
+            return Err("SvcParam value exceeds 65535 bytes".into());
+        }
+        buffer.write_u16(param.key)?;
+        buffer.write_u16(param.value.len() as u16)?;
+        buffer.write_bytes(&param.value)?;
+    }
+
+    Ok(())
+}
+
+// the mnemonic for the error field of a TSIG record
+pub(crate) fn tsig_error_name(error: u16) -> String {
+    match error {
+        0 => String::from("NOERROR"),
+        TSIG_BADSIG => String::from("BADSIG"),
+        TSIG_BADKEY => String::from("BADKEY"),
+        TSIG_BADTIME => String::from("BADTIME"),
+        TSIG_BADTRUNC => String::from("BADTRUNC"),
+        code => code.to_string(),
+    }
+}
+
+// names are printed fully qualified, so the root comes out as a lone dot. What would end a field or
+// start a comment in a zone file is escaped, so any name read off the wire can be read back in
+pub(crate) fn fqdn(name: &str) -> String {
+    let mut printed = String::with_capacity(name.len() + 1);
+    for c in name.chars() {
+        match c {
+            ' ' | ';' | '"' | '(' | ')' | '\\' => {
+                printed.push('\\');
+                printed.push(c);
+            }
+            c if c.is_ascii_control() => printed.push_str(&format!("\\{:03}", c as u32)),
+            c => printed.push(c),
+        }
+    }
+    printed.push('.');
+
+    printed
+}
+
+// names are compared lowercased and without the trailing dot, the root is the empty name
+#[cfg(feature = "std")]
+pub(crate) fn normal(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// lowercase labels with their lengths, never compressed, the form names are hashed and signed in
+#[cfg(any(feature = "dnssec", feature = "tsig"))]
+pub(crate) fn name_wire(name: &str) -> Result<Vec<u8>> {
+    let mut wire = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err("Single label exceeds 63 characters of length".into());
+        }
+        wire.push(label.len() as u8);
+        wire.extend(label.to_ascii_lowercase().bytes());
+    }
+    wire.push(0);
+
+    Ok(wire)
+}
+
+// seconds since the epoch, 0 for a clock set before it
+#[cfg(feature = "std")]
+pub(crate) fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// the character-strings of a TXT record's rdata, a length running past the end gets what's left
+pub fn character_strings(raw: &[u8]) -> Vec<&[u8]> {
+    let mut strings = Vec::new();
+    let mut rest = raw;
+    while let [length, tail @ ..] = rest {
+        let length = usize::from(*length).min(tail.len());
+        strings.push(&tail[..length]);
+        rest = &tail[length..];
+    }
+
+    strings
+}
+
+// a character-string in zone file quoting, quotes and backslashes are escaped and
+// anything unprintable is written as \DDD
+pub(crate) fn quote(text: &str) -> String {
+    quote_bytes(text.as_bytes())
+}
+
+pub(crate) fn quote_bytes(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7E => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// one line per record in presentation format, e.g. `www.example.com. 3600 IN A 93.184.216.34`
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsRecord::UNKNOWN {
+                domain,
+                qtype,
+                data_len,
+                data,
+                ttl,
+            } => {
+                // generic rdata encoding from RFC 3597
+                write!(f, "{} {} IN TYPE{} \\# {}", fqdn(domain), ttl, qtype, data_len)?;
+                if !data.is_empty() {
+                    write!(f, " {}", hex_encode(data))?;
+                }
+                Ok(())
+            }
+            DnsRecord::A { domain, address, ttl } => {
+                write!(f, "{} {} IN A {}", fqdn(domain), ttl, address)
+            }
+            DnsRecord::HINFO { domain, cpu, os, ttl } => {
+                write!(f, "{} {} IN HINFO {} {}", fqdn(domain), ttl, quote(cpu), quote(os))
+            }
+            DnsRecord::DNSKEY {
+                domain,
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN DNSKEY {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    flags,
+                    protocol,
+                    algorithm,
+                    base64_encode(public_key)
+                )
+            }
+            DnsRecord::AAAA { domain, address, ttl } => {
+                write!(f, "{} {} IN AAAA {}", fqdn(domain), ttl, address)
+            }
+            DnsRecord::NS { domain, host, ttl } => {
+                write!(f, "{} {} IN NS {}", fqdn(domain), ttl, fqdn(host))
+            }
+            DnsRecord::CNAME { domain, host, ttl } => {
+                write!(f, "{} {} IN CNAME {}", fqdn(domain), ttl, fqdn(host))
+            }
+            DnsRecord::MX {
+                domain,
+                priority,
+                host,
+                ttl,
+            } => {
+                write!(f, "{} {} IN MX {} {}", fqdn(domain), ttl, priority, fqdn(host))
+            }
+            DnsRecord::TXT { domain, raw, ttl } => {
+                write!(f, "{} {} IN TXT", fqdn(domain), ttl)?;
+                for text in character_strings(raw) {
+                    write!(f, " {}", quote_bytes(text))?;
+                }
+                Ok(())
+            }
+            DnsRecord::SOA {
+                domain,
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN SOA {} {} {} {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    fqdn(mname),
+                    fqdn(rname),
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum
+                )
+            }
+            DnsRecord::SRV {
+                domain,
+                priority,
+                weight,
+                port,
+                host,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN SRV {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    priority,
+                    weight,
+                    port,
+                    fqdn(host)
+                )
+            }
+            DnsRecord::PTR { domain, host, ttl } => {
+                write!(f, "{} {} IN PTR {}", fqdn(domain), ttl, fqdn(host))
+            }
+            DnsRecord::CAA {
+                domain,
+                flags,
+                tag,
+                value,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN CAA {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    flags,
+                    tag,
+                    quote_bytes(value)
+                )
+            }
+            DnsRecord::TLSA {
+                domain,
+                usage,
+                selector,
+                matching_type,
+                certificate,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN TLSA {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    usage,
+                    selector,
+                    matching_type,
+                    hex_encode(certificate)
+                )
+            }
+            DnsRecord::NAPTR {
+                domain,
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN NAPTR {} {} {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    order,
+                    preference,
+                    quote(flags),
+                    quote(services),
+                    quote(regexp),
+                    fqdn(replacement)
+                )
+            }
+            DnsRecord::SVCB {
+                domain,
+                priority,
+                target,
+                params,
+                ttl,
+            } => {
+                write!(f, "{} {} IN SVCB {} {}", fqdn(domain), ttl, priority, fqdn(target))?;
+                if !params.0.is_empty() {
+                    write!(f, " {}", params)?;
+                }
+                Ok(())
+            }
+            DnsRecord::HTTPS {
+                domain,
+                priority,
+                target,
+                params,
+                ttl,
+            } => {
+                write!(f, "{} {} IN HTTPS {} {}", fqdn(domain), ttl, priority, fqdn(target))?;
+                if !params.0.is_empty() {
+                    write!(f, " {}", params)?;
+                }
+                Ok(())
+            }
+            DnsRecord::DS {
+                domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN DS {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    key_tag,
+                    algorithm,
+                    digest_type,
+                    hex_encode(digest)
+                )
+            }
+            DnsRecord::RRSIG {
+                domain,
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+                ttl,
+            } => {
+                write!(
+                    f,
+                    "{} {} IN RRSIG {} {} {} {} {} {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    format_timestamp(*expiration),
+                    format_timestamp(*inception),
+                    key_tag,
+                    fqdn(signer_name),
+                    base64_encode(signature)
+                )
+            }
+            DnsRecord::NSEC {
+                domain,
+                next_domain,
+                types,
+                ttl,
+            } => {
+                write!(f, "{} {} IN NSEC {}", fqdn(domain), ttl, fqdn(next_domain))?;
+                for qtype in types {
+                    write!(f, " {}", qtype)?;
+                }
+                Ok(())
+            }
+            DnsRecord::NSEC3 {
+                domain,
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed,
+                types,
+                ttl,
+            } => {
+                // an empty salt is written as a dash
+                let salt = if salt.is_empty() {
+                    String::from("-")
+                } else {
+                    hex_encode(salt)
+                };
+                write!(
+                    f,
+                    "{} {} IN NSEC3 {} {} {} {} {}",
+                    fqdn(domain),
+                    ttl,
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    base32hex_encode(next_hashed)
+                )?;
+                for qtype in types {
+                    write!(f, " {}", qtype)?;
+                }
+                Ok(())
+            }
+            // not a real record, so shown the way dig shows its pseudo section
+            DnsRecord::OPT { edns } => {
+                write!(
+                    f,
+                    "; EDNS: version: {}, flags:{}; udp: {}",
+                    edns.version,
+                    if edns.dnssec_ok { " do" } else { "" },
+                    edns.payload_size
+                )?;
+                for option in &edns.options {
+                    match option.code {
+                        OPTION_NSID => {
+                            let (hex, text) = (hex_encode(&option.data), quote_bytes(&option.data));
+                            write!(f, "\n; NSID: {} ({})", hex, text)?
+                        }
+                        OPTION_COOKIE => write!(f, "\n; COOKIE: {}", hex_encode(&option.data))?,
+                        OPTION_PADDING => write!(f, "\n; PADDING: {} bytes", option.data.len())?,
+                        OPTION_EXTENDED_ERROR => match ExtendedError::from_data(&option.data) {
+                            Ok(error) => write!(f, "\n; EDE: {}", error)?,
+                            Err(_) => write!(f, "\n; EDE: {}", hex_encode(&option.data))?,
+                        },
+                        OPTION_CLIENT_SUBNET => match ClientSubnet::from_data(&option.data) {
+                            Ok(subnet) => write!(f, "\n; CLIENT-SUBNET: {}", subnet)?,
+                            Err(_) => write!(f, "\n; CLIENT-SUBNET: {}", hex_encode(&option.data))?,
+                        },
+                        code => write!(f, "\n; OPT={}: {}", code, hex_encode(&option.data))?,
+                    }
+                }
+                Ok(())
+            }
+            DnsRecord::TSIG {
+                domain,
+                algorithm,
+                time_signed,
+                fudge,
+                mac,
+                original_id,
+                error,
+                other,
+            } => {
+                write!(
+                    f,
+                    "{} 0 ANY TSIG {} {} {} {} {} {} {} {}",
+                    fqdn(domain),
+                    fqdn(algorithm),
+                    time_signed,
+                    fudge,
+                    mac.len(),
+                    base64_encode(mac),
+                    original_id,
+                    tsig_error_name(*error),
+                    other.len()
+                )
+            }
+            DnsRecord::UPDATE {
+                domain,
+                qtype,
+                class,
+                data,
+            } => {
+                let class = if *class == CLASS_NONE { "NONE" } else { "ANY" };
+                write!(f, "{} 0 {} {}", fqdn(domain), class, qtype)?;
+                if !data.is_empty() {
+                    write!(f, " \\# {} {}", data.len(), hex_encode(data))?;
+                }
+                Ok(())
+            }
+            DnsRecord::CLASS { class, record } => {
+                // the way it's printed in IN, with its own class in place of the IN after the ttl
+                let text = record.to_string();
+                match text.split_once(" IN ") {
+                    Some((start, rest)) => write!(f, "{} {} {}", start, class_name(*class), rest),
+                    None => f.write_str(&text),
+                }
+            }
+        }
+    }
+}
+
+// CH and HS by their names, any other class in the generic form from RFC 3597
+fn class_name(class: u16) -> String {
+    match class {
+        CLASS_CH => "CH".to_string(),
+        CLASS_HS => "HS".to_string(),
+        _ => format!("CLASS{}", class),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::base64_decode;
+
+    // a record in wire format: a root owner, then type, class IN, a ttl of 300 and rdata
+    fn wire(qtype: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut data = vec![0];
+        data.extend_from_slice(&qtype.to_be_bytes());
+        data.extend_from_slice(&[0, 1, 0, 0, 1, 44]);
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(rdata);
+        data
+    }
+
+    // the root zone's KSK-2017, flags 257 (a zone key that signs keys), protocol 3, RSA/SHA-256
+    const ROOT_KSK: &str = concat!(
+        "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN",
+        "7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8",
+        "efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLY",
+        "A4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+    );
+
+    fn parse(data: &[u8]) -> Result<DnsRecord> {
+        DnsRecord::read(&mut BytePacketBuffer::from_bytes(data)?)
+    }
+
+    fn round_trip(record: &DnsRecord) -> DnsRecord {
+        let mut buffer = BytePacketBuffer::new();
+        let end = record.write(&mut buffer).unwrap();
+
+        parse(&buffer.buffer[..end]).unwrap()
+    }
+
+    #[test]
+    fn rejects_rdata_longer_than_the_buffer() {
+        let mut data = wire(0xFF00, &[1, 2, 3]);
+        assert!(parse(&data).is_ok());
+        // claim 200 bytes of record data where there are three
+        let length = data.len() - 5;
+        data[length..length + 2].copy_from_slice(&200u16.to_be_bytes());
+        assert!(parse(&data).is_err());
+
+        let mut data = wire(QueryType::A.to_num(), &[192, 0, 2, 1]);
+        data.truncate(data.len() - 1);
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn hinfo_reads_and_writes_both_strings() {
+        let record = parse(&wire(QueryType::HINFO.to_num(), b"\x06x86_64\x05Linux")).unwrap();
+        assert_eq!(
+            record,
+            DnsRecord::HINFO {
+                domain: String::new(),
+                cpu: "x86_64".to_string(),
+                os: "Linux".to_string(),
+                ttl: 300,
+            }
+        );
+        assert_eq!(record.rdata().unwrap(), b"\x06x86_64\x05Linux");
+        assert_eq!(round_trip(&record), record);
+
+        // the os string is missing
+        assert!(parse(&wire(QueryType::HINFO.to_num(), b"\x06x86_64")).is_err());
+    }
+
+    #[test]
+    fn unknown_records_keep_their_bytes() {
+        let data = [0xDE, 0xAD, 0x00, 0xFF, 0x00];
+        let record = parse(&wire(65280, &data)).unwrap();
+
+        assert_eq!(record.query_type(), QueryType::UNKNOWN(65280));
+        assert!(matches!(&record, DnsRecord::UNKNOWN { data_len: 5, data: kept, .. } if kept == &data));
+        assert_eq!(record.rdata().unwrap(), data);
+        assert_eq!(round_trip(&record), record);
+        assert_eq!(record.to_string(), ". 300 IN TYPE65280 \\# 5 dead00ff00");
+
+        let empty = parse(&wire(65280, &[])).unwrap();
+        assert_eq!(empty.rdata().unwrap(), []);
+    }
+
+    #[test]
+    fn dnskey_splits_the_fixed_fields_from_the_key() {
+        let key = base64_decode(ROOT_KSK).unwrap();
+        let rdata = [&[0x01, 0x01, 3, 8][..], &key].concat();
+
+        let record = parse(&wire(QueryType::DNSKEY.to_num(), &rdata)).unwrap();
+        let DnsRecord::DNSKEY {
+            flags,
+            protocol,
+            algorithm,
+            public_key,
+            ..
+        } = &record
+        else {
+            panic!("not a DNSKEY: {:?}", record);
+        };
+        assert_eq!((*flags, *protocol, *algorithm), (257, 3, 8));
+        // a 2048 bit modulus after the exponent length byte and the 3 byte exponent
+        assert_eq!(public_key.len(), 260);
+        assert_eq!(public_key, &key);
+        assert_eq!(round_trip(&record), record);
+
+        assert!(parse(&wire(QueryType::DNSKEY.to_num(), &[0x01, 0x01, 3])).is_err());
+    }
+
+    #[test]
+    fn compressed_names_in_rdata_leave_the_position_after_the_record() {
+        let mut data = b"\x07example\x03com\x00".to_vec();
+        let mx = data.len();
+        // example.com MX 10 mail.example.com, the host pointing back at the name above
+        data.extend_from_slice(&[0xC0, 0, 0, 15, 0, 1, 0, 0, 1, 44, 0, 9, 0, 10]);
+        data.extend_from_slice(b"\x04mail\xC0\x00");
+        let a = data.len();
+        // mail.example.com A 192.0.2.25, owned by the host of the MX
+        data.extend_from_slice(&[0xC0, (mx + 14) as u8, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 192, 0, 2, 25]);
+
+        let mut buffer = BytePacketBuffer::from_bytes(&data).unwrap();
+        buffer.seek(mx).unwrap();
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert!(matches!(&record, DnsRecord::MX { priority: 10, host, .. } if host == "mail.example.com"));
+        assert_eq!(buffer.pos(), a);
+
+        let record = DnsRecord::read(&mut buffer).unwrap();
+        assert_eq!(record.domain(), "mail.example.com");
+        assert!(matches!(record, DnsRecord::A { address, .. } if address == Ipv4Addr::new(192, 0, 2, 25)));
+        assert_eq!(buffer.pos(), data.len());
+    }
+
+    #[test]
+    fn rdata_read_past_its_length_is_an_error() {
+        // an MX whose rdata length leaves out the end of the host name
+        let mut data = wire(QueryType::MX.to_num(), b"\x00\x0a\x04mail\x00");
+        let length = data.len() - 10;
+        data[length..length + 2].copy_from_slice(&6u16.to_be_bytes());
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn txt_bytes_survive_zone_format() {
+        // latin-1 and a control byte that aren't utf-8, an empty string and a quote
+        let rdata = b"\x04caf\xe9\x00\x05a\"b\\\x01";
+        let record = parse(&wire(QueryType::TXT.to_num(), rdata)).unwrap();
+        let text = record.to_string();
+        assert_eq!(text, r#". 300 IN TXT "caf\233" "" "a\"b\\\001""#);
+
+        let parsed = crate::presentation::parse_record(&text, "", 3600).unwrap();
+        assert!(matches!(&parsed, DnsRecord::TXT { raw, .. } if raw == rdata));
+        assert_eq!(round_trip(&parsed), record);
+    }
+
+    #[test]
+    fn txt_strings_are_read_as_text() {
+        // SPF policies longer than a single character-string are split across several
+        let record = DnsRecord::new_txt(
+            "example.com".to_string(),
+            vec!["v=spf1 ip4:192.0.2.0/24 include:_spf.example.net", " -all"],
+            300,
+        )
+        .unwrap();
+        let strings = record.txt_strings().unwrap();
+        assert_eq!(strings, ["v=spf1 ip4:192.0.2.0/24 include:_spf.example.net", " -all"]);
+        assert_eq!(
+            strings.concat(),
+            "v=spf1 ip4:192.0.2.0/24 include:_spf.example.net -all"
+        );
+
+        let latin1 = parse(&wire(QueryType::TXT.to_num(), b"\x04caf\xe9")).unwrap();
+        assert_eq!(latin1.txt_strings().unwrap(), ["caf\u{fffd}"]);
+        assert_eq!(
+            parse(&wire(QueryType::A.to_num(), &[192, 0, 2, 1]))
+                .unwrap()
+                .txt_strings(),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "dnssec", feature = "tsig"))]
+    fn names_are_written_lowercase_and_uncompressed() {
+        assert_eq!(name_wire("WWW.Example.com.").unwrap(), b"\x03www\x07example\x03com\x00");
+        assert_eq!(name_wire("").unwrap(), [0]);
+        assert_eq!(name_wire(".").unwrap(), [0]);
+
+        let longest = "a".repeat(63);
+        assert_eq!(name_wire(&longest).unwrap().len(), 65);
+        assert!(name_wire(&format!("{}a.example", longest)).is_err());
+    }
+
+    #[test]
+    fn caa_values_survive_zone_format() {
+        for value in [&b"letsencrypt.org"[..], b"", b"caf\xe9;\"x\""] {
+            let mut rdata = b"\x80\x05issue".to_vec();
+            rdata.extend_from_slice(value);
+            let record = parse(&wire(QueryType::CAA.to_num(), &rdata)).unwrap();
+            assert!(matches!(&record, DnsRecord::CAA { flags: 128, value: read, .. } if read == value));
+
+            let parsed = crate::presentation::parse_record(&record.to_string(), "", 3600).unwrap();
+            assert_eq!(parsed, record, "{}", record);
+            assert_eq!(round_trip(&parsed), record);
+        }
+    }
+
+    #[test]
+    fn other_classes_are_kept() {
+        // version.bind CH TXT "9.18.1", the way BIND answers for its version
+        let mut data = b"\x07version\x04bind\x00\x00\x10\x00\x03\x00\x00\x00\x00\x00\x07".to_vec();
+        data.extend_from_slice(b"\x069.18.1");
+        let record = parse(&data).unwrap();
+        assert_eq!(record.class(), CLASS_CH);
+        assert_eq!(record.query_type(), QueryType::TXT);
+        assert_eq!(record.domain(), "version.bind");
+        assert_eq!(record.to_string(), r#"version.bind. 0 CH TXT "9.18.1""#);
+
+        let mut buffer = BytePacketBuffer::new();
+        let end = record.write(&mut buffer).unwrap();
+        assert_eq!(&buffer.buffer[..end], &data[..]);
+        assert_eq!(record.rdata().unwrap(), b"\x069.18.1");
+
+        // and IN records stay as they are
+        let record = parse(&wire(QueryType::A.to_num(), &[192, 0, 2, 1])).unwrap();
+        assert!(matches!(record, DnsRecord::A { .. }));
+        assert_eq!(record.class(), CLASS_IN);
+    }
+}
@@ -0,0 +1,661 @@
+use std::net::Ipv4Addr;
+#[cfg(feature = "tokio")]
+use std::{future::Future, pin::Pin, time::Duration};
+
+#[cfg(feature = "tokio")]
+use crate::client::exchange_async;
+use crate::{
+    client::{exchange, matches_query, restore_case, DEFAULT_TIMEOUT},
+    edns::DEFAULT_PAYLOAD_SIZE,
+    random_id,
+    rng::random_case,
+    DnsError, DnsPacket, DnsRecord, Edns, QueryType, Result, ResultCode,
+};
+
+// a.root-servers.net through m.root-servers.net
+pub const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+// how many referrals and cname hops a single lookup may follow before it's treated as a loop
+const MAX_HOPS: usize = 32;
+
+// RFC 9156 stops hiding labels after this many queries for one name, so very long names don't
+// take forever
+const MAX_MINIMISE_COUNT: usize = 10;
+
+// resolves name by itself, starting at the root servers and following referrals down to the
+// servers authoritative for it, cname chains are followed and collected into the answer section.
+// Each server only sees as much of the name as it needs to refer onwards
+pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+    recursive_lookup_from(qname, qtype, &ROOT_SERVERS, false)
+}
+
+// the same walk starting at roots instead of the root servers built in, like the ones RootHints
+// knows. dnssec_ok sets the DO bit on every query, so the answer comes with the signatures and
+// denials of existence a validator needs
+pub fn recursive_lookup_from(qname: &str, qtype: QueryType, roots: &[Ipv4Addr], dnssec_ok: bool) -> Result<DnsPacket> {
+    let mut hops = 0;
+    resolve(qname, qtype, roots, dnssec_ok, &mut hops)
+}
+
+fn resolve(qname: &str, qtype: QueryType, roots: &[Ipv4Addr], dnssec_ok: bool, hops: &mut usize) -> Result<DnsPacket> {
+    let mut walk = Walk::new(qname, qtype, roots, dnssec_ok);
+
+    loop {
+        count_hop(&walk.qname, hops)?;
+
+        let (name, asked) = walk.query();
+        let response = match ask_any(&name, asked, walk.dnssec_ok, &walk.servers) {
+            // some servers choke on the shortened names, the full one still has to work
+            Err(_) if walk.minimising() => {
+                walk.stop_minimising();
+                continue;
+            }
+            response => response?,
+        };
+        match walk.advance(response) {
+            Step::Done(response) => return Ok(response),
+            Step::Next => {}
+            Step::Unglued(nameservers) => walk.servers = nameserver_addresses(&nameservers, roots, hops)?,
+        }
+    }
+}
+
+// what a response means for the walk down the tree
+enum Step {
+    Done(DnsPacket),
+    // the walk has its next servers or name and can go on
+    Next,
+    // a delegation without glue, so the name servers' own addresses have to be resolved first
+    Unglued(Vec<String>),
+}
+
+// how far down the tree a lookup has come
+struct Walk {
+    qname: String,
+    qtype: QueryType,
+    // where every walk starts, the root servers
+    roots: Vec<Ipv4Addr>,
+    servers: Vec<Ipv4Addr>,
+    // the answers of every response, so the final one carries the whole cname chain
+    chain: Vec<DnsRecord>,
+    // labels in the zone the servers are authoritative for
+    zone_labels: usize,
+    // labels of qname the servers get to see, one more than their zone has
+    shown: usize,
+    minimised: usize,
+    dnssec_ok: bool,
+}
+
+impl Walk {
+    fn new(qname: &str, qtype: QueryType, roots: &[Ipv4Addr], dnssec_ok: bool) -> Walk {
+        Walk {
+            qname: qname.to_string(),
+            qtype,
+            roots: roots.to_vec(),
+            servers: roots.to_vec(),
+            chain: Vec::new(),
+            zone_labels: 0,
+            shown: 1,
+            minimised: 0,
+            dnssec_ok,
+        }
+    }
+
+    // a cname without the type asked for, the walk starts over for its target
+    fn restart(&mut self, qname: String) {
+        self.qname = qname;
+        self.servers = self.roots.clone();
+        self.zone_labels = 0;
+        self.shown = 1;
+        self.minimised = 0;
+    }
+
+    fn minimising(&self) -> bool {
+        self.shown < label_count(&self.qname) && self.minimised < MAX_MINIMISE_COUNT
+    }
+
+    fn stop_minimising(&mut self) {
+        self.minimised = MAX_MINIMISE_COUNT;
+    }
+
+    // DS records are served by the parent of the zone they're for, so a referral to it is as far as
+    // the walk goes for them
+    fn follows(&self, zone: &str) -> bool {
+        // referrals are only ever to zones containing qname
+        self.qtype != QueryType::DS || label_count(zone) < label_count(&self.qname)
+    }
+
+    // what to ask the servers next, while minimising it's the shown labels with type A, which RFC 9156
+    // picks because servers handle it best
+    fn query(&self) -> (String, QueryType) {
+        if !self.minimising() {
+            return (self.qname.clone(), self.qtype);
+        }
+
+        let labels: Vec<&str> = self.qname.trim_end_matches('.').split('.').collect();
+        (labels[labels.len() - self.shown..].join("."), QueryType::A)
+    }
+
+    fn advance(&mut self, response: DnsPacket) -> Step {
+        if self.minimising() {
+            self.minimised += 1;
+            return self.advance_minimised(response);
+        }
+
+        self.advance_full(response)
+    }
+
+    // a referral means the shown name is a zone of its own, anything else that isn't an error means it
+    // isn't, so the next server sees one label more
+    fn advance_minimised(&mut self, response: DnsPacket) -> Step {
+        if response.header.result_code != ResultCode::NOERROR {
+            // NXDOMAIN would mean the whole name doesn't exist, but servers get empty non-terminals wrong
+            // often enough that the full name is asked instead
+            self.stop_minimising();
+            return Step::Next;
+        }
+
+        match referral(&response, &self.qname) {
+            Some((zone, nameservers))
+                if !response.header.authoritative_answer
+                    && response.answers.is_empty()
+                    && label_count(&zone) > self.zone_labels
+                    && self.follows(&zone) =>
+            {
+                self.delegate(&zone, &response, nameservers)
+            }
+            _ => {
+                self.shown += 1;
+                Step::Next
+            }
+        }
+    }
+
+    fn advance_full(&mut self, mut response: DnsPacket) -> Step {
+        // an error, or an answer from the servers for the zone, ends the walk
+        if response.header.result_code != ResultCode::NOERROR
+            || response.header.authoritative_answer
+            || !response.answers.is_empty()
+        {
+            let target = cname_target(&response, &self.qname, self.qtype);
+            self.chain.append(&mut response.answers);
+            if let Some(target) = target {
+                self.restart(target);
+                return Step::Next;
+            }
+
+            response.answers = core::mem::take(&mut self.chain);
+            response.header = response.synced_header();
+            return Step::Done(response);
+        }
+
+        match referral(&response, &self.qname) {
+            Some((zone, nameservers)) if self.follows(&zone) => self.delegate(&zone, &response, nameservers),
+            _ => Step::Done(response),
+        }
+    }
+
+    fn delegate(&mut self, zone: &str, response: &DnsPacket, nameservers: Vec<String>) -> Step {
+        self.zone_labels = label_count(zone);
+        self.shown = self.zone_labels + 1;
+
+        let glue = glue_addresses(response, &nameservers);
+        if glue.is_empty() {
+            Step::Unglued(nameservers)
+        } else {
+            self.servers = glue;
+            Step::Next
+        }
+    }
+}
+
+fn count_hop(qname: &str, hops: &mut usize) -> Result<()> {
+    *hops += 1;
+    if *hops > MAX_HOPS {
+        return Err(format!("Gave up on {} after {} referrals", qname, MAX_HOPS).into());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn label_count(name: &str) -> usize {
+    name.split('.').filter(|label| !label.is_empty()).count()
+}
+
+// the first server that answers wins, the last error is kept in case none do
+pub(crate) fn ask_any(qname: &str, qtype: QueryType, dnssec_ok: bool, servers: &[Ipv4Addr]) -> Result<DnsPacket> {
+    let query = iterative_query(qname, qtype, dnssec_ok);
+
+    let mut last_error: DnsError = "No servers to query".into();
+    for server in servers {
+        match exchange(&query, (*server, 53), Some(DEFAULT_TIMEOUT)) {
+            Ok(response) if matches_query(&query, &response) => return Ok(restore_case(response, qname)),
+            Ok(_) => last_error = format!("Response from {} doesn't match the query", server).into(),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// the resolver does the recursion itself, so servers are asked not to
+fn iterative_query(qname: &str, qtype: QueryType, dnssec_ok: bool) -> DnsPacket {
+    let mut query = DnsPacket::new_query(random_id(), &random_case(qname), qtype);
+    query.header.recursion_desired = false;
+    if dnssec_ok {
+        let mut edns = Edns::new(DEFAULT_PAYLOAD_SIZE);
+        edns.dnssec_ok = true;
+        query.set_edns(edns);
+    }
+
+    query
+}
+
+fn cname_target(response: &DnsPacket, qname: &str, qtype: QueryType) -> Option<String> {
+    if qtype == QueryType::CNAME || qtype == QueryType::ANY {
+        return None;
+    }
+
+    let mut name = qname.to_ascii_lowercase();
+    let mut target = None;
+    let mut steps = 0;
+    // the server may have already followed part of the chain, so walk it to its end
+    while let Some(host) = response.answers.iter().find_map(|record| match record {
+        DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(&name) => Some(host),
+        _ => None,
+    }) {
+        name = host.to_ascii_lowercase();
+        target = Some(host.clone());
+
+        // guards against a chain that loops back on itself
+        steps += 1;
+        if steps > MAX_HOPS {
+            break;
+        }
+    }
+
+    let answered = response
+        .answers
+        .iter()
+        .any(|record| record.query_type() == qtype && record.domain().eq_ignore_ascii_case(&name));
+    if answered {
+        None
+    } else {
+        target
+    }
+}
+
+// the zone a referral delegates to and its name servers, only for zones that actually contain qname
+fn referral(response: &DnsPacket, qname: &str) -> Option<(String, Vec<String>)> {
+    let zone = response.authorities.iter().find_map(|record| match record {
+        DnsRecord::NS { domain, .. } if in_zone(qname, domain) => Some(domain.clone()),
+        _ => None,
+    })?;
+
+    let nameservers = response
+        .authorities
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::NS { domain, host, .. } if domain.eq_ignore_ascii_case(&zone) => Some(host.clone()),
+            _ => None,
+        })
+        .collect();
+
+    Some((zone, nameservers))
+}
+
+fn glue_addresses(response: &DnsPacket, nameservers: &[String]) -> Vec<Ipv4Addr> {
+    response
+        .resources
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::A { domain, address, .. } if nameservers.iter().any(|ns| ns.eq_ignore_ascii_case(domain)) => {
+                Some(*address)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn nameserver_addresses(nameservers: &[String], roots: &[Ipv4Addr], hops: &mut usize) -> Result<Vec<Ipv4Addr>> {
+    let mut last_error: DnsError = "Referral without any name servers".into();
+
+    for ns in nameservers {
+        match resolve(ns, QueryType::A, roots, false, hops) {
+            Ok(response) => {
+                let addresses = addresses_of(&response);
+                if !addresses.is_empty() {
+                    return Ok(addresses);
+                }
+                last_error = format!("Name server {} has no address", ns).into();
+            }
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// the same walk as recursive_lookup, each server asked waits at most timeout
+#[cfg(feature = "tokio")]
+pub async fn recursive_lookup_async(qname: &str, qtype: QueryType, timeout: Duration) -> Result<DnsPacket> {
+    recursive_lookup_from_async(qname, qtype, &ROOT_SERVERS, false, timeout).await
+}
+
+#[cfg(feature = "tokio")]
+pub async fn recursive_lookup_from_async(
+    qname: &str,
+    qtype: QueryType,
+    roots: &[Ipv4Addr],
+    dnssec_ok: bool,
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    let mut hops = 0;
+    resolve_async(qname, qtype, roots, dnssec_ok, timeout, &mut hops).await
+}
+
+// boxed since resolving a name server's address recurses back into here
+#[cfg(feature = "tokio")]
+fn resolve_async<'a>(
+    qname: &'a str,
+    qtype: QueryType,
+    roots: &'a [Ipv4Addr],
+    dnssec_ok: bool,
+    timeout: Duration,
+    hops: &'a mut usize,
+) -> Pin<Box<dyn Future<Output = Result<DnsPacket>> + Send + 'a>> {
+    Box::pin(async move {
+        let mut walk = Walk::new(qname, qtype, roots, dnssec_ok);
+
+        loop {
+            count_hop(&walk.qname, hops)?;
+
+            let (name, asked) = walk.query();
+            let response = match ask_any_async(&name, asked, walk.dnssec_ok, &walk.servers, timeout).await {
+                Err(_) if walk.minimising() => {
+                    walk.stop_minimising();
+                    continue;
+                }
+                response => response?,
+            };
+            match walk.advance(response) {
+                Step::Done(response) => return Ok(response),
+                Step::Next => {}
+                Step::Unglued(nameservers) => {
+                    walk.servers = nameserver_addresses_async(&nameservers, roots, timeout, hops).await?
+                }
+            }
+        }
+    })
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn ask_any_async(
+    qname: &str,
+    qtype: QueryType,
+    dnssec_ok: bool,
+    servers: &[Ipv4Addr],
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    let query = iterative_query(qname, qtype, dnssec_ok);
+
+    let mut last_error: DnsError = "No servers to query".into();
+    for server in servers {
+        match exchange_async(&query, (*server, 53), Some(timeout)).await {
+            Ok(response) if matches_query(&query, &response) => return Ok(restore_case(response, qname)),
+            Ok(_) => last_error = format!("Response from {} doesn't match the query", server).into(),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+#[cfg(feature = "tokio")]
+async fn nameserver_addresses_async(
+    nameservers: &[String],
+    roots: &[Ipv4Addr],
+    timeout: Duration,
+    hops: &mut usize,
+) -> Result<Vec<Ipv4Addr>> {
+    let mut last_error: DnsError = "Referral without any name servers".into();
+
+    for ns in nameservers {
+        match resolve_async(ns, QueryType::A, roots, false, timeout, hops).await {
+            Ok(response) => {
+                let addresses = addresses_of(&response);
+                if !addresses.is_empty() {
+                    return Ok(addresses);
+                }
+                last_error = format!("Name server {} has no address", ns).into();
+            }
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn addresses_of(response: &DnsPacket) -> Vec<Ipv4Addr> {
+    response
+        .answers
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::A { address, .. } => Some(*address),
+            _ => None,
+        })
+        .collect()
+}
+
+// whether name is zone itself or somewhere below it, the root zone contains everything
+pub(crate) fn in_zone(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: Ipv4Addr = Ipv4Addr::new(198, 41, 0, 4);
+
+    fn from_roots(qname: &str, qtype: QueryType) -> Walk {
+        Walk::new(qname, qtype, &[ROOT], false)
+    }
+
+    // what a server that isn't authoritative for qname sends: the name servers of the zone below it,
+    // with glue for the ones given an address
+    fn referral_to(zone: &str, nameservers: &[(&str, Option<Ipv4Addr>)]) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        for (host, glue) in nameservers {
+            response.authorities.push(DnsRecord::NS {
+                domain: zone.to_string(),
+                host: host.to_string(),
+                ttl: 3600,
+            });
+            if let Some(address) = glue {
+                response.resources.push(DnsRecord::A {
+                    domain: host.to_string(),
+                    address: *address,
+                    ttl: 3600,
+                });
+            }
+        }
+
+        response
+    }
+
+    fn authoritative(code: ResultCode, answers: Vec<DnsRecord>) -> DnsPacket {
+        let mut response = DnsPacket::new();
+        response.header.authoritative_answer = true;
+        response.header.result_code = code;
+        response.answers = answers;
+
+        response
+    }
+
+    fn address(name: &str, last: u8) -> DnsRecord {
+        DnsRecord::A {
+            domain: name.to_string(),
+            address: Ipv4Addr::new(192, 0, 2, last),
+            ttl: 300,
+        }
+    }
+
+    fn done(step: Step) -> DnsPacket {
+        match step {
+            Step::Done(response) => response,
+            Step::Next => panic!("The walk went on"),
+            Step::Unglued(nameservers) => panic!("The walk needs addresses for {:?}", nameservers),
+        }
+    }
+
+    const COM: Ipv4Addr = Ipv4Addr::new(192, 5, 6, 30);
+    const EXAMPLE: Ipv4Addr = Ipv4Addr::new(199, 43, 135, 53);
+
+    #[test]
+    fn each_zone_sees_one_label_more_than_its_own() {
+        let mut walk = from_roots("www.example.com", QueryType::AAAA);
+        assert_eq!(walk.query(), ("com".to_string(), QueryType::A));
+
+        let step = walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        assert!(matches!(step, Step::Next));
+        assert_eq!(walk.servers, [COM]);
+        assert_eq!(walk.query(), ("example.com".to_string(), QueryType::A));
+
+        walk.advance(referral_to("example.com", &[("a.iana-servers.net", Some(EXAMPLE))]));
+        assert_eq!(walk.servers, [EXAMPLE]);
+        // every label is shown now, so the real question goes out
+        assert!(!walk.minimising());
+        assert_eq!(walk.query(), ("www.example.com".to_string(), QueryType::AAAA));
+
+        let answer = done(walk.advance(authoritative(ResultCode::NOERROR, vec![address("www.example.com", 1)])));
+        assert_eq!(answer.answers, [address("www.example.com", 1)]);
+    }
+
+    #[test]
+    fn names_that_arent_zones_are_shown_further() {
+        let mut walk = from_roots("a.b.example.com", QueryType::TXT);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        walk.advance(referral_to("example.com", &[("a.iana-servers.net", Some(EXAMPLE))]));
+        assert_eq!(walk.query(), ("b.example.com".to_string(), QueryType::A));
+
+        // b is an empty non-terminal, or has records of its own, without being a zone cut
+        assert!(matches!(
+            walk.advance(authoritative(ResultCode::NOERROR, Vec::new())),
+            Step::Next
+        ));
+        assert_eq!(walk.servers, [EXAMPLE]);
+        assert_eq!(walk.query(), ("a.b.example.com".to_string(), QueryType::TXT));
+
+        // a referral back to the zone the walk is already at is no progress either
+        let mut walk = from_roots("a.b.example.com", QueryType::TXT);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        assert_eq!(walk.query(), ("b.example.com".to_string(), QueryType::A));
+    }
+
+    #[test]
+    fn errors_while_minimising_ask_the_full_name() {
+        let mut walk = from_roots("a.b.example.com", QueryType::TXT);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        walk.advance(referral_to("example.com", &[("a.iana-servers.net", Some(EXAMPLE))]));
+
+        // servers that get empty non-terminals wrong say NXDOMAIN for b, when a.b may well be there
+        assert!(matches!(
+            walk.advance(authoritative(ResultCode::NXDOMAIN, Vec::new())),
+            Step::Next
+        ));
+        assert!(!walk.minimising());
+        assert_eq!(walk.query(), ("a.b.example.com".to_string(), QueryType::TXT));
+        let denial = done(walk.advance(authoritative(ResultCode::NXDOMAIN, Vec::new())));
+        assert_eq!(denial.header.result_code, ResultCode::NXDOMAIN);
+
+        // and very long names are only minimised so far
+        let name = format!("{}example.com", "a.".repeat(20));
+        let mut walk = from_roots(&name, QueryType::A);
+        for _ in 0..MAX_MINIMISE_COUNT {
+            assert!(walk.minimising());
+            walk.advance(authoritative(ResultCode::NOERROR, Vec::new()));
+        }
+        assert_eq!(walk.query(), (name, QueryType::A));
+    }
+
+    #[test]
+    fn referrals_without_glue_need_their_servers_resolved() {
+        let mut walk = from_roots("www.example.com", QueryType::A);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        let step = walk.advance(referral_to(
+            "example.com",
+            &[("ns1.example.net", None), ("ns2.example.net", None)],
+        ));
+        match step {
+            Step::Unglued(nameservers) => {
+                assert_eq!(nameservers, ["ns1.example.net", "ns2.example.net"])
+            }
+            _ => panic!("The walk went on without addresses"),
+        }
+        assert_eq!(walk.zone_labels, 2);
+
+        // glue for servers of other zones isn't taken
+        let mut walk = from_roots("www.example.com", QueryType::A);
+        let mut response = referral_to("com", &[("a.gtld-servers.net", None)]);
+        response.resources.push(address("a.gtld-servers.org", 9));
+        assert!(matches!(walk.advance(response), Step::Unglued(_)));
+    }
+
+    #[test]
+    fn cnames_start_the_walk_over_for_their_target() {
+        let mut walk = from_roots("www.example.com", QueryType::A);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        walk.advance(referral_to("example.com", &[("a.iana-servers.net", Some(EXAMPLE))]));
+
+        let alias = DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            host: "cdn.example.net".to_string(),
+            ttl: 300,
+        };
+        assert!(matches!(
+            walk.advance(authoritative(ResultCode::NOERROR, vec![alias.clone()])),
+            Step::Next
+        ));
+        assert_eq!(walk.servers, [ROOT]);
+        assert_eq!(walk.query(), ("net".to_string(), QueryType::A));
+
+        walk.advance(referral_to("net", &[("a.gtld-servers.net", Some(COM))]));
+        walk.advance(referral_to("example.net", &[("ns.example.net", Some(EXAMPLE))]));
+        assert_eq!(walk.query(), ("cdn.example.net".to_string(), QueryType::A));
+        let answer = done(walk.advance(authoritative(ResultCode::NOERROR, vec![address("cdn.example.net", 2)])));
+        assert_eq!(answer.answers, [alias, address("cdn.example.net", 2)]);
+        assert_eq!(answer.header.answers, 2);
+    }
+
+    #[test]
+    fn ds_walks_stop_above_the_zone_cut() {
+        let mut walk = from_roots("example.com", QueryType::DS);
+        walk.advance(referral_to("com", &[("a.gtld-servers.net", Some(COM))]));
+        assert_eq!(walk.query(), ("example.com".to_string(), QueryType::DS));
+
+        // the com servers are the ones with the DS, a referral to example.com isn't followed
+        let response = referral_to("example.com", &[("a.iana-servers.net", Some(EXAMPLE))]);
+        let response = done(walk.advance(response));
+        assert!(matches!(&response.authorities[..], [DnsRecord::NS { .. }]));
+        assert_eq!(walk.servers, [COM]);
+    }
+}
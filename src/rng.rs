@@ -0,0 +1,43 @@
+// a fresh query id, unpredictable ids make spoofed responses harder to pull off, rand's thread rng
+// is a cryptographic one
+#[cfg(feature = "rand")]
+pub fn random_id() -> u16 {
+    rand::random::<u16>()
+}
+
+// straight from the operating system's generator, the way rand seeds its own, so nothing about the
+// ids that came before tells an attacker what the next one will be
+#[cfg(not(feature = "rand"))]
+pub fn random_id() -> u16 {
+    let mut bytes = [0u8; 2];
+    getrandom::getrandom(&mut bytes).expect("The operating system's random number generator failed");
+
+    u16::from_be_bytes(bytes)
+}
+
+// name with the case of every letter picked at random, servers copy the question as it was asked so
+// a spoofed response also has to guess the case, which is what dns 0x20 relies on
+pub(crate) fn random_case(name: &str) -> String {
+    let (mut bits, mut left) = (0u16, 0);
+
+    name.chars()
+        .map(|c| {
+            if !c.is_ascii_alphabetic() {
+                return c;
+            }
+            if left == 0 {
+                bits = random_id();
+                left = 16;
+            }
+            let upper = bits & 1 == 1;
+            bits >>= 1;
+            left -= 1;
+
+            if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
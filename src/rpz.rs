@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::{edns::masked, record::fqdn, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result, ResultCode, Zone};
+
+// the kinds of triggers that are skipped, the ones on name servers and clients
+const UNSUPPORTED_TRIGGERS: [&str; 3] = [".rpz-nsdname", ".rpz-nsip", ".rpz-client-ip"];
+
+// what a response policy zone says to do with a query it has a trigger for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    // CNAME . answers as if the name didn't exist
+    NxDomain,
+    // CNAME *. as if it didn't have the type
+    NoData,
+    // CNAME rpz-passthru. answers the way it would be without any policy, which exempts it from the
+    // policy zones after this one and from the blocklist too
+    Passthru,
+    // any other records are answered instead, with the name asked for as their owner
+    LocalData(Vec<DnsRecord>),
+}
+
+impl Policy {
+    // what the policy answers question with, None for PASSTHRU
+    pub(crate) fn rewrite(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let mut answer = DnsPacket::new();
+        match self {
+            Policy::Passthru => return None,
+            Policy::NxDomain => answer.header.result_code = ResultCode::NXDOMAIN,
+            Policy::NoData => {}
+            Policy::LocalData(records) => {
+                // a cname stands in for every type, which the rest of the way is resolved for
+                let cname = records.iter().find(|record| matches!(record, DnsRecord::CNAME { .. }));
+                let qtype = question.qtype;
+                let matching = records
+                    .iter()
+                    .filter(|record| qtype == QueryType::ANY || record.query_type() == qtype);
+                let records: Vec<&DnsRecord> = match cname {
+                    Some(cname) if qtype != QueryType::CNAME => vec![cname],
+                    _ => matching.collect(),
+                };
+
+                for record in records {
+                    let mut record = record.clone();
+                    record.set_domain(question.name.clone());
+                    answer.answers.push(record);
+                }
+            }
+        }
+
+        Some(answer)
+    }
+}
+
+// a response policy zone like draft-vixie-dnsop-dns-rpz has them, the way resolvers are handed
+// filtering policies as an ordinary zone. Names in it are triggers for the names they're asked without
+// the zone's origin, bad.example.rpz.example for bad.example and *.bad.example.rpz.example for
+// everything below it. Names under rpz-ip trigger on the addresses in answers instead, 24.0.2.0.192 for
+// 192.0.2.0/24 and 64.zz.db8.2001 for 2001:db8::/64. The records at a trigger are the policy. The
+// triggers on name servers and clients and the rpz-drop and rpz-tcp-only policies aren't supported and
+// are skipped
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rpz {
+    pub origin: String,
+    // by lowercased name, with a leading * for the names below one
+    names: HashMap<String, Policy>,
+    // networks, with their prefix length
+    addresses: Vec<(IpAddr, u8, Policy)>,
+}
+
+impl Rpz {
+    // the policies of a zone, loaded from a file or transferred from wherever they're distributed
+    pub fn from_zone(zone: &Zone) -> Result<Rpz> {
+        let mut rpz = Rpz {
+            origin: zone.origin.clone(),
+            ..Rpz::default()
+        };
+        let suffix = format!(".{}", zone.origin.to_ascii_lowercase());
+
+        for record in zone.records() {
+            let owner = record.domain().to_ascii_lowercase();
+            // the apex only has the SOA and NS records any zone needs
+            let Some(trigger) = owner.strip_suffix(&suffix) else {
+                continue;
+            };
+            let Some(policy) = policy_of(record, trigger) else {
+                continue;
+            };
+
+            match trigger.strip_suffix(".rpz-ip") {
+                Some(network) => {
+                    let (address, prefix) = network_of(network)
+                        .ok_or_else(|| format!("{} isn't a network in {}", network, fqdn(&rpz.origin)))?;
+                    rpz.add_address(address, prefix, policy);
+                }
+                None if UNSUPPORTED_TRIGGERS.iter().any(|kind| trigger.ends_with(kind)) => {}
+                None => rpz.add_name(trigger, policy),
+            }
+        }
+
+        Ok(rpz)
+    }
+
+    pub fn from_file(path: &Path, origin: &str) -> Result<Rpz> {
+        Rpz::from_zone(&Zone::from_file(path, origin)?)
+    }
+
+    // how many triggers there are, on names and on addresses
+    pub fn len(&self) -> usize {
+        self.names.len() + self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the policy for name, its own trigger before the wildcard of the closest parent that has one
+    pub fn name_policy(&self, name: &str) -> Option<&Policy> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(policy) = self.names.get(&name) {
+            return Some(policy);
+        }
+
+        let mut parent = name.as_str();
+        while !parent.is_empty() {
+            parent = parent.split_once('.').map_or("", |(_, parent)| parent);
+            let wildcard = match parent.is_empty() {
+                true => String::from("*"),
+                false => format!("*.{}", parent),
+            };
+            if let Some(policy) = self.names.get(&wildcard) {
+                return Some(policy);
+            }
+        }
+
+        None
+    }
+
+    // the policy for the addresses in answer, the longest matching network wins
+    pub fn address_policy(&self, answer: &DnsPacket) -> Option<&Policy> {
+        let addresses: Vec<IpAddr> = answer
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::A { address, .. } => Some(IpAddr::V4(*address)),
+                DnsRecord::AAAA { address, .. } => Some(IpAddr::V6(*address)),
+                _ => None,
+            })
+            .collect();
+
+        self.addresses
+            .iter()
+            .filter(|(network, prefix, _)| {
+                addresses
+                    .iter()
+                    .any(|address| address.is_ipv4() == network.is_ipv4() && masked(*address, *prefix) == *network)
+            })
+            .max_by_key(|(_, prefix, _)| *prefix)
+            .map(|(_, _, policy)| policy)
+    }
+
+    // local data adds to what a trigger has, any other policy is the first one it was given
+    fn add_name(&mut self, trigger: &str, policy: Policy) {
+        match (self.names.get_mut(trigger), policy) {
+            (Some(Policy::LocalData(records)), Policy::LocalData(more)) => records.extend(more),
+            (Some(_), _) => {}
+            (None, policy) => {
+                self.names.insert(trigger.to_string(), policy);
+            }
+        }
+    }
+
+    fn add_address(&mut self, address: IpAddr, prefix: u8, policy: Policy) {
+        let existing = self
+            .addresses
+            .iter_mut()
+            .find(|(other, length, _)| (*other, *length) == (address, prefix));
+        match (existing, policy) {
+            (Some((_, _, Policy::LocalData(records))), Policy::LocalData(more)) => records.extend(more),
+            (Some(_), _) => {}
+            (None, policy) => self.addresses.push((address, prefix, policy)),
+        }
+    }
+}
+
+// the first policy among policies for the name asked for, None when none of them has one
+pub(crate) fn name_policy<'a>(policies: &'a [Rpz], name: &str) -> Option<&'a Policy> {
+    policies.iter().find_map(|rpz| rpz.name_policy(name))
+}
+
+pub(crate) fn address_policy<'a>(policies: &'a [Rpz], answer: &DnsPacket) -> Option<&'a Policy> {
+    policies.iter().find_map(|rpz| rpz.address_policy(answer))
+}
+
+// the question left for the upstreams when a rewritten answer is a cname
+pub(crate) fn onward(answer: &DnsPacket, qtype: QueryType) -> Option<DnsQuestion> {
+    match answer.answers.last() {
+        Some(DnsRecord::CNAME { host, .. }) if qtype != QueryType::CNAME => Some(DnsQuestion::new(host.clone(), qtype)),
+        _ => None,
+    }
+}
+
+// the policy the record at trigger gives, None for the ones that aren't supported
+fn policy_of(record: DnsRecord, trigger: &str) -> Option<Policy> {
+    let DnsRecord::CNAME { host, .. } = &record else {
+        return Some(Policy::LocalData(vec![record]));
+    };
+
+    let host = host.to_ascii_lowercase();
+    match host.as_str() {
+        "" => Some(Policy::NxDomain),
+        "*" => Some(Policy::NoData),
+        // a cname back to the name itself is how PASSTHRU used to be written
+        "rpz-passthru" => Some(Policy::Passthru),
+        _ if host == trigger => Some(Policy::Passthru),
+        "rpz-drop" | "rpz-tcp-only" => None,
+        _ if host.starts_with("*.") => None,
+        _ => Some(Policy::LocalData(vec![record])),
+    }
+}
+
+// the network in the labels of an rpz-ip trigger, the prefix length then the address backwards. ipv6
+// has 16 bit groups in hex and zz for the :: in them
+fn network_of(labels: &str) -> Option<(IpAddr, u8)> {
+    let (prefix, address) = labels.split_once('.')?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let mut parts: Vec<&str> = address.split('.').collect();
+    parts.reverse();
+
+    let address = match parts.len() {
+        4 if prefix <= 32 => IpAddr::V4(parts.join(".").parse::<Ipv4Addr>().ok()?),
+        _ if prefix <= 128 => {
+            let groups: Vec<&str> = parts.iter().map(|part| if *part == "zz" { "" } else { part }).collect();
+            let mut text = groups.join(":");
+            if text.starts_with(':') {
+                text.insert(0, ':');
+            }
+            if text.ends_with(':') {
+                text.push(':');
+            }
+            IpAddr::V6(text.parse::<Ipv6Addr>().ok()?)
+        }
+        _ => return None,
+    };
+
+    // the trigger is for the network, whatever bits past the prefix say
+    Some((masked(address, prefix), prefix))
+}
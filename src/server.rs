@@ -0,0 +1,864 @@
+#[cfg(feature = "tokio")]
+use std::sync::Arc;
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tokio")]
+use crate::AsyncResolver;
+use crate::{
+    buffer::{DEFAULT_BUFFER_SIZE, MAX_BUFFER_SIZE},
+    cookie::{CookieCheck, Cookies},
+    edns::{
+        DEFAULT_PAYLOAD_SIZE, EDE_NO_REACHABLE_AUTHORITY, OPTION_COOKIE, OPTION_NSID, OPTION_PADDING,
+        RESPONSE_PADDING_BLOCK,
+    },
+    header::{OPCODE_NOTIFY, OPCODE_QUERY},
+    parse_bytes,
+    querylog::{EventLog, LogEntry, Trace},
+    ratelimit::{RateLimiter, Verdict},
+    tcp::{read_framed, write_framed},
+    zone::closest_zone,
+    AccessControl, BytePacketBuffer, DnsError, DnsHeader, DnsPacket, DnsQuestion, DnsRecord, Edns, ExtendedError,
+    QueryLog, QueryType, Resolver, Result, ResultCode, Secondary, Zone,
+};
+#[cfg(feature = "tsig")]
+use crate::{
+    record::tsig_error_name,
+    tsig::{check_request, RequestSignature},
+    TsigKey,
+};
+
+// an unprivileged port, so trying the server out doesn't need root
+pub const DEFAULT_LISTEN: (Ipv4Addr, u16) = (Ipv4Addr::UNSPECIFIED, 2053);
+
+// how answers for incoming queries are found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Upstream {
+    // pass every question on to other resolvers, in order, moving on to the next one when a server
+    // times out or answers SERVFAIL as often as it may
+    Forward(Vec<ForwardServer>),
+    // resolve every question from the root servers down
+    Recursive,
+    // ask the local network over multicast DNS and wait this long for answers, for .local names
+    Multicast(Duration),
+    // ask the local link over LLMNR and wait this long for answers, for single label names
+    Llmnr(Duration),
+}
+
+impl Upstream {
+    // forwarding to a single server with the resolver's timeout and no retries
+    pub fn forward(server: (Ipv4Addr, u16)) -> Upstream {
+        Upstream::Forward(vec![ForwardServer::new(server)])
+    }
+
+    // what the query log calls it, answers from forwarding servers name the server instead
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Upstream::Forward(_) => "forward",
+            Upstream::Recursive => "recursive",
+            Upstream::Multicast(_) => "mdns",
+            Upstream::Llmnr(_) => "llmnr",
+        }
+    }
+}
+
+// a resolver to forward to, and how patient to be with it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ForwardServer {
+    pub address: (Ipv4Addr, u16),
+    // None waits as long as the resolver's timeout
+    pub timeout: Option<Duration>,
+    // how many more times it's asked after the first try fails
+    pub retries: u32,
+}
+
+impl ForwardServer {
+    pub fn new(address: (Ipv4Addr, u16)) -> ForwardServer {
+        ForwardServer {
+            address,
+            timeout: None,
+            retries: 0,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> ForwardServer {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> ForwardServer {
+        self.retries = retries;
+        self
+    }
+}
+
+// how a query reached the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+    Https,
+    Quic,
+}
+
+impl Transport {
+    pub fn is_encrypted(self) -> bool {
+        matches!(self, Transport::Tls | Transport::Https | Transport::Quic)
+    }
+}
+
+// where a query came from, for what depends on who's asking
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Client {
+    pub address: IpAddr,
+    pub transport: Transport,
+}
+
+impl Client {
+    pub(crate) fn new(address: IpAddr, transport: Transport) -> Client {
+        Client { address, transport }
+    }
+}
+
+// answers queries on udp and tcp until binding or receiving fails, problems with a single query
+// are answered with an error code or reported to the resolver's event log instead of stopping the server
+pub fn serve(listen: (Ipv4Addr, u16), resolver: Resolver) -> Result<()> {
+    let socket = UdpSocket::bind(listen)?;
+    let listener = TcpListener::bind(listen)?;
+    let tcp_resolver = resolver.clone();
+    thread::spawn(move || serve_tcp(listener, tcp_resolver));
+
+    let mut data = vec![0; MAX_BUFFER_SIZE];
+    loop {
+        let (length, source) = socket.recv_from(&mut data)?;
+        let client = Client::new(source.ip(), Transport::Udp);
+        let Some((response, limit)) = respond(&data[..length], client, &resolver) else {
+            continue;
+        };
+
+        match encode_response(&response, limit) {
+            Ok(buffer) => {
+                if let Err(e) = socket.send_to(&buffer.buffer[..buffer.pos()], source) {
+                    resolver
+                        .events
+                        .report(format_args!("Failed to answer {}: {}", source, e));
+                }
+            }
+            Err(e) => {
+                let events = &resolver.events;
+                events.report(format_args!("Failed to encode the response for {}: {}", source, e));
+            }
+        }
+    }
+}
+
+// how long a tcp connection may sit without a new query before it's closed
+pub const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn serve_tcp(listener: TcpListener, resolver: Resolver) {
+    accept_connections(listener, resolver.events.clone(), move |stream| {
+        let client = Client::new(stream.peer_addr()?.ip(), Transport::Tcp);
+        handle_connection(stream, client, &resolver)
+    });
+}
+
+// every connection gets its own thread, so one slow client doesn't stall the others
+pub(crate) fn accept_connections<F>(listener: TcpListener, events: EventLog, handle: F)
+where
+    F: Fn(TcpStream) -> Result<()> + Clone + Send + 'static,
+{
+    for stream in listener.incoming() {
+        let stream = match stream.and_then(|stream| {
+            stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT))?;
+            Ok(stream)
+        }) {
+            Ok(stream) => stream,
+            Err(e) => {
+                events.report(format_args!("Failed to accept a tcp connection: {}", e));
+                continue;
+            }
+        };
+
+        let (handle, events) = (handle.clone(), events.clone());
+        thread::spawn(move || {
+            if let Err(e) = handle(stream) {
+                events.report(format_args!("Tcp connection failed: {}", e));
+            }
+        });
+    }
+}
+
+// answers queries on the connection in the order they come, until the client closes it or goes idle,
+// the stream can be plain tcp or tls wrapped around it
+pub(crate) fn handle_connection<S: Read + Write>(mut stream: S, client: Client, resolver: &Resolver) -> Result<()> {
+    loop {
+        // the client closing the connection or going quiet both just end it
+        let Ok(request) = read_framed(&mut stream) else {
+            return Ok(());
+        };
+        let Some((response, limit)) = respond(&request.buffer, client, resolver) else {
+            continue;
+        };
+
+        let buffer = encode_response(&response, limit)?;
+        write_framed(&mut stream, &buffer.buffer[..buffer.pos()])?;
+    }
+}
+
+// parses a query and answers it along with how big the answer may be, None when there's nothing worth
+// answering
+fn respond(request: &[u8], client: Client, resolver: &Resolver) -> Option<(DnsPacket, usize)> {
+    let (started, trace) = (Instant::now(), Trace::default());
+    let query = match parse_bytes(request) {
+        // answering responses could set off a loop between two servers
+        Ok(query) if query.header.response => return None,
+        Ok(query) => query,
+        Err(e) => {
+            resolver.events.report(format_args!("Malformed query: {}", e));
+            return format_error(request, client.transport);
+        }
+    };
+    let limit = response_limit(&query, client.transport);
+
+    if let Err(refusal) = screen(&query, client, &resolver.cookies, resolver.limiter.as_deref()) {
+        return refusal.map(|refusal| (refusal, limit));
+    }
+    #[cfg(feature = "tsig")]
+    let signature = match authenticate(request, &query, &resolver.tsig_keys, &resolver.events) {
+        Ok(signature) => signature,
+        Err(refusal) => return refusal.map(|refusal| (refusal, limit)),
+    };
+    // cookies, rate limits, access lists and keys are the server's, what the query is answered with
+    // depends on the view
+    let view = resolver.view_for(client.address);
+    let authoritative = answer_authoritatively(&query, &view.zones, view.secondary.as_ref());
+    let response = if query.header.opcode == OPCODE_NOTIFY {
+        answer_notify(&query, client.address, view.secondary.as_ref())
+    } else if let Some(refusal) = refusal(&query, client.address, &resolver.access, authoritative.is_some()) {
+        refusal
+    } else if let Some(response) = authoritative {
+        response
+    } else {
+        let subnet = view.subnet_for(Some(client.address), query.edns().and_then(Edns::client_subnet));
+        let outcome = question_of(&query).map(|question| view.resolve_traced(question, subnet, &trace));
+        report_failure(&query, &outcome, &resolver.events);
+        build_response(&query, outcome)
+    };
+    let response = finish(response, &query, client, &resolver.cookies, resolver.nsid.as_deref());
+    let log = resolver.query_log.as_deref();
+    log_query(log, &resolver.events, &query, &response, client, started, &trace);
+
+    #[cfg(feature = "tsig")]
+    if let Some(signature) = &signature {
+        return Some((signed(response, signature, limit, &resolver.events), limit));
+    }
+
+    Some((response, limit))
+}
+
+// the query's line in the log, if there is one. Writing it failing doesn't stop the query from being
+// answered
+fn log_query(
+    log: Option<&QueryLog>,
+    events: &EventLog,
+    query: &DnsPacket,
+    response: &DnsPacket,
+    client: Client,
+    started: Instant,
+    trace: &Trace,
+) {
+    let Some(log) = log else {
+        return;
+    };
+    let written = log.record(&LogEntry {
+        client: client.address,
+        transport: client.transport,
+        question: question_of(query).ok(),
+        result_code: response.header.result_code,
+        latency: started.elapsed(),
+        trace,
+    });
+    if let Err(e) = written {
+        events.report(format_args!("Failed to write the query log: {}", e));
+    }
+}
+
+// what happens before a query is resolved: a udp client past its rate limit is dropped, or now and then
+// answered with just the TC bit, and a malformed cookie answered with FORMERR. Clients whose server
+// cookie checks out can't be spoofed, so they get the more generous limit
+fn screen(
+    query: &DnsPacket,
+    client: Client,
+    cookies: &Cookies,
+    limiter: Option<&RateLimiter>,
+) -> core::result::Result<(), Option<DnsPacket>> {
+    let cookie = cookies.check(query, client.address);
+
+    // only udp sources can be spoofed
+    if let Some(limiter) = limiter.filter(|_| client.transport == Transport::Udp) {
+        match limiter.allow(client.address, cookie == CookieCheck::Valid) {
+            Verdict::Allow => {}
+            Verdict::Drop => return Err(None),
+            Verdict::Slip => return Err(Some(truncated(&build_response(query, Err(ResultCode::NOERROR))))),
+        }
+    }
+    if cookie == CookieCheck::Malformed {
+        return Err(Some(build_response(query, Err(ResultCode::FORMERR))));
+    }
+
+    Ok(())
+}
+
+// REFUSED for a client the server doesn't let have the query answered. Every query has to be allowed by
+// the query list, transfers by the transfer list as well, and answers that don't come from the server's
+// own zones by the recursion list. Clients that may not recurse aren't told recursion is available
+fn refusal(query: &DnsPacket, client: IpAddr, access: &AccessControl, authoritative: bool) -> Option<DnsPacket> {
+    let transfer = query
+        .questions
+        .iter()
+        .any(|question| matches!(question.qtype, QueryType::AXFR | QueryType::IXFR));
+    let recursion = access.recursion.allows(client);
+    let allowed =
+        access.query.allows(client) && (!transfer || access.transfer.allows(client)) && (authoritative || recursion);
+    if allowed {
+        return None;
+    }
+
+    let mut refusal = build_response(query, Err(ResultCode::REFUSED));
+    refusal.header.recursion_available = recursion;
+
+    Some(refusal)
+}
+
+// the signature a signed query checked out with, for signing its response. A signature that doesn't
+// check out gets the query refused with NOTAUTH and the TSIG error saying why
+#[cfg(feature = "tsig")]
+fn authenticate(
+    request: &[u8],
+    query: &DnsPacket,
+    keys: &[TsigKey],
+    events: &EventLog,
+) -> core::result::Result<Option<RequestSignature>, Option<DnsPacket>> {
+    let signature = match check_request(keys, request) {
+        Ok(signature) => signature,
+        Err(e) => {
+            events.report(format_args!("Malformed TSIG: {}", e));
+            return Err(Some(build_response(query, Err(ResultCode::FORMERR))));
+        }
+    };
+
+    match signature {
+        Some(signature) if signature.error != 0 => {
+            let error = tsig_error_name(signature.error);
+            let key = signature.key_name();
+            events.report(format_args!("Refused a query signed with key {}: {}", key, error));
+            let mut refusal = build_response(query, Err(ResultCode::NOTAUTH));
+            if let Err(e) = signature.sign(&mut refusal) {
+                events.report(format_args!("Failed to sign the refusal: {}", e));
+            }
+            Err(Some(refusal))
+        }
+        signature => Ok(signature),
+    }
+}
+
+// the response signed for the query's signature. A response that's over the limit once it's signed is
+// truncated before signing, so the client can still tell the TC bit is genuine
+#[cfg(feature = "tsig")]
+fn signed(response: DnsPacket, signature: &RequestSignature, limit: usize, events: &EventLog) -> DnsPacket {
+    let sign = |mut response: DnsPacket| match signature.sign(&mut response) {
+        Ok(()) => response,
+        Err(e) => {
+            events.report(format_args!("Failed to sign the response: {}", e));
+            response
+        }
+    };
+
+    let signed = sign(response.clone());
+    let mut buffer = BytePacketBuffer::with_capacity(limit);
+    if signed.write(&mut buffer).is_err() {
+        return sign(truncated(&response));
+    }
+
+    signed
+}
+
+// the options that come from the server rather than the answer: queries with a cookie get theirs
+// back along with a server cookie for the next query, queries with an empty NSID option get the
+// NSID, and queries over encrypted transports that came padded get a padded response
+fn finish(
+    mut response: DnsPacket,
+    query: &DnsPacket,
+    client: Client,
+    cookies: &Cookies,
+    nsid: Option<&[u8]>,
+) -> DnsPacket {
+    let Some(asked) = query.edns() else {
+        return response;
+    };
+    let cookie = cookies.response_cookie(query, client.address);
+    let nsid = nsid.filter(|_| asked.option(OPTION_NSID).is_some());
+    let padded = client.transport.is_encrypted() && asked.option(OPTION_PADDING).is_some();
+
+    if let Some(edns) = response.edns_mut() {
+        if let Some(cookie) = cookie {
+            edns.set_option(OPTION_COOKIE, cookie);
+        }
+        if let Some(nsid) = nsid {
+            edns.set_option(OPTION_NSID, nsid.to_vec());
+        }
+    }
+    // last, since it depends on the size of everything else
+    if padded {
+        response.pad(RESPONSE_PADDING_BLOCK);
+    }
+
+    response
+}
+
+// builds the response to a single query, resolution failures turn into SERVFAIL
+pub fn handle_query(query: &DnsPacket, resolver: &Resolver) -> DnsPacket {
+    answer_query(query, None, resolver)
+}
+
+// the client's address is what the client subnet sent upstream is made from, unless the query
+// already names one
+fn answer_query(query: &DnsPacket, client: Option<IpAddr>, resolver: &Resolver) -> DnsPacket {
+    if let Some(response) = answer_authoritatively(query, &resolver.zones, resolver.secondary.as_ref()) {
+        return response;
+    }
+    let subnet = resolver.subnet_for(client, query.edns().and_then(Edns::client_subnet));
+    let outcome = question_of(query).map(|question| resolver.resolve_in(question, subnet));
+    report_failure(query, &outcome, &resolver.events);
+
+    build_response(query, outcome)
+}
+
+// the response from the closest zone the server has for the question, None when it has none and the
+// query is resolved instead. AA is set unless it's a referral to the servers of a child zone
+fn answer_authoritatively(query: &DnsPacket, zones: &[Zone], secondary: Option<&Secondary>) -> Option<DnsPacket> {
+    let question = question_of(query).ok()?;
+    let answer = match closest_zone(zones, &question.name) {
+        Some(zone) => zone.answer(&question.name, question.qtype),
+        None => secondary?.answer(question)?,
+    };
+
+    // transfers come over tcp as a stream of messages, which the server doesn't send
+    if matches!(question.qtype, QueryType::AXFR | QueryType::IXFR) {
+        return Some(build_response(query, Err(ResultCode::NOTIMP)));
+    }
+    let authoritative = answer.header.authoritative_answer;
+    let mut response = build_response(query, Ok(Ok(answer)));
+    response.header.authoritative_answer = authoritative;
+
+    Some(response)
+}
+
+// the acknowledgement of a NOTIFY, which is all a secondary answers right away, the refresh it sets off
+// happens in the background. Servers without secondary zones aren't authoritative for any of them
+fn answer_notify(query: &DnsPacket, client: IpAddr, secondary: Option<&Secondary>) -> DnsPacket {
+    let code = match query.questions.as_slice() {
+        [question] if question.qtype == QueryType::SOA => {
+            let serial = query.answers.iter().find_map(|record| match record {
+                DnsRecord::SOA { serial, .. } => Some(*serial),
+                _ => None,
+            });
+            match secondary {
+                Some(secondary) => secondary.notified(&question.name, client, serial),
+                None => ResultCode::NOTAUTH,
+            }
+        }
+        _ => ResultCode::FORMERR,
+    };
+
+    let mut response = build_response(query, Err(code));
+    response.header.authoritative_answer = code == ResultCode::NOERROR;
+    response.header.recursion_available = false;
+
+    response
+}
+
+// the single question of a query, or the result code to refuse it with
+fn question_of(query: &DnsPacket) -> core::result::Result<&DnsQuestion, ResultCode> {
+    if query.header.opcode != OPCODE_QUERY {
+        return Err(ResultCode::NOTIMP);
+    }
+
+    match query.questions.as_slice() {
+        [question] => Ok(question),
+        // nobody really supports more than one question per query
+        _ => Err(ResultCode::FORMERR),
+    }
+}
+
+fn build_response(query: &DnsPacket, outcome: core::result::Result<Result<DnsPacket>, ResultCode>) -> DnsPacket {
+    let mut response = query.clone().into_response();
+    let (mut scope, mut errors) = (0, Vec::new());
+    let dnssec_ok = query.edns().is_some_and(|edns| edns.dnssec_ok);
+
+    match outcome {
+        Ok(Ok(answer)) => {
+            if let Some(edns) = answer.edns() {
+                scope = edns.client_subnet().map_or(0, |subnet| subnet.scope_prefix);
+                errors = edns.extended_errors();
+            }
+            // RFC 6840 only sets AD for clients that show they understand it
+            let aware = dnssec_ok || query.header.authed_data;
+            response.header.authed_data = answer.header.authed_data && aware;
+            response.header.result_code = answer.header.result_code;
+            // and only clients that asked for them get the signatures and denials
+            let wanted = |record: &DnsRecord| dnssec_ok || !dnssec_record(record, query.questions[0].qtype);
+            response.answers = answer.answers.into_iter().filter(wanted).collect();
+            response.authorities = answer.authorities.into_iter().filter(wanted).collect();
+            // the upstream's OPT record describes its connection to us, not ours to the client
+            response.resources = answer
+                .resources
+                .into_iter()
+                .filter(|record| !matches!(record, DnsRecord::OPT { .. }))
+                .collect();
+        }
+        Ok(Err(e)) => {
+            response.header.result_code = ResultCode::SERVFAIL;
+            // a validator fails with the extended error that says what's wrong with the answer
+            let error = match e {
+                DnsError::Bogus(error) => error,
+                e => ExtendedError::new(EDE_NO_REACHABLE_AUTHORITY).with_text(&e.to_string()),
+            };
+            errors.push(error);
+        }
+        Err(code) => response.header.result_code = code,
+    }
+
+    // EDNS is only spoken to clients that spoke it first
+    if let Some(edns) = query.edns() {
+        let mut reply = Edns::new(DEFAULT_PAYLOAD_SIZE);
+        reply.dnssec_ok = dnssec_ok;
+        // a client subnet comes back with how much of it the answer holds for, RFC 7871 wants one
+        // even when it wasn't used
+        if let Some(subnet) = edns.client_subnet() {
+            reply.set_client_subnet(&subnet.with_scope(scope));
+        }
+        // extended errors are about the answer, so the upstream's are passed on
+        for error in &errors {
+            reply.add_extended_error(error);
+        }
+        response.set_edns(reply);
+    }
+    response.header = response.synced_header();
+
+    response
+}
+
+// a question that couldn't be resolved, which build_response answers with SERVFAIL
+fn report_failure(query: &DnsPacket, outcome: &core::result::Result<Result<DnsPacket>, ResultCode>, events: &EventLog) {
+    if let Ok(Err(e)) = outcome {
+        let question = &query.questions[0];
+        events.report(format_args!(
+            "Failed to resolve {} {}: {}",
+            question.name, question.qtype, e
+        ));
+    }
+}
+
+// the records only DNSSEC aware clients get, unless they asked for that type
+fn dnssec_record(record: &DnsRecord, qtype: QueryType) -> bool {
+    let record_type = record.query_type();
+
+    record_type != qtype && matches!(record_type, QueryType::RRSIG | QueryType::NSEC | QueryType::NSEC3)
+}
+
+// what a udp client can take, which is 512 bytes unless its query advertised more over EDNS, and
+// never more than the server advertises itself
+pub fn udp_limit(query: &DnsPacket) -> usize {
+    query
+        .edns()
+        .map(|edns| {
+            edns.payload_size
+                .clamp(DEFAULT_BUFFER_SIZE as u16, DEFAULT_PAYLOAD_SIZE) as usize
+        })
+        .unwrap_or(DEFAULT_BUFFER_SIZE)
+}
+
+// the most the response to a query can take, only udp needs to be kept small
+fn response_limit(query: &DnsPacket, transport: Transport) -> usize {
+    match transport {
+        Transport::Udp => udp_limit(query),
+        _ => MAX_BUFFER_SIZE,
+    }
+}
+
+// writes the response within limit bytes, a response that doesn't fit is sent without its
+// records and the TC bit set so the client knows to ask again over tcp
+pub fn encode_response(response: &DnsPacket, limit: usize) -> Result<BytePacketBuffer> {
+    let mut buffer = BytePacketBuffer::with_capacity(limit);
+    if response.write(&mut buffer).is_ok() {
+        return Ok(buffer);
+    }
+
+    let mut buffer = BytePacketBuffer::with_capacity(limit);
+    truncated(response).write(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+// the response with just its question and OPT record and the TC bit set
+fn truncated(response: &DnsPacket) -> DnsPacket {
+    let mut truncated = DnsPacket::new();
+    truncated.header = response.header.clone();
+    truncated.header.truncated_message = true;
+    truncated.questions = response.questions.clone();
+    if let Some(edns) = response.edns() {
+        truncated.set_edns(edns.clone());
+    }
+
+    truncated
+}
+
+// the async counterpart of serve, every query is answered on its own task so slow upstreams
+// don't hold up the rest
+#[cfg(feature = "tokio")]
+pub async fn serve_async(listen: (Ipv4Addr, u16), resolver: AsyncResolver) -> Result<()> {
+    let socket = Arc::new(tokio::net::UdpSocket::bind(listen).await?);
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    tokio::spawn(serve_tcp_async(listener, resolver.clone()));
+
+    let mut data = vec![0; MAX_BUFFER_SIZE];
+    loop {
+        let (length, source) = socket.recv_from(&mut data).await?;
+        let request = data[..length].to_vec();
+        let socket = socket.clone();
+        let resolver = resolver.clone();
+
+        tokio::spawn(async move {
+            let client = Client::new(source.ip(), Transport::Udp);
+            let Some((response, limit)) = respond_async(&request, client, &resolver).await else {
+                return;
+            };
+
+            match encode_response(&response, limit) {
+                Ok(buffer) => {
+                    if let Err(e) = socket.send_to(&buffer.buffer[..buffer.pos()], source).await {
+                        resolver
+                            .events
+                            .report(format_args!("Failed to answer {}: {}", source, e));
+                    }
+                }
+                Err(e) => {
+                    let events = &resolver.events;
+                    events.report(format_args!("Failed to encode the response for {}: {}", source, e));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn serve_tcp_async(listener: tokio::net::TcpListener, resolver: AsyncResolver) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, source)) => {
+                let (client, resolver) = (Client::new(source.ip(), Transport::Tcp), resolver.clone());
+                tokio::spawn(async move {
+                    let events = resolver.events.clone();
+                    if let Err(e) = handle_connection_async(stream, client, resolver).await {
+                        events.report(format_args!("Tcp connection failed: {}", e));
+                    }
+                });
+            }
+            Err(e) => resolver
+                .events
+                .report(format_args!("Failed to accept a tcp connection: {}", e)),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn handle_connection_async(
+    mut stream: tokio::net::TcpStream,
+    client: Client,
+    resolver: AsyncResolver,
+) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    loop {
+        let mut length = [0; 2];
+        match tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut length)).await {
+            Ok(Ok(_)) => {}
+            // closed or idle
+            _ => return Ok(()),
+        }
+        let mut request = vec![0; u16::from_be_bytes(length) as usize];
+        tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut request))
+            .await
+            .map_err(|_| DnsError::Timeout)??;
+
+        let Some((response, limit)) = respond_async(&request, client, &resolver).await else {
+            continue;
+        };
+
+        let buffer = encode_response(&response, limit)?;
+        let message = &buffer.buffer[..buffer.pos()];
+        stream.write_all(&(message.len() as u16).to_be_bytes()).await?;
+        stream.write_all(message).await?;
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn respond_async(
+    request: &[u8],
+    client: Client,
+    resolver: &AsyncResolver,
+) -> Option<(DnsPacket, usize)> {
+    let (started, trace) = (Instant::now(), Trace::default());
+    let query = match parse_bytes(request) {
+        // answering responses could set off a loop between two servers
+        Ok(query) if query.header.response => return None,
+        Ok(query) => query,
+        Err(e) => {
+            resolver.events.report(format_args!("Malformed query: {}", e));
+            return format_error(request, client.transport);
+        }
+    };
+    let limit = response_limit(&query, client.transport);
+
+    if let Err(refusal) = screen(&query, client, &resolver.cookies, resolver.limiter.as_deref()) {
+        return refusal.map(|refusal| (refusal, limit));
+    }
+    #[cfg(feature = "tsig")]
+    let signature = match authenticate(request, &query, &resolver.tsig_keys, &resolver.events) {
+        Ok(signature) => signature,
+        Err(refusal) => return refusal.map(|refusal| (refusal, limit)),
+    };
+    let view = resolver.view_for(client.address);
+    let authoritative = answer_authoritatively(&query, &view.zones, view.secondary.as_ref());
+    let response = if query.header.opcode == OPCODE_NOTIFY {
+        answer_notify(&query, client.address, view.secondary.as_ref())
+    } else if let Some(refusal) = refusal(&query, client.address, &resolver.access, authoritative.is_some()) {
+        refusal
+    } else if let Some(response) = authoritative {
+        response
+    } else {
+        let subnet = view.subnet_for(Some(client.address), query.edns().and_then(Edns::client_subnet));
+        let outcome = match question_of(&query) {
+            Ok(question) => Ok(view.resolve_traced(question, subnet, &trace).await),
+            Err(code) => Err(code),
+        };
+        report_failure(&query, &outcome, &resolver.events);
+        build_response(&query, outcome)
+    };
+    let response = finish(response, &query, client, &resolver.cookies, resolver.nsid.as_deref());
+    let log = resolver.query_log.as_deref();
+    log_query(log, &resolver.events, &query, &response, client, started, &trace);
+
+    #[cfg(feature = "tsig")]
+    if let Some(signature) = &signature {
+        return Some((signed(response, signature, limit, &resolver.events), limit));
+    }
+
+    Some((response, limit))
+}
+
+// a FORMERR for a query that couldn't be parsed, as long as there's a header to take the id from. There's
+// no OPT record to go by, so a udp client gets the classic 512 bytes
+fn format_error(data: &[u8], transport: Transport) -> Option<(DnsPacket, usize)> {
+    let mut header = DnsHeader::new();
+    header.read(&mut BytePacketBuffer::from_bytes(data).ok()?).ok()?;
+    if header.response {
+        return None;
+    }
+
+    let mut response = DnsPacket::new();
+    response.header.id = header.id;
+    response.header.opcode = header.opcode;
+    response.header.recursion_desired = header.recursion_desired;
+    response.header.recursion_available = true;
+    response.header.response = true;
+    response.header.result_code = ResultCode::FORMERR;
+    let limit = response_limit(&response, transport);
+
+    Some((response, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // a resolver whose event log collects what it's told, forwarding to a port nothing answers on
+    fn collecting() -> (Resolver, Arc<Mutex<Vec<String>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collected = events.clone();
+        let log = EventLog::new(move |message| collected.lock().unwrap().push(message.to_string()));
+        let upstream = Upstream::Forward(vec![ForwardServer::new((Ipv4Addr::LOCALHOST, 9)).with_retries(0)]);
+        let resolver = Resolver::new(upstream)
+            .with_timeout(Duration::from_millis(200))
+            .with_event_log(log);
+
+        (resolver, events)
+    }
+
+    #[test]
+    fn problems_go_to_the_event_log() {
+        let (resolver, events) = collecting();
+        let client = Client::new(IpAddr::V4(Ipv4Addr::LOCALHOST), Transport::Udp);
+        // a header promising a question that isn't there
+        let request = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let response = respond(&request, client, &resolver);
+        assert_eq!(
+            response.map(|(response, _)| response.header.result_code),
+            Some(ResultCode::FORMERR)
+        );
+        assert!(events.lock().unwrap()[0].starts_with("Malformed query: "));
+
+        let query = DnsPacket::new_query(1, "example.com", QueryType::A);
+        assert_eq!(handle_query(&query, &resolver).header.result_code, ResultCode::SERVFAIL);
+        assert!(events.lock().unwrap()[1].starts_with("Failed to resolve example.com A: "));
+    }
+
+    // answers for a zone holding a TXT set of around 800 bytes, more than 512 but less than the server's
+    // own payload size
+    fn answering(payload_size: Option<u16>) -> (DnsPacket, usize) {
+        let mut text = String::from("example.com. 3600 IN SOA ns admin 1 7200 900 86400 300\n");
+        for i in 0..20 {
+            text.push_str(&format!("big.example.com. 3600 IN TXT \"{:0>30}\"\n", i));
+        }
+        let zone = Zone::parse(&text, "example.com").unwrap();
+        let resolver = Resolver::new(Upstream::forward((Ipv4Addr::LOCALHOST, 9))).with_zone(zone);
+
+        let mut query = DnsPacket::new_query(7, "big.example.com", QueryType::TXT);
+        if let Some(payload_size) = payload_size {
+            query.set_edns(Edns::new(payload_size));
+        }
+        let mut request = BytePacketBuffer::new();
+        query.write(&mut request).unwrap();
+        let client = Client::new(IpAddr::V4(Ipv4Addr::LOCALHOST), Transport::Udp);
+
+        respond(&request.buffer[..request.pos()], client, &resolver).unwrap()
+    }
+
+    #[test]
+    fn udp_responses_fit_what_the_query_advertised() {
+        let (response, limit) = answering(Some(512));
+        assert_eq!(limit, 512);
+        let buffer = encode_response(&response, limit).unwrap();
+        let sent = parse_bytes(&buffer.buffer[..buffer.pos()]).unwrap();
+        assert!(sent.header.truncated_message);
+        assert!(sent.answers.is_empty());
+
+        // the server's own OPT record advertises more, but that isn't what the client can take
+        assert!(response.edns().unwrap().payload_size > 512);
+        assert_eq!(answering(None).1, 512);
+
+        let (response, limit) = answering(Some(4096));
+        assert_eq!(limit, DEFAULT_PAYLOAD_SIZE as usize);
+        let buffer = encode_response(&response, limit).unwrap();
+        let sent = parse_bytes(&buffer.buffer[..buffer.pos()]).unwrap();
+        assert!(!sent.header.truncated_message);
+        assert_eq!(sent.answers.len(), 20);
+    }
+}
@@ -0,0 +1,319 @@
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+use core::{
+    net::{IpAddr, SocketAddr},
+    str::{self, FromStr},
+};
+
+use crate::{encoding::base64_decode, DnsError, Result};
+
+// the protocol identifiers of the DNS stamps specification, the first byte of every stamp
+pub const STAMP_PLAIN: u8 = 0x00;
+pub const STAMP_DNSCRYPT: u8 = 0x01;
+pub const STAMP_DOH: u8 = 0x02;
+pub const STAMP_DOT: u8 = 0x03;
+pub const STAMP_DOQ: u8 = 0x04;
+pub const STAMP_ODOH_TARGET: u8 = 0x05;
+pub const STAMP_DNSCRYPT_RELAY: u8 = 0x81;
+pub const STAMP_ODOH_RELAY: u8 = 0x85;
+
+// what a stamp says about the server, the way public resolver lists tell them apart
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StampProperties {
+    // it validates DNSSEC
+    pub dnssec: bool,
+    // it doesn't keep logs of the queries
+    pub no_logs: bool,
+    // it doesn't block anything
+    pub no_filter: bool,
+}
+
+impl StampProperties {
+    fn from_bits(bits: u64) -> StampProperties {
+        StampProperties {
+            dnssec: bits & 1 != 0,
+            no_logs: bits & 2 != 0,
+            no_filter: bits & 4 != 0,
+        }
+    }
+}
+
+// a DNS stamp like sdns://AgcAAAAAAAAABzEuMC4wLjEAEmRucy5jbG91ZGZsYXJlLmNvbQovZG5zLXF1ZXJ5, everything
+// needed to reach a server in one string that resolver lists publish and users paste. Addresses are
+// None when the stamp leaves them out for the hostname to be resolved instead, and without a port they
+// get the one the transport usually has. Hashes are the SHA256 digests of the TBSCertificate of a
+// certificate in the chain the server presents, and bootstrap addresses are resolvers to look the
+// hostname up with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsStamp {
+    Plain {
+        properties: StampProperties,
+        address: SocketAddr,
+    },
+    DnsCrypt {
+        properties: StampProperties,
+        address: SocketAddr,
+        provider_key: [u8; 32],
+        provider_name: String,
+    },
+    Https {
+        properties: StampProperties,
+        address: Option<SocketAddr>,
+        hashes: Vec<Vec<u8>>,
+        // may have a port of its own
+        hostname: String,
+        path: String,
+        bootstrap: Vec<IpAddr>,
+    },
+    Tls {
+        properties: StampProperties,
+        address: Option<SocketAddr>,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<IpAddr>,
+    },
+    Quic {
+        properties: StampProperties,
+        address: Option<SocketAddr>,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        bootstrap: Vec<IpAddr>,
+    },
+    // an oblivious DNS over HTTPS target, which is only ever reached through a relay
+    ObliviousTarget {
+        properties: StampProperties,
+        hostname: String,
+        path: String,
+    },
+    // what anonymized DNSCrypt passes queries through
+    DnsCryptRelay {
+        address: SocketAddr,
+    },
+    ObliviousRelay {
+        properties: StampProperties,
+        address: Option<SocketAddr>,
+        hashes: Vec<Vec<u8>>,
+        hostname: String,
+        path: String,
+        bootstrap: Vec<IpAddr>,
+    },
+}
+
+impl DnsStamp {
+    // the stamp in the bytes sdns:// is followed by
+    pub fn from_bytes(data: &[u8]) -> Result<DnsStamp> {
+        let mut reader = StampReader { data };
+        let protocol = reader.byte()?;
+
+        let stamp = match protocol {
+            STAMP_PLAIN => DnsStamp::Plain {
+                properties: reader.properties()?,
+                address: required(reader.address(53)?)?,
+            },
+            STAMP_DNSCRYPT => DnsStamp::DnsCrypt {
+                properties: reader.properties()?,
+                address: required(reader.address(443)?)?,
+                provider_key: reader
+                    .prefixed()?
+                    .try_into()
+                    .map_err(|_| "A provider key is 32 bytes")?,
+                provider_name: reader.text()?,
+            },
+            STAMP_DOH => DnsStamp::Https {
+                properties: reader.properties()?,
+                address: reader.address(443)?,
+                hashes: reader.hashes()?,
+                hostname: reader.text()?,
+                path: reader.text()?,
+                bootstrap: reader.bootstrap()?,
+            },
+            STAMP_DOT | STAMP_DOQ => {
+                let (properties, address) = (reader.properties()?, reader.address(853)?);
+                let (hashes, hostname, bootstrap) = (reader.hashes()?, reader.text()?, reader.bootstrap()?);
+                match protocol {
+                    STAMP_DOT => DnsStamp::Tls {
+                        properties,
+                        address,
+                        hashes,
+                        hostname,
+                        bootstrap,
+                    },
+                    _ => DnsStamp::Quic {
+                        properties,
+                        address,
+                        hashes,
+                        hostname,
+                        bootstrap,
+                    },
+                }
+            }
+            STAMP_ODOH_TARGET => DnsStamp::ObliviousTarget {
+                properties: reader.properties()?,
+                hostname: reader.text()?,
+                path: reader.text()?,
+            },
+            STAMP_DNSCRYPT_RELAY => DnsStamp::DnsCryptRelay {
+                address: required(reader.address(443)?)?,
+            },
+            STAMP_ODOH_RELAY => DnsStamp::ObliviousRelay {
+                properties: reader.properties()?,
+                address: reader.address(443)?,
+                hashes: reader.hashes()?,
+                hostname: reader.text()?,
+                path: reader.text()?,
+                bootstrap: reader.bootstrap()?,
+            },
+            protocol => return Err(format!("Unknown DNS stamp protocol {:#04x}", protocol).into()),
+        };
+
+        Ok(stamp)
+    }
+
+    // relays don't have any, they say nothing about the servers behind them
+    pub fn properties(&self) -> StampProperties {
+        match self {
+            DnsStamp::Plain { properties, .. }
+            | DnsStamp::DnsCrypt { properties, .. }
+            | DnsStamp::Https { properties, .. }
+            | DnsStamp::Tls { properties, .. }
+            | DnsStamp::Quic { properties, .. }
+            | DnsStamp::ObliviousTarget { properties, .. }
+            | DnsStamp::ObliviousRelay { properties, .. } => *properties,
+            DnsStamp::DnsCryptRelay { .. } => StampProperties::default(),
+        }
+    }
+
+    pub fn address(&self) -> Option<SocketAddr> {
+        match self {
+            DnsStamp::Plain { address, .. }
+            | DnsStamp::DnsCrypt { address, .. }
+            | DnsStamp::DnsCryptRelay { address } => Some(*address),
+            DnsStamp::Https { address, .. }
+            | DnsStamp::Tls { address, .. }
+            | DnsStamp::Quic { address, .. }
+            | DnsStamp::ObliviousRelay { address, .. } => *address,
+            DnsStamp::ObliviousTarget { .. } => None,
+        }
+    }
+}
+
+// sdns:// and the stamp in unpadded base64url
+impl FromStr for DnsStamp {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<DnsStamp> {
+        let encoded = s
+            .strip_prefix("sdns://")
+            .ok_or_else(|| format!("{} isn't an sdns:// stamp", s))?;
+        DnsStamp::from_bytes(&base64_decode(encoded)?)
+    }
+}
+
+// reads the fields of a stamp in order, every one of them is either fixed size or starts with its
+// length
+struct StampReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StampReader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let (first, rest) = self.data.split_first().ok_or("The DNS stamp ends early")?;
+        self.data = rest;
+
+        Ok(*first)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < length {
+            return Err("The DNS stamp ends early".into());
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    // a little endian u64 of flags
+    fn properties(&mut self) -> Result<StampProperties> {
+        let bits = self.take(8)?.try_into().map_err(|_| "The DNS stamp ends early")?;
+        Ok(StampProperties::from_bits(u64::from_le_bytes(bits)))
+    }
+
+    // a length byte and as many bytes after it
+    fn prefixed(&mut self) -> Result<&'a [u8]> {
+        let length = self.byte()?;
+        self.take(usize::from(length))
+    }
+
+    fn text(&mut self) -> Result<String> {
+        let bytes = self.prefixed()?;
+        Ok(str::from_utf8(bytes)
+            .map_err(|_| "A DNS stamp has text that isn't utf-8")?
+            .to_owned())
+    }
+
+    // a set of prefixed values where the top bit of every length but the last says another one follows,
+    // and a single zero length for none. The optional sets at the end may be left out altogether
+    fn set(&mut self) -> Result<Vec<&'a [u8]>> {
+        let mut values = Vec::new();
+        while !self.data.is_empty() {
+            let length = self.byte()?;
+            values.push(self.take(usize::from(length & 0x7f))?);
+            if length & 0x80 == 0 {
+                break;
+            }
+        }
+        values.retain(|value| !value.is_empty());
+
+        Ok(values)
+    }
+
+    fn hashes(&mut self) -> Result<Vec<Vec<u8>>> {
+        Ok(self.set()?.into_iter().map(<[u8]>::to_vec).collect())
+    }
+
+    fn bootstrap(&mut self) -> Result<Vec<IpAddr>> {
+        self.set()?
+            .into_iter()
+            .map(|value| {
+                let text = str::from_utf8(value).map_err(|_| "A DNS stamp has text that isn't utf-8")?;
+                parse_address(text, 53).map(|address| address.ip())
+            })
+            .collect()
+    }
+
+    // ip, ip:port, [ipv6] or [ipv6]:port, nothing at all is None
+    fn address(&mut self, default_port: u16) -> Result<Option<SocketAddr>> {
+        let text = self.text()?;
+        if text.is_empty() {
+            return Ok(None);
+        }
+
+        parse_address(&text, default_port).map(Some)
+    }
+}
+
+fn parse_address(text: &str, default_port: u16) -> Result<SocketAddr> {
+    let invalid = || format!("{} isn't an address in a DNS stamp", text);
+    let (ip, port) = match text.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']').ok_or_else(invalid)?;
+            (ip, port.strip_prefix(':'))
+        }
+        None if text.matches(':').count() == 1 => {
+            let (ip, port) = text.split_once(':').ok_or_else(invalid)?;
+            (ip, Some(port))
+        }
+        None => (text, None),
+    };
+    let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => default_port,
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn required(address: Option<SocketAddr>) -> Result<SocketAddr> {
+    address.ok_or_else(|| "The DNS stamp has no address".into())
+}
@@ -0,0 +1,177 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{encoding::base64_encode, record::quote_bytes, Result};
+
+// SvcParamKeys from RFC 9460
+pub const KEY_MANDATORY: u16 = 0;
+pub const KEY_ALPN: u16 = 1;
+pub const KEY_NO_DEFAULT_ALPN: u16 = 2;
+pub const KEY_PORT: u16 = 3;
+pub const KEY_IPV4HINT: u16 = 4;
+pub const KEY_ECH: u16 = 5;
+pub const KEY_IPV6HINT: u16 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
+// the key/value list at the end of SVCB and HTTPS records, values are kept raw and decoded on access
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SvcParams(pub Vec<SvcParam>);
+
+impl SvcParams {
+    pub fn get(&self, key: u16) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|param| param.key == key)
+            .map(|param| param.value.as_slice())
+    }
+
+    // keys the client has to understand to use the record
+    pub fn mandatory(&self) -> Option<Vec<u16>> {
+        let value = self.get(KEY_MANDATORY)?;
+        Some(
+            value
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect(),
+        )
+    }
+
+    // protocol ids like h2 or h3, stored as a sequence of length prefixed strings
+    pub fn alpn(&self) -> Option<Vec<String>> {
+        let mut value = self.get(KEY_ALPN)?;
+
+        let mut ids = Vec::new();
+        while let Some((&len, rest)) = value.split_first() {
+            let id = rest.get(..len as usize)?;
+            ids.push(String::from_utf8_lossy(id).to_string());
+            value = &rest[len as usize..];
+        }
+
+        Some(ids)
+    }
+
+    pub fn no_default_alpn(&self) -> bool {
+        self.get(KEY_NO_DEFAULT_ALPN).is_some()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self.get(KEY_PORT)? {
+            [high, low] => Some(u16::from_be_bytes([*high, *low])),
+            _ => None,
+        }
+    }
+
+    pub fn ipv4hint(&self) -> Option<Vec<Ipv4Addr>> {
+        let value = self.get(KEY_IPV4HINT)?;
+        Some(
+            value
+                .chunks_exact(4)
+                .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+                .collect(),
+        )
+    }
+
+    // the encrypted client hello config list, opaque to us
+    pub fn ech(&self) -> Option<&[u8]> {
+        self.get(KEY_ECH)
+    }
+
+    pub fn ipv6hint(&self) -> Option<Vec<Ipv6Addr>> {
+        let value = self.get(KEY_IPV6HINT)?;
+        Some(
+            value
+                .chunks_exact(16)
+                .map(|octets| {
+                    let mut address = [0; 16];
+                    address.copy_from_slice(octets);
+                    Ipv6Addr::from(address)
+                })
+                .collect(),
+        )
+    }
+}
+
+fn key_name(key: u16) -> String {
+    match key {
+        KEY_MANDATORY => "mandatory".to_string(),
+        KEY_ALPN => "alpn".to_string(),
+        KEY_NO_DEFAULT_ALPN => "no-default-alpn".to_string(),
+        KEY_PORT => "port".to_string(),
+        KEY_IPV4HINT => "ipv4hint".to_string(),
+        KEY_ECH => "ech".to_string(),
+        KEY_IPV6HINT => "ipv6hint".to_string(),
+        _ => format!("key{}", key),
+    }
+}
+
+// the other way around, for reading params back from presentation format
+pub(crate) fn key_number(name: &str) -> Result<u16> {
+    match name.to_ascii_lowercase().as_str() {
+        "mandatory" => Ok(KEY_MANDATORY),
+        "alpn" => Ok(KEY_ALPN),
+        "no-default-alpn" => Ok(KEY_NO_DEFAULT_ALPN),
+        "port" => Ok(KEY_PORT),
+        "ipv4hint" => Ok(KEY_IPV4HINT),
+        "ech" => Ok(KEY_ECH),
+        "ipv6hint" => Ok(KEY_IPV6HINT),
+        other => other
+            .strip_prefix("key")
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| format!("Unknown SvcParam key {}", name).into()),
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// presentation format from RFC 9460, e.g. `alpn=h2,h3 port=443`
+impl fmt::Display for SvcParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        for param in &self.0 {
+            write!(f, "{}", separator)?;
+            separator = " ";
+
+            // anything that doesn't decode cleanly falls back to the generic quoted form
+            let known = match param.key {
+                KEY_MANDATORY => self
+                    .mandatory()
+                    .map(|keys| keys.iter().map(|key| key_name(*key)).collect::<Vec<String>>().join(",")),
+                KEY_ALPN => self.alpn().map(|ids| ids.join(",")),
+                KEY_NO_DEFAULT_ALPN if param.value.is_empty() => {
+                    write!(f, "no-default-alpn")?;
+                    continue;
+                }
+                KEY_PORT => self.port().map(|port| port.to_string()),
+                KEY_IPV4HINT => self.ipv4hint().map(|hints| join(&hints)),
+                KEY_ECH => Some(base64_encode(&param.value)),
+                KEY_IPV6HINT => self.ipv6hint().map(|hints| join(&hints)),
+                _ => None,
+            };
+
+            match known {
+                Some(value) => write!(f, "{}={}", key_name(param.key), value)?,
+                None => write!(f, "{}={}", key_name(param.key), quote_bytes(&param.value))?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpStream},
+    time::Duration,
+};
+
+#[cfg(feature = "tsig")]
+use crate::TsigKey;
+use crate::{
+    client::{build_query, encode, DEFAULT_TIMEOUT},
+    BytePacketBuffer, DnsError, DnsPacket, QueryType, Result,
+};
+
+// over tcp every message is preceded by its length as a big endian u16
+pub(crate) fn write_framed(stream: &mut impl Write, message: &[u8]) -> Result<()> {
+    let length = u16::try_from(message.len()).map_err(|_| "Message exceeds 65535 bytes")?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+
+    Ok(())
+}
+
+pub(crate) fn read_framed(stream: &mut impl Read) -> Result<BytePacketBuffer> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).map_err(timeout_error)?;
+
+    let mut buffer = BytePacketBuffer::with_capacity(u16::from_be_bytes(length) as usize);
+    stream.read_exact(&mut buffer.buffer).map_err(timeout_error)?;
+
+    Ok(buffer)
+}
+
+pub(crate) fn timeout_error(e: std::io::Error) -> DnsError {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => DnsError::Timeout,
+        _ => DnsError::from(e),
+    }
+}
+
+// queries a server over tcp, keeping the connection open so following queries skip the handshake
+pub struct TcpClient {
+    pub server: (Ipv4Addr, u16),
+    pub timeout: Duration,
+    stream: Option<TcpStream>,
+}
+
+impl TcpClient {
+    // the connection is only made once the first query goes out
+    pub fn new(server: (Ipv4Addr, u16)) -> TcpClient {
+        TcpClient {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            stream: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> TcpClient {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn lookup(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.exchange(&build_query(qname, qtype))
+    }
+
+    // sends an already built query, a reused connection the server has since closed is reopened once
+    pub fn exchange(&mut self, query: &DnsPacket) -> Result<DnsPacket> {
+        self.exchange_wire(query).map(|(packet, _)| packet)
+    }
+
+    // sends query signed with key and fails unless the response is signed with it too
+    #[cfg(feature = "tsig")]
+    pub fn exchange_signed(&mut self, query: &DnsPacket, key: &TsigKey) -> Result<DnsPacket> {
+        let mut query = query.clone();
+        let mac = key.sign(&mut query)?;
+        let (response, message) = self.exchange_wire(&query)?;
+        key.verify(&message, Some(&mac))?;
+
+        Ok(response)
+    }
+
+    pub(crate) fn exchange_wire(&mut self, query: &DnsPacket) -> Result<(DnsPacket, Vec<u8>)> {
+        let (server, timeout) = (self.server, self.timeout);
+        exchange_reusing(&mut self.stream, || connect(server, timeout), query)
+    }
+}
+
+pub(crate) fn connect(server: (Ipv4Addr, u16), timeout: Duration) -> Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&server.into(), timeout).map_err(timeout_error)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+
+    Ok(stream)
+}
+
+// one framed exchange over the stream kept in slot, which is opened with connect when empty and
+// emptied again whenever it can't be trusted anymore, shared by the tcp and tls clients. The response
+// comes back as it arrived too
+pub(crate) fn exchange_reusing<S: Read + Write>(
+    slot: &mut Option<S>,
+    connect: impl Fn() -> Result<S>,
+    query: &DnsPacket,
+) -> Result<(DnsPacket, Vec<u8>)> {
+    let request = encode(query)?;
+    let message = &request.buffer[..request.pos()];
+
+    let send = |slot: &mut Option<S>| -> Result<BytePacketBuffer> {
+        let stream = match slot {
+            Some(stream) => stream,
+            None => slot.insert(connect()?),
+        };
+
+        write_framed(stream, message)?;
+        read_framed(stream)
+    };
+
+    let reused = slot.is_some();
+    let result = match send(slot) {
+        // a timeout means the server is slow rather than gone, so asking again wouldn't help
+        Err(e) if reused && e != DnsError::Timeout => {
+            *slot = None;
+            send(slot)
+        }
+        result => result,
+    };
+    // a failed exchange can leave half a message on the connection, so it's not used again
+    let mut response = result.inspect_err(|_| *slot = None)?;
+
+    let packet = DnsPacket::from_buffer(&mut response)?;
+    if packet.header.id != query.header.id {
+        *slot = None;
+        return Err(format!(
+            "Response id {} doesn't match query id {}",
+            packet.header.id, query.header.id
+        )
+        .into());
+    }
+
+    Ok((packet, response.buffer))
+}
+
+// a single query over a fresh tcp connection, for answers too big for udp
+pub fn lookup_tcp(qname: &str, qtype: QueryType, server: (Ipv4Addr, u16)) -> Result<DnsPacket> {
+    TcpClient::new(server).lookup(qname, qtype)
+}
+
+// the tokio version of a single exchange over a fresh connection, timeout bounds all of it
+#[cfg(feature = "tokio")]
+pub(crate) async fn exchange_tcp_async(
+    query: &DnsPacket,
+    server: (Ipv4Addr, u16),
+    timeout: Duration,
+) -> Result<DnsPacket> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let request = encode(query)?;
+    let message = &request.buffer[..request.pos()];
+    let length = u16::try_from(message.len()).map_err(|_| "Message exceeds 65535 bytes")?;
+
+    let exchange = async {
+        let mut stream = tokio::net::TcpStream::connect(server).await?;
+        stream.write_all(&length.to_be_bytes()).await?;
+        stream.write_all(message).await?;
+
+        let mut length = [0; 2];
+        stream.read_exact(&mut length).await?;
+        let mut response = BytePacketBuffer::with_capacity(u16::from_be_bytes(length) as usize);
+        stream.read_exact(&mut response.buffer).await?;
+
+        Ok::<_, DnsError>(response)
+    };
+    let mut response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| DnsError::Timeout)??;
+
+    let packet = DnsPacket::from_buffer(&mut response)?;
+    if packet.header.id != query.header.id {
+        return Err(format!(
+            "Response id {} doesn't match query id {}",
+            packet.header.id, query.header.id
+        )
+        .into());
+    }
+
+    Ok(packet)
+}
@@ -0,0 +1,123 @@
+use std::{
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{
+    client::{build_query, DEFAULT_TIMEOUT},
+    edns::QUERY_PADDING_BLOCK,
+    server::{accept_connections, handle_connection, Client, Transport},
+    tcp::{connect, exchange_reusing},
+    DnsPacket, QueryType, Resolver, Result,
+};
+
+// RFC 7858 reserves 853 for DNS over TLS
+pub const DOT_PORT: u16 = 853;
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// queries a server over TLS, the framing is the same as plain tcp and the connection is kept open
+// between queries, so only the first one pays for the handshake
+pub struct TlsClient {
+    pub server: (Ipv4Addr, u16),
+    pub timeout: Duration,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    stream: Option<TlsStream>,
+}
+
+impl TlsClient {
+    // server_name is sent as SNI and has to match the certificate, it can also be the ip itself
+    // for servers like 1.1.1.1 whose certificates list their addresses
+    pub fn new(server: (Ipv4Addr, u16), server_name: &str) -> Result<TlsClient> {
+        let config = client_config();
+
+        Ok(TlsClient {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            server_name: ServerName::try_from(server_name.to_string())?,
+            config: Arc::new(config),
+            stream: None,
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> TlsClient {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn lookup(&mut self, qname: &str, qtype: QueryType) -> Result<DnsPacket> {
+        self.exchange(&build_query(qname, qtype))
+    }
+
+    pub fn exchange(&mut self, query: &DnsPacket) -> Result<DnsPacket> {
+        let (server, timeout) = (self.server, self.timeout);
+        let (config, server_name) = (&self.config, &self.server_name);
+
+        exchange_reusing(
+            &mut self.stream,
+            || {
+                let tcp = connect(server, timeout)?;
+                let connection = ClientConnection::new(config.clone(), server_name.clone())?;
+                Ok(StreamOwned::new(connection, tcp))
+            },
+            &padded(query),
+        )
+        .map(|(packet, _)| packet)
+    }
+}
+
+// what goes over an encrypted transport, padded so its length says less about the name asked for
+pub(crate) fn padded(query: &DnsPacket) -> DnsPacket {
+    let mut query = query.clone();
+    query.pad(QUERY_PADDING_BLOCK);
+
+    query
+}
+
+// checks servers against the bundled webpki roots
+pub(crate) fn client_config() -> ClientConfig {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+// what serve_tls presents to clients, from a pem certificate chain and its private key
+pub fn server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let chain = CertificateDer::pem_file_iter(cert_path)?.collect::<core::result::Result<Vec<_>, _>>()?;
+    if chain.is_empty() {
+        return Err(format!("No certificates in {}", cert_path.display()).into());
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key)?;
+
+    Ok(Arc::new(config))
+}
+
+// answers DNS over TLS on listen the same way serve answers plain tcp, the handshake happens on each
+// connection's own thread so a stalled one doesn't hold up the rest
+pub fn serve_tls(listen: (Ipv4Addr, u16), resolver: Resolver, config: Arc<ServerConfig>) -> Result<()> {
+    let listener = TcpListener::bind(listen)?;
+
+    accept_connections(listener, resolver.events.clone(), move |tcp| {
+        let connection = ServerConnection::new(config.clone())?;
+        let client = Client::new(tcp.peer_addr()?.ip(), Transport::Tls);
+        handle_connection(StreamOwned::new(connection, tcp), client, &resolver)
+    });
+
+    Ok(())
+}
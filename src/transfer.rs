@@ -0,0 +1,368 @@
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    client::{encode, DEFAULT_TIMEOUT},
+    record::fqdn,
+    rng::random_id,
+    tcp::{connect, read_framed, write_framed},
+    DnsError, DnsPacket, DnsRecord, QueryType, Result,
+};
+#[cfg(feature = "tsig")]
+use crate::{tsig::StreamVerifier, ResultCode, TsigKey};
+
+// a full transfer of zone from server, with its SOA first and not repeated at the end
+pub fn axfr(zone: &str, server: (Ipv4Addr, u16)) -> Result<Vec<DnsRecord>> {
+    ZoneTransfer::new(server).axfr(zone)
+}
+
+// what an IXFR brought back
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZoneChanges {
+    // the zone is still at the serial asked about
+    UpToDate,
+    // the server sent the whole zone instead, SOA first
+    Full(Vec<DnsRecord>),
+    // the changes from the serial asked about to the current one, oldest first
+    Incremental(Vec<ZoneDiff>),
+}
+
+// one version of a zone to the next. The old SOA is the first record deleted and the new one the first
+// added, the way RFC 1995 sends them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZoneDiff {
+    pub from_serial: u32,
+    pub to_serial: u32,
+    pub deleted: Vec<DnsRecord>,
+    pub added: Vec<DnsRecord>,
+}
+
+// zone transfers from one server over tcp, the timeout is for each message to arrive rather than the
+// whole zone, which can take a while
+#[derive(Clone, Debug)]
+pub struct ZoneTransfer {
+    pub server: (Ipv4Addr, u16),
+    pub timeout: Duration,
+    #[cfg(feature = "tsig")]
+    pub key: Option<TsigKey>,
+}
+
+impl ZoneTransfer {
+    pub fn new(server: (Ipv4Addr, u16)) -> ZoneTransfer {
+        ZoneTransfer {
+            server,
+            timeout: DEFAULT_TIMEOUT,
+            #[cfg(feature = "tsig")]
+            key: None,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> ZoneTransfer {
+        self.timeout = timeout;
+        self
+    }
+
+    // signs the request with key, the server has to sign the transfer with it as well
+    #[cfg(feature = "tsig")]
+    pub fn with_key(mut self, key: TsigKey) -> ZoneTransfer {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn axfr(&self, zone: &str) -> Result<Vec<DnsRecord>> {
+        let mut records = Vec::new();
+        self.axfr_each(zone, |record| {
+            records.push(record);
+            Ok(())
+        })?;
+
+        Ok(records)
+    }
+
+    // an AXFR from RFC 5936, handing every record to on_record as it arrives so the zone doesn't have
+    // to fit in memory. The records can be spread over any number of messages and the transfer ends with
+    // the second copy of the SOA it started with
+    pub fn axfr_each(&self, zone: &str, mut on_record: impl FnMut(DnsRecord) -> Result<()>) -> Result<()> {
+        let zone = zone.trim_end_matches('.');
+        let mut messages = self.request(transfer_query(zone, QueryType::AXFR))?;
+
+        let first = messages.record()?;
+        if zone_soa_serial(&first, zone).is_none() {
+            return Err(not_started(zone));
+        }
+        on_record(first)?;
+
+        loop {
+            match messages.record()? {
+                DnsRecord::SOA { .. } => return messages.finish(),
+                record => on_record(record)?,
+            }
+        }
+    }
+
+    // an IXFR from RFC 1995 for what changed since serial. Servers that don't do incremental transfers
+    // either send the whole zone or answer NOTIMP, in which case it's asked for with an AXFR instead
+    pub fn ixfr(&self, zone: &str, serial: u32) -> Result<ZoneChanges> {
+        match self.ixfr_only(zone, serial) {
+            Err(DnsError::NotImplemented | DnsError::FormatError) => Ok(ZoneChanges::Full(self.axfr(zone)?)),
+            result => result,
+        }
+    }
+
+    fn ixfr_only(&self, zone: &str, serial: u32) -> Result<ZoneChanges> {
+        let zone = zone.trim_end_matches('.');
+
+        // the serial goes in an SOA in the authority section, the rest of it doesn't matter
+        let mut query = transfer_query(zone, QueryType::IXFR);
+        query.authorities.push(DnsRecord::SOA {
+            domain: zone.to_string(),
+            mname: String::new(),
+            rname: String::new(),
+            serial,
+            refresh: 0,
+            retry: 0,
+            expire: 0,
+            minimum: 0,
+            ttl: 0,
+        });
+        let mut messages = self.request(query)?;
+
+        // just the current SOA when there's nothing newer
+        let first = messages.record()?;
+        let current = zone_soa_serial(&first, zone).ok_or_else(|| not_started(zone))?;
+        if !serial_newer(current, serial) {
+            messages.finish()?;
+            return Ok(ZoneChanges::UpToDate);
+        }
+
+        // and an AXFR in all but name when the server has no history going back that far. A second
+        // copy of the current SOA right away is a zone that's nothing but its SOA
+        let mut from = match messages.record()? {
+            DnsRecord::SOA { serial, .. } if serial == current => {
+                messages.finish()?;
+                return Ok(ZoneChanges::Full(vec![first]));
+            }
+            soa @ DnsRecord::SOA { .. } => soa,
+            record => {
+                let mut records = vec![first, record];
+                loop {
+                    match messages.record()? {
+                        DnsRecord::SOA { .. } => break,
+                        record => records.push(record),
+                    }
+                }
+                messages.finish()?;
+                return Ok(ZoneChanges::Full(records));
+            }
+        };
+
+        // otherwise every version comes as the old SOA, what was deleted, the new SOA and what was added,
+        // until a version ends with the current serial and is followed by the current SOA once more
+        let mut diffs = Vec::new();
+        loop {
+            let mut diff = ZoneDiff {
+                from_serial: soa_serial(&from).unwrap_or_default(),
+                to_serial: 0,
+                deleted: vec![from],
+                added: Vec::new(),
+            };
+            let to = messages.read_until_soa(&mut diff.deleted)?;
+            diff.to_serial = soa_serial(&to).unwrap_or_default();
+            diff.added.push(to);
+            from = messages.read_until_soa(&mut diff.added)?;
+
+            let done = diff.to_serial == current;
+            diffs.push(diff);
+            if done {
+                messages.finish()?;
+                return Ok(ZoneChanges::Incremental(diffs));
+            }
+        }
+    }
+
+    // brings records, a copy of zone, up to date. There's an IXFR from the serial of its SOA when it has
+    // one, otherwise the whole zone is transferred. Returns whether anything changed
+    pub fn refresh(&self, zone: &str, records: &mut Vec<DnsRecord>) -> Result<bool> {
+        let changes = match zone_serial(records) {
+            Some(serial) => self.ixfr(zone, serial)?,
+            None => ZoneChanges::Full(self.axfr(zone)?),
+        };
+
+        match changes {
+            ZoneChanges::UpToDate => Ok(false),
+            ZoneChanges::Full(zone) => {
+                *records = zone;
+                Ok(true)
+            }
+            ZoneChanges::Incremental(diffs) => {
+                apply_changes(records, &diffs)?;
+                Ok(true)
+            }
+        }
+    }
+
+    // sends the request for a transfer
+    fn request(&self, mut query: DnsPacket) -> Result<Messages> {
+        #[cfg(feature = "tsig")]
+        let verifier = match &self.key {
+            Some(key) => Some(StreamVerifier::new(key, &key.sign(&mut query)?)),
+            None => None,
+        };
+        query.header = query.synced_header();
+
+        let mut stream = connect(self.server, self.timeout)?;
+        let request = encode(&query)?;
+        write_framed(&mut stream, &request.buffer[..request.pos()])?;
+
+        Ok(Messages {
+            stream,
+            id: query.header.id,
+            pending: VecDeque::new(),
+            #[cfg(feature = "tsig")]
+            verifier,
+        })
+    }
+}
+
+// applies diffs, oldest first, to records, a copy of the zone at the serial the first one starts from.
+// Nothing is changed unless all of them apply
+pub fn apply_changes(records: &mut Vec<DnsRecord>, diffs: &[ZoneDiff]) -> Result<()> {
+    let mut zone = records.clone();
+    for diff in diffs {
+        let serial = zone_serial(&zone).ok_or("The zone has no SOA to apply changes to")?;
+        if serial != diff.from_serial {
+            let from = diff.from_serial;
+            return Err(format!("The changes are from serial {}, the zone is at {}", from, serial).into());
+        }
+
+        for record in &diff.deleted {
+            let index = zone
+                .iter()
+                .position(|existing| same_record(existing, record))
+                .ok_or_else(|| format!("The changes delete {}, which isn't in the zone", record))?;
+            zone.remove(index);
+        }
+        // the SOA stays in front, where transfers have it
+        for record in &diff.added {
+            match record {
+                DnsRecord::SOA { .. } => zone.insert(0, record.clone()),
+                _ => zone.push(record.clone()),
+            }
+        }
+    }
+
+    *records = zone;
+    Ok(())
+}
+
+// the serial of the zone's SOA among records
+pub fn zone_serial(records: &[DnsRecord]) -> Option<u32> {
+    records.iter().find_map(soa_serial)
+}
+
+// whether serial a comes after b in the wrapping serial number arithmetic of RFC 1982
+pub(crate) fn serial_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000_0000
+}
+
+fn soa_serial(record: &DnsRecord) -> Option<u32> {
+    match record {
+        DnsRecord::SOA { serial, .. } => Some(*serial),
+        _ => None,
+    }
+}
+
+fn zone_soa_serial(record: &DnsRecord, zone: &str) -> Option<u32> {
+    soa_serial(record).filter(|_| record.domain().eq_ignore_ascii_case(zone))
+}
+
+// records match when they differ at most in their TTL and the case of their owner
+fn same_record(a: &DnsRecord, b: &DnsRecord) -> bool {
+    a.query_type() == b.query_type()
+        && a.domain().eq_ignore_ascii_case(b.domain())
+        && matches!((a.rdata(), b.rdata()), (Ok(a), Ok(b)) if a == b)
+}
+
+fn transfer_query(zone: &str, qtype: QueryType) -> DnsPacket {
+    let mut query = DnsPacket::new_query(random_id(), zone, qtype);
+    query.header.recursion_desired = false;
+
+    query
+}
+
+fn not_started(zone: &str) -> crate::DnsError {
+    format!("The transfer of {} doesn't start with its SOA", fqdn(zone)).into()
+}
+
+// the response messages of one transfer as they come off the connection, each checked against the
+// request and its signature
+struct Messages {
+    stream: TcpStream,
+    id: u16,
+    // the records of the last message not taken yet
+    pending: VecDeque<DnsRecord>,
+    #[cfg(feature = "tsig")]
+    verifier: Option<StreamVerifier>,
+}
+
+impl Messages {
+    fn next(&mut self) -> Result<DnsPacket> {
+        let mut message = read_framed(&mut self.stream)?;
+        let packet = DnsPacket::from_buffer(&mut message)?;
+        if packet.header.id != self.id {
+            return Err(format!("Response id {} doesn't match query id {}", packet.header.id, self.id).into());
+        }
+
+        // a server that refuses without signing says so through the result code
+        #[cfg(feature = "tsig")]
+        if let Some(verifier) = &mut self.verifier {
+            let signed = packet
+                .resources
+                .iter()
+                .any(|record| matches!(record, DnsRecord::TSIG { .. }));
+            if signed || packet.header.result_code == ResultCode::NOERROR {
+                verifier.verify(&message.buffer)?;
+            }
+        }
+        packet.check_response_code()?;
+
+        Ok(packet)
+    }
+
+    // the next record of the transfer, whichever message it's in
+    fn record(&mut self) -> Result<DnsRecord> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(record);
+            }
+
+            let answers = self.next()?.answers;
+            if answers.is_empty() {
+                return Err("A message of the transfer has no records".into());
+            }
+            self.pending.extend(answers);
+        }
+    }
+
+    // adds records to section up to the next SOA, which is returned
+    fn read_until_soa(&mut self, section: &mut Vec<DnsRecord>) -> Result<DnsRecord> {
+        loop {
+            match self.record()? {
+                soa @ DnsRecord::SOA { .. } => return Ok(soa),
+                record => section.push(record),
+            }
+        }
+    }
+
+    fn finish(&self) -> Result<()> {
+        #[cfg(feature = "tsig")]
+        if let Some(verifier) = &self.verifier {
+            verifier.finish()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,765 @@
+use std::{net::Ipv4Addr, path::Path, str::FromStr, time::Duration};
+
+use ring::hmac;
+
+use crate::{
+    buffer::MAX_BUFFER_SIZE,
+    client::exchange_wire,
+    encoding::base64_decode,
+    record::{name_wire, normal, tsig_error_name, unix_time, CLASS_ANY, TSIG_BADKEY, TSIG_BADSIG, TSIG_BADTIME},
+    BytePacketBuffer, DnsError, DnsHeader, DnsPacket, DnsQuestion, DnsRecord, QueryType, Result,
+};
+
+// how far apart the clocks of the two sides may be, the five minutes RFC 8945 recommends
+pub const DEFAULT_FUDGE: u16 = 300;
+
+// the HMACs ring has, which are the ones RFC 8945 requires along with the only ones BIND generates
+// keys for by default. HMAC-MD5 is left out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TsigAlgorithm {
+    HmacSha1,
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    // the name the algorithm goes by in TSIG records and key files
+    pub fn name(self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha1 => "hmac-sha1",
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha384 => "hmac-sha384",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn hmac(self) -> hmac::Algorithm {
+        match self {
+            TsigAlgorithm::HmacSha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            TsigAlgorithm::HmacSha256 => hmac::HMAC_SHA256,
+            TsigAlgorithm::HmacSha384 => hmac::HMAC_SHA384,
+            TsigAlgorithm::HmacSha512 => hmac::HMAC_SHA512,
+        }
+    }
+}
+
+// the names in any case, with or without the trailing dot
+impl FromStr for TsigAlgorithm {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<TsigAlgorithm> {
+        match normal(s).as_str() {
+            "hmac-sha1" => Ok(TsigAlgorithm::HmacSha1),
+            "hmac-sha256" => Ok(TsigAlgorithm::HmacSha256),
+            "hmac-sha384" => Ok(TsigAlgorithm::HmacSha384),
+            "hmac-sha512" => Ok(TsigAlgorithm::HmacSha512),
+            _ => Err(format!("Unsupported TSIG algorithm {}", s).into()),
+        }
+    }
+}
+
+// a shared secret both sides of a transaction sign their messages with, known to them by its name
+#[derive(Clone, Debug)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub fudge: u16,
+    key: hmac::Key,
+}
+
+impl TsigKey {
+    pub fn new(name: &str, algorithm: TsigAlgorithm, secret: &[u8]) -> TsigKey {
+        TsigKey {
+            name: normal(name),
+            algorithm,
+            fudge: DEFAULT_FUDGE,
+            key: hmac::Key::new(algorithm.hmac(), secret),
+        }
+    }
+
+    pub fn with_fudge(mut self, fudge: u16) -> TsigKey {
+        self.fudge = fudge;
+        self
+    }
+
+    // a key the way dig -y takes it, [algorithm:]name:secret with the secret in base64, hmac-sha256 when
+    // there's no algorithm
+    pub fn parse(text: &str) -> Result<TsigKey> {
+        let fields: Vec<&str> = text.trim().split(':').collect();
+        let (algorithm, name, secret) = match fields.as_slice() {
+            [name, secret] => (TsigAlgorithm::HmacSha256, *name, *secret),
+            [algorithm, name, secret] => (algorithm.parse()?, *name, *secret),
+            _ => return Err(format!("TSIG key {} isn't [algorithm:]name:secret", text).into()),
+        };
+        if name.is_empty() {
+            return Err("TSIG key has no name".into());
+        }
+
+        Ok(TsigKey::new(name, algorithm, &base64_decode(secret)?))
+    }
+
+    // every key statement in a BIND key file, like the ones tsig-keygen writes
+    pub fn from_file(path: &Path) -> Result<Vec<TsigKey>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read TSIG keys from {}: {}", path.display(), e))?;
+
+        parse_key_file(&text)
+    }
+
+    // signs a query, the MAC returned is what the response has to be signed over
+    pub fn sign(&self, query: &mut DnsPacket) -> Result<Vec<u8>> {
+        self.sign_with(query, None, 0, Vec::new(), unix_time())
+    }
+
+    // signs a response to a request that came with request_mac
+    pub fn sign_response(&self, response: &mut DnsPacket, request_mac: &[u8]) -> Result<Vec<u8>> {
+        self.sign_with(response, Some(request_mac), 0, Vec::new(), unix_time())
+    }
+
+    fn sign_with(
+        &self,
+        packet: &mut DnsPacket,
+        request_mac: Option<&[u8]>,
+        error: u16,
+        other: Vec<u8>,
+        time_signed: u64,
+    ) -> Result<Vec<u8>> {
+        // whatever signature was there goes, a message only has room for one
+        packet
+            .resources
+            .retain(|record| !matches!(record, DnsRecord::TSIG { .. }));
+        packet.header = packet.synced_header();
+
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+        packet.write(&mut buffer)?;
+        let mut tsig = Tsig {
+            key: self.name.clone(),
+            algorithm: self.algorithm.name().to_string(),
+            time_signed,
+            fudge: self.fudge,
+            mac: Vec::new(),
+            original_id: packet.header.id,
+            error,
+            other,
+        };
+        let data = signed_data(request_mac, &buffer.buffer[..buffer.pos()], &tsig)?;
+        tsig.mac = hmac::sign(&self.key, &data).as_ref().to_vec();
+
+        let mac = tsig.mac.clone();
+        packet.resources.push(tsig.into_record());
+        packet.header = packet.synced_header();
+
+        Ok(mac)
+    }
+
+    // checks the signature on message as it came off the wire, request_mac being the MAC of the
+    // request when it's a response. Returns the message's own MAC
+    pub fn verify(&self, message: &[u8], request_mac: Option<&[u8]>) -> Result<Vec<u8>> {
+        let (start, tsig) = find_signature(message)?.ok_or("The message isn't signed")?;
+        // a server that couldn't check the request says why without signing the answer
+        if tsig.error != 0 {
+            return Err(format!("The TSIG signature was refused with {}", tsig_error_name(tsig.error)).into());
+        }
+
+        self.failure(self.check(message, start, &tsig, request_mac), &tsig)?;
+
+        Ok(tsig.mac)
+    }
+
+    // the TSIG error the signature over message[..start] fails with, 0 when it's good
+    fn check(&self, message: &[u8], start: usize, tsig: &Tsig, request_mac: Option<&[u8]>) -> u16 {
+        // names off the wire always have a wire form, one that doesn't can't be this key's
+        let Ok(data) = signed_data(request_mac, &unsigned(message, start, tsig.original_id), tsig) else {
+            return TSIG_BADKEY;
+        };
+        self.check_data(&data, tsig)
+    }
+
+    // the same for a MAC over data
+    fn check_data(&self, data: &[u8], tsig: &Tsig) -> u16 {
+        if normal(&tsig.key) != self.name || normal(&tsig.algorithm) != self.algorithm.name() {
+            return TSIG_BADKEY;
+        }
+
+        if hmac::verify(&self.key, data, &tsig.mac).is_err() {
+            return TSIG_BADSIG;
+        }
+        // the time is only checked once the signature has been, so it can't be lied about
+        if tsig.skew() > u64::from(tsig.fudge) {
+            return TSIG_BADTIME;
+        }
+
+        0
+    }
+
+    // the error a client gets for a signature that failed with the TSIG error
+    fn failure(&self, error: u16, tsig: &Tsig) -> Result<()> {
+        match error {
+            0 => Ok(()),
+            TSIG_BADKEY => Err(format!("The message is signed with {}, not {}", tsig.key, self.name).into()),
+            TSIG_BADTIME => Err(format!("The message was signed {} seconds off", tsig.skew()).into()),
+            _ => Err("The TSIG signature of the message doesn't check out".into()),
+        }
+    }
+}
+
+// sends query signed with key over udp, or over tcp when the answer doesn't fit, and fails unless the
+// response is signed with the same key
+pub fn exchange_signed(
+    query: &DnsPacket,
+    server: (Ipv4Addr, u16),
+    timeout: Option<Duration>,
+    key: &TsigKey,
+) -> Result<DnsPacket> {
+    let mut query = query.clone();
+    let mac = key.sign(&mut query)?;
+    let (response, message) = exchange_wire(&query, server, timeout)?;
+    key.verify(&message, Some(&mac))?;
+
+    Ok(response)
+}
+
+// checks the signatures on the messages of a zone transfer, which RFC 8945 chains together. The first
+// is signed like any response, the ones after it over the MAC before them and only the time and fudge
+// of their TSIG variables. Up to 99 messages in a row may be left unsigned, they're covered by the
+// next signature instead
+#[derive(Clone, Debug)]
+pub struct StreamVerifier {
+    key: TsigKey,
+    prior_mac: Vec<u8>,
+    first: bool,
+    // the messages since the last signed one
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl StreamVerifier {
+    pub fn new(key: &TsigKey, request_mac: &[u8]) -> StreamVerifier {
+        StreamVerifier {
+            key: key.clone(),
+            prior_mac: request_mac.to_vec(),
+            first: true,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    // the next message as it came off the wire
+    pub fn verify(&mut self, message: &[u8]) -> Result<()> {
+        if self.first {
+            self.prior_mac = self.key.verify(message, Some(&self.prior_mac))?;
+            self.first = false;
+            return Ok(());
+        }
+
+        let Some((start, tsig)) = find_signature(message)? else {
+            self.unsigned_count += 1;
+            if self.unsigned_count > 99 {
+                return Err("More than 99 messages in a row aren't signed".into());
+            }
+            self.unsigned.extend(message);
+            return Ok(());
+        };
+        if tsig.error != 0 {
+            return Err(format!("The TSIG signature was refused with {}", tsig_error_name(tsig.error)).into());
+        }
+
+        let mut data = Vec::with_capacity(self.unsigned.len() + message.len() + 64);
+        data.extend((self.prior_mac.len() as u16).to_be_bytes());
+        data.extend(&self.prior_mac);
+        data.extend(&self.unsigned);
+        data.extend(unsigned(message, start, tsig.original_id));
+        data.extend(&tsig.time_signed.to_be_bytes()[2..]);
+        data.extend(tsig.fudge.to_be_bytes());
+        self.key.failure(self.key.check_data(&data, &tsig), &tsig)?;
+
+        self.prior_mac = tsig.mac;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+
+        Ok(())
+    }
+
+    // the last message has to be signed, or whatever came after the last signature could be forged
+    pub fn finish(&self) -> Result<()> {
+        if self.first || self.unsigned_count > 0 {
+            return Err("The last message of the transfer isn't signed".into());
+        }
+
+        Ok(())
+    }
+}
+
+// the signature on a query a server got, and which of its keys it checked out with, for signing the
+// response with the same key
+#[derive(Clone, Debug)]
+pub struct RequestSignature {
+    key: Option<TsigKey>,
+    tsig: Tsig,
+    // 0 when the signature is good, otherwise the TSIG error the query is refused with
+    pub error: u16,
+}
+
+impl RequestSignature {
+    pub fn key_name(&self) -> &str {
+        &self.tsig.key
+    }
+
+    // signs the response to the request. A refusal carries the error and is only really signed for
+    // BADTIME, other errors mean the key didn't check out, so the client couldn't check it either
+    pub fn sign(&self, response: &mut DnsPacket) -> Result<()> {
+        match (&self.key, self.error) {
+            (Some(key), 0) => key.sign_response(response, &self.tsig.mac).map(|_| ()),
+            (Some(key), TSIG_BADTIME) => {
+                // the client gets the server's clock to see how far off it is
+                let now = unix_time().to_be_bytes()[2..].to_vec();
+                let (mac, time_signed) = (&self.tsig.mac, self.tsig.time_signed);
+                key.sign_with(response, Some(mac), TSIG_BADTIME, now, time_signed)
+                    .map(|_| ())
+            }
+            _ => {
+                let tsig = Tsig {
+                    mac: Vec::new(),
+                    original_id: response.header.id,
+                    error: self.error,
+                    other: Vec::new(),
+                    ..self.tsig.clone()
+                };
+                response.resources.push(tsig.into_record());
+                response.header = response.synced_header();
+                Ok(())
+            }
+        }
+    }
+}
+
+// the signature on request checked against keys, None when it isn't signed. Errors are for messages
+// too broken to find out
+pub fn check_request(keys: &[TsigKey], request: &[u8]) -> Result<Option<RequestSignature>> {
+    let Some((start, tsig)) = find_signature(request)? else {
+        return Ok(None);
+    };
+
+    let (name, algorithm) = (normal(&tsig.key), normal(&tsig.algorithm));
+    let key = keys
+        .iter()
+        .find(|key| key.name == name && key.algorithm.name() == algorithm);
+    let error = match key {
+        Some(key) => key.check(request, start, &tsig, None),
+        None => TSIG_BADKEY,
+    };
+
+    Ok(Some(RequestSignature {
+        key: key.cloned(),
+        tsig,
+        error,
+    }))
+}
+
+// the fields of a TSIG record
+#[derive(Clone, Debug)]
+struct Tsig {
+    key: String,
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+impl Tsig {
+    fn into_record(self) -> DnsRecord {
+        DnsRecord::TSIG {
+            domain: self.key,
+            algorithm: self.algorithm,
+            time_signed: self.time_signed,
+            fudge: self.fudge,
+            mac: self.mac,
+            original_id: self.original_id,
+            error: self.error,
+            other: self.other,
+        }
+    }
+
+    // how many seconds the signing time is away from now
+    fn skew(&self) -> u64 {
+        unix_time().abs_diff(self.time_signed)
+    }
+}
+
+// where the TSIG record of a message starts and what's in it, None when the message isn't signed
+fn find_signature(message: &[u8]) -> Result<Option<(usize, Tsig)>> {
+    let mut buffer = BytePacketBuffer::from_bytes(message)?;
+    let mut header = DnsHeader::new();
+    header.read(&mut buffer)?;
+    for _ in 0..header.questions {
+        DnsQuestion::new(String::new(), QueryType::UNKNOWN(0)).read(&mut buffer)?;
+    }
+
+    let records = [header.answers, header.authoritative_entries, header.resource_entries]
+        .iter()
+        .map(|count| *count as usize)
+        .sum();
+    for index in 0..records {
+        let start = buffer.pos();
+        let DnsRecord::TSIG {
+            domain,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        } = DnsRecord::read(&mut buffer)?
+        else {
+            continue;
+        };
+        // RFC 8945 only allows it as the very last record of the additional section
+        if index + 1 != records || header.resource_entries == 0 {
+            return Err("TSIG record isn't the last one of the message".into());
+        }
+
+        let tsig = Tsig {
+            key: domain,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        };
+        return Ok(Some((start, tsig)));
+    }
+
+    Ok(None)
+}
+
+// the message the way it was before it was signed: without the TSIG record, one record less in the
+// additional count and with the id it was signed with, which a forwarder may have changed since
+fn unsigned(message: &[u8], start: usize, original_id: u16) -> Vec<u8> {
+    let mut unsigned = message[..start].to_vec();
+    unsigned[0..2].copy_from_slice(&original_id.to_be_bytes());
+    let additional = u16::from_be_bytes([unsigned[10], unsigned[11]]).saturating_sub(1);
+    unsigned[10..12].copy_from_slice(&additional.to_be_bytes());
+
+    unsigned
+}
+
+// what the MAC is computed over: the request's MAC for a response, the message, then the TSIG
+// variables with the names in canonical form
+fn signed_data(request_mac: Option<&[u8]>, message: &[u8], tsig: &Tsig) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(message.len() + 128);
+    if let Some(mac) = request_mac {
+        data.extend((mac.len() as u16).to_be_bytes());
+        data.extend(mac);
+    }
+    data.extend(message);
+
+    data.extend(name_wire(&tsig.key)?);
+    data.extend(CLASS_ANY.to_be_bytes());
+    data.extend(0u32.to_be_bytes());
+    data.extend(name_wire(&tsig.algorithm)?);
+    data.extend(&tsig.time_signed.to_be_bytes()[2..]);
+    data.extend(tsig.fudge.to_be_bytes());
+    data.extend(tsig.error.to_be_bytes());
+    data.extend((tsig.other.len() as u16).to_be_bytes());
+    data.extend(&tsig.other);
+
+    Ok(data)
+}
+
+// the key statements of a named.conf style file, comments in any of its three styles are skipped:
+//   key "name" { algorithm hmac-sha256; secret "base64"; };
+fn parse_key_file(text: &str) -> Result<Vec<TsigKey>> {
+    let tokens = tokenize(text);
+    let mut keys = Vec::new();
+
+    let mut index = 0;
+    while index < tokens.len() {
+        if tokens[index] != "key" {
+            index += 1;
+            continue;
+        }
+        let name = tokens.get(index + 1).ok_or("A key statement has no name")?;
+        if tokens.get(index + 2).map(String::as_str) != Some("{") {
+            return Err(format!("Key {} doesn't open with {{", name).into());
+        }
+
+        let (mut algorithm, mut secret) = (None, None);
+        index += 3;
+        while index < tokens.len() && tokens[index] != "}" {
+            match (tokens[index].as_str(), tokens.get(index + 1)) {
+                ("algorithm", Some(value)) => algorithm = Some(value.parse::<TsigAlgorithm>()?),
+                ("secret", Some(value)) => secret = Some(base64_decode(value)?),
+                _ => {}
+            }
+            // on to the statement after the next semicolon
+            while index < tokens.len() && tokens[index] != ";" && tokens[index] != "}" {
+                index += 1;
+            }
+            if tokens.get(index).map(String::as_str) == Some(";") {
+                index += 1;
+            }
+        }
+
+        let algorithm = algorithm.ok_or(format!("Key {} has no algorithm", name))?;
+        let secret = secret.ok_or(format!("Key {} has no secret", name))?;
+        keys.push(TsigKey::new(name, algorithm, &secret));
+    }
+
+    if keys.is_empty() {
+        return Err("No keys in the key file".into());
+    }
+
+    Ok(keys)
+}
+
+// words, quoted strings without their quotes and the braces and semicolons on their own
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' => tokens.push(chars.by_ref().take_while(|c| *c != '"').collect()),
+            '{' | '}' | ';' => tokens.push(c.to_string()),
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                let in_word = |c: &char| !c.is_whitespace() && !matches!(c, '{' | '}' | ';' | '"');
+                while let Some(c) = chars.next_if(in_word) {
+                    word.push(c);
+                }
+                tokens.push(word);
+            }
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> TsigKey {
+        TsigKey::new(
+            "transfer.example.",
+            TsigAlgorithm::HmacSha256,
+            b"a secret only the two sides know",
+        )
+    }
+
+    fn wire(packet: &DnsPacket) -> Vec<u8> {
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_BUFFER_SIZE);
+        packet.write(&mut buffer).unwrap();
+        buffer.buffer[..buffer.pos()].to_vec()
+    }
+
+    fn signed_query() -> (Vec<u8>, Vec<u8>) {
+        let mut query = DnsPacket::new_query(0x1234, "example.com", QueryType::SOA);
+        let mac = key().sign(&mut query).unwrap();
+        (wire(&query), mac)
+    }
+
+    #[test]
+    fn signatures_check_out() {
+        let (query, mac) = signed_query();
+        assert_eq!(key().verify(&query, None).unwrap(), mac);
+        let signature = check_request(&[key()], &query).unwrap().unwrap();
+        assert_eq!((signature.key_name(), signature.error), ("transfer.example", 0));
+
+        // the response is signed over the query's MAC, so it only checks out against that
+        let mut response = DnsPacket::new_query(0x1234, "example.com", QueryType::SOA);
+        response.header.response = true;
+        signature.sign(&mut response).unwrap();
+        let response = wire(&response);
+        assert!(key().verify(&response, Some(&mac)).is_ok());
+        assert!(key().verify(&response, Some(&[0; 32])).is_err());
+
+        let unsigned = wire(&DnsPacket::new_query(0x1234, "example.com", QueryType::SOA));
+        assert!(check_request(&[key()], &unsigned).unwrap().is_none());
+        assert!(key().verify(&unsigned, None).is_err());
+    }
+
+    #[test]
+    fn tampering_and_other_keys_are_refused() {
+        let (mut query, _) = signed_query();
+        let error = |keys: &[TsigKey], query: &[u8]| check_request(keys, query).unwrap().unwrap().error;
+
+        let secret = b"a secret only the two sides know";
+        let other_name = TsigKey::new("other.example", TsigAlgorithm::HmacSha256, secret);
+        assert_eq!(error(&[other_name], &query), TSIG_BADKEY);
+        let other_algorithm = TsigKey::new("transfer.example", TsigAlgorithm::HmacSha512, b"a secret");
+        assert_eq!(error(&[other_algorithm], &query), TSIG_BADKEY);
+        let other_secret = TsigKey::new("transfer.example", TsigAlgorithm::HmacSha256, b"a guess");
+        assert_eq!(error(std::slice::from_ref(&other_secret), &query), TSIG_BADSIG);
+        assert!(other_secret.verify(&query, None).is_err());
+
+        // the query's type, SOA to AXFR
+        assert_eq!(query[12 + 13..12 + 15], [0, 6]);
+        query[12 + 14] = 252;
+        assert_eq!(error(&[key()], &query), TSIG_BADSIG);
+        assert!(key().verify(&query, None).is_err());
+    }
+
+    #[test]
+    fn signatures_outside_the_fudge_are_refused() {
+        let key = key().with_fudge(60);
+        let sign_at = |time_signed: u64| {
+            let mut query = DnsPacket::new_query(1, "example.com", QueryType::SOA);
+            key.sign_with(&mut query, None, 0, Vec::new(), time_signed).unwrap();
+            wire(&query)
+        };
+
+        for time_signed in [unix_time() - 30, unix_time() + 30] {
+            let signature = check_request(std::slice::from_ref(&key), &sign_at(time_signed)).unwrap();
+            assert_eq!(signature.unwrap().error, 0);
+        }
+        for time_signed in [unix_time() - 600, unix_time() + 600] {
+            let query = sign_at(time_signed);
+            let signature = check_request(std::slice::from_ref(&key), &query).unwrap().unwrap();
+            assert_eq!(signature.error, TSIG_BADTIME);
+            assert!(key
+                .verify(&query, None)
+                .unwrap_err()
+                .to_string()
+                .contains("seconds off"));
+
+            // the refusal is still signed, with the server's clock in the other data
+            let mut response = DnsPacket::new_query(1, "example.com", QueryType::SOA);
+            signature.sign(&mut response).unwrap();
+            let Some(DnsRecord::TSIG { error, other, .. }) = response.resources.last() else {
+                panic!("The refusal isn't signed");
+            };
+            assert_eq!((*error, other.len()), (TSIG_BADTIME, 6));
+        }
+    }
+
+    // a message of a transfer after the first, signed over the MAC before it, the unsigned messages
+    // in between and only the time and fudge of its own TSIG variables
+    fn sign_next(prior_mac: &[u8], between: &[Vec<u8>], mut message: DnsPacket) -> (Vec<u8>, Vec<u8>) {
+        let key = key();
+        let time_signed = unix_time();
+        let mut data = (prior_mac.len() as u16).to_be_bytes().to_vec();
+        data.extend(prior_mac);
+        data.extend(between.concat());
+        data.extend(wire(&message));
+        data.extend(&time_signed.to_be_bytes()[2..]);
+        data.extend(key.fudge.to_be_bytes());
+
+        let tsig = Tsig {
+            key: key.name.clone(),
+            algorithm: key.algorithm.name().to_string(),
+            time_signed,
+            fudge: key.fudge,
+            mac: hmac::sign(&key.key, &data).as_ref().to_vec(),
+            original_id: message.header.id,
+            error: 0,
+            other: Vec::new(),
+        };
+        let mac = tsig.mac.clone();
+        message.resources.push(tsig.into_record());
+        message.header = message.synced_header();
+
+        (wire(&message), mac)
+    }
+
+    fn transfer_message(address: u8) -> DnsPacket {
+        let mut message = DnsPacket::new_query(0x1234, "example.com", QueryType::AXFR);
+        message.header.response = true;
+        message.answers.push(DnsRecord::A {
+            domain: format!("host{}.example.com", address),
+            address: Ipv4Addr::new(192, 0, 2, address),
+            ttl: 300,
+        });
+        message
+    }
+
+    #[test]
+    fn transfers_are_checked_message_by_message() {
+        let mut query = DnsPacket::new_query(0x1234, "example.com", QueryType::AXFR);
+        let request_mac = key().sign(&mut query).unwrap();
+
+        let mut first = transfer_message(1);
+        let first_mac = key().sign_response(&mut first, &request_mac).unwrap();
+        let first = wire(&first);
+        let unsigned = vec![wire(&transfer_message(2)), wire(&transfer_message(3))];
+        let (last, _) = sign_next(&first_mac, &unsigned, transfer_message(4));
+
+        let mut verifier = StreamVerifier::new(&key(), &request_mac);
+        assert!(verifier.finish().is_err());
+        for message in [&first, &unsigned[0], &unsigned[1], &last] {
+            verifier.verify(message).unwrap();
+        }
+        verifier.finish().unwrap();
+
+        // a message slipped in between changes what the next signature covers
+        let mut verifier = StreamVerifier::new(&key(), &request_mac);
+        for message in [&first, &unsigned[0], &wire(&transfer_message(9)), &unsigned[1]] {
+            verifier.verify(message).unwrap();
+        }
+        assert!(verifier.verify(&last).is_err());
+
+        // and the transfer can't end on messages nobody signed
+        let mut verifier = StreamVerifier::new(&key(), &request_mac);
+        verifier.verify(&first).unwrap();
+        verifier.verify(&unsigned[0]).unwrap();
+        assert!(verifier.finish().is_err());
+
+        // the first message is checked against the request's MAC
+        assert!(StreamVerifier::new(&key(), &first_mac).verify(&first).is_err());
+    }
+
+    #[test]
+    fn key_files_are_read() {
+        let text = r#"
+            # written by tsig-keygen
+            key "transfer.example" {
+                algorithm hmac-sha256;
+                secret "YSBzZWNyZXQ=";
+            };
+            // a second key, with its statements in the other order
+            key other.example. { secret "b3RoZXI="; /* legacy */ algorithm HMAC-SHA1; };
+        "#;
+        let keys = parse_key_file(text).unwrap();
+        assert_eq!(keys.len(), 2);
+        let names: Vec<(&str, TsigAlgorithm)> = keys.iter().map(|key| (key.name.as_str(), key.algorithm)).collect();
+        assert_eq!(
+            names,
+            [
+                ("transfer.example", TsigAlgorithm::HmacSha256),
+                ("other.example", TsigAlgorithm::HmacSha1)
+            ]
+        );
+
+        // the secret is what the signatures are made with
+        let mut query = DnsPacket::new_query(1, "example.com", QueryType::SOA);
+        TsigKey::new("transfer.example", TsigAlgorithm::HmacSha256, b"a secret")
+            .sign(&mut query)
+            .unwrap();
+        assert_eq!(check_request(&keys, &wire(&query)).unwrap().unwrap().error, 0);
+
+        assert!(parse_key_file("").is_err());
+        assert!(parse_key_file("key \"x\" { algorithm hmac-sha256; };").is_err());
+        assert!(parse_key_file("key \"x\" { secret \"YQ==\"; };").is_err());
+        assert!(parse_key_file("key \"x\" { algorithm hmac-md5; secret \"YQ==\"; };").is_err());
+        assert!(parse_key_file("key \"x\" algorithm hmac-sha256;").is_err());
+    }
+}
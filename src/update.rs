@@ -0,0 +1,142 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use crate::{
+    client::exchange,
+    header::OPCODE_UPDATE,
+    record::{fqdn, CLASS_ANY, CLASS_NONE},
+    resolver::in_zone,
+    rng::random_id,
+    DnsPacket, DnsQuestion, DnsRecord, QueryType, Result,
+};
+#[cfg(feature = "tsig")]
+use crate::{tsig::exchange_signed, TsigKey};
+
+// an RFC 2136 dynamic update of one zone: prerequisites the primary checks first, then the changes it
+// makes if every one of them holds. It's all or nothing, either every change is made or none is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    pub zone: String,
+    pub prerequisites: Vec<DnsRecord>,
+    pub updates: Vec<DnsRecord>,
+}
+
+impl Update {
+    pub fn new(zone: &str) -> Update {
+        Update {
+            zone: zone.trim_end_matches('.').to_string(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    // there are records of qtype at name, whatever they are
+    pub fn require_exists(mut self, name: &str, qtype: QueryType) -> Self {
+        self.prerequisites.push(marker(name, qtype, CLASS_ANY));
+        self
+    }
+
+    // the rrset of the record is exactly the records required this way, one call for each of them
+    pub fn require_record(mut self, mut record: DnsRecord) -> Self {
+        record.set_ttl(0);
+        self.prerequisites.push(record);
+        self
+    }
+
+    // there are no records of qtype at name
+    pub fn require_absent(mut self, name: &str, qtype: QueryType) -> Self {
+        self.prerequisites.push(marker(name, qtype, CLASS_NONE));
+        self
+    }
+
+    // name has records of some type
+    pub fn require_name(mut self, name: &str) -> Self {
+        self.prerequisites.push(marker(name, QueryType::ANY, CLASS_ANY));
+        self
+    }
+
+    // name has no records at all
+    pub fn require_no_name(mut self, name: &str) -> Self {
+        self.prerequisites.push(marker(name, QueryType::ANY, CLASS_NONE));
+        self
+    }
+
+    // adds record to whatever else is in its rrset
+    pub fn add_record(mut self, record: DnsRecord) -> Self {
+        self.updates.push(record);
+        self
+    }
+
+    // deletes every record of qtype at name
+    pub fn delete_rrset(mut self, name: &str, qtype: QueryType) -> Self {
+        self.updates.push(marker(name, qtype, CLASS_ANY));
+        self
+    }
+
+    // deletes everything at name
+    pub fn delete_name(mut self, name: &str) -> Self {
+        self.updates.push(marker(name, QueryType::ANY, CLASS_ANY));
+        self
+    }
+
+    // deletes the one record with the same data, its TTL doesn't matter
+    pub fn delete_record(mut self, record: DnsRecord) -> Result<Self> {
+        self.updates.push(DnsRecord::UPDATE {
+            domain: record.domain().to_string(),
+            qtype: record.query_type(),
+            class: CLASS_NONE,
+            data: record.rdata()?,
+        });
+        Ok(self)
+    }
+
+    // the message, with the zone as its only question, the prerequisites in the answers and the
+    // changes in the authorities. Names outside the zone are refused here rather than by the primary
+    pub fn to_packet(&self, id: u16) -> Result<DnsPacket> {
+        for record in self.prerequisites.iter().chain(&self.updates) {
+            if !in_zone(record.domain(), &self.zone) {
+                let (name, zone) = (fqdn(record.domain()), fqdn(&self.zone));
+                return Err(format!("{} is not in the zone {}", name, zone).into());
+            }
+        }
+
+        let mut packet = DnsPacket::new();
+        packet.header.id = id;
+        packet.header.opcode = OPCODE_UPDATE;
+        packet
+            .questions
+            .push(DnsQuestion::new(self.zone.clone(), QueryType::SOA));
+        packet.answers = self.prerequisites.clone();
+        packet.authorities = self.updates.clone();
+        packet.header = packet.synced_header();
+
+        Ok(packet)
+    }
+
+    // sends the update to the primary of the zone, a prerequisite that doesn't hold or a refusal comes
+    // back as the matching DnsError
+    pub fn send(&self, server: (Ipv4Addr, u16), timeout: Option<Duration>) -> Result<DnsPacket> {
+        let response = exchange(&self.to_packet(random_id())?, server, timeout)?;
+        response.check_response_code()?;
+
+        Ok(response)
+    }
+
+    // send, signed with key, which is how primaries usually want to be sure who is changing the zone
+    #[cfg(feature = "tsig")]
+    pub fn send_signed(&self, server: (Ipv4Addr, u16), timeout: Option<Duration>, key: &TsigKey) -> Result<DnsPacket> {
+        let response = exchange_signed(&self.to_packet(random_id())?, server, timeout, key)?;
+        response.check_response_code()?;
+
+        Ok(response)
+    }
+}
+
+// a prerequisite or deletion that's all name, type and class, with no record data
+fn marker(name: &str, qtype: QueryType, class: u16) -> DnsRecord {
+    DnsRecord::UPDATE {
+        domain: name.trim_end_matches('.').to_string(),
+        qtype,
+        class,
+        data: Vec::new(),
+    }
+}
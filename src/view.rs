@@ -0,0 +1,41 @@
+use std::net::IpAddr;
+
+use crate::ClientSubnet;
+
+// one side of a split horizon: the clients in its networks are answered by a resolver of its own,
+// with its own zones, upstreams and cache, so internal clients can get internal answers from the same
+// server that gives everyone else the public ones
+#[derive(Clone, Debug)]
+pub struct View<R> {
+    pub name: String,
+    pub networks: Vec<ClientSubnet>,
+    pub resolver: R,
+}
+
+impl<R> View<R> {
+    pub fn new(name: &str, resolver: R) -> View<R> {
+        View {
+            name: name.to_string(),
+            networks: Vec::new(),
+            resolver,
+        }
+    }
+
+    pub fn with_network(mut self, network: ClientSubnet) -> View<R> {
+        self.networks.push(network);
+        self
+    }
+
+    // whether client is in one of the view's networks
+    pub fn matches(&self, client: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(client))
+    }
+}
+
+// the resolver of the first of views client is in, None for the clients none of them are for
+pub(crate) fn view_for<R>(views: &[View<R>], client: IpAddr) -> Option<&R> {
+    views
+        .iter()
+        .find(|view| view.matches(client))
+        .map(|view| &view.resolver)
+}
@@ -0,0 +1,434 @@
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use crate::{
+    presentation::zone_file, record::fqdn, resolver::in_zone, zonefile::parse_zone, DnsPacket, DnsRecord, QueryType,
+    Result, ResultCode,
+};
+
+// how many cnames within the zone an answer follows before it's treated as a loop
+pub(crate) const MAX_CNAME_CHAIN: usize = 8;
+
+// the records of one zone, held in memory for answering authoritatively for it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Zone {
+    pub origin: String,
+    // by lowercased name, the records of each in the order they were added
+    names: BTreeMap<String, Vec<DnsRecord>>,
+}
+
+impl Zone {
+    pub fn new(origin: &str) -> Zone {
+        Zone {
+            origin: origin.trim_end_matches('.').to_string(),
+            names: BTreeMap::new(),
+        }
+    }
+
+    // a zone of records like a transfer brings them, one of which has to be the SOA of origin
+    pub fn from_records(origin: &str, records: impl IntoIterator<Item = DnsRecord>) -> Result<Zone> {
+        let mut zone = Zone::new(origin);
+        for record in records {
+            zone.add(record)?;
+        }
+        if zone.soa().is_none() {
+            return Err(format!("The zone {} has no SOA", fqdn(&zone.origin)).into());
+        }
+
+        Ok(zone)
+    }
+
+    // the zone in a zone file like RFC 1035 has them, with $ORIGIN, $TTL and $INCLUDE, parentheses
+    // around records that go over several lines and names relative to origin where they don't end in
+    // a dot. Included paths are relative to the directory of the file including them
+    pub fn from_file(path: &Path, origin: &str) -> Result<Zone> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read the zone file {}: {}", path.display(), e))?;
+        let directory = path.parent().unwrap_or(Path::new("."));
+
+        parse_zone(&text, origin, &path.display().to_string(), directory)
+    }
+
+    // from_file for the text of a zone file, with included paths relative to the working directory
+    pub fn parse(text: &str, origin: &str) -> Result<Zone> {
+        parse_zone(text, origin, "the zone file", Path::new("."))
+    }
+
+    // writes the zone to path as a zone file, which from_file reads back
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_string())
+            .map_err(|e| format!("Couldn't write the zone file {}: {}", path.display(), e).into())
+    }
+
+    // adds record, which has to be in the zone. There's one SOA, at the origin, a second replaces it
+    pub fn add(&mut self, record: DnsRecord) -> Result<()> {
+        let name = record.domain().to_ascii_lowercase();
+        if !in_zone(&name, &self.origin) {
+            let (name, origin) = (fqdn(record.domain()), fqdn(&self.origin));
+            return Err(format!("{} is not in the zone {}", name, origin).into());
+        }
+
+        let soa = matches!(record, DnsRecord::SOA { .. });
+        if soa && !record.domain().eq_ignore_ascii_case(&self.origin) {
+            let name = fqdn(record.domain());
+            return Err(format!("The SOA at {} isn't at the origin of the zone", name).into());
+        }
+
+        let records = self.names.entry(name).or_default();
+        if soa {
+            records.retain(|existing| !matches!(existing, DnsRecord::SOA { .. }));
+            records.insert(0, record);
+        } else if !records.contains(&record) {
+            records.push(record);
+        }
+
+        Ok(())
+    }
+
+    pub fn soa(&self) -> Option<&DnsRecord> {
+        self.names
+            .get(&self.origin.to_ascii_lowercase())?
+            .iter()
+            .find(|record| matches!(record, DnsRecord::SOA { .. }))
+    }
+
+    pub fn serial(&self) -> Option<u32> {
+        match self.soa()? {
+            DnsRecord::SOA { serial, .. } => Some(*serial),
+            _ => None,
+        }
+    }
+
+    // every record of the zone with the SOA first, the way a transfer sends them
+    pub fn records(&self) -> Vec<DnsRecord> {
+        let soa = self.soa().cloned();
+        let rest = self
+            .names
+            .values()
+            .flatten()
+            .filter(|record| !matches!(record, DnsRecord::SOA { .. }));
+
+        soa.into_iter().chain(rest.cloned()).collect()
+    }
+
+    // the answer to qname, which has to be in the zone, as the zone's authoritative server gives it:
+    // the records asked for with addresses for the names they point at, a referral to the servers of a
+    // child zone or a denial with the SOA to cache it by. Only referrals come without the AA bit.
+    // Names that don't exist are answered from a wildcard when there's one that covers them
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> DnsPacket {
+        let mut answer = DnsPacket::new();
+        answer.header.authoritative_answer = true;
+        // records synthesized from a wildcard get the name as it was asked for
+        let mut asked = qname.trim_end_matches('.').to_string();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let name = asked.to_ascii_lowercase();
+            if let Some(servers) = self.delegation(&name, qtype) {
+                // still authoritative for the cnames that led below the cut, if any
+                answer.header.authoritative_answer = !answer.answers.is_empty();
+                answer.resources = self.addresses(&servers);
+                answer.authorities = servers;
+                return answer;
+            }
+
+            let Some(records) = self.records_at(&name, &asked) else {
+                return self.denial(answer, ResultCode::NXDOMAIN);
+            };
+
+            let matching: Vec<DnsRecord> = records
+                .iter()
+                .filter(|record| qtype == QueryType::ANY || record.query_type() == qtype)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                answer.resources = self.addresses(&matching);
+                answer.answers.extend(matching);
+                return answer;
+            }
+
+            // a cname stands in for every other type at its name, the chain is followed while it stays
+            // in the zone and the client asks onwards from wherever it leaves
+            let cname = records
+                .into_iter()
+                .find(|record| matches!(record, DnsRecord::CNAME { .. }));
+            let Some(cname) = cname else {
+                return self.denial(answer, ResultCode::NOERROR);
+            };
+            // a loop ends where it comes around again
+            if answer.answers.contains(&cname) {
+                return answer;
+            }
+            let DnsRecord::CNAME { host, .. } = &cname else {
+                return answer;
+            };
+            asked = host.clone();
+            answer.answers.push(cname);
+            if !in_zone(&asked, &self.origin) {
+                return answer;
+            }
+        }
+
+        answer
+    }
+
+    // the records at name the way RFC 4592 has them. A name that exists has its own, none at all when
+    // it's only there as the parent of other names. One that doesn't gets those of the wildcard below
+    // its closest encloser, the nearest of its parents that exists, with asked as their owner. None when
+    // there's no such wildcard, and so no such name
+    fn records_at(&self, name: &str, asked: &str) -> Option<Vec<DnsRecord>> {
+        if self.exists(name) {
+            return Some(self.names.get(name).cloned().unwrap_or_default());
+        }
+
+        let mut encloser = name;
+        loop {
+            encloser = match encloser.split_once('.') {
+                Some((_, parent)) => parent,
+                None if !encloser.is_empty() => "",
+                None => return None,
+            };
+            if !in_zone(encloser, &self.origin) {
+                return None;
+            }
+            if self.exists(encloser) {
+                break;
+            }
+        }
+
+        // so a.b doesn't come from * when b is there, not even as an empty non-terminal, only from *.b
+        let wildcard = match encloser.is_empty() {
+            true => String::from("*"),
+            false => format!("*.{}", encloser),
+        };
+        if !self.exists(&wildcard) {
+            return None;
+        }
+        let mut records = self.names.get(&wildcard).cloned().unwrap_or_default();
+        for record in &mut records {
+            record.set_domain(asked.to_string());
+        }
+
+        Some(records)
+    }
+
+    // whether name is in the zone, with records of its own or as an empty non-terminal, a name with
+    // nothing but names below it. The root exists in any zone that has names at all
+    fn exists(&self, name: &str) -> bool {
+        let below = format!(".{}", name);
+        self.names.contains_key(name)
+            || self
+                .names
+                .keys()
+                .any(|other| name.is_empty() || other.ends_with(&below))
+    }
+
+    // the NS records of the topmost zone cut between the origin and name. The DS records of a child
+    // zone are the parent's though, so they're answered from above its cut
+    fn delegation(&self, name: &str, qtype: QueryType) -> Option<Vec<DnsRecord>> {
+        let labels: Vec<&str> = name.split('.').collect();
+        let origin_labels = self.origin.split('.').filter(|label| !label.is_empty()).count();
+
+        for start in (0..labels.len().saturating_sub(origin_labels)).rev() {
+            if start == 0 && qtype == QueryType::DS {
+                break;
+            }
+            let Some(records) = self.names.get(&labels[start..].join(".")) else {
+                continue;
+            };
+            let servers: Vec<DnsRecord> = records
+                .iter()
+                .filter(|record| matches!(record, DnsRecord::NS { .. }))
+                .cloned()
+                .collect();
+            if !servers.is_empty() {
+                return Some(servers);
+            }
+        }
+
+        None
+    }
+
+    // the A and AAAA records in the zone for the names records point at, for the additional section
+    fn addresses<'a>(&self, records: impl IntoIterator<Item = &'a DnsRecord>) -> Vec<DnsRecord> {
+        let mut addresses = Vec::new();
+        for record in records {
+            let host = match record {
+                DnsRecord::NS { host, .. } | DnsRecord::MX { host, .. } | DnsRecord::SRV { host, .. } => host,
+                _ => continue,
+            };
+            let Some(records) = self.names.get(&host.to_ascii_lowercase()) else {
+                continue;
+            };
+            for address in records {
+                let glue = matches!(address, DnsRecord::A { .. } | DnsRecord::AAAA { .. });
+                if glue && !addresses.contains(address) {
+                    addresses.push(address.clone());
+                }
+            }
+        }
+
+        addresses
+    }
+
+    // NXDOMAIN or NODATA, with the SOA in the authorities for as long as RFC 2308 lets it be cached
+    fn denial(&self, mut answer: DnsPacket, code: ResultCode) -> DnsPacket {
+        answer.header.result_code = code;
+        if let Some(mut soa) = self.soa().cloned() {
+            if let DnsRecord::SOA { minimum, ttl, .. } = &mut soa {
+                *ttl = (*ttl).min(*minimum);
+            }
+            answer.authorities.push(soa);
+        }
+
+        answer
+    }
+}
+
+// the zone file, SOA first, with the names in the zone relative to its origin
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&zone_file(&self.origin, &self.records()))
+    }
+}
+
+// the zone among zones closest to containing name, None when none of them does
+pub(crate) fn closest_zone<'a>(zones: impl IntoIterator<Item = &'a Zone>, name: &str) -> Option<&'a Zone> {
+    zones
+        .into_iter()
+        .filter(|zone| in_zone(name, &zone.origin))
+        .max_by_key(|zone| zone.origin.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    // RFC 4592's example zone, trimmed to what the rules need and given addresses to tell names apart
+    fn zone() -> Zone {
+        let text = "\
+$ORIGIN example.
+@                 3600 IN SOA ns.example. hostmaster 1 7200 900 1209600 300
+@                 3600 IN NS  ns.example.
+ns                3600 IN A   192.0.2.53
+*                 3600 IN A   192.0.2.1
+*                 3600 IN MX  10 host1.example.
+host1             3600 IN A   192.0.2.10
+sub.*             3600 IN TXT \"this is not a wildcard\"
+_ssh._tcp.host1   3600 IN SRV 0 0 22 host1.example.
+_ssh._tcp.host2   3600 IN SRV 0 0 22 host2.example.
+subdel            3600 IN NS  ns.subdel.example.
+ns.subdel         3600 IN A   192.0.2.54
+";
+        Zone::parse(text, "example").unwrap()
+    }
+
+    fn addresses(records: &[DnsRecord]) -> Vec<(&str, Ipv4Addr)> {
+        let addresses = records.iter().filter_map(|record| match record {
+            DnsRecord::A { domain, address, .. } => Some((domain.as_str(), *address)),
+            _ => None,
+        });
+        addresses.collect()
+    }
+
+    #[test]
+    fn wildcards_answer_for_names_that_dont_exist() {
+        let zone = zone();
+        let records = zone.records_at("host3.example", "Host3.Example").unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.domain() == "Host3.Example"));
+        assert_eq!(addresses(&records), [("Host3.Example", Ipv4Addr::new(192, 0, 2, 1))]);
+
+        // more than one label below the closest encloser still comes from its wildcard
+        let records = zone.records_at("foo.bar.example", "foo.bar.example").unwrap();
+        assert_eq!(addresses(&records), [("foo.bar.example", Ipv4Addr::new(192, 0, 2, 1))]);
+
+        let answer = zone.answer("host3.example", QueryType::MX);
+        assert!(answer.header.authoritative_answer);
+        assert!(matches!(&answer.answers[..], [DnsRecord::MX { domain, .. }] if domain == "host3.example"));
+        assert_eq!(
+            addresses(&answer.resources),
+            [("host1.example", Ipv4Addr::new(192, 0, 2, 10))]
+        );
+        // and the types the wildcard doesn't have are NODATA, not NXDOMAIN
+        assert_eq!(
+            zone.answer("host3.example", QueryType::AAAA).header.result_code,
+            ResultCode::NOERROR
+        );
+    }
+
+    #[test]
+    fn names_that_exist_block_wildcards() {
+        let zone = zone();
+        // host1 exists, so it has its own A and no MX rather than the wildcard's
+        let records = zone.records_at("host1.example", "host1.example").unwrap();
+        assert_eq!(addresses(&records), [("host1.example", Ipv4Addr::new(192, 0, 2, 10))]);
+        let answer = zone.answer("host1.example", QueryType::MX);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.header.result_code, ResultCode::NOERROR);
+
+        // _tcp.host1 exists as an empty non-terminal, with no records and no wildcard for it
+        assert_eq!(
+            zone.records_at("_tcp.host1.example", "_tcp.host1.example"),
+            Some(Vec::new())
+        );
+        let answer = zone.answer("_tcp.host1.example", QueryType::A);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.header.result_code, ResultCode::NOERROR);
+
+        // the closest encloser of _telnet._tcp.host1 is _tcp.host1, which has no wildcard below it
+        assert_eq!(
+            zone.records_at("_telnet._tcp.host1.example", "_telnet._tcp.host1.example"),
+            None
+        );
+        let answer = zone.answer("_telnet._tcp.host1.example", QueryType::A);
+        assert_eq!(answer.header.result_code, ResultCode::NXDOMAIN);
+        assert!(matches!(&answer.authorities[..], [DnsRecord::SOA { ttl: 300, .. }]));
+
+        // an owner with labels in front of the * is an ordinary name, found only as itself
+        assert_eq!(
+            zone.records_at("sub.*.example", "sub.*.example")
+                .map(|records| records.len()),
+            Some(1)
+        );
+        let records = zone.records_at("sub.host3.example", "sub.host3.example").unwrap();
+        assert_eq!(
+            addresses(&records),
+            [("sub.host3.example", Ipv4Addr::new(192, 0, 2, 1))]
+        );
+    }
+
+    #[test]
+    fn names_below_a_delegation_are_referred() {
+        let zone = zone();
+        for name in ["subdel.example", "host.subdel.example", "a.b.subdel.example"] {
+            let answer = zone.answer(name, QueryType::A);
+            assert!(!answer.header.authoritative_answer, "{}", name);
+            assert_eq!(answer.header.result_code, ResultCode::NOERROR);
+            assert!(answer.answers.is_empty());
+            let servers = match &answer.authorities[..] {
+                [DnsRecord::NS { domain, host, .. }] => (domain.as_str(), host.as_str()),
+                authorities => panic!("{:?}", authorities),
+            };
+            assert_eq!(servers, ("subdel.example", "ns.subdel.example"));
+            assert_eq!(
+                addresses(&answer.resources),
+                [("ns.subdel.example", Ipv4Addr::new(192, 0, 2, 54))]
+            );
+        }
+
+        // the glue below the cut is the child's data, only handed out with the referral
+        let answer = zone.answer("ns.subdel.example", QueryType::A);
+        assert!(!answer.header.authoritative_answer);
+        assert!(answer.answers.is_empty());
+
+        // the DS of the child is the parent's to answer, from above the cut
+        let answer = zone.answer("subdel.example", QueryType::DS);
+        assert!(answer.header.authoritative_answer);
+        assert!(answer.answers.is_empty());
+        assert!(matches!(&answer.authorities[..], [DnsRecord::SOA { .. }]));
+        // the apex's own NS records aren't a delegation
+        let answer = zone.answer("example", QueryType::NS);
+        assert!(answer.header.authoritative_answer);
+        assert!(matches!(&answer.answers[..], [DnsRecord::NS { .. }]));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    presentation::{absolute_name, record_from_fields, seconds, tokenize, ttl_and_class},
+    record::fqdn,
+    DnsRecord, QueryType, Result, Zone,
+};
+
+// how deep $INCLUDE files may include others, so a file including itself is caught
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// one entry of a zone file, which parentheses can spread over several lines
+struct Entry {
+    line: usize,
+    // entries starting with whitespace are for the owner of the one before
+    same_owner: bool,
+    text: String,
+}
+
+// where a zone file is in reading it, $INCLUDE files start out from the state of the file including
+// them, without changing it
+#[derive(Clone)]
+struct State {
+    origin: String,
+    // from $TTL
+    ttl: Option<u32>,
+    // the last TTL a record gave, which records without one got before there was $TTL
+    last_ttl: Option<u32>,
+    owner: Option<String>,
+    // the directory $INCLUDE paths are relative to
+    directory: PathBuf,
+    depth: usize,
+}
+
+impl State {
+    fn new(origin: &str, directory: &Path) -> State {
+        State {
+            origin: origin.to_string(),
+            ttl: None,
+            last_ttl: None,
+            owner: None,
+            directory: directory.to_path_buf(),
+            depth: 0,
+        }
+    }
+}
+
+// the zone with origin in the text of a zone file, source names it in errors
+pub(crate) fn parse_zone(text: &str, origin: &str, source: &str, directory: &Path) -> Result<Zone> {
+    let origin = origin.trim_end_matches('.');
+    let mut state = State::new(origin, directory);
+
+    let mut zone = Zone::new(origin);
+    parse_into(text, source, &mut state, &mut |record| zone.add(record))?;
+    if zone.soa().is_none() {
+        return Err(format!("{} has no SOA for {}", source, fqdn(origin)).into());
+    }
+
+    Ok(zone)
+}
+
+// the records in text written like those of a zone file, without one having to be an SOA and with
+// names that don't end in a dot taken as absolute unless there's an $ORIGIN
+pub(crate) fn parse_records(text: &str, source: &str, directory: &Path) -> Result<Vec<DnsRecord>> {
+    let mut state = State::new("", directory);
+
+    let mut records = Vec::new();
+    parse_into(text, source, &mut state, &mut |record| {
+        records.push(record);
+        Ok(())
+    })?;
+
+    Ok(records)
+}
+
+// every record read is handed to add, which can turn it down with an error
+type Add<'a> = dyn FnMut(DnsRecord) -> Result<()> + 'a;
+
+fn parse_into(text: &str, source: &str, state: &mut State, add: &mut Add) -> Result<()> {
+    for entry in entries(text).map_err(|e| format!("{}: {}", source, e))? {
+        parse_entry(&entry, state, add).map_err(|e| format!("Line {} of {}: {}", entry.line, source, e))?;
+    }
+
+    Ok(())
+}
+
+fn parse_entry(entry: &Entry, state: &mut State, add: &mut Add) -> Result<()> {
+    let fields = tokenize(&entry.text)?;
+    let Some((first, rest)) = fields.split_first() else {
+        return Ok(());
+    };
+
+    if !entry.same_owner && !first.quoted && first.text.starts_with('$') {
+        return match (first.text.to_ascii_uppercase().as_str(), rest) {
+            ("$ORIGIN", [origin]) => {
+                state.origin = absolute_name(&origin.text, &state.origin)?;
+                Ok(())
+            }
+            ("$TTL", [ttl]) => {
+                state.ttl = Some(seconds(&ttl.text)?);
+                Ok(())
+            }
+            ("$INCLUDE", [path, origin @ ..]) if origin.len() <= 1 => {
+                let mut included = state.clone();
+                if let [origin] = origin {
+                    included.origin = absolute_name(&origin.text, &state.origin)?;
+                }
+                include(&state.directory.join(&path.text), &mut included, add)
+            }
+            (directive, _) => Err(format!("Unsupported or malformed {} directive", directive).into()),
+        };
+    }
+
+    let (owner, rest) = match entry.same_owner {
+        true => (state.owner.clone().ok_or("The first record has no owner")?, &fields[..]),
+        false => (absolute_name(&first.text, &state.origin)?, rest),
+    };
+    let (ttl, rest) = ttl_and_class(rest)?;
+    let (qtype, rdata) = rest.split_first().ok_or("The record has no type")?;
+    let qtype = QueryType::from_str(&qtype.text)?;
+
+    // RFC 2308 has records without a TTL get the $TTL, before it they got the last one given
+    if ttl.is_some() {
+        state.last_ttl = ttl;
+    }
+    let ttl = ttl.or(state.ttl).or(state.last_ttl);
+    let ttl = ttl.ok_or("The record has no TTL and there's no $TTL before it")?;
+
+    add(record_from_fields(owner.clone(), ttl, qtype, rdata, &state.origin)?)?;
+    state.owner = Some(owner);
+
+    Ok(())
+}
+
+fn include(path: &Path, state: &mut State, add: &mut Add) -> Result<()> {
+    if state.depth >= MAX_INCLUDE_DEPTH {
+        let depth = MAX_INCLUDE_DEPTH;
+        return Err(format!("Including {} goes more than {} files deep", path.display(), depth).into());
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+
+    state.depth += 1;
+    state.owner = None;
+    if let Some(directory) = path.parent() {
+        state.directory = directory.to_path_buf();
+    }
+    parse_into(&text, &path.display().to_string(), state, add)
+}
+
+// the entries of a zone file with comments left out and the lines inside parentheses joined together
+fn entries(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut depth = 0;
+    let (mut in_quotes, mut in_comment) = (false, false);
+
+    let mut line = 1;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let current = entry.get_or_insert_with(|| Entry {
+            line,
+            same_owner: c == ' ' || c == '\t',
+            text: String::new(),
+        });
+        match c {
+            '\n' => {
+                if in_quotes {
+                    return Err(format!("The quoted string on line {} is never closed", line).into());
+                }
+                line += 1;
+                in_comment = false;
+                match depth {
+                    0 => entries.extend(entry.take()),
+                    _ => current.text.push(' '),
+                }
+            }
+            _ if in_comment => {}
+            '\\' => {
+                current.text.push('\\');
+                let escaped = chars.next();
+                // an escaped newline is part of the entry, but the lines after it still count
+                if escaped == Some('\n') {
+                    line += 1;
+                }
+                current.text.extend(escaped);
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                current.text.push('"');
+            }
+            _ if in_quotes => current.text.push(c),
+            ';' => in_comment = true,
+            '(' => {
+                depth += 1;
+                current.text.push(' ');
+            }
+            ')' if depth == 0 => {
+                return Err(format!("Line {} closes a parenthesis that isn't open", line).into());
+            }
+            ')' => {
+                depth -= 1;
+                current.text.push(' ');
+            }
+            c => current.text.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("The quoted string on line {} is never closed", line).into());
+    }
+    match entry {
+        Some(entry) if depth > 0 => Err(format!("The parenthesis on line {} is never closed", entry.line).into()),
+        entry => {
+            entries.extend(entry);
+            Ok(entries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn records(text: &str) -> Result<Vec<DnsRecord>> {
+        parse_records(text, "test.zone", Path::new("."))
+    }
+
+    fn a(domain: &str, octet: u8, ttl: u32) -> DnsRecord {
+        DnsRecord::A {
+            domain: domain.to_string(),
+            address: Ipv4Addr::new(192, 0, 2, octet),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn soa_records_span_lines_in_parentheses() {
+        let text = "\
+$ORIGIN example.com.
+@   3600 IN SOA ns1 hostmaster ( ; the primary and who runs it
+            2024010101 ; serial
+            7200       ; refresh
+            900 1209600
+            300 )
+";
+        let zone = parse_zone(text, "example.com", "test.zone", Path::new(".")).unwrap();
+        let soa = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 900,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        };
+        assert_eq!(zone.records(), [soa]);
+
+        let error = records("@ 3600 IN SOA ns1 hostmaster (\n 1 2 3 4 5\n").unwrap_err();
+        assert!(
+            error.to_string().contains("parenthesis on line 1 is never closed"),
+            "{}",
+            error
+        );
+        assert!(records("www 300 IN A 192.0.2.1 )").is_err());
+    }
+
+    #[test]
+    fn relative_owners_are_under_the_origin() {
+        let text = "\
+$ORIGIN example.com.
+www     300 IN A 192.0.2.1
+@       300 IN A 192.0.2.2
+api.eu. 300 IN A 192.0.2.3
+$ORIGIN internal
+db      300 IN A 192.0.2.4
+";
+        assert_eq!(
+            records(text).unwrap(),
+            [
+                a("www.example.com", 1, 300),
+                a("example.com", 2, 300),
+                a("api.eu", 3, 300),
+                a("db.internal.example.com", 4, 300),
+            ]
+        );
+
+        // the targets of records are relative to the origin too
+        let cname = records("$ORIGIN example.com.\nwww 300 IN CNAME web\n").unwrap();
+        let host = match &cname[..] {
+            [DnsRecord::CNAME { host, .. }] => host.as_str(),
+            records => panic!("{:?}", records),
+        };
+        assert_eq!(host, "web.example.com");
+    }
+
+    #[test]
+    fn owners_and_ttls_carry_over() {
+        let text = "\
+$ORIGIN example.com.
+www  600 IN A 192.0.2.1
+         IN A 192.0.2.2
+mail     IN A 192.0.2.3
+$TTL 1h
+         IN A 192.0.2.4
+ftp      IN A 192.0.2.5
+ftp   60 IN A 192.0.2.6
+         IN A 192.0.2.7
+";
+        assert_eq!(
+            records(text).unwrap(),
+            [
+                a("www.example.com", 1, 600),
+                a("www.example.com", 2, 600),
+                a("mail.example.com", 3, 600),
+                a("mail.example.com", 4, 3600),
+                a("ftp.example.com", 5, 3600),
+                a("ftp.example.com", 6, 60),
+                a("ftp.example.com", 7, 3600),
+            ]
+        );
+
+        assert!(records("www IN A 192.0.2.1\n").is_err());
+        assert!(records(" 300 IN A 192.0.2.1\n").is_err());
+    }
+
+    #[test]
+    fn errors_count_escaped_newlines_as_lines() {
+        let text = "www 300 IN TXT \"one\\\ntwo\"\nwww 300 IN A 192.0.2.300\n";
+        let error = records(text).unwrap_err();
+        assert!(error.to_string().starts_with("Line 3 of test.zone"), "{}", error);
+    }
+
+    #[test]
+    fn includes_that_loop_are_refused() {
+        let directory = std::env::temp_dir().join(format!("dnslearning-include-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("hosts.zone"), "host 300 IN A 192.0.2.1\n").unwrap();
+        std::fs::write(directory.join("loop.zone"), "$INCLUDE loop.zone\n").unwrap();
+
+        // an include gets the origin it's given and leaves the file including it as it was
+        let text = "$ORIGIN example.com.\n$INCLUDE hosts.zone lab\nwww 300 IN A 192.0.2.2\n";
+        let included = parse_records(text, "test.zone", &directory);
+        let looped = parse_records("$INCLUDE loop.zone\n", "test.zone", &directory);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            included.unwrap(),
+            [a("host.lab.example.com", 1, 300), a("www.example.com", 2, 300)]
+        );
+        let error = looped.unwrap_err().to_string();
+        assert!(
+            error.contains(&format!("more than {} files deep", MAX_INCLUDE_DEPTH)),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,21 @@
+// asks a public resolver, so it only runs with --ignored where there's a network
+#![cfg(feature = "tokio")]
+
+use std::net::Ipv4Addr;
+
+use dns_learning::{lookup_async, DnsRecord, QueryType};
+
+#[tokio::test]
+#[ignore]
+async fn resolves_a_known_host() {
+    let response = lookup_async("example.com", QueryType::A, (Ipv4Addr::new(8, 8, 8, 8), 53))
+        .await
+        .unwrap();
+
+    assert!(response.header.response);
+    assert_eq!(response.questions[0].name, "example.com");
+    assert!(response
+        .answers
+        .iter()
+        .any(|record| matches!(record, DnsRecord::A { .. })));
+}